@@ -0,0 +1,349 @@
+use crate::Result;
+use crate::pixels::Pixels;
+use crate::png::ChunkReader;
+use std::io::{Read, Seek};
+
+/// Chunk types defined by the PNG specification and its common extensions
+pub const STANDARD_CHUNKS: &[&str] = &[
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCv",
+    "cLLi", "tEXt", "zTXt", "iTXt", "bKGD", "hIST", "pHYs", "sPLT", "eXIf", "tIME", "acTL", "fcTL",
+    "fdAT",
+];
+
+/// How often a chunk type occurs in a PNG and how many bytes of data it holds in all
+#[derive(Debug, PartialEq)]
+pub struct ChunkTally {
+    pub chunk_type: String,
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// The ancillary chunk types in `chunks` that are not in [`STANDARD_CHUNKS`], the likely
+/// places for hidden data, in the order they first appear. Only chunk headers are read.
+pub fn unknown_chunks<R: Read + Seek>(chunks: &mut ChunkReader<R>) -> Result<Vec<ChunkTally>> {
+    let mut tallies: Vec<ChunkTally> = vec![];
+    while let Some(header) = chunks.next_header()? {
+        let chunk_type = header.chunk_type.to_string();
+        if !header.chunk_type.is_critical() && !STANDARD_CHUNKS.contains(&chunk_type.as_str()) {
+            match tallies
+                .iter_mut()
+                .find(|tally| tally.chunk_type == chunk_type)
+            {
+                Some(tally) => {
+                    tally.count += 1;
+                    tally.bytes += header.length as u64;
+                }
+                None => tallies.push(ChunkTally {
+                    chunk_type,
+                    count: 1,
+                    bytes: header.length as u64,
+                }),
+            }
+        }
+        chunks.skip_chunk()?;
+    }
+    Ok(tallies)
+}
+
+/// Shannon entropy of `data` in bits per byte (0 to 8)
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let total = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Entropy relative to the most `data` could reach for its length, so short random
+/// chunks score close to 1 like long ones
+pub fn relative_entropy(data: &[u8]) -> f64 {
+    let max = (data.len().min(256) as f64).log2();
+    if max > 0.0 { entropy(data) / max } else { 0.0 }
+}
+
+/// Color samples (alpha skipped) split into one scanline-order stream per channel
+pub fn channel_samples(pixels: &Pixels) -> Result<Vec<Vec<u8>>> {
+    let channels = pixels.ihdr.channels() - pixels.ihdr.has_alpha() as usize;
+    let mut streams = vec![Vec::new(); channels];
+    for (i, offset) in pixels.sample_offsets()?.into_iter().enumerate() {
+        streams[i % channels].push(pixels.byte(offset));
+    }
+    Ok(streams)
+}
+
+/// Westfeld-Pfitzmann chi-square attack: the probability that the low bits of `samples`
+/// were replaced by message bits, which evens out the counts of each value pair 2k, 2k+1
+pub fn chi_square(samples: &[u8]) -> f64 {
+    let mut histogram = [0u64; 256];
+    for &sample in samples {
+        histogram[sample as usize] += 1;
+    }
+
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // Sparse pairs make the statistic unreliable
+        if expected < 5.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    1.0 - regularized_gamma_p((categories - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+/// Fridrich's RS analysis: estimates the fraction of samples in one channel whose low
+/// bit carries hidden data, from how flipping low bits changes the smoothness of groups
+/// of four neighbouring samples. `None` if there are too few samples to tell.
+pub fn rs_estimate(samples: &[u8]) -> Option<f64> {
+    const MASK: [bool; 4] = [false, true, true, false];
+    if samples.len() < 4 * 100 {
+        return None;
+    }
+
+    // Fractions of regular minus singular groups under the mask and the negated mask
+    let differences = |flip_all: bool| {
+        let mut sums = [0i64; 2];
+        let mut groups = 0;
+        for group in samples.chunks_exact(4) {
+            let group: Vec<i32> = group
+                .iter()
+                .map(|&s| (if flip_all { s ^ 1 } else { s }) as i32)
+                .collect();
+            let smoothness = |g: &[i32]| g.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<i32>();
+            let original = smoothness(&group);
+            for (sum, negative) in sums.iter_mut().zip([false, true]) {
+                let flipped: Vec<i32> = group
+                    .iter()
+                    .zip(MASK)
+                    .map(|(&x, masked)| match (masked, negative) {
+                        (false, _) => x,
+                        (true, false) => x ^ 1,
+                        // Shifted flipping: -1 <-> 0, 1 <-> 2, ...
+                        (true, true) => ((x + 1) ^ 1) - 1,
+                    })
+                    .collect();
+                *sum += (smoothness(&flipped) - original).signum() as i64;
+            }
+            groups += 1;
+        }
+        sums.map(|sum| sum as f64 / groups as f64)
+    };
+
+    let [d0, dn0] = differences(false);
+    let [d1, dn1] = differences(true);
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+
+    let x = if a.abs() < 1e-9 {
+        if b.abs() < 1e-9 {
+            return Some(0.0);
+        }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            // Sampling noise can push the roots off the real line near full embedding;
+            // the vertex is then the closest real answer
+            -b / (2.0 * a)
+        } else {
+            let roots = [
+                (-b + discriminant.sqrt()) / (2.0 * a),
+                (-b - discriminant.sqrt()) / (2.0 * a),
+            ];
+            if roots[0].abs() < roots[1].abs() {
+                roots[0]
+            } else {
+                roots[1]
+            }
+        }
+    };
+    Some((x / (x - 0.5)).clamp(0.0, 1.0))
+}
+
+/// Regularized lower incomplete gamma function P(a, x), as used by the chi-square CDF
+fn regularized_gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let log_prefix = a * x.ln() - x - ln_gamma(a);
+    if x < a + 1.0 {
+        // Series expansion
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..500 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term < sum * 1e-15 {
+                break;
+            }
+        }
+        (sum.ln() + log_prefix).exp().min(1.0)
+    } else {
+        // Continued fraction for Q(a, x) (modified Lentz)
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for n in 1..500 {
+            let an = -(n as f64) * (n as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (1.0 - (log_prefix.exp() * h)).max(0.0)
+    }
+}
+
+/// Natural log of the gamma function (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5;
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |sum, (i, &c)| {
+            sum + c / (x + 1.0 + i as f64)
+        });
+    (2.506_628_274_631_000_5 * series / x).ln() + (x + 0.5) * tmp.ln() - tmp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A smooth, slightly noisy signal like one channel of a photo
+    fn natural_samples(len: usize) -> Vec<u8> {
+        let mut state = 12345u32;
+        (0..len)
+            .map(|i| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let base = 128.0 + 90.0 * (i as f64 / 40.0).sin() * (i as f64 / 1700.0).cos();
+                (base + ((state >> 16) % 5) as f64 - 2.0) as u8
+            })
+            .collect()
+    }
+
+    /// Replaces the low bit of the first `fraction` of `samples` with pseudorandom bits
+    fn embed_random_bits(samples: &mut [u8], fraction: f64) {
+        let mut state = 777u32;
+        let count = (samples.len() as f64 * fraction) as usize;
+        for sample in &mut samples[..count] {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            *sample = (*sample & !1) | ((state >> 16) & 1) as u8;
+        }
+    }
+
+    #[test]
+    fn test_unknown_chunks() {
+        use crate::chunk::Chunk;
+        use crate::png::Png;
+
+        let chunk =
+            |chunk_type: &str, data: &[u8]| Chunk::new(chunk_type.parse().unwrap(), data.to_vec());
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("ruSt", b"one"),
+            chunk("tEXt", b"Title\0two"),
+            chunk("prVt", b"secret"),
+            chunk("ruSt", b"three"),
+            chunk("IEND", b""),
+        ]);
+        let bytes = png.as_bytes();
+        let mut chunks = ChunkReader::new(std::io::Cursor::new(bytes)).unwrap();
+        let tally = |chunk_type: &str, count, bytes| ChunkTally {
+            chunk_type: chunk_type.to_string(),
+            count,
+            bytes,
+        };
+        assert_eq!(
+            unknown_chunks(&mut chunks).unwrap(),
+            [tally("ruSt", 2, 8), tally("prVt", 1, 6)]
+        );
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert!((entropy(&[0, 1, 0, 1]) - 1.0).abs() < 1e-12);
+        let all: Vec<u8> = (0..=255).collect();
+        assert!((entropy(&all) - 8.0).abs() < 1e-12);
+        assert!((relative_entropy(&all[..16]) - 1.0).abs() < 1e-12);
+        assert!(relative_entropy(b"aaaaaaaaaaaaaaab") < 0.3);
+    }
+
+    #[test]
+    fn test_chi_square_detects_full_embedding() {
+        // Smooth histograms look like embedding, so use an image upscaled from 7 bits
+        let mut samples: Vec<u8> = natural_samples(30_000).iter().map(|s| s & !1).collect();
+        assert!(chi_square(&samples) < 0.1);
+        embed_random_bits(&mut samples, 1.0);
+        assert!(chi_square(&samples) > 0.9);
+    }
+
+    #[test]
+    fn test_rs_estimates_message_length() {
+        let clean = natural_samples(30_000);
+        assert!(rs_estimate(&clean).unwrap() < 0.05);
+
+        for fraction in [0.5, 1.0] {
+            let mut samples = clean.clone();
+            embed_random_bits(&mut samples, fraction);
+            let estimate = rs_estimate(&samples).unwrap();
+            assert!(
+                (estimate - fraction).abs() < 0.15,
+                "estimated {} for {}",
+                estimate,
+                fraction
+            );
+        }
+        assert_eq!(rs_estimate(&clean[..100]), None);
+    }
+
+    #[test]
+    fn test_regularized_gamma_p() {
+        // Chi-square CDF with 2 degrees of freedom is 1 - e^(-x/2)
+        for x in [0.5, 2.0, 10.0] {
+            let expected = 1.0 - (-x / 2.0f64).exp();
+            assert!((regularized_gamma_p(1.0, x / 2.0) - expected).abs() < 1e-9);
+        }
+        assert!((ln_gamma(5.0) - 24.0f64.ln()).abs() < 1e-9);
+    }
+}
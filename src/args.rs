@@ -1,34 +1,661 @@
-use clap::{Parser, Subcommand};
+use crate::atomic_file::DEFAULT_KEEP_BACKUPS;
+use crate::chunk::Chunk;
+use crate::completions::Shell;
+use crate::crypto::Cipher;
+use crate::encoding::Encoding;
+use crate::payload::{self, Compression};
+use crate::pixels::PixelFormat;
+use crate::{fec, lsb, watermark};
+use clap::{ArgGroup, Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[clap(
     author,
     version,
-    about = "A secure PNG steganography tool with atomic operations"
+    about = "A secure PNG steganography tool with atomic operations",
+    after_help = "Exit status: 0 success, 1 other failure, 2 usage error, 3 file not found, \
+                  4 unreadable file or message, 5 chunk not found, 6 invalid argument, \
+                  7 decryption or verification failure, 8 read or write error"
 )]
 pub struct Args {
     #[command(subcommand)]
     pub command: Commands,
+    /// How many timestamped backup versions of each file to keep; older ones are deleted
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        default_value_t = DEFAULT_KEEP_BACKUPS as u64,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub keep_backups: u64,
+    /// Keep backups in DIR, one subdirectory per image, instead of next to the images;
+    /// without DIR, in ~/.local/share/hidden-pixel-vault/backups. The HPV_BACKUP_DIR
+    /// environment variable sets a directory for every run.
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        num_args = 0..=1,
+        default_missing_value = ""
+    )]
+    pub backup_dir: Option<PathBuf>,
+    /// Name backups IMAGE<SUFFIX>.<TIME> instead of IMAGE.backup.<TIME>, e.g. with .bak
+    /// where other tools already use .backup. The HPV_BACKUP_SUFFIX environment variable
+    /// sets one for every run.
+    #[arg(long, global = true, value_name = "SUFFIX", value_parser = parse_suffix)]
+    pub backup_suffix: Option<String>,
+    /// Name temp files .IMAGE<SUFFIX><RANDOM> instead of .IMAGE.hpv-<RANDOM>. The
+    /// HPV_TEMP_SUFFIX environment variable sets one for every run.
+    #[arg(long, global = true, value_name = "SUFFIX", value_parser = parse_suffix)]
+    pub temp_suffix: Option<String>,
+    /// Store new backups zstd-compressed (.zst), to save space on large images
+    #[arg(long, global = true)]
+    pub compress_backups: bool,
+    /// Work out and report what would change, without writing, restoring or removing
+    /// any image or backup
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Print one JSON document on stdout instead of text: whether the command succeeded,
+    /// its result or error, and its messages
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Print nothing but decoded messages and errors, which go to stderr
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Also print each step, with byte counts and chunk offsets
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+    /// Print plain ASCII without emoji or box drawing, which is the default when output
+    /// is not a terminal or the NO_COLOR environment variable is set
+    #[arg(long, global = true)]
+    pub plain: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Encode a secret message into a PNG file
     Encode {
-        path: String,
+        path: PathBuf,
         chunk_type: String,
-        message: String,
+        #[command(flatten)]
+        message: MessageArgs,
+        /// Refuse to decode the message after this date (YYYY-MM-DD, UTC)
+        #[arg(long, value_name = "DATE")]
+        expires: Option<String>,
+        /// Write the result to this file, leaving the original untouched
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            conflicts_with_all = ["split", "shard", "glob"]
+        )]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        embedding: EmbeddingArgs,
+        #[command(flatten)]
+        encryption: Box<EncryptionArgs>,
+    },
+    /// Replace the message in an existing chunk of a PNG file in one step, keeping its place
+    Update {
+        path: PathBuf,
+        chunk_type: String,
+        #[command(flatten)]
+        message: MessageArgs,
+        /// Refuse to decode the message after this date (YYYY-MM-DD, UTC)
+        #[arg(long, value_name = "DATE")]
+        expires: Option<String>,
+        #[command(flatten)]
+        encryption: Box<EncryptionArgs>,
     },
     /// Decode a secret message from a PNG file
-    Decode { path: String, chunk_type: String },
+    Decode {
+        path: PathBuf,
+        #[arg(required_unless_present = "all")]
+        chunk_type: Option<String>,
+        /// The other images of a message written with --shard
+        #[arg(requires = "shard")]
+        more_paths: Vec<PathBuf>,
+        /// Reassemble a message sharded with `encode --shard` from PATH and MORE_PATHS
+        #[arg(long, requires = "more_paths")]
+        shard: bool,
+        /// Reveal what every non-standard chunk holds, for files whose chunk names are unknown
+        #[arg(
+            long,
+            conflicts_with_all = ["chunk_type", "shard", "out", "raw", "lsb", "idat", "standard_text", "xmp", "obfuscate_chunk"]
+        )]
+        all: bool,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        location: LocationArgs,
+        #[command(flatten)]
+        decryption: DecryptionArgs,
+    },
+    /// Recover a message split with `encode --split` from K or more images
+    Join {
+        chunk_type: String,
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        location: LocationArgs,
+        #[command(flatten)]
+        decryption: DecryptionArgs,
+    },
+    /// Re-encrypt an encrypted chunk with a new password or key file
+    Rekey {
+        path: PathBuf,
+        chunk_type: String,
+        #[command(flatten)]
+        rekey: RekeyArgs,
+    },
+    /// Generate an X25519 key pair, writing the private key to PATH and the public key to PATH.pub
+    Keygen {
+        path: PathBuf,
+        /// Generate an Ed25519 signing key for `sign`/`verify` instead
+        #[arg(long)]
+        signing: bool,
+    },
+    /// Store an Ed25519 signature of the image (or one chunk) in a signature chunk
+    Sign {
+        path: PathBuf,
+        /// Signing key file created with `keygen --signing`
+        #[arg(long)]
+        key: PathBuf,
+        /// Sign only this chunk instead of the whole image
+        #[arg(long)]
+        chunk: Option<String>,
+    },
+    /// Check every chunk CRC and the structure of a PNG file, then the Ed25519 signatures stored in it
+    Verify {
+        path: PathBuf,
+        /// Verifying key file (.pub) the signatures must have been made with
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// Only verify the signature covering this chunk
+        #[arg(long)]
+        chunk: Option<String>,
+    },
+    /// Write the raw data of a chunk to a file, e.g. an ICC profile, EXIF data or an encrypted payload
+    DumpChunk {
+        path: PathBuf,
+        chunk_type: String,
+        out_file: PathBuf,
+        /// Which chunk of that type to write when there are several, counting from 0
+        #[arg(long, value_name = "N")]
+        index: Option<usize>,
+    },
+    /// Write the decoded pixels of a PNG file to a file, for tools that work on pixel data
+    ExtractPixels {
+        path: PathBuf,
+        out_file: PathBuf,
+        /// What to write: bare scanlines, a Netpbm image or a clean PNG copy
+        #[arg(long, value_enum, default_value_t)]
+        format: PixelFormat,
+    },
+    /// Write a new single-color PNG, e.g. as a cover image for hidden messages
+    Create {
+        path: PathBuf,
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        width: u32,
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        height: u32,
+        /// Fill color as RRGGBB, or RRGGBBAA for an image with an alpha channel
+        #[arg(long, default_value = "ffffff")]
+        fill: String,
+    },
+    /// Add a chunk whose data is the contents of a file, e.g. to restore an ICC profile saved with dump-chunk
+    InjectChunk {
+        path: PathBuf,
+        chunk_type: String,
+        in_file: PathBuf,
+        /// Put the chunk right before the first chunk of this type, e.g. iCCP before PLTE or IDAT
+        #[arg(long, value_name = "CHUNK_TYPE", conflicts_with = "index")]
+        before: Option<String>,
+        /// Put the chunk at this position in the chunk list, counting IHDR as 0
+        #[arg(long, value_name = "N")]
+        index: Option<usize>,
+    },
+    /// Move every chunk of a type to another place in the file, keeping their order
+    MoveChunk {
+        path: PathBuf,
+        chunk_type: String,
+        #[command(flatten)]
+        placement: PlacementArgs,
+    },
+    /// Give every chunk of a type a new type, keeping its data and place
+    RenameChunk {
+        path: PathBuf,
+        old_type: String,
+        new_type: String,
+    },
+    /// Remove every ancillary chunk (metadata and hidden messages) except the kept types
+    Strip {
+        path: PathBuf,
+        /// Chunk type to keep, e.g. the vault chunk or iCCP (can be repeated)
+        #[arg(long, value_name = "CHUNK_TYPE")]
+        keep: Vec<String>,
+    },
+    /// Recompress the image data at maximum compression to win back space
+    Optimize {
+        path: PathBuf,
+        /// Also choose a new filter type for every row of pixels
+        #[arg(long)]
+        refilter: bool,
+    },
+    /// Fix recoverable damage in a PNG file: wrong CRCs, a truncated last chunk or a missing IEND
+    Repair { path: PathBuf },
     /// Remove a chunk from a PNG file
-    Remove { path: String, chunk_type: String },
+    Remove {
+        path: PathBuf,
+        chunk_type: String,
+        /// Remove only this chunk of that type, counting from 0, instead of the message
+        #[arg(long, value_name = "N")]
+        index: Option<usize>,
+        /// Write the result to this file, leaving the original untouched
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
     /// Print all available chunks in a PNG file
-    Print { path: String },
+    Print {
+        path: PathBuf,
+        /// Also show chunk sizes and payload details such as expiry
+        #[arg(long)]
+        detailed: bool,
+    },
+    /// Compare two PNG files chunk by chunk, e.g. an encoded file with its backup
+    Diff { old: PathBuf, new: PathBuf },
+    /// Show the dimensions, color format and chunk count of a PNG file
+    Info { path: PathBuf },
+    /// Show a thumbnail of a PNG file in the terminal, to check it is the right cover image
+    Preview {
+        path: PathBuf,
+        /// Widest the thumbnail may be, in characters
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u16).range(1..))]
+        width: u16,
+        /// Draw with plain characters instead of 24-bit ANSI colors
+        #[arg(long)]
+        ascii: bool,
+    },
+    /// Summarize the chunks of a PNG file by type and size, including how much hidden data adds
+    Stats { path: PathBuf },
+    /// Show how many bytes can be hidden in a PNG file with each mode
+    Capacity { path: PathBuf },
+    /// Run statistical steganalysis on a PNG file and estimate whether it hides data
+    Analyze { path: PathBuf },
+    /// Walk a directory tree and list the PNG files holding non-standard chunks, the
+    /// likely places for hidden data
+    Scan { dir: PathBuf },
+    /// Embed or verify a robust watermark that survives re-saving and mild recompression
+    Watermark {
+        #[command(subcommand)]
+        action: WatermarkAction,
+    },
     /// Restore original file from backup
-    Restore { path: String },
+    Restore {
+        path: PathBuf,
+        /// Restore this backup version instead of the newest: its number in `status`,
+        /// 1 for the newest, or its timestamp (20240101T120000). Without a value, lists
+        /// the versions to choose from.
+        #[arg(
+            long,
+            value_name = "N|TIMESTAMP",
+            num_args = 0..=1,
+            default_missing_value = ""
+        )]
+        version: Option<String>,
+    },
+    /// Revert the most recent encode or remove, from the journal kept of them
+    Undo {
+        /// An image to revert the last change of, or a directory to revert the last
+        /// change made to any image in it
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
     /// Clean up backup and temporary files
-    Cleanup { path: String },
+    #[command(group = ArgGroup::new("retention").multiple(true).args(["keep_last", "older_than"]))]
+    Cleanup {
+        /// An image, or with --prune also a directory to prune the backups of every image in
+        path: PathBuf,
+        /// Only delete the backup versions that --keep-last and --older-than select
+        #[arg(long, requires = "retention")]
+        prune: bool,
+        /// Keep this many of the newest backup versions of each image
+        #[arg(long, value_name = "N", requires = "prune")]
+        keep_last: Option<usize>,
+        /// Only delete backup versions at least this old, such as 30d, 12h or 2w
+        #[arg(long, value_name = "AGE", requires = "prune")]
+        older_than: Option<String>,
+        /// Move the files to the trash instead of deleting them, so that they can still
+        /// be put back
+        #[arg(long)]
+        trash: bool,
+    },
     /// Show file status and backup information
-    Status { path: String },
+    Status { path: PathBuf },
+    /// Browse and edit the chunks of a PNG file in a full-screen terminal interface
+    Interactive { path: PathBuf },
+    /// Print a shell completion script, e.g. `completions bash >> ~/.bashrc`
+    Completions { shell: Shell },
+    /// Print the completions of the last of WORDS, for the completion scripts
+    #[command(hide = true)]
+    Complete {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+    /// Write roff man pages for the tool and each subcommand into DIR, for packaging
+    #[command(hide = true)]
+    GenerateMan {
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WatermarkAction {
+    /// Hide a short identifier redundantly across the pixels of an 8-bit PNG
+    Embed {
+        path: PathBuf,
+        /// Identifier to embed, such as a recipient or license number (at most 12 bytes)
+        id: String,
+        #[command(flatten)]
+        options: WatermarkArgs,
+    },
+    /// Read the watermark of a PNG file, or check that it carries a given identifier
+    Verify {
+        path: PathBuf,
+        /// Identifier the watermark is expected to hold
+        #[arg(long)]
+        id: Option<String>,
+        #[command(flatten)]
+        options: WatermarkArgs,
+    },
+}
+
+/// Settings that `watermark embed` and `watermark verify` must share
+#[derive(clap::Args)]
+pub struct WatermarkArgs {
+    /// Secret that scatters the watermark; without it anyone can read the identifier
+    #[arg(long)]
+    pub key: Option<String>,
+    /// Quantization step of the block brightness: higher survives more, but is more visible
+    #[arg(
+        long,
+        default_value_t = watermark::DEFAULT_STRENGTH,
+        value_parser = clap::value_parser!(u8).range(2..=64)
+    )]
+    pub strength: u8,
+}
+
+/// The message `encode` hides
+#[derive(clap::Args)]
+pub struct MessageArgs {
+    /// Text to hide, or - to read it from stdin
+    #[arg(required_unless_present_any = ["file", "message_file"])]
+    pub message: Option<String>,
+    /// Hide the contents of this file instead, which may be binary (recover it with `decode --out`)
+    #[arg(long, value_name = "PATH", conflicts_with = "message")]
+    pub file: Option<PathBuf>,
+    /// Read the text to hide from this file, or from stdin with -, for long or multi-line
+    /// messages and the output of other programs
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["message", "file"])]
+    pub message_file: Option<PathBuf>,
+    /// How the message or file is encoded; base64 and hex are decoded before hiding
+    #[arg(long, value_enum, value_name = "ENCODING", default_value_t)]
+    pub input_encoding: Encoding,
+    /// Compress the message before hiding it; decode decompresses it automatically
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    pub compress: Option<Compression>,
+}
+
+/// Where and how `decode` and `join` hand over the recovered message
+#[derive(clap::Args)]
+pub struct OutputArgs {
+    /// Write the message to this file instead of printing it, e.g. one hidden with `encode --file`
+    #[arg(long, value_name = "PATH")]
+    pub out: Option<PathBuf>,
+    /// Write nothing but the message bytes to stdout, for piping into another program;
+    /// errors still go to stderr
+    #[arg(long, conflicts_with_all = ["out", "json"])]
+    pub raw: bool,
+    /// Show or write the message as base64 or hex, e.g. to pass binary data on safely
+    #[arg(long, value_enum, value_name = "ENCODING", default_value_t)]
+    pub output_encoding: Encoding,
+}
+
+/// Options controlling where `encode` hides the message
+#[derive(clap::Args)]
+#[command(group = ArgGroup::new("multi").args(["split", "shard"]))]
+pub struct EmbeddingArgs {
+    /// Additional cover images for --split or --shard, one piece per image
+    #[arg(requires = "multi")]
+    pub more_paths: Vec<PathBuf>,
+    /// Split the message into N Shamir shares (one per image), any K of which recover it
+    #[arg(long, value_name = "K/N", requires = "more_paths")]
+    pub split: Option<String>,
+    /// Cut the message into one shard per image, all of which are needed to recover it
+    #[arg(long, requires = "more_paths")]
+    pub shard: bool,
+    /// Treat the path as a pattern such as "photos/*.png" and hide the message in every
+    /// matching file, carrying on past failures; wildcards are expanded without this flag
+    /// too when no file has that exact name
+    #[arg(long, conflicts_with = "multi")]
+    pub glob: bool,
+    /// Hide the message in the low bits of the pixel colors instead of a separate chunk
+    #[arg(long, conflicts_with = "standard_text")]
+    pub lsb: bool,
+    /// Hide the message in padding inside the compressed image data (IDAT) instead of a
+    /// separate chunk; the pixels stay unchanged
+    #[arg(long, conflicts_with_all = ["lsb", "standard_text", "max_chunk_size", "decoys"])]
+    pub idat: bool,
+    /// Low bits per color channel used by --lsb: more bits hold more data but add more noise
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=lsb::MAX_BITS as i64),
+        requires = "lsb"
+    )]
+    pub bits: u8,
+    /// Spread messages larger than N bytes over several chunks of the same type, each at most N bytes
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(payload::MIN_FRAGMENT_CHUNK as i64..=Chunk::MAX_LENGTH as i64),
+        conflicts_with_all = ["lsb", "standard_text"]
+    )]
+    pub max_chunk_size: Option<u32>,
+    /// Also add N chunks of random data shaped like encrypted payloads, in random order with the real one
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(1..),
+        conflicts_with_all = ["lsb", "standard_text"]
+    )]
+    pub decoys: Option<u8>,
+    /// Add Reed-Solomon parity, about RATIO parity bytes per message byte (e.g. 0.25), so
+    /// `decode` can repair damaged bytes
+    #[arg(long, value_name = "RATIO", value_parser = parse_fec_ratio)]
+    pub fec: Option<f64>,
+    /// Write a standard tEXt/iTXt chunk with CHUNK_TYPE as its keyword, readable by common
+    /// image tools (encrypted payloads are stored as base64)
+    #[arg(long)]
+    pub standard_text: bool,
+    /// With --standard-text, compress the text into a zTXt chunk (or a compressed iTXt chunk for non-Latin-1 text)
+    #[arg(long, requires = "standard_text")]
+    pub ztxt: bool,
+    /// Store the message as property CHUNK_TYPE of the XMP packet, where DAM tools and
+    /// Photoshop show it (encrypted payloads are stored as base64)
+    #[arg(long, conflicts_with_all = ["lsb", "idat", "standard_text", "max_chunk_size", "decoys"])]
+    pub xmp: bool,
+}
+
+/// Checks a backup or temp suffix, which must stay within the file name
+pub fn parse_suffix(value: &str) -> std::result::Result<String, String> {
+    if value.is_empty() || value.contains(['/', '\\', '\0']) {
+        return Err(format!(
+            "'{}' is not part of a file name; expected something like .bak",
+            value
+        ));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_fec_ratio(value: &str) -> std::result::Result<f64, String> {
+    let ratio: f64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if ratio > 0.0 && ratio <= fec::MAX_RATIO {
+        Ok(ratio)
+    } else {
+        Err(format!(
+            "expected a ratio above 0 and at most {}",
+            fec::MAX_RATIO
+        ))
+    }
+}
+
+/// Options telling `decode` and `join` where the message is stored
+#[derive(clap::Args)]
+pub struct LocationArgs {
+    /// Read the message from the pixels, as written by encode --lsb
+    #[arg(long, conflicts_with = "standard_text")]
+    pub lsb: bool,
+    /// Read the message from the compressed image data, as written by encode --idat
+    #[arg(long, conflicts_with_all = ["lsb", "standard_text"])]
+    pub idat: bool,
+    /// Read the message from the tEXt/iTXt chunk with keyword CHUNK_TYPE, as written by encode --standard-text
+    #[arg(long)]
+    pub standard_text: bool,
+    /// Read the message from property CHUNK_TYPE of the XMP packet, as written by encode --xmp
+    #[arg(long, conflicts_with_all = ["lsb", "idat", "standard_text"])]
+    pub xmp: bool,
+}
+
+/// Options controlling how `encode` encrypts the message
+#[derive(clap::Args)]
+#[command(group = ArgGroup::new("symmetric").multiple(true).args(["password", "ask_pass", "key_file", "use_keyring"]))]
+#[command(group = ArgGroup::new("aead").multiple(true).args(["password", "ask_pass", "key_file", "use_keyring", "yubikey"]))]
+pub struct EncryptionArgs {
+    /// Encrypt the message with the selected cipher using this password (prompted for if no value is given)
+    #[arg(long, group = "secret", num_args = 0..=1)]
+    pub password: Option<Option<String>>,
+    /// Prompt for the password on the terminal without echoing it
+    #[arg(long, group = "secret")]
+    pub ask_pass: bool,
+    /// Encrypt the message with the selected cipher using the contents of this key file
+    #[arg(long, group = "secret")]
+    pub key_file: Option<PathBuf>,
+    /// Use the password stored under NAME in the OS keyring (stores it there when --password is also given)
+    #[arg(long, value_name = "NAME", conflicts_with = "key_file")]
+    pub use_keyring: Option<String>,
+    /// Cipher used with --password, --key-file, --use-keyring or --yubikey
+    #[arg(long, value_enum, default_value_t, requires = "aead")]
+    pub cipher: Cipher,
+    /// Append an HMAC-SHA256 integrity tag keyed from the password or key file
+    #[arg(long, requires = "symmetric")]
+    pub hmac: bool,
+    /// Also store this decoy message, revealed instead of the real one by --decoy-password
+    #[arg(long, value_name = "MESSAGE", requires_all = ["symmetric", "decoy_password"])]
+    pub decoy: Option<String>,
+    /// Password that decrypts the decoy message (prompted for if no value is given)
+    #[arg(long, value_name = "PASSWORD", requires = "decoy", num_args = 0..=1)]
+    pub decoy_password: Option<Option<String>>,
+    /// Treat CHUNK_TYPE as a label and store the message under a chunk type derived from it and the password
+    #[arg(long, requires = "symmetric", conflicts_with = "decoy")]
+    pub obfuscate_chunk: bool,
+    /// Wrap the data key with the HMAC challenge-response SLOT of a YubiKey (default: 2), so decoding needs the token
+    #[arg(
+        long,
+        value_name = "SLOT",
+        num_args = 0..=1,
+        value_parser = clap::value_parser!(u8).range(1..=2),
+        conflicts_with_all = ["symmetric", "recipients", "pubkeys", "gpg_recipients", "gpg_sign"]
+    )]
+    pub yubikey: Option<Option<u8>>,
+    /// Encrypt the message to an age recipient (age1...); can be repeated
+    #[arg(long = "recipient", conflicts_with = "symmetric")]
+    pub recipients: Vec<String>,
+    /// Encrypt the message to the public key(s) in this file; can be repeated
+    #[arg(long = "pubkey", conflicts_with = "symmetric")]
+    pub pubkeys: Vec<PathBuf>,
+    /// Encrypt the message with gpg to this OpenPGP key ID or email; can be repeated
+    #[arg(long = "gpg-recipient", conflicts_with_all = ["symmetric", "recipients", "pubkeys"])]
+    pub gpg_recipients: Vec<String>,
+    /// Sign the message with gpg, using the default key or the given key ID
+    #[arg(
+        long,
+        value_name = "KEY_ID",
+        num_args = 0..=1,
+        conflicts_with_all = ["symmetric", "recipients", "pubkeys"]
+    )]
+    pub gpg_sign: Option<Option<String>>,
+}
+
+/// Options controlling how `decode` decrypts the message
+#[derive(clap::Args)]
+pub struct DecryptionArgs {
+    /// Password used to decrypt an encrypted message (prompted for if no value is given)
+    #[arg(long, group = "secret", num_args = 0..=1)]
+    pub password: Option<Option<String>>,
+    /// Prompt for the password on the terminal without echoing it
+    #[arg(long, group = "secret")]
+    pub ask_pass: bool,
+    /// Key file used to decrypt a message encrypted with --key-file
+    #[arg(long, group = "secret")]
+    pub key_file: Option<PathBuf>,
+    /// Use the password stored under NAME in the OS keyring (stores it there when --password is also given)
+    #[arg(long, value_name = "NAME", conflicts_with = "key_file")]
+    pub use_keyring: Option<String>,
+    /// Private key (age identity) file used to decrypt a message encrypted to recipients
+    #[arg(long, visible_alias = "privkey", conflicts_with_all = ["secret", "use_keyring"])]
+    pub identity: Option<PathBuf>,
+    /// Show the message even if it has expired
+    #[arg(long)]
+    pub allow_expired: bool,
+    /// Look the message up under the chunk type derived from CHUNK_TYPE and the password, as written by encode --obfuscate-chunk
+    #[arg(long)]
+    pub obfuscate_chunk: bool,
+}
+
+/// Where `move-chunk` puts the chunks
+#[derive(clap::Args)]
+#[command(group = ArgGroup::new("placement").required(true))]
+pub struct PlacementArgs {
+    /// Put the chunks right before the first chunk of this type
+    #[arg(long, value_name = "CHUNK_TYPE", group = "placement")]
+    pub before: Option<String>,
+    /// Put the chunks right after the last chunk of this type
+    #[arg(long, value_name = "CHUNK_TYPE", group = "placement")]
+    pub after: Option<String>,
+    /// Put the chunks at this position in the chunk list, counting IHDR as 0
+    #[arg(long, value_name = "N", group = "placement")]
+    pub index: Option<usize>,
+}
+
+/// Current and new secrets for `rekey`
+#[derive(clap::Args)]
+#[command(group = ArgGroup::new("old").required(true))]
+#[command(group = ArgGroup::new("new").required(true))]
+pub struct RekeyArgs {
+    /// Current password (prompted for if no value is given)
+    #[arg(long, group = "old", num_args = 0..=1)]
+    pub password: Option<Option<String>>,
+    /// Current key file
+    #[arg(long, group = "old")]
+    pub key_file: Option<PathBuf>,
+    /// Use the current password stored under NAME in the OS keyring (updated to the new password afterwards)
+    #[arg(long, value_name = "NAME", group = "old")]
+    pub use_keyring: Option<String>,
+    /// New password (prompted for if no value is given)
+    #[arg(long, group = "new", num_args = 0..=1)]
+    pub new_password: Option<Option<String>>,
+    /// New key file
+    #[arg(long, group = "new", conflicts_with = "use_keyring")]
+    pub new_key_file: Option<PathBuf>,
+    /// CHUNK_TYPE is a label written with encode --obfuscate-chunk; the chunk is renamed after the new password
+    #[arg(long)]
+    pub obfuscate_chunk: bool,
 }
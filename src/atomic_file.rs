@@ -1,99 +1,409 @@
-use crate::Result;
-use std::fs;
+use crate::exit::Failure;
+use crate::output::{self, detail, say};
+use crate::{Result, date, journal, trash};
+use serde_json::{Value, json};
+use std::cell::OnceCell;
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Temp files older than this are left over from interrupted runs rather than in use
+/// by another one, and are removed when the file is next changed
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Backup versions kept of each file unless `--keep-backups` says otherwise
+pub const DEFAULT_KEEP_BACKUPS: usize = 5;
+
+static KEEP_BACKUPS: AtomicUsize = AtomicUsize::new(DEFAULT_KEEP_BACKUPS);
+
+/// Sets how many backup versions of each file are kept; once a change is committed,
+/// versions beyond that are deleted, oldest first
+pub fn set_keep_backups(keep: usize) {
+    KEEP_BACKUPS.store(keep.max(1), Ordering::Relaxed);
+}
+
+static BACKUP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+static COMPRESS_BACKUPS: AtomicBool = AtomicBool::new(false);
+
+/// Ending of the names of zstd-compressed backups
+const COMPRESSED_SUFFIX: &str = ".zst";
+
+/// Added to a backup's name for the file recording its SHA-256 checksum
+const CHECKSUM_SUFFIX: &str = ".sha256";
+
+/// Makes new backups zstd-compressed (`file.png.backup.20240101T120000.zst`); existing
+/// backups of either kind are restored alike
+pub fn set_compress_backups(compress: bool) {
+    COMPRESS_BACKUPS.store(compress, Ordering::Relaxed);
+}
+
+/// Added to an image's name for its backups unless `--backup-suffix` says otherwise,
+/// followed by their version: file.png.backup.20240101T120000
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".backup";
+
+/// Added to an image's name for its temp files unless `--temp-suffix` says otherwise,
+/// followed by a random ending: .file.png.hpv-k3x9qa
+pub const DEFAULT_TEMP_SUFFIX: &str = ".hpv-";
+
+static BACKUP_SUFFIX: OnceLock<String> = OnceLock::new();
+
+static TEMP_SUFFIX: OnceLock<String> = OnceLock::new();
+
+/// Names backups `file.png<suffix>.<version>` instead of `file.png.backup.<version>`,
+/// for directories where other tools already use `.backup`
+pub fn set_backup_suffix(suffix: String) {
+    let _ = BACKUP_SUFFIX.set(suffix);
+}
+
+/// Names temp files `.file.png<suffix><random>` instead of `.file.png.hpv-<random>`
+pub fn set_temp_suffix(suffix: String) {
+    let _ = TEMP_SUFFIX.set(suffix);
+}
+
+fn backup_suffix() -> &'static str {
+    BACKUP_SUFFIX
+        .get()
+        .map_or(DEFAULT_BACKUP_SUFFIX, String::as_str)
+}
+
+fn temp_suffix() -> &'static str {
+    TEMP_SUFFIX
+        .get()
+        .map_or(DEFAULT_TEMP_SUFFIX, String::as_str)
+}
+
+/// File in each directory of a central backup directory naming the image its backups
+/// were made of
+const ORIGINAL_MARKER: &str = "original";
+
+/// Keeps backups under `dir`, in one subdirectory per image, instead of next to the
+/// images themselves
+pub fn set_backup_dir(dir: PathBuf) {
+    let _ = BACKUP_DIR.set(dir);
+}
+
+/// The central backup directory used when `--backup-dir` is given without one:
+/// hidden-pixel-vault/backups in the user's data directory
+pub fn default_backup_dir() -> Option<PathBuf> {
+    let data_dir = if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| Some(Path::new(&std::env::var_os("HOME")?).join(".local/share")))
+    };
+    Some(data_dir?.join("hidden-pixel-vault").join("backups"))
+}
+
+/// Reports a change to the file at a path from its current to its new contents
+pub type ChangeReport = fn(&Path, &[u8], &[u8]);
+
+static DRY_RUN: OnceLock<ChangeReport> = OnceLock::new();
+
+/// Turns on `--dry-run`: changes are worked out in full and given to `report` with the
+/// file's current and new contents, but nothing is written, restored or removed
+pub fn set_dry_run(report: ChangeReport) {
+    let _ = DRY_RUN.set(report);
+}
+
+/// Whether `--dry-run` is on
+pub fn is_dry_run() -> bool {
+    DRY_RUN.get().is_some()
+}
+
+/// A backup version of a file, see [`AtomicFileHandler::backups`]
+pub struct Backup {
+    pub path: PathBuf,
+    /// When it was made, from its name; `None` for the `file.png.backup` of earlier
+    /// versions, which only ever kept one
+    pub created: Option<u64>,
+}
 
 pub struct AtomicFileHandler {
     target_path: PathBuf,
     temp_path: PathBuf,
-    backup_path: PathBuf,
+    /// Made once the file is locked, so that runs waiting for each other do not pick
+    /// the same name
+    backup_path: OnceCell<PathBuf>,
+    /// The target's subdirectory of the central backup directory, when backups are not
+    /// kept next to it
+    backup_dir: Option<PathBuf>,
+    /// Where changes are written instead of the target, see `with_output`
+    output: Option<PathBuf>,
+    /// Operation and chunk recorded in the journal for `undo`, see `journaled`
+    journal: Option<(String, String)>,
 }
 
 impl AtomicFileHandler {
     /// Creates a new AtomicFileHandler for the given file path
-    pub fn new(file_path: &str) -> Result<Self> {
-        let target = PathBuf::from(file_path);
+    pub fn new(file_path: &Path) -> Result<Self> {
+        let target = file_path.to_path_buf();
 
         // Validate target file exists
         if !target.exists() {
-            return Err(format!("File does not exist: {}", file_path).into());
+            return Err(Failure::FileNotFound
+                .error(format!("File does not exist: {}", file_path.display())));
         }
 
-        // Generate temp and backup paths
+        target
+            .extension()
+            .ok_or_else(|| Failure::Validation.error("File must have an extension"))?;
+        let backup_dir = match BACKUP_DIR.get() {
+            Some(dir) => Some(dir.join(central_backup_name(&target)?)),
+            None => None,
+        };
+
         let temp = Self::generate_temp_path(&target)?;
-        let backup = Self::generate_backup_path(&target)?;
 
         Ok(AtomicFileHandler {
             target_path: target,
             temp_path: temp,
-            backup_path: backup,
+            backup_path: OnceCell::new(),
+            backup_dir,
+            output: None,
+            journal: None,
         })
     }
 
-    /// Generates temporary file path: file.png -> file.png.tmp
+    /// Records each change made to the target in the journal of its directory as
+    /// `operation` on `chunk`, so that `undo` can revert it
+    pub fn journaled(mut self, operation: &str, chunk: &str) -> Self {
+        self.journal = Some((operation.to_string(), chunk.to_string()));
+        self
+    }
+
+    /// Writes changes to `output` instead of the target, which is left untouched and so
+    /// needs no backup
+    pub fn with_output(mut self, output: Option<&Path>) -> Result<Self> {
+        if let Some(output) = output {
+            let output = output.to_path_buf();
+            if fs::canonicalize(&output).ok() == Some(fs::canonicalize(&self.target_path)?) {
+                return Err(
+                    "--output names the file itself: leave it out to change the \
+                            file in place"
+                        .into(),
+                );
+            }
+            self.output = Some(output);
+        }
+        Ok(self)
+    }
+
+    /// Generates a temporary file path unique to this run, in the same directory so that
+    /// renaming it over the target stays atomic: file.png -> .file.png.hpv-k3x9qa
     fn generate_temp_path(target_path: &Path) -> Result<PathBuf> {
-        let mut temp = target_path.to_path_buf();
-        temp.set_extension(format!(
-            "{}.tmp",
-            target_path
-                .extension()
-                .ok_or("File must have an extension")?
-                .to_string_lossy()
-        ));
-        Ok(temp)
-    }
-
-    /// Generates backup file path: file.png -> file.png.backup
-    fn generate_backup_path(target_path: &Path) -> Result<PathBuf> {
-        let mut backup = target_path.to_path_buf();
-        backup.set_extension(format!(
-            "{}.backup",
-            target_path
-                .extension()
-                .ok_or("File must have an extension")?
-                .to_string_lossy()
-        ));
-        Ok(backup)
+        let mut random = [0u8; 6];
+        crate::crypto::random_bytes(&mut random)?;
+        let suffix: String = random
+            .iter()
+            .map(|&b| b"abcdefghijklmnopqrstuvwxyz0123456789"[b as usize % 36] as char)
+            .collect();
+        let mut name = temp_prefix(target_path);
+        name.push(suffix);
+        Ok(target_path.with_file_name(name))
+    }
+
+    /// Generates the path of the backup this run would make, named after the current
+    /// time: file.png -> file.png.backup.20240101T120000, with a counter appended
+    /// ("-2") when another backup was made in the same second, and ".zst" when backups
+    /// are compressed
+    fn generate_backup_path(target_path: &Path, backup_dir: Option<&Path>) -> PathBuf {
+        let mut base = backup_prefix(target_path);
+        base.push(date::compact_timestamp(date::now()));
+        let suffix = if COMPRESS_BACKUPS.load(Ordering::Relaxed) {
+            COMPRESSED_SUFFIX
+        } else {
+            ""
+        };
+        // Numbered after every backup made the same second, even one pruned since, so
+        // that the newest always sorts last and is not the one pruned
+        let dir = backup_dir.unwrap_or(parent_dir(target_path));
+        let last = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let rest = name_after(&name, &base)?;
+                match rest.strip_prefix('-') {
+                    Some(counter) => counter.split('.').next()?.parse::<u32>().ok(),
+                    None => (rest.is_empty() || rest.starts_with('.')).then_some(1),
+                }
+            })
+            .max();
+        let mut name = base;
+        if let Some(counter) = last {
+            name.push(format!("-{}", counter + 1));
+        }
+        name.push(suffix);
+        backup_file(target_path, backup_dir, &name)
+    }
+
+    /// The backup this run makes, named when first asked for
+    fn backup_path(&self) -> &Path {
+        self.backup_path.get_or_init(|| {
+            Self::generate_backup_path(&self.target_path, self.backup_dir.as_deref())
+        })
+    }
+
+    /// Every backup version of the target, newest first
+    pub fn backups(&self) -> Vec<Backup> {
+        let prefix = backup_prefix(&self.target_path);
+        let legacy = name_with(&self.target_path, "", backup_suffix());
+        let dir = match &self.backup_dir {
+            Some(dir) => dir.as_path(),
+            None => parent_dir(&self.target_path),
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return vec![];
+        };
+
+        let mut versions: Vec<((Option<u64>, u32), PathBuf)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let version = if name == legacy {
+                    (None, 0)
+                } else {
+                    let (created, counter) = parse_version(name_after(&name, &prefix)?)?;
+                    (Some(created), counter)
+                };
+                let path = backup_file(&self.target_path, self.backup_dir.as_deref(), &name);
+                Some((version, path))
+            })
+            .collect();
+        versions.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+        versions
+            .into_iter()
+            .map(|((created, _), path)| Backup { path, created })
+            .collect()
+    }
+
+    /// Deletes the oldest backup versions beyond the `--keep-backups` limit
+    fn prune_backups(&self) {
+        for backup in self.prune(Some(KEEP_BACKUPS.load(Ordering::Relaxed)), None, false) {
+            detail!("    Deleted old backup {}", backup.path.display());
+        }
+    }
+
+    /// Deletes the backup versions beyond the newest `keep_last`, with `older_than` only
+    /// those made at least that many seconds ago, returning the ones deleted. With `trash`
+    /// they are moved to the trash instead.
+    pub fn prune(
+        &self,
+        keep_last: Option<usize>,
+        older_than: Option<u64>,
+        trash: bool,
+    ) -> Vec<Backup> {
+        let cutoff = older_than.map(|age| date::now().saturating_sub(age));
+        let removed = self
+            .backups()
+            .into_iter()
+            .skip(keep_last.unwrap_or(0))
+            .filter(|backup| cutoff.is_none_or(|cutoff| backup_time(backup) <= cutoff))
+            // A version that cannot be deleted only takes space
+            .filter(|backup| is_dry_run() || remove_backup(&backup.path, trash).is_ok())
+            .collect();
+        if !is_dry_run() {
+            self.remove_empty_backup_dir();
+        }
+        removed
+    }
+
+    /// Removes the target's directory in the central backup directory once it holds no
+    /// more backups
+    fn remove_empty_backup_dir(&self) {
+        if let Some(backup_dir) = &self.backup_dir
+            && self.backups().is_empty()
+        {
+            let _ = fs::remove_file(backup_dir.join(ORIGINAL_MARKER));
+            let _ = fs::remove_dir(backup_dir);
+        }
     }
 
     /// Read the target file for operations that don't modify it
     pub fn read_file(&self) -> Result<Vec<u8>> {
         fs::read(&self.target_path).map_err(|e| {
-            format!(
+            Failure::from(&e).error(format!(
                 "Failed to read file '{}': {}",
                 self.target_path.display(),
                 e
-            )
-            .into()
+            ))
         })
     }
 
+    /// Opens the target for reading piece by piece, for files too large to read at once
+    pub fn open_reader(&self) -> Result<BufReader<File>> {
+        let file = File::open(&self.target_path).map_err(|e| {
+            Failure::from(&e).error(format!(
+                "Failed to read file '{}': {}",
+                self.target_path.display(),
+                e
+            ))
+        })?;
+        Ok(BufReader::new(file))
+    }
+
     /// Create a backup of the original file before modification
     pub fn create_backup(&self) -> Result<()> {
-        println!("🛡️  Created Backup: {}", self.backup_path.display());
+        say!("🛡️  Created Backup: {}", self.backup_path().display());
 
-        fs::copy(&self.target_path, &self.backup_path)
+        self.prepare_backup_dir()
+            .and_then(|()| write_backup(&self.target_path, self.backup_path()))
             .map_err(|e| format!("Failed to create backup: {}", e))?;
+        if let Ok(metadata) = fs::metadata(self.backup_path()) {
+            detail!("    Backup written: {} bytes", metadata.len());
+        }
 
         Ok(())
     }
 
     /// Create a backup silently (no output message)
     pub fn create_backup_silent(&self) -> Result<()> {
-        fs::copy(&self.target_path, &self.backup_path)
+        self.prepare_backup_dir()
+            .and_then(|()| write_backup(&self.target_path, self.backup_path()))
             .map_err(|e| format!("Failed to create backup: {}", e))?;
         Ok(())
     }
 
+    /// Creates the target's directory in the central backup directory, recording which
+    /// image it belongs to so that `restore` can be pointed at its backups
+    fn prepare_backup_dir(&self) -> io::Result<()> {
+        let Some(backup_dir) = &self.backup_dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(backup_dir)?;
+        let original = fs::canonicalize(&self.target_path)?;
+        fs::write(backup_dir.join(ORIGINAL_MARKER), path_bytes(&original))
+    }
+
     /// Start atomic modification: creates temp file with current content
     pub fn begin_atomic_write(&self) -> Result<Vec<u8>> {
         // Create backup first
+        remove_temp_files(&self.target_path, STALE_TEMP_AGE, false);
         self.create_backup()?;
 
         // Read current content
         let content = self.read_file()?;
+        detail!(
+            "    Read {} bytes from '{}'",
+            content.len(),
+            self.target_path.display()
+        );
 
-        // Create temp file with current content
-        fs::write(&self.temp_path, &content)
+        // Create temp file with current content; another run never has the same name
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.temp_path)
+            .and_then(|mut file| file.write_all(&content))
             .map_err(|e| format!("Failed to create temporary file: {}", e))?;
 
         Ok(content)
@@ -102,30 +412,71 @@ impl AtomicFileHandler {
     /// Start atomic modification silently: creates temp file with current content
     pub fn begin_atomic_write_silent(&self) -> Result<Vec<u8>> {
         // Create backup silently
+        remove_temp_files(&self.target_path, STALE_TEMP_AGE, false);
         self.create_backup_silent()?;
 
         // Read current content
         let content = self.read_file()?;
+        detail!(
+            "    Read {} bytes from '{}'",
+            content.len(),
+            self.target_path.display()
+        );
 
-        // Create temp file with current content
-        fs::write(&self.temp_path, &content)
+        // Create temp file with current content; another run never has the same name
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.temp_path)
+            .and_then(|mut file| file.write_all(&content))
             .map_err(|e| format!("Failed to create temporary file: {}", e))?;
 
         Ok(content)
     }
 
-    /// Write modified content to temp file
+    /// Write modified content to temp file, flushed to disk so that the rename in
+    /// `commit_atomic_write` can never expose a partly written file
     pub fn write_temp(&self, data: &[u8]) -> Result<()> {
-        fs::write(&self.temp_path, data)
-            .map_err(|e| format!("Failed to write to temporary file: {}", e).into())
+        let mut file = File::create(&self.temp_path).map_err(|e| {
+            Failure::from(&e).error(format!("Failed to write to temporary file: {}", e))
+        })?;
+        file.write_all(data)
+            .and_then(|()| file.sync_all())
+            .map_err(|e| {
+                Failure::from(&e).error(format!("Failed to write to temporary file: {}", e))
+            })?;
+        detail!(
+            "    Wrote {} bytes to temporary file '{}'",
+            data.len(),
+            self.temp_path.display()
+        );
+        Ok(())
     }
 
     /// Commit atomic operation: atomically replace target with temp file
     pub fn commit_atomic_write(&self) -> Result<()> {
+        copy_metadata(&self.target_path, &self.temp_path)
+            .map_err(|e| format!("Failed to commit changes: {}", e))?;
+
         // Atomic rename (this is the critical atomic operation)
-        fs::rename(&self.temp_path, &self.target_path)
+        replace_file(&self.temp_path, &self.target_path)
             .map_err(|e| format!("Failed to commit changes: {}", e))?;
 
+        // The rename itself only survives a power loss once the directory is on disk
+        sync_parent_dir(&self.target_path)
+            .map_err(|e| format!("Failed to commit changes: {}", e))?;
+        detail!(
+            "    Renamed the temporary file over '{}'",
+            self.target_path.display()
+        );
+        output::push(
+            "changed",
+            json!({
+                "file": self.target_path.to_string_lossy(),
+                "backup": self.backup_path.get().map(|path| path.to_string_lossy()),
+            }),
+        );
+        self.prune_backups();
         Ok(())
     }
 
@@ -137,10 +488,14 @@ impl AtomicFileHandler {
                 .map_err(|e| format!("Failed to remove temp file during rollback: {}", e))?;
         }
 
-        // Restore from backup if it exists
-        if self.backup_path.exists() {
-            fs::copy(&self.backup_path, &self.target_path)
+        // Restore from the backup of this run if it was made, which is not needed
+        // afterwards: the target is as it was before
+        if let Some(backup) = self.backup_path.get()
+            && backup.exists()
+        {
+            restore_backup(backup, &self.target_path)
                 .map_err(|e| format!("Failed to restore from backup: {}", e))?;
+            let _ = remove_backup(backup, false);
         }
 
         Ok(())
@@ -154,85 +509,208 @@ impl AtomicFileHandler {
                 .map_err(|e| format!("Failed to remove temp file during rollback: {}", e))?;
         }
 
-        // Restore from backup if it exists
-        if self.backup_path.exists() {
-            fs::copy(&self.backup_path, &self.target_path)
+        // Restore from the backup of this run if it was made, which is not needed
+        // afterwards: the target is as it was before
+        if let Some(backup) = self.backup_path.get()
+            && backup.exists()
+        {
+            restore_backup(backup, &self.target_path)
                 .map_err(|e| format!("Failed to restore from backup: {}", e))?;
+            let _ = remove_backup(backup, false);
         }
 
         Ok(())
     }
 
-    /// Restore original file from backup (user command)
+    /// Restore original file from the newest backup (user command)
     pub fn restore_original(&self) -> Result<()> {
-        if !self.backup_path.exists() {
-            return Err("No backup file found. Cannot restore original.".into());
-        }
+        let backup = self.backups().into_iter().next().ok_or_else(|| {
+            Failure::FileNotFound.error("No backup file found. Cannot restore original.")
+        })?;
+        self.restore_from(&backup)
+    }
 
-        println!("🔄  Restoring original file from backup...");
+    /// Restore the file from one of its backup versions
+    pub fn restore_from(&self, backup: &Backup) -> Result<()> {
+        if let Some(report) = DRY_RUN.get().copied() {
+            say!("🔄  Restoring from {} would change:", backup.path.display());
+            report(
+                &self.target_path,
+                &self.read_file()?,
+                &read_backup(&backup.path)?,
+            );
+            return Ok(());
+        }
+        say!("🔄  Restoring original file from backup...");
 
-        fs::copy(&self.backup_path, &self.target_path)
+        let _lock = FileLock::acquire(&self.target_path, true)?;
+        restore_backup(&backup.path, &self.target_path)
             .map_err(|e| format!("Failed to restore original file: {}", e))?;
 
-        println!("    Original file restored successfully ");
-        println!("    File: {}", self.target_path.display());
-        println!("    Restored from: {}", self.backup_path.display());
+        say!("    Original file restored successfully ");
+        say!("    File: {}", self.target_path.display());
+        say!("    Restored from: {}", backup.path.display());
+        output::set("file", self.target_path.to_string_lossy());
+        output::set("restored_from", backup.path.to_string_lossy());
         Ok(())
     }
 
-    /// Clean up backup and temp files
-    pub fn cleanup(&self) -> Result<()> {
+    /// The backup version `version` names: its number in [`backups`](Self::backups),
+    /// 1 for the newest, or its timestamp such as "20240101T120000"
+    pub fn find_backup(&self, version: &str) -> Result<Backup> {
+        let mut backups = self.backups();
+        if let Ok(number) = version.parse::<usize>() {
+            if !(1..=backups.len()).contains(&number) {
+                return Err(Failure::FileNotFound.error(format!(
+                    "No backup version {}: '{}' has {} backup(s)",
+                    number,
+                    self.target_path.display(),
+                    backups.len()
+                )));
+            }
+            return Ok(backups.swap_remove(number - 1));
+        }
+
+        let version = strip_compressed_suffix(version);
+        let prefix = backup_prefix(&self.target_path);
+        backups
+            .into_iter()
+            .find(|backup| {
+                backup
+                    .path
+                    .file_name()
+                    .and_then(|found| name_after(found, &prefix))
+                    .is_some_and(|found| strip_compressed_suffix(found) == version)
+            })
+            .ok_or_else(|| {
+                Failure::FileNotFound.error(format!(
+                    "No backup of '{}' made at {}",
+                    self.target_path.display(),
+                    version
+                ))
+            })
+    }
+
+    /// Clean up backup and temp files, with `trash` by moving them to the trash
+    pub fn cleanup(&self, trash: bool) -> Result<()> {
+        output::set("file", self.target_path.to_string_lossy());
+        output::set("trash", trash);
+        if is_dry_run() {
+            let temp_files = temp_files(&self.target_path, Duration::ZERO).len();
+            let backups = self.backups();
+            say!(
+                " 🧹  Cleanup would remove {} temp file(s) and {} backup file(s)",
+                temp_files,
+                backups.len()
+            );
+            output::set("temp_files", temp_files);
+            output::set("backups", Vec::<Value>::new());
+            for backup in &backups {
+                output::push("backups", backup.path.to_string_lossy());
+            }
+            return Ok(());
+        }
+
         let mut cleaned = Vec::new();
 
-        if self.temp_path.exists() {
-            fs::remove_file(&self.temp_path)
-                .map_err(|e| format!("Failed to remove temp file: {}", e))?;
-            cleaned.push("temp file");
+        let temp_files = remove_temp_files(&self.target_path, Duration::ZERO, trash);
+        output::set("temp_files", temp_files);
+        if temp_files > 0 {
+            cleaned.push("temp file".to_string());
         }
 
-        if self.backup_path.exists() {
-            fs::remove_file(&self.backup_path)
+        let backups = self.backups();
+        output::set("backups", Vec::<Value>::new());
+        for backup in &backups {
+            remove_backup(&backup.path, trash)
                 .map_err(|e| format!("Failed to remove backup file: {}", e))?;
-            cleaned.push("backup file");
+            output::push("backups", backup.path.to_string_lossy());
+        }
+        self.remove_empty_backup_dir();
+        match backups.len() {
+            0 => {}
+            1 => cleaned.push("backup file".to_string()),
+            count => cleaned.push(format!("{} backup files", count)),
         }
 
         if !cleaned.is_empty() {
-            if cleaned.len() == 1 && cleaned[0] == "backup file" {
-                println!(" 🧹  Cleaned up: backup file is removed");
+            if trash {
+                say!(" 🗑️  Moved to the trash: {}", cleaned.join(" and "));
+            } else if cleaned.len() == 1 && cleaned[0] == "backup file" {
+                say!(" 🧹  Cleaned up: backup file is removed");
             } else {
-                println!(" 🧹  Cleaned up: {}", cleaned.join(" and "));
+                say!(" 🧹  Cleaned up: {}", cleaned.join(" and "));
             }
         } else {
-            println!(" ℹ️   No files to clean up");
+            say!(" ℹ️   No files to clean up");
         }
 
         Ok(())
     }
 
-    /// Check if backup exists
+    /// Check if any backup version exists
     pub fn has_backup(&self) -> bool {
-        self.backup_path.exists()
+        !self.backups().is_empty()
     }
 
     /// Get file paths for display
     pub fn target_path(&self) -> &Path {
         &self.target_path
     }
-
-    pub fn backup_path(&self) -> &Path {
-        &self.backup_path
-    }
 }
 
 // Safe atomic operation wrapper
 impl AtomicFileHandler {
+    /// Works out the change without touching the target for `--dry-run`, which reports
+    /// it, or `with_output`, which writes it to the output file; gives `modify_fn` back
+    /// when neither applies
+    fn modify_elsewhere<F>(&self, modify_fn: F, announce: bool) -> Result<Option<F>>
+    where
+        F: FnOnce(Vec<u8>) -> Result<Vec<u8>>,
+    {
+        let report = DRY_RUN.get().copied();
+        if report.is_none() && self.output.is_none() {
+            return Ok(Some(modify_fn));
+        }
+
+        let original_content = self.read_file()?;
+        let modified_content = modify_fn(original_content.clone())?;
+        let destination = self.output.as_deref().unwrap_or(&self.target_path);
+        if let Some(report) = report {
+            report(destination, &original_content, &modified_content);
+            return Ok(None);
+        }
+
+        write_output(destination, &modified_content)?;
+        if announce {
+            say!(
+                "📄  Wrote '{}', leaving '{}' unchanged",
+                destination.display(),
+                self.target_path.display()
+            );
+        }
+        Ok(None)
+    }
+
     /// Execute a modification operation atomically with auto-rollback on failure
     pub fn atomic_modify<F>(&self, modify_fn: F) -> Result<()>
     where
         F: FnOnce(Vec<u8>) -> Result<Vec<u8>>,
     {
+        let Some(modify_fn) = self.modify_elsewhere(modify_fn, true)? else {
+            return Ok(());
+        };
+
+        // Wait for other runs changing the file, which would race for the same backup
+        // and overwrite each other's changes
+        let _lock = FileLock::acquire(&self.target_path, true)?;
+
         // Begin atomic operation
         let original_content = self.begin_atomic_write()?;
+        let before = self
+            .journal
+            .as_ref()
+            .map(|_| journal::digest(&original_content));
 
         // Apply modification
         match modify_fn(original_content) {
@@ -249,6 +727,7 @@ impl AtomicFileHandler {
                     return Err(e);
                 }
 
+                self.record_change(before, &modified_content);
                 Ok(())
             }
             Err(e) => {
@@ -264,8 +743,17 @@ impl AtomicFileHandler {
     where
         F: FnOnce(Vec<u8>) -> Result<Vec<u8>>,
     {
+        let Some(modify_fn) = self.modify_elsewhere(modify_fn, false)? else {
+            return Ok(());
+        };
+        let _lock = FileLock::acquire(&self.target_path, false)?;
+
         // Begin atomic operation silently
         let original_content = self.begin_atomic_write_silent()?;
+        let before = self
+            .journal
+            .as_ref()
+            .map(|_| journal::digest(&original_content));
 
         // Apply modification
         match modify_fn(original_content) {
@@ -282,6 +770,7 @@ impl AtomicFileHandler {
                     return Err(e);
                 }
 
+                self.record_change(before, &modified_content);
                 Ok(())
             }
             Err(e) => {
@@ -292,3 +781,1158 @@ impl AtomicFileHandler {
         }
     }
 }
+
+// Journal of changes for `undo`
+impl AtomicFileHandler {
+    /// Adds the change just committed, from content hashing to `before`, to the journal
+    /// when the handler is `journaled`. The change is made by then, so failing to record
+    /// it is only reported.
+    fn record_change(&self, before: Option<String>, modified_content: &[u8]) {
+        let (Some((operation, chunk)), Some(before), Some(backup)) =
+            (&self.journal, before, self.backup_path.get())
+        else {
+            return;
+        };
+        let entry = journal::Entry {
+            timestamp: date::now(),
+            operation: operation.clone(),
+            chunk: chunk.clone(),
+            before,
+            after: journal::digest(modified_content),
+            file: PathBuf::from(self.target_path.file_name().unwrap_or_default()),
+            // Absolute, as `undo` may be run from another directory
+            backup: fs::canonicalize(backup).unwrap_or_else(|_| backup.clone()),
+        };
+        if let Err(e) = journal::record(parent_dir(&self.target_path), &entry) {
+            say!("⚠️  Could not record the change for undo: {}", e);
+        }
+    }
+}
+
+/// The journal of the images in `dir` when backups go to the central backup directory,
+/// named after the directory like the backup directories of images are
+pub fn central_journal(dir: &Path) -> Result<Option<PathBuf>> {
+    match BACKUP_DIR.get() {
+        Some(central) => Ok(Some(
+            central.join(format!("{}.journal", central_backup_name(dir)?)),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// The directory holding `path`, which is "." for bare file names
+pub fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Path of the backup file `name` of `target`, in `backup_dir` when it has one in the
+/// central backup directory and next to it otherwise
+fn backup_file(target: &Path, backup_dir: Option<&Path>, name: &OsStr) -> PathBuf {
+    match backup_dir {
+        Some(dir) => dir.join(name),
+        None => target.with_file_name(name),
+    }
+}
+
+/// The file name of `target` between `before` and `after`, kept as it is when it is not
+/// valid UTF-8
+fn name_with(target: &Path, before: &str, after: &str) -> OsString {
+    let mut name = OsString::from(before);
+    name.push(target.file_name().unwrap_or_default());
+    name.push(after);
+    name
+}
+
+/// The rest of the file name `name` after `prefix`, when it starts with it and the rest
+/// is text, such as the version of a backup
+fn name_after<'a>(name: &'a OsStr, prefix: &OsStr) -> Option<&'a str> {
+    let rest = name
+        .as_encoded_bytes()
+        .strip_prefix(prefix.as_encoded_bytes())?;
+    std::str::from_utf8(rest).ok()
+}
+
+/// `path` as bytes, to record it in a file; on Unix any file name round-trips through
+/// `path_from_bytes`
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+/// The path recorded as `bytes` by `path_bytes`
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Start of the backup file names of `target`, followed by their version
+fn backup_prefix(target: &Path) -> OsString {
+    name_with(target, "", &format!("{}.", backup_suffix()))
+}
+
+/// Creation time and same-second counter of a backup named with `version`, such as
+/// "20240101T120000", "20240101T120000-2" or "20240101T120000.zst"
+fn parse_version(version: &str) -> Option<(u64, u32)> {
+    let version = strip_compressed_suffix(version);
+    let (timestamp, counter) = match version.split_once('-') {
+        Some((timestamp, counter)) => (timestamp, counter.parse().ok()?),
+        None => (version, 1),
+    };
+    Some((date::parse_compact_timestamp(timestamp)?, counter))
+}
+
+/// The file a backup at `path` was made of, when `path` is named like a backup. Backups
+/// in a central backup directory lead to the image recorded next to them.
+pub fn backup_original(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.as_encoded_bytes();
+    let marker = backup_suffix().as_bytes();
+    let split = name
+        .windows(marker.len())
+        .rposition(|window| window == marker)?;
+    let version = std::str::from_utf8(&name[split + marker.len()..]).ok()?;
+    let is_backup =
+        version.is_empty() || version.strip_prefix('.').and_then(parse_version).is_some();
+    if !is_backup || split == 0 {
+        return None;
+    }
+    match fs::read(path.with_file_name(ORIGINAL_MARKER)) {
+        Ok(recorded) => Some(path_from_bytes(&recorded)),
+        // SAFETY: split right before the suffix, which is UTF-8, so the start is a whole
+        // encoded name
+        Err(_) => Some(
+            path.with_file_name(unsafe { OsStr::from_encoded_bytes_unchecked(&name[..split]) }),
+        ),
+    }
+}
+
+/// When `backup` was made, going by when it was last written for the backups of
+/// earlier versions, whose names carry no time
+fn backup_time(backup: &Backup) -> u64 {
+    backup.created.unwrap_or_else(|| {
+        fs::metadata(&backup.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |age| age.as_secs())
+    })
+}
+
+/// The images in `dir` that have backups: those named by the backup files next to
+/// them, or with a central backup directory, those recorded there
+pub fn backed_up_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = BTreeSet::new();
+    match BACKUP_DIR.get() {
+        Some(central) => {
+            let Ok(dir_absolute) = fs::canonicalize(dir) else {
+                return vec![];
+            };
+            for entry in fs::read_dir(central).into_iter().flatten().flatten() {
+                let Ok(recorded) = fs::read(entry.path().join(ORIGINAL_MARKER)) else {
+                    continue;
+                };
+                let recorded = path_from_bytes(&recorded);
+                if recorded.parent() == Some(&dir_absolute)
+                    && let Some(name) = recorded.file_name()
+                {
+                    files.insert(dir.join(name));
+                }
+            }
+        }
+        None => {
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                if let Some(original) = backup_original(&entry.path()) {
+                    files.insert(original);
+                }
+            }
+        }
+    }
+    files.into_iter().filter(|file| file.is_file()).collect()
+}
+
+/// Name of the directory holding the backups of `target` in the central backup
+/// directory, derived from its absolute path so that images with the same name in
+/// different directories do not share backups
+fn central_backup_name(target: &Path) -> Result<String> {
+    let absolute = fs::canonicalize(target)
+        .map_err(|e| format!("Failed to resolve '{}': {}", target.display(), e))?;
+    let digest = crate::crypto::sha256(&path_bytes(&absolute));
+    Ok(crate::encoding::hex_encode(&digest[..16]))
+}
+
+/// Start of the temp file names of `target`, followed by a random suffix
+fn temp_prefix(target: &Path) -> OsString {
+    name_with(target, ".", temp_suffix())
+}
+
+/// Temp files of `target` last modified at least `age` ago, including the
+/// `file.png.tmp` files of earlier versions
+fn temp_files(target: &Path, age: Duration) -> Vec<PathBuf> {
+    let prefix = temp_prefix(target);
+    let legacy = name_with(target, "", ".tmp");
+    let Ok(entries) = fs::read_dir(parent_dir(target)) else {
+        return vec![];
+    };
+
+    let now = SystemTime::now();
+    entries
+        .flatten()
+        .filter(|entry| {
+            let file_name = entry.file_name();
+            name_after(&file_name, &prefix).is_some() || file_name == legacy
+        })
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() >= age)
+        })
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Removes the temp files `temp_files` finds, returning how many were removed. Failures
+/// are ignored: a straggler that stays is harmless.
+fn remove_temp_files(target: &Path, age: Duration, trash: bool) -> usize {
+    temp_files(target, age)
+        .into_iter()
+        .filter(|path| discard(path, trash).is_ok())
+        .count()
+}
+
+/// Whether the backup at `path` is zstd-compressed
+pub fn is_compressed_backup(path: &Path) -> bool {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .ends_with(COMPRESSED_SUFFIX.as_bytes())
+}
+
+/// A backup version such as "20240101T120000.zst" without its compression suffix
+fn strip_compressed_suffix(version: &str) -> &str {
+    version.strip_suffix(COMPRESSED_SUFFIX).unwrap_or(version)
+}
+
+/// Writes a backup of `target` to `backup`, compressed when its name says so, and
+/// records the SHA-256 of what was written next to it
+fn write_backup(target: &Path, backup: &Path) -> io::Result<()> {
+    if is_compressed_backup(backup) {
+        let mut file = File::create(backup)?;
+        zstd::stream::copy_encode(File::open(target)?, &mut file, 0)?;
+        file.sync_all()?;
+    } else {
+        copy_synced(target, backup)?;
+    }
+
+    // In the format of sha256sum, so that `sha256sum -c` can check backups too
+    let name = path_bytes(Path::new(backup.file_name().unwrap_or_default()));
+    let digest = crate::encoding::hex_encode(&crate::crypto::sha256(&fs::read(backup)?));
+    fs::write(
+        checksum_path(backup),
+        [format!("{}  ", digest).as_bytes(), &name, b"\n"].concat(),
+    )
+}
+
+/// The file recording the checksum of the backup at `backup`
+fn checksum_path(backup: &Path) -> PathBuf {
+    backup.with_file_name(name_with(backup, "", CHECKSUM_SUFFIX))
+}
+
+/// Whether the backup at `backup` still has the SHA-256 recorded when it was made;
+/// `None` for backups made before checksums were recorded
+pub fn verify_backup(backup: &Path) -> Result<Option<bool>> {
+    let Ok(recorded) = fs::read(checksum_path(backup)) else {
+        return Ok(None);
+    };
+    let recorded = String::from_utf8_lossy(&recorded);
+    let data = fs::read(backup).map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to read backup '{}': {}",
+            backup.display(),
+            e
+        ))
+    })?;
+    let digest = crate::encoding::hex_encode(&crate::crypto::sha256(&data));
+    let recorded = recorded.split_whitespace().next().unwrap_or_default();
+    Ok(Some(recorded.eq_ignore_ascii_case(&digest)))
+}
+
+/// Fails for a backup that no longer matches its recorded checksum, so that a silently
+/// corrupted backup is never copied over the working file
+fn check_backup(backup: &Path) -> Result<()> {
+    if verify_backup(backup)? == Some(false) {
+        return Err(format!(
+            "Backup '{}' does not match its recorded SHA-256 checksum: it is corrupted \
+             and will not be restored",
+            backup.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Contents of the file a backup holds, decompressing compressed backups
+pub fn read_backup(backup: &Path) -> Result<Vec<u8>> {
+    check_backup(backup)?;
+    let data = fs::read(backup).map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to read backup '{}': {}",
+            backup.display(),
+            e
+        ))
+    })?;
+    if !is_compressed_backup(backup) {
+        return Ok(data);
+    }
+    zstd::decode_all(data.as_slice())
+        .map_err(|e| format!("Failed to decompress backup '{}': {}", backup.display(), e).into())
+}
+
+/// Overwrites `target` with the file `backup` holds, once it is known to be intact. It
+/// is replaced the way changes are committed, keeping its owner, permissions and times;
+/// the caller holds its [`FileLock`].
+pub fn restore_backup(backup: &Path, target: &Path) -> Result<()> {
+    let data = read_backup(backup)?;
+    write_atomically(target, &data, target.exists())
+        .map_err(|e| Failure::from(&e).error(e.to_string()))?;
+    detail!(
+        "    Wrote {} bytes from '{}' to '{}'",
+        data.len(),
+        backup.display(),
+        target.display()
+    );
+    Ok(())
+}
+
+/// Deletes a backup with its checksum file, or with `trash` moves both to the trash
+fn remove_backup(backup: &Path, trash: bool) -> io::Result<()> {
+    discard(backup, trash)?;
+    let checksum = checksum_path(backup);
+    if checksum.exists() {
+        let _ = discard(&checksum, trash);
+    }
+    Ok(())
+}
+
+/// Deletes `path`, or with `trash` moves it to the trash
+fn discard(path: &Path, trash: bool) -> io::Result<()> {
+    if trash {
+        trash::move_to_trash(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Copies `from` to `to` and flushes the copy to disk, for backups that must outlive
+/// a crash during the write that follows them
+fn copy_synced(from: &Path, to: &Path) -> io::Result<()> {
+    fs::copy(from, to)?;
+    File::open(to)?.sync_all()
+}
+
+/// Renames `temp` over `target`. Where they are on different filesystems, as for an
+/// image mounted on its own into a container, the rename cannot work and `temp` is
+/// copied over `target` instead: not atomic, but the backup made before the change
+/// still holds the original. Linux reports a rename over such a mount point as busy
+/// rather than crossing devices.
+fn replace_file(temp: &Path, target: &Path) -> io::Result<()> {
+    match replace::rename(temp, target) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::CrossesDevices | io::ErrorKind::ResourceBusy
+            ) =>
+        {
+            copy_synced(temp, target)?;
+            fs::remove_file(temp)
+        }
+        result => result,
+    }
+}
+
+/// Writes `data` to a new or replaced file at `path` the way changes are committed: to a
+/// temp file next to it, flushed to disk, then renamed over it
+fn write_output(path: &Path, data: &[u8]) -> Result<()> {
+    write_atomically(path, data, false).map_err(|e| {
+        Failure::from(&e).error(format!("Failed to write '{}': {}", path.display(), e))
+    })?;
+    detail!("    Wrote {} bytes to '{}'", data.len(), path.display());
+    Ok(())
+}
+
+/// Writes `data` to a temp file next to `path`, flushes it to disk and renames it over
+/// `path`, first giving it the metadata of the file it replaces with `keep_metadata`.
+/// The temp file is removed again when any step fails.
+fn write_atomically(path: &Path, data: &[u8], keep_metadata: bool) -> io::Result<()> {
+    let temp =
+        AtomicFileHandler::generate_temp_path(path).map_err(|e| io::Error::other(e.to_string()))?;
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| match keep_metadata {
+            true => copy_metadata(path, &temp),
+            false => Ok(()),
+        })
+        .and_then(|()| replace_file(&temp, path))
+        .and_then(|()| sync_parent_dir(path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// Gives `temp` the owner, extended attributes, times and permissions of `target`,
+/// which it is about to replace. The owner and extended attributes are copied where
+/// permitted: only root can give a file away, and some attributes are protected.
+fn copy_metadata(target: &Path, temp: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(target)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, chown};
+        if chown(temp, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+            let _ = chown(temp, None, Some(metadata.gid()));
+        }
+    }
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    xattr::copy(target, temp);
+
+    // Times before permissions, which may make the file read-only
+    let times = fs::FileTimes::new()
+        .set_accessed(metadata.accessed()?)
+        .set_modified(metadata.modified()?);
+    File::options().write(true).open(temp)?.set_times(times)?;
+    fs::set_permissions(temp, metadata.permissions())
+}
+
+/// Flushes the directory entry of `path` to disk, making a rename into it durable.
+/// Other platforms offer no way to open a directory for this and sync on rename.
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(parent_dir(path))?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Advisory lock on changes to a file, held by one run at a time until dropped. It is
+/// taken on a hidden `.file.png.hpv.lock` next to the file, which is removed again on
+/// release; the file itself cannot carry the lock because each commit replaces it.
+pub struct FileLock {
+    path: PathBuf,
+    /// Holds the lock while open
+    #[cfg_attr(not(windows), allow(dead_code))]
+    file: Option<File>,
+}
+
+impl FileLock {
+    /// Locks `target`, waiting for the run holding the lock to finish, which is said
+    /// when `announce` is set
+    pub fn acquire(target: &Path, announce: bool) -> Result<FileLock> {
+        let path = target.with_file_name(name_with(target, ".", ".hpv.lock"));
+        let failed = |e: io::Error| format!("Failed to lock '{}': {}", target.display(), e);
+
+        let mut announced = false;
+        loop {
+            let file = match lock::try_lock(&path).map_err(failed)? {
+                Some(file) => file,
+                None => {
+                    if announce && !announced {
+                        say!(
+                            "⏳  Waiting for another run to finish changing '{}'...",
+                            target.display()
+                        );
+                        announced = true;
+                    }
+                    lock::lock(&path).map_err(failed)?
+                }
+            };
+            // The run before may have removed the lock file while this one waited on it
+            if lock::is_current(&file, &path) {
+                return Ok(FileLock {
+                    path,
+                    file: Some(file),
+                });
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Nobody else can open the file while it is held on Windows, so it can only be
+        // removed once closed; elsewhere it is removed first, before the lock goes
+        #[cfg(windows)]
+        drop(self.file.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod xattr {
+    use std::ffi::{CString, c_char, c_void};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+
+    // Same value on Linux and macOS
+    const ERANGE: i32 = 34;
+
+    #[cfg(target_os = "linux")]
+    unsafe extern "C" {
+        fn listxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+        fn getxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut c_void,
+            size: usize,
+        ) -> isize;
+        fn setxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const c_void,
+            size: usize,
+            flags: i32,
+        ) -> i32;
+    }
+
+    // macOS adds a position, for resource forks, and options
+    #[cfg(target_os = "macos")]
+    unsafe extern "C" {
+        #[link_name = "listxattr"]
+        fn listxattr_at(path: *const c_char, list: *mut c_char, size: usize, options: i32)
+        -> isize;
+        #[link_name = "getxattr"]
+        fn getxattr_at(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut c_void,
+            size: usize,
+            position: u32,
+            options: i32,
+        ) -> isize;
+        #[link_name = "setxattr"]
+        fn setxattr_at(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const c_void,
+            size: usize,
+            position: u32,
+            options: i32,
+        ) -> i32;
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn listxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize {
+        unsafe { listxattr_at(path, list, size, 0) }
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn getxattr(
+        path: *const c_char,
+        name: *const c_char,
+        value: *mut c_void,
+        size: usize,
+    ) -> isize {
+        unsafe { getxattr_at(path, name, value, size, 0, 0) }
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn setxattr(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const c_void,
+        size: usize,
+        flags: i32,
+    ) -> i32 {
+        unsafe { setxattr_at(path, name, value, size, 0, flags) }
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    /// Reads a list or value whose size `call` reports when given no buffer, asking
+    /// again if it grew in between
+    fn read_sized(call: impl Fn(*mut c_void, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = call(ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buf = vec![0u8; size as usize];
+            let read = call(buf.as_mut_ptr().cast(), buf.len());
+            if read >= 0 {
+                buf.truncate(read as usize);
+                return Ok(buf);
+            }
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(ERANGE) {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Copies the extended attributes of `from` to `to`, skipping any that cannot be
+    /// read or set
+    pub fn copy(from: &Path, to: &Path) {
+        let (Ok(from), Ok(to)) = (c_path(from), c_path(to)) else {
+            return;
+        };
+        let Ok(names) =
+            read_sized(|buf, size| unsafe { listxattr(from.as_ptr(), buf.cast(), size) })
+        else {
+            return;
+        };
+        for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+            let Ok(name) = CString::new(name) else {
+                continue;
+            };
+            let value = read_sized(|buf, size| unsafe {
+                getxattr(from.as_ptr(), name.as_ptr(), buf, size)
+            });
+            if let Ok(value) = value {
+                unsafe {
+                    setxattr(
+                        to.as_ptr(),
+                        name.as_ptr(),
+                        value.as_ptr().cast(),
+                        value.len(),
+                        0,
+                    )
+                };
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod lock {
+    use std::fs::{self, File};
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    // Same values on Linux and macOS
+    const LOCK_EX: i32 = 2;
+    const LOCK_NB: i32 = 4;
+
+    unsafe extern "C" {
+        fn flock(fd: i32, operation: i32) -> i32;
+    }
+
+    fn open(path: &Path) -> io::Result<File> {
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+    }
+
+    /// The locked lock file, or `None` when another process holds it
+    pub fn try_lock(path: &Path) -> io::Result<Option<File>> {
+        let file = open(path)?;
+        if unsafe { flock(file.as_raw_fd(), LOCK_EX | LOCK_NB) } == 0 {
+            return Ok(Some(file));
+        }
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::WouldBlock => Ok(None),
+            _ => Err(error),
+        }
+    }
+
+    /// The locked lock file, once no other process holds it
+    pub fn lock(path: &Path) -> io::Result<File> {
+        let file = open(path)?;
+        while unsafe { flock(file.as_raw_fd(), LOCK_EX) } != 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        Ok(file)
+    }
+
+    /// Whether `file` is still the one at `path`
+    pub fn is_current(file: &File, path: &Path) -> bool {
+        match (file.metadata(), fs::metadata(path)) {
+            (Ok(locked), Ok(current)) => {
+                locked.dev() == current.dev() && locked.ino() == current.ino()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(not(windows))]
+mod replace {
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    /// Renames `temp` over `target`, which POSIX makes atomic
+    pub fn rename(temp: &Path, target: &Path) -> io::Result<()> {
+        fs::rename(temp, target)
+    }
+}
+
+#[cfg(windows)]
+mod replace {
+    use std::ffi::c_void;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::time::Duration;
+
+    const REPLACEFILE_IGNORE_MERGE_ERRORS: u32 = 0x2;
+    const MOVEFILE_REPLACE_EXISTING: u32 = 0x1;
+    const MOVEFILE_WRITE_THROUGH: u32 = 0x8;
+    const ERROR_FILE_NOT_FOUND: i32 = 2;
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_UNABLE_TO_MOVE_REPLACEMENT: i32 = 1176;
+
+    /// How many times a replacement blocked by another process holding the target open,
+    /// such as a virus scanner or an image viewer, is tried, 100 ms apart
+    const ATTEMPTS: u32 = 50;
+
+    unsafe extern "system" {
+        fn ReplaceFileW(
+            replaced: *const u16,
+            replacement: *const u16,
+            backup: *const u16,
+            flags: u32,
+            exclude: *mut c_void,
+            reserved: *mut c_void,
+        ) -> i32;
+        fn MoveFileExW(existing: *const u16, new: *const u16, flags: u32) -> i32;
+    }
+
+    fn wide(path: &Path) -> Vec<u16> {
+        path.as_os_str().encode_wide().chain([0]).collect()
+    }
+
+    /// Replaces `target` with `temp` in one step. `ReplaceFileW` keeps the target's
+    /// attributes, ACLs and streams; a target it cannot replace, such as one that does
+    /// not exist yet, is replaced by a rename written through to disk before it
+    /// returns. `fs::rename` fails instead while another process has the target open.
+    pub fn rename(temp: &Path, target: &Path) -> io::Result<()> {
+        let (temp, target) = (wide(temp), wide(target));
+        let mut attempt = 1;
+        loop {
+            let replaced = unsafe {
+                ReplaceFileW(
+                    target.as_ptr(),
+                    temp.as_ptr(),
+                    ptr::null(),
+                    REPLACEFILE_IGNORE_MERGE_ERRORS,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                )
+            };
+            let error = match replaced {
+                0 => io::Error::last_os_error(),
+                _ => return Ok(()),
+            };
+            let error = match error.raw_os_error() {
+                Some(ERROR_FILE_NOT_FOUND | ERROR_UNABLE_TO_MOVE_REPLACEMENT) => {
+                    let flags = MOVEFILE_REPLACE_EXISTING | MOVEFILE_WRITE_THROUGH;
+                    if unsafe { MoveFileExW(temp.as_ptr(), target.as_ptr(), flags) } != 0 {
+                        return Ok(());
+                    }
+                    io::Error::last_os_error()
+                }
+                _ => error,
+            };
+            let busy = matches!(
+                error.raw_os_error(),
+                Some(ERROR_SHARING_VIOLATION | ERROR_ACCESS_DENIED)
+            );
+            if !busy || attempt == ATTEMPTS {
+                return Err(error);
+            }
+            attempt += 1;
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+#[cfg(windows)]
+mod lock {
+    use std::fs::{self, File};
+    use std::io;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+
+    /// The lock file opened without sharing, or `None` when another process has it open
+    pub fn try_lock(path: &Path) -> io::Result<Option<File>> {
+        let opened = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .share_mode(0)
+            .open(path);
+        match opened {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The lock file opened without sharing, once no other process has it open
+    pub fn lock(path: &Path) -> io::Result<File> {
+        loop {
+            if let Some(file) = try_lock(path)? {
+                return Ok(file);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// Always true: an open lock file cannot be removed
+    pub fn is_current(_file: &File, _path: &Path) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Backup names and compression are settings of the whole process, so the tests
+    /// taking backups run one at a time
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// A fresh directory holding `image.png`, and a handler for it
+    fn setup(name: &str) -> (PathBuf, AtomicFileHandler) {
+        let dir = std::env::temp_dir().join(format!("hpv-atomic-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("image.png");
+        fs::write(&target, b"original").unwrap();
+        let handler = AtomicFileHandler::new(&target).unwrap();
+        (dir, handler)
+    }
+
+    /// Writes a backup of the handler's target named with `version`
+    fn make_backup(handler: &AtomicFileHandler, version: &str) -> PathBuf {
+        let mut name = backup_prefix(&handler.target_path);
+        name.push(version);
+        let path = backup_file(&handler.target_path, handler.backup_dir.as_deref(), &name);
+        write_backup(&handler.target_path, &path).unwrap();
+        path
+    }
+
+    fn names(backups: &[Backup]) -> Vec<String> {
+        backups
+            .iter()
+            .map(|backup| {
+                let name = backup.path.file_name().unwrap();
+                name_after(name, &backup_prefix(Path::new("image.png")))
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_backup_versions() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, handler) = setup("versions");
+        handler.create_backup_silent().unwrap();
+        let backup = handler.backup_path().to_path_buf();
+        let version = name_after(
+            backup.file_name().unwrap(),
+            &backup_prefix(&handler.target_path),
+        )
+        .unwrap();
+        let (created, counter) = parse_version(version).unwrap();
+        assert!(date::now() - created < 5);
+        assert_eq!(counter, 1);
+        assert_eq!(fs::read(&backup).unwrap(), b"original");
+        assert_eq!(backup_original(&backup), Some(handler.target_path.clone()));
+
+        make_backup(&handler, "20240101T120000");
+        make_backup(&handler, "20240101T120000-2");
+        make_backup(&handler, "20230101T120000");
+        let backups = handler.backups();
+        assert_eq!(
+            names(&backups)[1..],
+            ["20240101T120000-2", "20240101T120000", "20230101T120000"]
+        );
+        assert_eq!(backups[0].path, backup);
+
+        assert_eq!(handler.find_backup("1").unwrap().path, backup);
+        assert_eq!(
+            names(&[handler.find_backup("20240101T120000").unwrap()]),
+            ["20240101T120000"]
+        );
+        assert!(handler.find_backup("5").is_err());
+        assert!(handler.find_backup("20200101T120000").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lock_blocks_a_second_writer() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, handler) = setup("lock");
+        let lock_path = dir.join(".image.png.hpv.lock");
+        let held = FileLock::acquire(&handler.target_path, false).unwrap();
+        assert!(lock::try_lock(&lock_path).unwrap().is_none());
+
+        let target = handler.target_path.clone();
+        let writer = std::thread::spawn(move || {
+            AtomicFileHandler::new(&target)
+                .and_then(|handler| handler.atomic_modify(|_| Ok(b"changed".to_vec())))
+                .map_err(|e| e.to_string())
+        });
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(!writer.is_finished(), "the second writer did not wait");
+        assert_eq!(fs::read(&handler.target_path).unwrap(), b"original");
+
+        drop(held);
+        writer.join().unwrap().unwrap();
+        assert_eq!(fs::read(&handler.target_path).unwrap(), b"changed");
+        assert!(!lock_path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_same_second_counter() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, handler) = setup("counter");
+        let name = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+        // Retried in the rare case that a second passes between the names
+        let [first, second, third] = loop {
+            let first = AtomicFileHandler::generate_backup_path(&handler.target_path, None);
+            fs::write(&first, b"").unwrap();
+            let second = AtomicFileHandler::generate_backup_path(&handler.target_path, None);
+            fs::write(&second, b"").unwrap();
+            let third = AtomicFileHandler::generate_backup_path(&handler.target_path, None);
+            let names = [name(&first), name(&second), name(&third)];
+            if names.iter().all(|name| name.starts_with(&names[0])) {
+                break names;
+            }
+            fs::remove_file(&first).unwrap();
+            fs::remove_file(&second).unwrap();
+        };
+        assert_eq!(second, format!("{}-2", first));
+        assert_eq!(third, format!("{}-3", first));
+        // Numbered backups of the same second count as newer than the first
+        let backups: Vec<String> = handler.backups().iter().map(|b| name(&b.path)).collect();
+        assert_eq!(backups, [second, first]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, handler) = setup("prune");
+        const DAY: u64 = 24 * 60 * 60;
+        let versions: Vec<String> = [400 * DAY, 30 * DAY, DAY, 0]
+            .iter()
+            .map(|age| date::compact_timestamp(date::now() - age))
+            .collect();
+        for version in &versions {
+            make_backup(&handler, version);
+        }
+
+        // --older-than only takes backups made at least that long ago
+        let removed = handler.prune(None, Some(7 * DAY), false);
+        assert_eq!(names(&removed), [versions[1].as_str(), &versions[0]]);
+        for backup in &removed {
+            assert!(!backup.path.exists());
+            assert!(!checksum_path(&backup.path).exists());
+        }
+        assert_eq!(
+            names(&handler.backups()),
+            [versions[3].as_str(), &versions[2]]
+        );
+
+        // --keep N keeps the newest N
+        make_backup(&handler, "20200101T120000");
+        make_backup(&handler, "20210101T120000");
+        let removed = handler.prune(Some(2), None, false);
+        assert_eq!(names(&removed), ["20210101T120000", "20200101T120000"]);
+        assert_eq!(
+            names(&handler.backups()),
+            [versions[3].as_str(), &versions[2]]
+        );
+
+        // Both together only take old backups beyond the newest N
+        let removed = handler.prune(Some(1), Some(7 * DAY), false);
+        assert!(removed.is_empty());
+        assert_eq!(handler.prune(Some(1), None, false).len(), 1);
+        assert_eq!(names(&handler.backups()), [versions[3].as_str()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_central_backup_dir() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, mut handler) = setup("central");
+        let central = dir.join("backups");
+        let name = central_backup_name(&handler.target_path).unwrap();
+        assert_eq!(name.len(), 32);
+        handler.backup_dir = Some(central.join(&name));
+
+        handler.create_backup_silent().unwrap();
+        let backup = handler.backup_path().to_path_buf();
+        assert_eq!(backup.parent(), Some(central.join(&name).as_path()));
+        // Nothing is left next to the image
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(handler.backups().len(), 1);
+        assert_eq!(
+            backup_original(&backup),
+            Some(fs::canonicalize(&handler.target_path).unwrap())
+        );
+
+        // The directory goes once its last backup does
+        assert_eq!(handler.prune(Some(0), None, false).len(), 1);
+        assert!(!central.join(&name).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_suffixes() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        // Set for the whole process, so the other tests build names from backup_suffix()
+        set_backup_suffix(".bak".to_string());
+        set_temp_suffix(".tmp-".to_string());
+        assert_eq!(backup_suffix(), ".bak");
+        assert_eq!(temp_suffix(), ".tmp-");
+
+        let (dir, handler) = setup("suffixes");
+        handler.create_backup_silent().unwrap();
+        let backup = handler
+            .backup_path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert!(backup.starts_with("image.png.bak.2"), "{}", backup);
+        assert_eq!(handler.backups().len(), 1);
+        assert_eq!(
+            backup_original(&dir.join(&backup)),
+            Some(handler.target_path.clone())
+        );
+        let temp = handler
+            .temp_path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert!(temp.starts_with(".image.png.tmp-"), "{}", temp);
+
+        // A stray file with the default suffix is not one of its backups
+        fs::write(dir.join("image.png.backup.20240101T120000"), b"").unwrap();
+        assert_eq!(handler.backups().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_checksum() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, handler) = setup("checksum");
+        handler.create_backup_silent().unwrap();
+        let backup = handler.backup_path().to_path_buf();
+        let recorded = fs::read_to_string(checksum_path(&backup)).unwrap();
+        let name = backup.file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            recorded,
+            format!(
+                "{}  {}\n",
+                crate::encoding::hex_encode(&crate::crypto::sha256(b"original")),
+                name
+            )
+        );
+        assert_eq!(verify_backup(&backup).unwrap(), Some(true));
+
+        fs::write(&handler.target_path, b"changed").unwrap();
+        restore_backup(&backup, &handler.target_path).unwrap();
+        assert_eq!(fs::read(&handler.target_path).unwrap(), b"original");
+
+        // A corrupted backup is found out and never copied over the image
+        fs::write(&backup, b"0riginal").unwrap();
+        fs::write(&handler.target_path, b"changed").unwrap();
+        assert_eq!(verify_backup(&backup).unwrap(), Some(false));
+        assert!(read_backup(&backup).is_err());
+        assert!(restore_backup(&backup, &handler.target_path).is_err());
+        assert_eq!(fs::read(&handler.target_path).unwrap(), b"changed");
+
+        // Backups from before checksums were recorded are trusted
+        fs::remove_file(checksum_path(&backup)).unwrap();
+        assert_eq!(verify_backup(&backup).unwrap(), None);
+        assert_eq!(read_backup(&backup).unwrap(), b"0riginal");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compressed_backup() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let _output = output::serialize_tests();
+        let (dir, handler) = setup("compressed");
+        let data: Vec<u8> = b"IDAT ".repeat(1000);
+        fs::write(&handler.target_path, &data).unwrap();
+
+        set_compress_backups(true);
+        let created = handler.create_backup_silent();
+        set_compress_backups(false);
+        created.unwrap();
+
+        let backup = handler.backup_path().to_path_buf();
+        assert!(is_compressed_backup(&backup));
+        assert!(backup.to_string_lossy().ends_with(".zst"));
+        let stored = fs::read(&backup).unwrap();
+        assert_eq!(stored[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+        assert!(stored.len() < data.len());
+        assert_eq!(verify_backup(&backup).unwrap(), Some(true));
+
+        // Listed and found by version like any other backup
+        assert_eq!(handler.backups()[0].path, backup);
+        let version = names(&handler.backups())[0].clone();
+        assert!(version.ends_with(".zst"));
+        let version = version.strip_suffix(".zst").unwrap();
+        assert_eq!(handler.find_backup(version).unwrap().path, backup);
+
+        fs::write(&handler.target_path, b"changed").unwrap();
+        handler
+            .restore_from(&handler.find_backup("1").unwrap())
+            .unwrap();
+        assert_eq!(fs::read(&handler.target_path).unwrap(), data);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -17,6 +17,13 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
+        Chunk::parse(value)
+    }
+}
+
+impl Chunk {
+    /// Parses raw chunk bytes, rejecting a CRC mismatch
+    fn parse(value: &[u8]) -> Result<Chunk> {
         let mut iter = value.iter().copied();
 
         let first4: [u8; 4] = iter
@@ -48,21 +55,26 @@ impl TryFrom<&[u8]> for Chunk {
 
         let crc = u32::from_be_bytes(crc_bytes);
 
-        let bytes_for_crc = Chunk::get_bytes_for_crc(&chunk_type, &data);
-        let calculated_crc = CRC.checksum(&bytes_for_crc);
-
-        if calculated_crc != crc {
-            Err(Error::from(
-                "Invalid CRC : The File might be corrupted or tampered with so be careful using it.",
-            ))
-        } else {
-            Ok(Chunk {
-                length,
-                chunk_type,
-                data,
-                crc,
-            })
+        Chunk::from_parts(chunk_type, data, crc, true)
+    }
+
+    /// Builds a chunk from its type, data and stored CRC, read separately, rejecting a
+    /// CRC mismatch only when `check_crc` is set
+    pub fn from_parts(
+        chunk_type: ChunkType,
+        data: Vec<u8>,
+        crc: u32,
+        check_crc: bool,
+    ) -> Result<Chunk> {
+        if check_crc && Chunk::checksum(&chunk_type.bytes(), &data) != crc {
+            return Err(Error::from(Chunk::INVALID_CRC));
         }
+        Ok(Chunk {
+            length: data.len() as u32,
+            chunk_type,
+            data,
+            crc,
+        })
     }
 }
 
@@ -78,6 +90,11 @@ impl Display for Chunk {
 }
 
 impl Chunk {
+    /// Largest data length the PNG spec allows in a single chunk
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
+    pub const INVALID_CRC: &str =
+        "Invalid CRC : The File might be corrupted or tampered with so be careful using it.";
+
     pub fn get_bytes_for_crc(chunk_type: &ChunkType, data: &Vec<u8>) -> Vec<u8> {
         let mut container = vec![];
         container.extend(chunk_type.bytes());
@@ -85,6 +102,19 @@ impl Chunk {
         container
     }
 
+    /// CRC-32 of a chunk with type `chunk_type` holding `data`, as stored after its data
+    pub fn checksum(chunk_type: &[u8; 4], data: &[u8]) -> u32 {
+        let mut digest = CRC.digest();
+        digest.update(chunk_type);
+        digest.update(data);
+        digest.finalize()
+    }
+
+    /// Running CRC-32 for chunks whose type and data arrive in pieces
+    pub fn digest() -> crc::Digest<'static, u32> {
+        CRC.digest()
+    }
+
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
         Chunk {
             length: data.len() as u32,
@@ -93,16 +123,17 @@ impl Chunk {
             data,
         }
     }
+    #[allow(dead_code)]
     fn length(&self) -> u32 {
         self.length
     }
     pub fn chunk_type(&self) -> &ChunkType {
         &self.chunk_type
     }
-    fn data(&self) -> &[u8] {
+    pub fn data(&self) -> &[u8] {
         self.data.as_slice()
     }
-    fn crc(&self) -> u32 {
+    pub fn crc(&self) -> u32 {
         self.crc
     }
     pub fn data_as_string(&self) -> Result<String> {
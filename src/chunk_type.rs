@@ -37,9 +37,10 @@ impl FromStr for ChunkType {
 impl Display for ChunkType {
     // This function defines how to convert ChunkType to a readable string.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        // Convert the bytes [82, 85, 83, 84] to a UTF-8 string ("RUST").
+        // Convert the bytes [82, 85, 83, 84] to a UTF-8 string ("RUST"). Bytes of a
+        // damaged file that are not UTF-8 show as replacement characters.
         // Write that string to the formatter output
-        write!(f, "{}", String::from_utf8(self.bytes.into()).unwrap())
+        write!(f, "{}", String::from_utf8_lossy(&self.bytes))
     }
 }
 
@@ -52,19 +53,19 @@ impl ChunkType {
     //     self.is_reserved_bit_valid() && self.bytes.into_iter().all(|c| c.is_ascii())
     // }
 
-    fn _is_critical(&self) -> bool {
+    pub fn is_critical(&self) -> bool {
         self.bytes[0].is_ascii_uppercase()
     }
 
-    fn _is_public(&self) -> bool {
+    pub fn is_public(&self) -> bool {
         self.bytes[1].is_ascii_uppercase()
     }
 
-    fn _is_reserved_bit_valid(&self) -> bool {
+    pub fn is_reserved_bit_valid(&self) -> bool {
         self.bytes[2].is_ascii_uppercase()
     }
 
-    fn _is_safe_to_copy(&self) -> bool {
+    pub fn is_safe_to_copy(&self) -> bool {
         self.bytes[3].is_ascii_lowercase()
     }
 }
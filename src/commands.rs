@@ -1,184 +1,3613 @@
-use crate::Result;
-use crate::atomic_file::AtomicFileHandler;
+use crate::analysis;
+use crate::args::{
+    Args, DecryptionArgs, EmbeddingArgs, EncryptionArgs, LocationArgs, MessageArgs, OutputArgs,
+    PlacementArgs, RekeyArgs, WatermarkAction, WatermarkArgs,
+};
+use crate::atomic_file::{self, AtomicFileHandler, Backup, FileLock};
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
-use crate::png::Png;
+use crate::completions::{self, Shell};
+use crate::container::{self, Container, Format, PIXEL_NAMES};
+use crate::crypto::{self, KeyProvider, Secret};
+use crate::date;
+use crate::encoding::{self, Encoding};
+use crate::exit::Failure;
+use crate::fec;
+use crate::glob;
+use crate::idat;
+use crate::journal;
+use crate::keyring;
+use crate::lsb;
+use crate::man;
+use crate::output::{self, Verbosity, detail, say};
+use crate::payload::{self, Compression, Header, Kind, Manifest};
+use crate::pixels::{self, Ihdr, PixelFormat, Pixels};
+use crate::png::{
+    self, ANIMATION_CHUNKS, ChunkReader, Placement, Png, check_integrity, describe_animation_chunk,
+    describe_color_chunk,
+};
+use crate::preview::Style;
+use crate::prompt;
+use crate::signature::{self, SIGNATURE_CHUNK, SignatureRecord};
+use crate::text;
+use crate::watermark;
+use crate::xmp;
+use crate::{Error, Result};
+use clap::CommandFactory;
+use serde_json::{Value, json};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-pub fn print(path: &str) -> Result<()> {
+/// Longest text value `print` shows for a text chunk, in characters
+const TEXT_PREVIEW_CHARS: usize = 60;
+
+pub fn print(path: &Path, detailed: bool) -> Result<()> {
     let handler = AtomicFileHandler::new(path)?;
-    let buffer = handler.read_file()?;
+    let mut reader = handler.open_reader()?;
 
-    let png =
-        Png::try_from(buffer.as_slice()).map_err(|e| format!("Failed to parse PNG: {}", e))?;
+    if container::detect(reader.fill_buf()?)? != Format::Png {
+        let container = container::parse(&handler.read_file()?)?
+            .ok_or_else(|| Failure::Parse.error("Unrecognized file format"))?;
+        return print_container(&handler, container.as_ref(), detailed);
+    }
 
-    let chunk_types: Vec<String> = png
-        .chunks()
-        .iter()
-        .map(|c| c.chunk_type().to_string())
-        .collect();
+    // PNG files are read a chunk at a time, so huge images need little memory. The
+    // detailed listing shows CRC mismatches instead of refusing the file.
+    let mut chunks = if detailed {
+        ChunkReader::ignoring_crc(reader)?
+    } else {
+        ChunkReader::new(reader)?
+    };
+    let parse_error = |e: Error| format!("Failed to parse PNG: {}", e);
 
-    println!(
+    say!(
         " 📋  Available chunks in '{}':",
         handler.target_path().display()
     );
-    for chunk in chunk_types {
-        println!("  • {}", chunk);
+    output::set("file", handler.target_path().to_string_lossy());
+    while let Some(header) = chunks.next_header().map_err(parse_error)? {
+        if !detailed {
+            // Only chunks with something to show are read, the others skipped
+            let chunk_type = header.chunk_type.to_string();
+            let summary = if ANIMATION_CHUNKS.contains(&chunk_type.as_str())
+                || text::TEXT_CHUNKS.contains(&chunk_type.as_str())
+            {
+                chunk_summary(&chunks.read_chunk().map_err(parse_error)?)
+            } else {
+                // Image data is not even read, so listing huge images is instant
+                if chunk_type == "IDAT" {
+                    chunks.skip_chunk().map_err(parse_error)?;
+                }
+                String::new()
+            };
+            say!("  • {}{}", chunk_type, summary);
+            output::push(
+                "chunks",
+                json!({
+                    "type": chunk_type,
+                    "summary": unwrap_summary(&summary),
+                    "length": header.length,
+                    "offset": header.offset,
+                }),
+            );
+            continue;
+        }
+
+        let chunk = chunks.read_chunk()?;
+        say!(
+            "  • {}{} ({} bytes)",
+            chunk.chunk_type(),
+            chunk_summary(&chunk),
+            header.length
+        );
+        let computed = Chunk::checksum(&chunk.chunk_type().bytes(), chunk.data());
+        let crc = if computed == chunk.crc() {
+            format!("{:08x} ✅", computed)
+        } else {
+            format!("stored {:08x}, computed {:08x} ❌", chunk.crc(), computed)
+        };
+        say!(
+            "    Offset: {} ({:#x}), CRC: {}",
+            header.offset,
+            header.offset,
+            crc
+        );
+        say!("    Flags: {}", chunk_flags(chunk.chunk_type()));
+        output::push(
+            "chunks",
+            json!({
+                "type": chunk.chunk_type().to_string(),
+                "summary": unwrap_summary(&chunk_summary(&chunk)),
+                "length": header.length,
+                "offset": header.offset,
+                "crc": format!("{:08x}", chunk.crc()),
+                "crc_valid": computed == chunk.crc(),
+            }),
+        );
+        if let Some(color) = describe_color_chunk(&chunk) {
+            say!("    Color: {}", color);
+        }
+        print_payload_details(chunk.data());
+    }
+    let trailing = io::copy(&mut chunks.into_remainder()?, &mut io::sink())?;
+    if trailing > 0 {
+        say!("  ⚠️  {} bytes of trailing data after IEND", trailing);
+        output::set("trailing_bytes", trailing);
+    }
+    Ok(())
+}
+
+/// What an animation or text chunk holds, e.g. " (Software: GIMP 2.10)", so `print` shows
+/// existing metadata without decoding every chunk; empty for other chunks
+fn chunk_summary(chunk: &Chunk) -> String {
+    let text = match text::read_text_preview(chunk, TEXT_PREVIEW_CHARS) {
+        Ok(Some((keyword, value))) => Some(format!(
+            "{}: {}",
+            keyword,
+            text::shorten(&value, TEXT_PREVIEW_CHARS)
+        )),
+        Ok(None) => None,
+        Err(e) => Some(format!("unreadable: {}", e)),
+    };
+    describe_animation_chunk(chunk)
+        .or(text)
+        .map(|description| format!(" ({})", description))
+        .unwrap_or_default()
+}
+
+/// The description inside a `chunk_summary`, for the JSON result; `None` if it is empty
+fn unwrap_summary(summary: &str) -> Option<&str> {
+    summary.strip_prefix(" (")?.strip_suffix(')')
+}
+
+/// The properties encoded in the letter case of a chunk type, e.g. "ancillary, private,
+/// safe to copy"
+fn chunk_flags(chunk_type: &ChunkType) -> String {
+    let mut flags = vec![
+        if chunk_type.is_critical() {
+            "critical"
+        } else {
+            "ancillary"
+        },
+        if chunk_type.is_public() {
+            "public"
+        } else {
+            "private"
+        },
+        if chunk_type.is_safe_to_copy() {
+            "safe to copy"
+        } else {
+            "unsafe to copy"
+        },
+    ];
+    if !chunk_type.is_reserved_bit_valid() {
+        flags.push("reserved bit set ⚠️");
+    }
+    flags.join(", ")
+}
+
+/// `print` for the non-PNG formats in [`container`]
+fn print_container(
+    handler: &AtomicFileHandler,
+    container: &dyn Container,
+    detailed: bool,
+) -> Result<()> {
+    say!(
+        " 📋  Available blocks in '{}' ({}):",
+        handler.target_path().display(),
+        container.format()
+    );
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("format", container.format().to_string());
+    for block in container.blocks() {
+        let label = block
+            .label
+            .map(|label| format!(" '{}'", label))
+            .unwrap_or_default();
+        output::push(
+            "blocks",
+            json!({
+                "name": block.name.as_str(),
+                "label": block.label,
+                "length": block.data.len(),
+            }),
+        );
+        if !detailed {
+            say!("  • {}{}", block.name, label);
+            continue;
+        }
+        say!("  • {}{} ({} bytes)", block.name, label, block.data.len());
+        if block.label.is_some() {
+            print_payload_details(block.data);
+        }
+    }
+    Ok(())
+}
+
+/// Header fields of a payload written by `encode`, for `print --detailed`
+fn print_payload_details(data: &[u8]) {
+    let Ok((header, _)) = payload::unwrap(data) else {
+        return;
+    };
+    if header.version == 0 {
+        return;
+    }
+    say!(
+        "    Payload: v{}, {}",
+        header.version,
+        header.kind.describe()
+    );
+    if let Some(expires) = header.expires {
+        let status = if header.is_expired(date::now()) {
+            " (expired)"
+        } else {
+            ""
+        };
+        say!("    Expires: {}{}", date::format_timestamp(expires), status);
+    }
+    if let Some(compression) = header.compression {
+        say!("    Compressed: {}", compression.name());
+    }
+    if let Some(manifest) = &header.manifest {
+        say!("    Contents: {}", describe_manifest(manifest));
+    }
+}
+
+/// One-line summary of a hidden file, e.g. "notes.pdf (application/pdf, 5120 bytes)"
+fn describe_manifest(manifest: &Manifest) -> String {
+    let mut description = format!(
+        "{} ({}, {} bytes",
+        manifest.name, manifest.mime_type, manifest.size
+    );
+    if let Some(created) = manifest.created {
+        description.push_str(&format!(", created {}", date::format_timestamp(created)));
+    }
+    description.push(')');
+    description
+}
+
+pub fn diff(old_path: &Path, new_path: &Path) -> Result<()> {
+    let old_handler = AtomicFileHandler::new(old_path)?;
+    let new_handler = AtomicFileHandler::new(new_path)?;
+    // Either side may be a compressed backup, to compare it with the current file
+    let read = |handler: &AtomicFileHandler| -> Result<Png> {
+        let path = handler.target_path();
+        if atomic_file::is_compressed_backup(path) {
+            return parse_png(&atomic_file::read_backup(path)?);
+        }
+        parse_png(&handler.read_file()?)
+    };
+    let old = read(&old_handler)?;
+    let new = read(&new_handler)?;
+
+    say!(
+        "🔍  Comparing '{}' with '{}':",
+        old_handler.target_path().display(),
+        new_handler.target_path().display()
+    );
+    let result = png::diff(&old, &new);
+    print_chunk_changes(&result);
+    let pixels = compare_pixels(&old, &new);
+    say!("  • Pixels: {}", pixels);
+
+    output::set("old", old_handler.target_path().to_string_lossy());
+    output::set("new", new_handler.target_path().to_string_lossy());
+    for change in &result.changes {
+        output::push("changes", chunk_change_value(change));
+    }
+    output::set("reordered", result.reordered);
+    output::set("pixels", pixels);
+
+    if result.changes.is_empty() && !result.reordered {
+        say!(" ✅ The files have the same chunks");
+    } else {
+        say!(" ✅ {} chunk(s) differ", result.changes.len());
+    }
+    Ok(())
+}
+
+/// Reports what a change to the file at `path` would do without making it, for
+/// `--dry-run`: its size and, for PNG files, the chunks added, removed or changed
+pub fn report_dry_run(path: &Path, old: &[u8], new: &[u8]) {
+    say!(
+        "🔍  Dry run: '{}' would go from {} to {} bytes",
+        path.display(),
+        old.len(),
+        new.len()
+    );
+    output::push(
+        "dry_run",
+        json!({
+            "file": path.to_string_lossy(),
+            "size": old.len(),
+            "new_size": new.len(),
+        }),
+    );
+    if let (Ok(old), Ok(new)) = (parse_png(old), parse_png(new)) {
+        print_chunk_changes(&png::diff(&old, &new));
+    }
+}
+
+/// Lists the chunk changes `png::diff` found, one per line
+fn print_chunk_changes(result: &png::Diff<'_>) {
+    let describe = |chunk: &Chunk| {
+        format!(
+            "{} ({} bytes, CRC {:08x})",
+            chunk.chunk_type(),
+            chunk.data().len(),
+            chunk.crc()
+        )
+    };
+    for change in &result.changes {
+        match change {
+            png::ChunkChange::Added { index, chunk } => {
+                say!("  ➕ Added {} at index {}", describe(chunk), index)
+            }
+            png::ChunkChange::Removed { index, chunk } => {
+                say!("  ➖ Removed {} from index {}", describe(chunk), index)
+            }
+            png::ChunkChange::Changed { index, old, new } => say!(
+                "  ✏️  Changed {} at index {}: {} -> {} bytes, CRC {:08x} -> {:08x}",
+                new.chunk_type(),
+                index,
+                old.data().len(),
+                new.data().len(),
+                old.crc(),
+                new.crc()
+            ),
+        }
+    }
+    if result.reordered {
+        say!("  🔀 Chunks both files have appear in a different order");
+    }
+}
+
+/// A chunk change for the JSON result of `diff`, with the chunk on each side
+fn chunk_change_value(change: &png::ChunkChange<'_>) -> Value {
+    let chunk_value = |chunk: &Chunk| {
+        json!({
+            "type": chunk.chunk_type().to_string(),
+            "length": chunk.data().len(),
+            "crc": chunk.crc(),
+        })
+    };
+    match change {
+        png::ChunkChange::Added { index, chunk } => json!({
+            "change": "added",
+            "index": index,
+            "new": chunk_value(chunk),
+        }),
+        png::ChunkChange::Removed { index, chunk } => json!({
+            "change": "removed",
+            "index": index,
+            "old": chunk_value(chunk),
+        }),
+        png::ChunkChange::Changed { index, old, new } => json!({
+            "change": "changed",
+            "index": index,
+            "old": chunk_value(old),
+            "new": chunk_value(new),
+        }),
+    }
+}
+
+/// Whether two PNG files decode to the same pixels, in words for `diff`
+fn compare_pixels(old: &Png, new: &Png) -> String {
+    let (old_pixels, new_pixels) = match (Pixels::from_png(old), Pixels::from_png(new)) {
+        (Ok(old_pixels), Ok(new_pixels)) => (old_pixels, new_pixels),
+        (Err(e), _) | (_, Err(e)) => return format!("could not compare ({})", e),
+    };
+    if old_pixels.ihdr != new_pixels.ihdr {
+        let format = |ihdr: &Ihdr| format!("{}x{} {}", ihdr.width, ihdr.height, ihdr.describe());
+        return format!(
+            "image format differs ({} vs {})",
+            format(&old_pixels.ihdr),
+            format(&new_pixels.ihdr)
+        );
+    }
+    let changed = old_pixels
+        .data()
+        .iter()
+        .zip(new_pixels.data())
+        .filter(|(a, b)| a != b)
+        .count();
+    if changed > 0 {
+        format!("{} of {} bytes differ", changed, old_pixels.data().len())
+    } else if pixels::idat_data(old).ok() != pixels::idat_data(new).ok() {
+        "identical, only their compression differs".to_string()
+    } else {
+        "identical".to_string()
+    }
+}
+
+pub fn info(path: &Path) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+
+    let png = parse_png(&buffer)?;
+    let ihdr = Ihdr::from_png(&png)?;
+
+    say!("🖼️  Image info for '{}':", handler.target_path().display());
+    say!("  • Width: {} px", ihdr.width);
+    say!("  • Height: {} px", ihdr.height);
+    say!("  • Bit depth: {}", ihdr.bit_depth);
+    say!(
+        "  • Color type: {} ({})",
+        ihdr.color_type,
+        ihdr.color_name()
+    );
+    say!(
+        "  • Interlace method: {}",
+        if ihdr.interlaced {
+            "1 (Adam7)"
+        } else {
+            "0 (none)"
+        }
+    );
+    say!("  • File size: {} bytes", buffer.len());
+    say!("  • Chunks: {}", png.chunks().len());
+
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("width", ihdr.width);
+    output::set("height", ihdr.height);
+    output::set("bit_depth", u32::from(ihdr.bit_depth));
+    output::set("color_type", u32::from(ihdr.color_type));
+    output::set("color_name", ihdr.color_name());
+    output::set("interlaced", ihdr.interlaced);
+    output::set("size", buffer.len());
+    output::set("chunks", png.chunks().len());
+    Ok(())
+}
+
+pub fn preview(path: &Path, width: u16, ascii: bool) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let png = parse_png(&handler.read_file()?)?;
+    let pixels = Pixels::from_png(&png)?;
+    let rgba = pixels.to_rgba(png.chunk_by_type("PLTE").map(Chunk::data))?;
+
+    let style = if ascii { Style::Ascii } else { Style::Color };
+    let ihdr = pixels.ihdr;
+    say!(
+        "🖼️  Preview of '{}' ({}x{}, {}):",
+        handler.target_path().display(),
+        ihdr.width,
+        ihdr.height,
+        ihdr.describe()
+    );
+    for line in crate::preview::render(
+        &rgba,
+        ihdr.width as usize,
+        ihdr.height as usize,
+        width as usize,
+        style,
+    )? {
+        say!("{}", line);
     }
     Ok(())
 }
 
-pub fn decode(path: &str, chunk_type: &str) -> Result<()> {
+/// Share of the file above which `stats` warns that hidden data may stand out
+const CONSPICUOUS_PERCENT: f64 = 5.0;
+
+/// Chunk sizes are data lengths; shares of the file also count the 12 bytes of length,
+/// type and CRC around every chunk
+pub fn stats(path: &Path) -> Result<()> {
     let handler = AtomicFileHandler::new(path)?;
     let buffer = handler.read_file()?;
+    let png = parse_png(&buffer)?;
+    let file_size = buffer.len();
+    let percent = |bytes: usize| bytes as f64 * 100.0 / file_size as f64;
+    let stored = |chunk: &Chunk| chunk.data().len() + 12;
 
-    let png =
-        Png::try_from(buffer.as_slice()).map_err(|e| format!("Failed to parse PNG: {}", e))?;
-
-    match png.chunk_by_type(chunk_type) {
-        Some(target) => match target.data_as_string() {
-            Ok(message) => {
-                println!("🔓  Hidden message found:");
-                println!("    File: {}", handler.target_path().display());
-                println!("    Chunk: {}", chunk_type);
-                println!("    Message: {}", message);
-                Ok(())
-            }
-            Err(_) => {
-                println!(
-                    " ❌  Cannot decode message from chunk '{}': This chunk contains binary data, not text",
-                    chunk_type
+    say!(
+        "📊  Chunk statistics for '{}' ({} bytes):",
+        handler.target_path().display(),
+        file_size
+    );
+    let mut by_type: Vec<(String, usize, usize)> = Vec::new();
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type().to_string();
+        match by_type
+            .iter_mut()
+            .find(|(other, _, _)| *other == chunk_type)
+        {
+            Some((_, count, bytes)) => {
+                *count += 1;
+                *bytes += chunk.data().len();
+            }
+            None => by_type.push((chunk_type, 1, chunk.data().len())),
+        }
+    }
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("size", file_size);
+    say!("  • Chunks by type:");
+    for (chunk_type, count, bytes) in &by_type {
+        say!("      {} × {}: {} bytes", count, chunk_type, bytes);
+        output::push(
+            "types",
+            json!({
+                "type": chunk_type.as_str(),
+                "count": count,
+                "bytes": bytes,
+            }),
+        );
+    }
+
+    let idat: usize = png.chunks_by_type("IDAT").map(stored).sum();
+    say!(
+        "  • Image data (IDAT): {} bytes, {:.1}% of the file",
+        idat,
+        percent(idat)
+    );
+    output::set(
+        "image_data",
+        json!({
+            "bytes": idat,
+            "percent": percent(idat),
+        }),
+    );
+
+    let ancillary: Vec<&Chunk> = png.ancillary_chunks().collect();
+    let ancillary_bytes: usize = ancillary.iter().map(|chunk| stored(chunk)).sum();
+    say!(
+        "  • Ancillary chunks: {}, {} bytes, {:.1}% of the file",
+        ancillary.len(),
+        ancillary_bytes,
+        percent(ancillary_bytes)
+    );
+    output::set(
+        "ancillary",
+        json!({
+            "count": ancillary.len(),
+            "bytes": ancillary_bytes,
+            "percent": percent(ancillary_bytes),
+        }),
+    );
+
+    let vault: Vec<&Chunk> = ancillary
+        .into_iter()
+        .filter(|chunk| is_vault_chunk(chunk))
+        .collect();
+    let vault_bytes: usize = vault.iter().map(|chunk| stored(chunk)).sum();
+    let overhead = vault_bytes as f64 * 100.0 / (file_size - vault_bytes) as f64;
+    say!(
+        "  • Vault chunks (messages and signatures): {}, {} bytes, adding {:.1}% to the file",
+        vault.len(),
+        vault_bytes,
+        overhead
+    );
+    output::set(
+        "vault",
+        json!({
+            "count": vault.len(),
+            "bytes": vault_bytes,
+            "overhead_percent": overhead,
+        }),
+    );
+    output::set("trailing_bytes", png.trailing_data().len());
+    if !png.trailing_data().is_empty() {
+        say!(
+            "  • Trailing data after IEND: {} bytes",
+            png.trailing_data().len()
+        );
+    }
+
+    let mut largest: Vec<(usize, &Chunk)> = png.chunks().iter().enumerate().collect();
+    largest.sort_by_key(|(_, chunk)| std::cmp::Reverse(chunk.data().len()));
+    say!("  • Largest chunks:");
+    for (index, chunk) in largest.iter().take(3) {
+        say!(
+            "      {} at index {}: {} bytes",
+            chunk.chunk_type(),
+            index,
+            chunk.data().len()
+        );
+        output::push(
+            "largest",
+            json!({
+                "type": chunk.chunk_type().to_string(),
+                "index": index,
+                "length": chunk.data().len(),
+            }),
+        );
+    }
+
+    if overhead > CONSPICUOUS_PERCENT {
+        say!(
+            "💡  Tip: Hidden data adds more than {}% to this file and may stand out; --lsb or --idat keep it out of separate chunks",
+            CONSPICUOUS_PERCENT
+        );
+    }
+    Ok(())
+}
+
+/// Whether `chunk` holds a message written by `encode` or a signature written by `sign`.
+/// Decoys and messages of old versions carry no header and are not recognized.
+fn is_vault_chunk(chunk: &Chunk) -> bool {
+    chunk.chunk_type().to_string() == SIGNATURE_CHUNK
+        || payload::unwrap(chunk.data()).is_ok_and(|(header, _)| header.version != 0)
+}
+
+pub fn capacity(path: &Path) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+
+    let png = parse_png(&buffer)?;
+    let ihdr = Ihdr::from_png(&png)?;
+
+    say!(
+        "📏  Capacity of '{}' ({}x{}, {}):",
+        handler.target_path().display(),
+        ihdr.width,
+        ihdr.height,
+        ihdr.describe()
+    );
+    say!(
+        "  • Chunk mode: up to {} bytes per chunk, effectively unlimited",
+        Chunk::MAX_LENGTH
+    );
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("width", ihdr.width);
+    output::set("height", ihdr.height);
+    output::set("pixel_format", ihdr.describe());
+    output::set("chunk_max_bytes", Chunk::MAX_LENGTH);
+    match lsb::capacity(&ihdr, 1) {
+        Ok(_) => {
+            for bits in 1..=lsb::MAX_BITS {
+                let bytes = lsb::capacity(&ihdr, bits)?;
+                say!("  • LSB mode, --bits {}: {} bytes", bits, bytes);
+                output::push(
+                    "lsb",
+                    json!({
+                        "bits": u32::from(bits),
+                        "bytes": bytes,
+                    }),
+                );
+            }
+        }
+        Err(e) => {
+            say!("  • LSB mode: not available ({})", e);
+            output::set("lsb_error", output::reason(e.as_ref()));
+        }
+    }
+    say!("  • IDAT mode: effectively unlimited, but each hidden byte adds 8 bytes of image data");
+    say!(
+        "💡  Payload headers and encryption take some of this space (about 60 bytes with --password)"
+    );
+    Ok(())
+}
+
+/// Lists the PNG files under `dir` that hold non-standard ancillary chunks, reading
+/// only their chunk headers. Other files and backups are passed over, and a PNG that
+/// cannot be read is reported without stopping the scan.
+pub fn scan(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Err(format!("'{}' is not a directory", dir.display()).into());
+    }
+    say!(
+        "🔎  Scanning '{}' for non-standard chunks...",
+        dir.display()
+    );
+
+    let (mut scanned, mut flagged, mut unreadable) = (0usize, 0usize, 0);
+    let (mut total_chunks, mut total_bytes) = (0, 0);
+    for path in glob::files_under(dir) {
+        if atomic_file::backup_original(&path).is_some() {
+            continue;
+        }
+        let Ok(mut chunks) = File::open(&path)
+            .map_err(Error::from)
+            .and_then(|file| ChunkReader::new(BufReader::new(file)))
+        else {
+            continue;
+        };
+        scanned += 1;
+        let tallies = match analysis::unknown_chunks(&mut chunks) {
+            Ok(tallies) => tallies,
+            Err(e) => {
+                let reason = output::reason(e.as_ref());
+                say!("  ❌ {}: {}", path.display(), reason);
+                output::push(
+                    "unreadable",
+                    json!({
+                        "file": path.to_string_lossy(),
+                        "error": reason,
+                    }),
                 );
-                println!(
-                    " 💡  Tip: This chunk may be a critical PNG chunk or contain non-text data"
+                unreadable += 1;
+                continue;
+            }
+        };
+        if tallies.is_empty() {
+            detail!("  ✅ {}: only standard chunks", path.display());
+            continue;
+        }
+
+        flagged += 1;
+        let listed: Vec<String> = tallies
+            .iter()
+            .map(|tally| match tally.count {
+                1 => format!("{} ({} bytes)", tally.chunk_type, tally.bytes),
+                count => format!("{} ×{} ({} bytes)", tally.chunk_type, count, tally.bytes),
+            })
+            .collect();
+        say!("  ⚠️  {}: {}", path.display(), listed.join(", "));
+        total_chunks += tallies.iter().map(|tally| tally.count).sum::<usize>();
+        total_bytes += tallies.iter().map(|tally| tally.bytes).sum::<u64>();
+        let chunks: Vec<Value> = tallies
+            .iter()
+            .map(|tally| {
+                json!({
+                    "type": tally.chunk_type.as_str(),
+                    "count": tally.count,
+                    "bytes": tally.bytes,
+                })
+            })
+            .collect();
+        output::push(
+            "files",
+            json!({
+                "file": path.to_string_lossy(),
+                "chunks": chunks,
+            }),
+        );
+    }
+
+    output::set("scanned", scanned);
+    output::set("flagged", flagged);
+    if scanned == 0 {
+        say!(" ℹ️   No PNG files found");
+    } else if flagged == 0 {
+        say!(
+            " ✅ None of {} PNG file(s) has non-standard chunks",
+            scanned
+        );
+    } else {
+        say!(
+            "📊  {} of {} PNG file(s) have non-standard chunks: {} chunk(s), {} bytes in all",
+            flagged,
+            scanned,
+            total_chunks,
+            total_bytes
+        );
+    }
+    if unreadable > 0 {
+        say!("⚠️  {} PNG file(s) could not be read", unreadable);
+    }
+    Ok(())
+}
+
+pub fn analyze(path: &Path) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+
+    let png = parse_png(&buffer)?;
+
+    say!("🔬  Analyzing '{}':", handler.target_path().display());
+    // Findings that point at hidden data: 2 = strong, 1 = weak
+    let mut suspicion = 0;
+
+    say!("  Chunks:");
+    for chunk in png.chunks() {
+        let name = chunk.chunk_type().to_string();
+        let data = chunk.data();
+        let mut finding = None;
+        if data.starts_with(payload::MAGIC) {
+            finding = Some("starts with a Hidden Pixel Vault payload header");
+            suspicion = 2;
+        } else if !analysis::STANDARD_CHUNKS.contains(&name.as_str()) {
+            finding = if data.len() >= 16 && analysis::relative_entropy(data) > 0.9 {
+                suspicion = suspicion.max(2);
+                Some("unknown chunk with random-looking data")
+            } else {
+                suspicion = suspicion.max(1);
+                Some("unknown chunk")
+            };
+        }
+        let entropy = analysis::entropy(data);
+        say!(
+            "  • {} ({} bytes, entropy {:.2} bits/byte){}",
+            name,
+            data.len(),
+            entropy,
+            finding.map(|f| format!(" ⚠️  {}", f)).unwrap_or_default()
+        );
+        output::push(
+            "chunks",
+            json!({
+                "type": name,
+                "length": data.len(),
+                "entropy": entropy,
+                "finding": finding,
+            }),
+        );
+    }
+
+    match Pixels::from_png(&png).and_then(|pixels| {
+        let streams = analysis::channel_samples(&pixels)?;
+        Ok((pixels.ihdr.describe(), streams))
+    }) {
+        Ok((description, streams)) => {
+            say!("  Pixels ({}):", description);
+            let all: Vec<u8> = streams.concat();
+            let chi_square = analysis::chi_square(&all);
+            say!(
+                "  • Chi-square test on value pairs: {:.1}% probability of LSB embedding",
+                chi_square * 100.0
+            );
+            output::set("pixel_format", description);
+            output::set("chi_square", chi_square);
+
+            let estimates: Vec<f64> = streams
+                .iter()
+                .filter_map(|stream| analysis::rs_estimate(stream))
+                .collect();
+            let rs = if estimates.is_empty() {
+                say!("  • RS analysis: image too small to tell");
+                0.0
+            } else {
+                let rs = estimates.iter().sum::<f64>() / estimates.len() as f64;
+                say!(
+                    "  • RS analysis: about {:.1}% of color samples carry hidden bits ({})",
+                    rs * 100.0,
+                    estimates
+                        .iter()
+                        .map(|e| format!("{:.1}%", e * 100.0))
+                        .collect::<Vec<_>>()
+                        .join(", ")
                 );
-                Ok(())
+                output::set("rs_estimates", estimates);
+                output::set("rs", rs);
+                rs
+            };
+
+            // Clean photos typically give RS estimates of a few percent
+            if rs >= 0.15 || chi_square >= 0.95 {
+                suspicion = 2;
+            } else if rs >= 0.08 || chi_square >= 0.5 {
+                suspicion = suspicion.max(1);
             }
+        }
+        Err(e) => {
+            say!("  Pixels: not analyzed ({})", e);
+            output::set("pixels_error", output::reason(e.as_ref()));
+        }
+    }
+
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set(
+        "verdict",
+        match suspicion {
+            2 => "likely",
+            1 => "possible",
+            _ => "unlikely",
         },
-        None => Err(format!(" Chunk type '{}' not found", chunk_type).into()),
+    );
+    match suspicion {
+        2 => say!("🔴  Verdict: hidden data is likely"),
+        1 => say!("🟡  Verdict: some signs of hidden data"),
+        _ => say!("🟢  Verdict: no signs of hidden data"),
+    }
+    say!(
+        "💡  Statistical tests give estimates, not proof: smooth or edited images can trigger them, and small scattered payloads can slip by"
+    );
+    Ok(())
+}
+
+pub fn manage_watermark(action: &WatermarkAction) -> Result<()> {
+    match action {
+        WatermarkAction::Embed { path, id, options } => watermark_embed(path, id, options),
+        WatermarkAction::Verify { path, id, options } => {
+            watermark_verify(path, id.as_deref(), options)
+        }
+    }
+}
+
+fn watermark_embed(path: &Path, id: &str, options: &WatermarkArgs) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+
+    say!(
+        "💧  Watermarking '{}' with '{}'...",
+        handler.target_path().display(),
+        id
+    );
+
+    handler.atomic_modify(|content| {
+        let png = parse_png(&content)?;
+        let mut pixels = Pixels::from_png(&png)?;
+        let copies = watermark::embed(&mut pixels, id, options.key.as_deref(), options.strength)?;
+        say!(
+            " ✅ Watermark embedded ({} copies of every bit, strength {})",
+            copies,
+            options.strength
+        );
+        Ok(pixels.to_png(png)?.as_bytes())
+    })?;
+
+    if options.key.is_none() {
+        say!(" 💡  Tip: Without --key anyone running `watermark verify` can read the identifier");
+    }
+    Ok(())
+}
+
+fn watermark_verify(path: &Path, id: Option<&str>, options: &WatermarkArgs) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+
+    let png = parse_png(&buffer)?;
+    let pixels = Pixels::from_png(&png)?;
+    let key = options.key.as_deref();
+
+    let detection = watermark::detect(&pixels, key, options.strength)?;
+    if let Some(id) = id {
+        if let Some(found) = detection.id.as_deref().filter(|&found| found != id) {
+            return Err(format!(
+                "'{}' carries the watermark '{}', not '{}'",
+                handler.target_path().display(),
+                found,
+                id
+            )
+            .into());
+        }
+        // A damaged watermark may not decode, but most of its blocks still match
+        let rate = watermark::match_rate(&pixels, id, key, options.strength)?;
+        if rate < watermark::MATCH_THRESHOLD {
+            return Err(format!(
+                "Watermark '{}' not found in '{}' (only {:.0}% of blocks match, {:.0}% needed)",
+                id,
+                handler.target_path().display(),
+                rate * 100.0,
+                watermark::MATCH_THRESHOLD * 100.0
+            )
+            .into());
+        }
+        say!(
+            " ✅ Watermark '{}' present ({:.0}% of blocks match)",
+            id,
+            rate * 100.0
+        );
+        return Ok(());
+    }
+
+    match detection.id {
+        Some(found) => {
+            say!("💧  Watermark found:");
+            say!("    File: {}", handler.target_path().display());
+            say!("    Identifier: {}", found);
+            say!(
+                "    Agreement: {:.0}% of blocks",
+                detection.agreement * 100.0
+            );
+            Ok(())
+        }
+        None => Err(format!(
+            "No watermark found in '{}'; check --key and --strength match those used to embed it",
+            handler.target_path().display()
+        )
+        .into()),
+    }
+}
+
+/// Reveals the message in `chunk_type`, or without one (`--all`) every message in the
+/// non-standard chunks of the file
+pub fn decode(
+    path: &Path,
+    chunk_type: Option<&str>,
+    shards: &[PathBuf],
+    location: &LocationArgs,
+    decryption: &DecryptionArgs,
+    output: &OutputArgs,
+) -> Result<()> {
+    quiet_if_raw(output);
+    let Some(chunk_type) = chunk_type else {
+        return decode_all(path, decryption, output);
+    };
+    if !shards.is_empty() {
+        let paths: Vec<PathBuf> = std::iter::once(path.to_path_buf())
+            .chain(shards.iter().cloned())
+            .collect();
+        return unshard(chunk_type, &paths, location, decryption, output);
+    }
+
+    let handler = AtomicFileHandler::new(path)?;
+    let mut reader = handler.open_reader()?;
+
+    let secret = decryption_secret(decryption)?;
+    let chunk_type = &stored_chunk_type(chunk_type, secret.as_ref(), decryption.obfuscate_chunk)?;
+
+    let found = if container::detect(reader.fill_buf()?)? == Format::Png {
+        let png = read_png_for_payload(&handler, reader, chunk_type, location)?;
+        read_payload(&png, chunk_type, location, secret.as_ref())?
+    } else {
+        let container = container::parse(&handler.read_file()?)?
+            .ok_or_else(|| Failure::Parse.error("Unrecognized file format"))?;
+        container_payload(container.as_ref(), chunk_type, location, secret.as_ref())?
+    };
+
+    let data = found.ok_or_else(|| {
+        Failure::ChunkNotFound.error(if decryption.obfuscate_chunk {
+            " No message found for this chunk label and password".to_string()
+        } else if location.lsb {
+            format!(" No pixel-domain message labelled '{}' found", chunk_type)
+        } else if location.idat {
+            format!(
+                " No message labelled '{}' found in the image data stream",
+                chunk_type
+            )
+        } else if location.standard_text {
+            format!(" No text chunk with keyword '{}' found", chunk_type)
+        } else if location.xmp {
+            format!(" No XMP property '{}' found", chunk_type)
+        } else {
+            format!(" Chunk type '{}' not found", chunk_type)
+        })
+    })?;
+
+    reveal(
+        &handler.target_path().display().to_string(),
+        chunk_type,
+        &data,
+        secret,
+        decryption,
+        output,
+    )
+}
+
+/// Reveals whatever each non-standard chunk type of a PNG holds, for files whose chunk
+/// names are unknown: messages are decoded (and decrypted with the given secret), other
+/// data is reported with its size and entropy. A chunk that fails does not stop the scan.
+fn decode_all(path: &Path, decryption: &DecryptionArgs, output: &OutputArgs) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let png = parse_png(&handler.read_file()?)?;
+    let source = handler.target_path().display().to_string();
+    let secret = decryption_secret(decryption)?;
+
+    let mut chunk_types: Vec<String> = vec![];
+    for chunk in png.ancillary_chunks() {
+        let chunk_type = chunk.chunk_type().to_string();
+        if !analysis::STANDARD_CHUNKS.contains(&chunk_type.as_str())
+            && !chunk_types.contains(&chunk_type)
+        {
+            chunk_types.push(chunk_type);
+        }
+    }
+    if chunk_types.is_empty() {
+        say!(" ✅ '{}' has no non-standard chunks", source);
+        return Ok(());
+    }
+    say!(
+        "🔎  Scanning {} non-standard chunk type(s) in '{}'...",
+        chunk_types.len(),
+        source
+    );
+
+    let mut revealed = 0;
+    for chunk_type in &chunk_types {
+        let blocks: Vec<&[u8]> = png.chunks_by_type(chunk_type).map(Chunk::data).collect();
+        let data = match collect_payload(&blocks, chunk_type) {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                say!("⚠️  Chunk '{}': {}", chunk_type, e);
+                continue;
+            }
+        };
+        let report_binary = || {
+            say!(
+                "📦  Chunk '{}': {} bytes of binary data, entropy {:.2} bits/byte",
+                chunk_type,
+                data.len(),
+                analysis::entropy(&data)
+            )
+        };
+        let is_message = png.chunk_by_type(chunk_type).is_some_and(is_vault_chunk)
+            || std::str::from_utf8(&data).is_ok();
+        // Messages of old versions have no header, so with a secret binary data may
+        // still be one of them
+        if !is_message && secret.is_none() {
+            report_binary();
+            continue;
+        }
+        let secret = secret.as_ref().map(|s| Secret::from(s.expose().to_vec()));
+        match reveal(&source, chunk_type, &data, secret, decryption, output) {
+            Ok(()) => revealed += 1,
+            Err(e) if is_message => say!("⚠️  {}", e),
+            Err(_) => report_binary(),
+        }
+    }
+
+    say!(
+        " ✅ Scanned {} chunk type(s), {} decoded",
+        chunk_types.len(),
+        revealed
+    );
+    say!(
+        "💡  Tip: Messages hidden with --lsb, --idat, --standard-text or --xmp are not in chunks of their own; decode them by label"
+    );
+    Ok(())
+}
+
+/// Reconstructs a message split with `encode --split` from the shares in `paths`
+pub fn join(
+    chunk_type: &str,
+    paths: &[PathBuf],
+    location: &LocationArgs,
+    decryption: &DecryptionArgs,
+    output: &OutputArgs,
+) -> Result<()> {
+    quiet_if_raw(output);
+    let secret = decryption_secret(decryption)?;
+    let chunk_type = &stored_chunk_type(chunk_type, secret.as_ref(), decryption.obfuscate_chunk)?;
+
+    let mut shares = Vec::with_capacity(paths.len());
+    for path in paths {
+        let data =
+            read_cover_payload(path, chunk_type, location, secret.as_ref())?.ok_or_else(|| {
+                Failure::ChunkNotFound.error(format!(
+                    " Chunk type '{}' not found in '{}'",
+                    chunk_type,
+                    path.display()
+                ))
+            })?;
+
+        match payload::unwrap(&data)? {
+            (header, share) if header.kind == Kind::Share => shares.push(share.to_vec()),
+            _ => {
+                return Err(format!(
+                    "Chunk '{}' in '{}' is not a share of a split message",
+                    chunk_type,
+                    path.display()
+                )
+                .into());
+            }
+        }
     }
+
+    let shares: Vec<&[u8]> = shares.iter().map(Vec::as_slice).collect();
+    let data = crypto::combine_shares(&shares)
+        .map_err(|e| format!("Failed to join chunk '{}': {}", chunk_type, e))?;
+    say!("🧩  Joined {} shares", shares.len());
+
+    reveal(
+        &display_paths(paths),
+        chunk_type,
+        data.expose(),
+        secret,
+        decryption,
+        output,
+    )
+}
+
+/// `paths` as a comma-separated list, to name the images a message was recovered from
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reassembles a message sharded with `encode --shard` from all of `paths`, in any order
+fn unshard(
+    chunk_type: &str,
+    paths: &[PathBuf],
+    location: &LocationArgs,
+    decryption: &DecryptionArgs,
+    output: &OutputArgs,
+) -> Result<()> {
+    let secret = decryption_secret(decryption)?;
+    let chunk_type = &stored_chunk_type(chunk_type, secret.as_ref(), decryption.obfuscate_chunk)?;
+
+    let mut shards = Vec::with_capacity(paths.len());
+    for path in paths {
+        let data =
+            read_cover_payload(path, chunk_type, location, secret.as_ref())?.ok_or_else(|| {
+                Failure::ChunkNotFound.error(format!(
+                    " Chunk type '{}' not found in '{}'",
+                    chunk_type,
+                    path.display()
+                ))
+            })?;
+
+        match payload::unwrap(&data)? {
+            (header, shard) if header.kind == Kind::Shard => shards.push(shard.to_vec()),
+            _ => {
+                return Err(format!(
+                    "Chunk '{}' in '{}' is not a shard of a sharded message",
+                    chunk_type,
+                    path.display()
+                )
+                .into());
+            }
+        }
+    }
+
+    let shards: Vec<&[u8]> = shards.iter().map(Vec::as_slice).collect();
+    let data = payload::reassemble(&shards)
+        .map_err(|e| format!("Failed to reassemble chunk '{}': {}", chunk_type, e))?;
+    say!("🧩  Reassembled {} shards", shards.len());
+
+    reveal(
+        &display_paths(paths),
+        chunk_type,
+        &data,
+        secret,
+        decryption,
+        output,
+    )
+}
+
+/// [`read_payload`] for the file at `path`, which may also be one of the formats in
+/// [`container`]
+fn read_cover_payload(
+    path: &Path,
+    chunk_type: &str,
+    location: &LocationArgs,
+    secret: Option<&Secret>,
+) -> Result<Option<Vec<u8>>> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+    if let Some(container) = container::parse(&buffer)? {
+        return container_payload(container.as_ref(), chunk_type, location, secret);
+    }
+    let png = parse_png(&buffer).map_err(|e| format!("{} ('{}')", e, path.display()))?;
+    read_payload(&png, chunk_type, location, secret)
+}
+
+/// Parses `data` as a PNG, naming the format instead if it is another supported one
+fn parse_png(data: &[u8]) -> Result<Png> {
+    require_png(data)?;
+    Png::try_from(data).map_err(|e| Failure::Parse.error(format!("Failed to parse PNG: {}", e)))
+}
+
+fn require_png(data: &[u8]) -> Result<()> {
+    match container::detect(data)? {
+        Format::Png => Ok(()),
+        format => Err(Failure::Parse.error(format!(
+            "This command needs a PNG file, not a {} file",
+            format.name()
+        ))),
+    }
+}
+
+/// The payload labelled `chunk_type` in a non-PNG file
+fn container_payload(
+    container: &dyn Container,
+    chunk_type: &str,
+    location: &LocationArgs,
+    secret: Option<&Secret>,
+) -> Result<Option<Vec<u8>>> {
+    check_container_options(
+        container,
+        location.idat,
+        location.standard_text,
+        location.xmp,
+    )?;
+    if location.lsb {
+        let names = container.sample_names();
+        return read_pixel_payload(&container.pixels()?, names, chunk_type, secret);
+    }
+    collect_payload(&container.payloads(chunk_type), chunk_type)
+}
+
+/// Rejects the locations that only exist in PNG files
+fn check_container_options(
+    container: &dyn Container,
+    idat: bool,
+    standard_text: bool,
+    xmp: bool,
+) -> Result<()> {
+    let option = if idat {
+        "--idat"
+    } else if standard_text {
+        "--standard-text"
+    } else if xmp {
+        "--xmp"
+    } else {
+        return Ok(());
+    };
+    Err(format!(
+        "{} needs a PNG file; {} files hold messages in {}s",
+        option,
+        container.format(),
+        container.block_kind()
+    )
+    .into())
+}
+
+/// The payload held by `blocks`, the data of every chunk (or block) with the same
+/// label: the first one, or with --max-chunk-size the message its fragments make up
+fn collect_payload(blocks: &[&[u8]], chunk_type: &str) -> Result<Option<Vec<u8>>> {
+    let Some(first) = blocks.first() else {
+        return Ok(None);
+    };
+    if !is_fragment(first) {
+        return Ok(Some(first.to_vec()));
+    }
+    let fragments = blocks
+        .iter()
+        .map(|data| match payload::unwrap(data)? {
+            (header, body) if header.kind == Kind::Fragment => Ok(body),
+            _ => Err(format!("Chunk '{}' mixes fragments with other data", chunk_type).into()),
+        })
+        .collect::<Result<Vec<&[u8]>>>()?;
+    say!("🧩  Reassembling {} fragments", fragments.len());
+    Ok(Some(payload::reassemble(&fragments)?))
+}
+
+fn is_fragment(data: &[u8]) -> bool {
+    matches!(payload::unwrap(data), Ok((header, _)) if header.kind == Kind::Fragment)
+}
+
+/// The chunks `read_payload` needs to find the message `chunk_type` at `location`, read
+/// a chunk at a time from `reader` so that other chunks of huge images are skipped
+fn read_png_for_payload(
+    handler: &AtomicFileHandler,
+    reader: impl Read + Seek,
+    chunk_type: &str,
+    location: &LocationArgs,
+) -> Result<Png> {
+    let wanted = |found: &str| {
+        if location.lsb || location.idat {
+            ["IHDR", "PLTE", "IDAT"].contains(&found)
+        } else if location.standard_text || location.xmp {
+            text::TEXT_CHUNKS.contains(&found)
+        } else {
+            found == chunk_type
+        }
+    };
+    match Png::read_filtered(reader, wanted) {
+        Ok(png) => Ok(png),
+        // Damaged chunks may still hold a payload that --fec parity can repair
+        Err(e) => {
+            let png = Png::from_bytes_ignoring_crc(&handler.read_file()?)
+                .map_err(|_| format!("Failed to parse PNG: {}", e))?;
+            say!("⚠️  The file is damaged ({}); reading it anyway", e);
+            Ok(png)
+        }
+    }
+}
+
+/// Returns the payload stored under `chunk_type`: read from that chunk, a text chunk
+/// with that keyword, the pixels or the image data stream, depending on `location`
+fn read_payload(
+    png: &Png,
+    chunk_type: &str,
+    location: &LocationArgs,
+    secret: Option<&Secret>,
+) -> Result<Option<Vec<u8>>> {
+    if location.standard_text {
+        return Ok(text::find_text(png, chunk_type)?.map(|text| text_payload(&text)));
+    }
+    if location.xmp {
+        return Ok(xmp::extract(png, chunk_type)?.map(|text| text_payload(&text)));
+    }
+    if location.idat {
+        let found = idat::extract(png, chunk_type)?;
+        if found.is_some() {
+            say!("🗜️  Reading the message from padding in the compressed image data");
+        }
+        return Ok(found);
+    }
+    if !location.lsb {
+        let Some(chunk) = png.chunk_by_type(chunk_type) else {
+            return Ok(None);
+        };
+        // Only some chunks may have been read, so their offsets are not known here
+        for chunk in png.chunks_by_type(chunk_type) {
+            detail!(
+                "    Chunk '{}': {} bytes of data",
+                chunk_type,
+                chunk.data().len()
+            );
+        }
+        // Standard text chunks requested by type (e.g. zTXt from another program) yield their text
+        if let Some((_, text)) = text::read_text_chunk(chunk)? {
+            return Ok(Some(text_payload(&text)));
+        }
+        let blocks: Vec<&[u8]> = png.chunks_by_type(chunk_type).map(Chunk::data).collect();
+        return collect_payload(&blocks, chunk_type);
+    }
+    read_pixel_payload(&Pixels::from_png(png)?, PIXEL_NAMES, chunk_type, secret)
+}
+
+/// The `--lsb` payload hidden in `pixels`, which are called `names` in messages
+fn read_pixel_payload(
+    pixels: &Pixels,
+    (samples, channel): (&str, &str),
+    chunk_type: &str,
+    secret: Option<&Secret>,
+) -> Result<Option<Vec<u8>>> {
+    let mut found = None;
+    if let Some(secret) = secret {
+        let order = lsb::sample_order(pixels, chunk_type, Some(secret.expose()))?;
+        found = lsb::extract(pixels, &order, chunk_type)?;
+    }
+    // Messages written without a password, or before scattering existed, are in scanline order
+    if found.is_none() {
+        found = lsb::extract(pixels, &pixels.sample_offsets()?, chunk_type)?;
+    }
+    let Some((bits, data)) = found else {
+        return Ok(None);
+    };
+    say!(
+        "🎨  Reading {} bit(s) per {} from the {}",
+        bits,
+        channel,
+        samples
+    );
+    Ok(Some(data))
+}
+
+/// Text written by `--standard-text`: the message itself when it is plain with no header
+/// fields to keep, so other tools show it as is, and base64 of the whole payload otherwise
+fn payload_text(data: &[u8]) -> Result<String> {
+    let (header, body) = payload::unwrap(data)?;
+    if header.kind == Kind::Plain
+        && header.expires.is_none()
+        && header.manifest.is_none()
+        && header.compression.is_none()
+    {
+        return Ok(String::from_utf8(body.to_vec())?);
+    }
+    Ok(encoding::base64_encode(data, true))
+}
+
+/// Turns the text of a `--standard-text` chunk back into a payload
+fn text_payload(text: &str) -> Vec<u8> {
+    match encoding::base64_decode(text) {
+        Ok(data) if data.starts_with(payload::MAGIC) => data,
+        _ => payload::wrap(&Header::new(Kind::Plain), text.as_bytes()),
+    }
+}
+
+/// Returns the symmetric secret given to `decode` or `join`, if any
+fn decryption_secret(decryption: &DecryptionArgs) -> Result<Option<Secret>> {
+    let password = resolve_password(&decryption.password, decryption.ask_pass, false)?;
+    read_secret(
+        password,
+        decryption.key_file.as_deref(),
+        decryption.use_keyring.as_deref(),
+    )
+}
+
+/// Returns the chunk type a message is stored under: `label` itself, or with
+/// `--obfuscate-chunk` a name derived from the secret and `label`
+fn stored_chunk_type(label: &str, secret: Option<&Secret>, obfuscate: bool) -> Result<String> {
+    if !obfuscate {
+        return Ok(label.to_string());
+    }
+    let secret = secret.ok_or("--obfuscate-chunk needs --password, --key-file or --use-keyring")?;
+    crypto::derive_chunk_type(secret.expose(), label)
+}
+
+/// With `--raw` the message is the only output, so everything else is left out
+fn quiet_if_raw(output: &OutputArgs) {
+    if output.raw {
+        output::set_verbosity(Verbosity::Quiet);
+    }
+}
+
+/// Decrypts (if needed) and prints the message stored in a chunk's data, with `out`
+/// writes it to that file, or with `raw` writes its bytes alone to stdout
+fn reveal(
+    source: &str,
+    chunk_type: &str,
+    chunk_data: &[u8],
+    secret: Option<Secret>,
+    decryption: &DecryptionArgs,
+    output: &OutputArgs,
+) -> Result<()> {
+    let identity = decryption.identity.as_deref();
+    let unreadable =
+        |e: Error| Failure::Parse.error(format!("Cannot decode chunk '{}': {}", chunk_type, e));
+    let (header, body) = payload::unwrap(chunk_data).map_err(unreadable)?;
+    let kind = header.kind;
+
+    let data = match kind {
+        Kind::Plain => Secret::from(body.to_vec()),
+        Kind::Password => {
+            let secret = secret.as_ref().ok_or_else(|| {
+                Failure::Crypto.error(format!(
+                    "Chunk '{}' is password-encrypted, use --password, --key-file or --use-keyring to decrypt it",
+                    chunk_type
+                ))
+            })?;
+            crypto::decrypt(secret.expose(), body).map_err(|e| {
+                Failure::Crypto.error(format!("Failed to decrypt chunk '{}': {}", chunk_type, e))
+            })?
+        }
+        Kind::Age => {
+            let identity = identity.ok_or_else(|| {
+                Failure::Crypto.error(format!(
+                    "Chunk '{}' is age-encrypted, use --identity (or --privkey) to decrypt it",
+                    chunk_type
+                ))
+            })?;
+            crypto::decrypt_with_identity_file(identity, body).map_err(|e| {
+                Failure::Crypto.error(format!("Failed to decrypt chunk '{}': {}", chunk_type, e))
+            })?
+        }
+        Kind::OpenPgp => {
+            say!("🔏  OpenPGP payload detected, decrypting with gpg...");
+            crypto::gpg_decrypt(body).map_err(|e| {
+                Failure::Crypto.error(format!("Failed to decrypt chunk '{}': {}", chunk_type, e))
+            })?
+        }
+        Kind::Share => {
+            let (k, n, index) = crypto::share_info(body)?;
+            return Err(format!(
+                "Chunk '{}' holds share {} of a {}/{} split, use `join` with at least {} images to recover it",
+                chunk_type, index, k, n, k
+            )
+            .into());
+        }
+        Kind::Shard => {
+            let (index, count) = payload::piece_position(body)?;
+            return Err(format!(
+                "Chunk '{}' holds shard {} of {}, use `decode --shard` with all {} images to recover it",
+                chunk_type,
+                index + 1,
+                count,
+                count
+            )
+            .into());
+        }
+        Kind::Fec => {
+            let (inner, corrected) = fec::decode(body)
+                .map_err(|e| format!("Failed to repair chunk '{}': {}", chunk_type, e))?;
+            if corrected > 0 {
+                say!(
+                    "🩹  Repaired {} damaged byte(s) with Reed-Solomon parity",
+                    corrected
+                );
+            }
+            return reveal(source, chunk_type, &inner, secret, decryption, output);
+        }
+        Kind::Fragment => {
+            return Err(format!(
+                "Chunk '{}' holds a lone fragment of a larger message",
+                chunk_type
+            )
+            .into());
+        }
+        Kind::Hardware => {
+            let provider = crypto::hardware_provider(body)?;
+            say!(
+                "🔑  Hardware-wrapped payload detected, touch {} if it blinks...",
+                provider.name()
+            );
+            crypto::decrypt_with_provider(provider.as_ref(), body).map_err(|e| {
+                Failure::Crypto.error(format!("Failed to decrypt chunk '{}': {}", chunk_type, e))
+            })?
+        }
+    };
+
+    // Verify the integrity tag rather than printing a possibly corrupted message
+    let data = if crypto::has_hmac(data.expose()) {
+        let secret = secret.as_ref().ok_or_else(|| {
+            Failure::Crypto.error(format!(
+                "Chunk '{}' carries an HMAC integrity tag, use --password, --key-file or --use-keyring to verify it",
+                chunk_type
+            ))
+        })?;
+        let message = crypto::verify_hmac(secret.expose(), data.expose()).map_err(|e| {
+            Failure::Crypto.error(format!(
+                "Integrity check failed for chunk '{}': {}",
+                chunk_type, e
+            ))
+        })?;
+        say!("🛡️  Integrity tag verified");
+        message
+    } else {
+        data
+    };
+
+    // Encrypted messages carry the header fields inside the ciphertext
+    let (header, data) = if kind.is_encrypted() {
+        let (fields, message) = payload::open_fields(data.expose()).map_err(unreadable)?;
+        (fields, Secret::from(message.to_vec()))
+    } else {
+        (header, data)
+    };
+    if let Some(expires) = header.expires.filter(|_| header.is_expired(date::now())) {
+        let expired = format!(
+            "The message in chunk '{}' expired on {}",
+            chunk_type,
+            date::format_timestamp(expires)
+        );
+        if !decryption.allow_expired {
+            return Err(format!("{}; pass --allow-expired to read it anyway", expired).into());
+        }
+        say!("⚠️  {}", expired);
+    }
+    let data = match header.compression {
+        Some(compression) => Secret::from(
+            compression
+                .decompress(data.expose())
+                .map_err(|e| format!("Failed to decompress chunk '{}': {}", chunk_type, e))?,
+        ),
+        None => data,
+    };
+    let data = match output.output_encoding {
+        Encoding::Raw => data,
+        encoding => Secret::from(encoding.encode(data.expose())),
+    };
+
+    if output.raw {
+        let mut stdout = io::stdout().lock();
+        return match stdout
+            .write_all(data.expose())
+            .and_then(|()| stdout.flush())
+        {
+            // The reader, such as `head`, may stop early
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                Err(Failure::from(&e)
+                    .error(format!("Failed to write the message to stdout: {}", e)))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    // A hidden file is restored under its original name unless --out says otherwise
+    let restored = match (output.out.as_deref(), &header.manifest) {
+        (Some(out), _) => Some((out.to_path_buf(), false)),
+        (None, Some(manifest)) => Some((restore_path(manifest)?, true)),
+        (None, None) => None,
+    };
+    if let Some((out, restoring)) = restored {
+        let out = out.as_path();
+        save_message(out, data.expose(), restoring)?;
+        say!("💾  Hidden message saved:");
+        say!("    File: {}", source);
+        say!("    Chunk: {}", chunk_type);
+        if let Some(manifest) = &header.manifest {
+            say!("    Contents: {}", describe_manifest(manifest));
+            if manifest.size != data.expose().len() as u64 {
+                say!(
+                    "⚠️  The manifest records {} bytes but {} were recovered",
+                    manifest.size,
+                    data.expose().len()
+                );
+            }
+        }
+        say!(
+            "    Written: {} ({} bytes)",
+            out.display(),
+            data.expose().len()
+        );
+        if let Some(expires) = header.expires {
+            say!("    Expires: {}", date::format_timestamp(expires));
+        }
+        output::push(
+            "found",
+            json!({
+                "file": source,
+                "chunk": chunk_type,
+                "written": out.to_string_lossy(),
+                "size": data.expose().len(),
+                "expires": header.expires,
+            }),
+        );
+        return Ok(());
+    }
+
+    match std::str::from_utf8(data.expose()) {
+        Ok(message) => {
+            say!("🔓  Hidden message found:");
+            say!("    File: {}", source);
+            say!("    Chunk: {}", chunk_type);
+            output::labelled("    Message: ", message);
+            if let Some(expires) = header.expires {
+                say!("    Expires: {}", date::format_timestamp(expires));
+            }
+            output::push(
+                "found",
+                json!({
+                    "file": source,
+                    "chunk": chunk_type,
+                    "message": message,
+                    "expires": header.expires,
+                }),
+            );
+            Ok(())
+        }
+        Err(_) => {
+            output::push(
+                "found",
+                json!({
+                    "file": source,
+                    "chunk": chunk_type,
+                    "binary": true,
+                    "size": data.expose().len(),
+                }),
+            );
+            say!(
+                " 💡  Tip: Use --out FILE to save binary data, such as a file hidden with --file, or --output-encoding hex|base64 to show it"
+            );
+            if kind == Kind::Plain && secret.is_none() && identity.is_none() {
+                say!(
+                    " 💡  Tip: This chunk may be encrypted (use --password or --key-file) or contain non-text data"
+                );
+            } else {
+                say!(" 💡  Tip: This chunk may be a critical PNG chunk or contain non-text data");
+            }
+            Err(Failure::Parse.error(format!(
+                "Cannot decode message from chunk '{}': This chunk contains binary data, not text",
+                chunk_type
+            )))
+        }
+    }
+}
+
+/// Where `decode` restores a hidden file when no --out is given: its recorded name,
+/// stripped of any directories, in the current directory. [`save_message`] refuses to
+/// replace a file already there.
+fn restore_path(manifest: &Manifest) -> Result<PathBuf> {
+    let name = std::path::Path::new(&manifest.name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| {
+            format!(
+                "The hidden file's recorded name '{}' is not usable, use --out FILE to save it",
+                manifest.name
+            )
+        })?;
+    Ok(PathBuf::from(name))
+}
+
+/// Writes a recovered message to `out`. A file restored under its recorded name is only
+/// created if nothing is there, checked in the same step, so it never replaces a file.
+fn save_message(out: &Path, data: &[u8], restoring: bool) -> Result<()> {
+    let mut options = OpenOptions::new();
+    if restoring {
+        options.write(true).create_new(true);
+    } else {
+        options.write(true).create(true).truncate(true);
+    }
+    options
+        .open(out)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| {
+            Failure::from(&e).error(match e.kind() {
+                io::ErrorKind::AlreadyExists => format!(
+                    "'{}' already exists, use --out FILE to save the hidden file elsewhere",
+                    out.display()
+                ),
+                _ => format!("Failed to write '{}': {}", out.display(), e),
+            })
+        })
+}
+
+pub fn encode(
+    path: &Path,
+    chunk_type: &str,
+    message: &MessageArgs,
+    embedding: &EmbeddingArgs,
+    encryption: &EncryptionArgs,
+    expires: Option<&str>,
+    output: Option<&Path>,
+) -> Result<()> {
+    // With --obfuscate-chunk the given chunk type is only a label, validated once derived
+    if embedding.standard_text {
+        text::validate_keyword(chunk_type)?;
+    } else if embedding.xmp {
+        xmp::validate_label(chunk_type)?;
+    } else if !encryption.obfuscate_chunk {
+        validate_chunk_type(chunk_type)?;
+    }
+
+    let password = resolve_password(&encryption.password, encryption.ask_pass, true)?;
+    let secret = read_secret(
+        password,
+        encryption.key_file.as_deref(),
+        encryption.use_keyring.as_deref(),
+    )?;
+
+    let chunk_type = &stored_chunk_type(chunk_type, secret.as_ref(), encryption.obfuscate_chunk)?;
+    if encryption.obfuscate_chunk {
+        say!(
+            "🕵️  Storing the message in chunk '{}', derived from the password",
+            chunk_type
+        );
+    }
+
+    // Parse the --split threshold before encrypting anything
+    let threshold = embedding
+        .split
+        .as_deref()
+        .map(|spec| parse_split(spec, 1 + embedding.more_paths.len()))
+        .transpose()?;
+    if !embedding.standard_text && !embedding.xmp {
+        parse_chunk_type(chunk_type)?;
+    }
+
+    let mut data = build_payload(message, encryption, secret.as_ref(), expires)?;
+    if let Some(ratio) = embedding.fec {
+        let protected = fec::encode(&data, ratio)?;
+        say!(
+            "🛟  Added Reed-Solomon parity: {} bytes protect a {}-byte payload",
+            protected.len() - data.len(),
+            data.len()
+        );
+        data = payload::wrap(&Header::new(Kind::Fec), &protected);
+    }
+
+    if embedding.glob || (!path.exists() && glob::is_pattern(&path.to_string_lossy())) {
+        if output.is_some() {
+            return Err("--output takes a single image, not a pattern".into());
+        }
+        return embed_all(path, chunk_type, &data, embedding, secret.as_ref());
+    }
+    if threshold.is_none() && !embedding.shard {
+        return embed(path, chunk_type, data, embedding, secret.as_ref(), output);
+    }
+
+    let paths: Vec<&Path> = std::iter::once(path)
+        .chain(embedding.more_paths.iter().map(PathBuf::as_path))
+        .collect();
+    // Check every cover image before modifying any of them
+    for path in &paths {
+        let handler = AtomicFileHandler::new(path)?;
+        let buffer = handler.read_file()?;
+        if let Some(container) = container::parse(&buffer)? {
+            check_container_options(
+                container.as_ref(),
+                embedding.idat,
+                embedding.standard_text,
+                embedding.xmp,
+            )?;
+            if embedding.lsb {
+                let pixels = container.pixels()?;
+                let order =
+                    lsb::sample_order(&pixels, chunk_type, secret.as_ref().map(Secret::expose))?;
+                check_pixels_unused(&pixels, &order, path)?;
+            } else {
+                check_container_unused(container.as_ref(), chunk_type, path)?;
+            }
+            continue;
+        }
+        let png = parse_png(&buffer).map_err(|e| format!("{} ('{}')", e, path.display()))?;
+        if embedding.lsb {
+            let pixels = Pixels::from_png(&png)?;
+            let order =
+                lsb::sample_order(&pixels, chunk_type, secret.as_ref().map(Secret::expose))?;
+            check_pixels_unused(&pixels, &order, path)?;
+        } else if embedding.idat {
+            check_idat_unused(&png, path)?;
+        } else {
+            check_chunk_unused(&png, chunk_type, embedding, path)?;
+        }
+    }
+
+    let pieces = if let Some((k, n)) = threshold {
+        say!(
+            "🧩  Splitting message into {} shares, any {} of which recover it",
+            n,
+            k
+        );
+        crypto::split_secret(&data, k, n)?
+            .iter()
+            .map(|share| payload::wrap(&Header::new(Kind::Share), share))
+            .collect()
+    } else {
+        say!(
+            "🧩  Sharding message over {} images, all of which are needed to recover it",
+            paths.len()
+        );
+        payload::shard(&data, paths.len())?
+    };
+    // The backup each image got, to put back the ones changed so far when a later one
+    // fails, so that no partial set of pieces is left behind
+    let mut changed: Vec<(&Path, Backup)> = vec![];
+    for (path, piece) in paths.iter().zip(pieces) {
+        if let Err(e) = embed(path, chunk_type, piece, embedding, secret.as_ref(), None) {
+            restore_changed(&changed);
+            return Err(e);
+        }
+        if !atomic_file::is_dry_run()
+            && let Some(backup) = AtomicFileHandler::new(path)?.backups().into_iter().next()
+        {
+            changed.push((path, backup));
+        }
+    }
+    Ok(())
+}
+
+/// Puts each of the images in `changed` back from its backup, newest change first;
+/// failures are only reported, as the error that led here is the one to return
+fn restore_changed(changed: &[(&Path, Backup)]) {
+    for (path, backup) in changed.iter().rev() {
+        let restored = FileLock::acquire(path, false)
+            .and_then(|_lock| atomic_file::restore_backup(&backup.path, path));
+        match restored {
+            Ok(()) => say!(
+                "↩️  Restored '{}' from '{}'",
+                path.display(),
+                backup.path.display()
+            ),
+            Err(e) => say!(
+                "⚠️  Could not restore '{}' from '{}': {}",
+                path.display(),
+                backup.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Replaces the message in the chunks of type `chunk_type` with a new one built like
+/// `encode` does, in one rewrite with one backup. The new chunk takes the place of the
+/// first old one; fragments of the old message go.
+pub fn update(
+    path: &Path,
+    chunk_type: &str,
+    message: &MessageArgs,
+    encryption: &EncryptionArgs,
+    expires: Option<&str>,
+) -> Result<()> {
+    if !encryption.obfuscate_chunk {
+        validate_chunk_type(chunk_type)?;
+    }
+    let password = resolve_password(&encryption.password, encryption.ask_pass, true)?;
+    let secret = read_secret(
+        password,
+        encryption.key_file.as_deref(),
+        encryption.use_keyring.as_deref(),
+    )?;
+    let chunk_type: &str =
+        &stored_chunk_type(chunk_type, secret.as_ref(), encryption.obfuscate_chunk)?;
+    let chunk_type_obj = parse_chunk_type(chunk_type)?;
+
+    let handler = AtomicFileHandler::new(path)?;
+    // Check the chunk exists before building the payload and creating a backup
+    let buffer = handler.read_file()?;
+    if let Some(container) = container::parse(&buffer)? {
+        return Err(format!(
+            "update works with PNG files; use remove and encode for {} files",
+            container.format()
+        )
+        .into());
+    }
+    if parse_png(&buffer)?.chunk_by_type(chunk_type).is_none() {
+        return Err(Failure::ChunkNotFound.error(format!(
+            " Chunk type '{}' not found in '{}'\n💡  Tip: Use encode to add a new message",
+            chunk_type,
+            path.display()
+        )));
+    }
+
+    let data = build_payload(message, encryption, secret.as_ref(), expires)?;
+    say!(
+        "🔁  Updating the message in '{}'...",
+        handler.target_path().display()
+    );
+    handler.atomic_modify(|content| {
+        let mut png = parse_png(&content)?;
+        let position = png
+            .chunks()
+            .iter()
+            .position(|chunk| chunk.chunk_type().to_string() == chunk_type)
+            .ok_or_else(|| {
+                Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", chunk_type))
+            })?;
+        let old: usize = png.chunks_by_type(chunk_type).map(|c| c.data().len()).sum();
+        png.chunks_mut()
+            .retain(|chunk| chunk.chunk_type().to_string() != chunk_type);
+        let new = data.len();
+        png.insert_chunk_at(position, Chunk::new(chunk_type_obj, data))?;
+        say!(
+            " ✅ Message in chunk '{}' updated ({} -> {} bytes)",
+            chunk_type,
+            old,
+            new
+        );
+        Ok(png.as_bytes())
+    })
+}
+
+/// The payload `encode` hides: the message of `message`, compressed and encrypted as
+/// the options say, with header fields recording `expires` (YYYY-MM-DD) that are
+/// encrypted along with the message when it is encrypted
+fn build_payload(
+    message: &MessageArgs,
+    encryption: &EncryptionArgs,
+    secret: Option<&Secret>,
+    expires: Option<&str>,
+) -> Result<Vec<u8>> {
+    // Collect recipients from public key files before touching the image
+    let mut recipients = encryption.recipients.clone();
+    for pubkey in &encryption.pubkeys {
+        recipients.extend(crypto::read_public_key_file(pubkey)?);
+    }
+
+    let provider = encryption
+        .yubikey
+        .map(|slot| crypto::YubiKey::new(slot.unwrap_or(2)))
+        .transpose()?;
+
+    let expires = expires.map(date::parse_end_of_day).transpose()?;
+    if let Some(expires) = expires.filter(|&expires| expires < date::now()) {
+        return Err(Failure::Validation.error(format!(
+            "The expiry date {} is already in the past",
+            date::format_timestamp(expires)
+        )));
+    }
+
+    let (data, manifest) = read_message(message)?;
+    let requested_compression = message.compress;
+    let (message, compression) = match message.compress {
+        Some(compression) => compress(data, compression)?,
+        None => (data, None),
+    };
+    let header = Header {
+        expires,
+        manifest,
+        compression,
+        ..Header::new(Kind::Plain)
+    };
+    let encrypted = secret.is_some()
+        || provider.is_some()
+        || !recipients.is_empty()
+        || !encryption.gpg_recipients.is_empty()
+        || encryption.gpg_sign.is_some();
+    if !encrypted {
+        return Ok(payload::wrap(&header, &message));
+    }
+    // The header fields are encrypted along with the message
+    let message = payload::seal_fields(&header, &message);
+
+    // Encrypt (or sign) the message with whichever encryption option is given
+    let (kind, body) = if let Some(secret) = secret {
+        let message = if encryption.hmac {
+            Secret::from(crypto::append_hmac(secret.expose(), &message)?)
+        } else {
+            Secret::from(message)
+        };
+        let body = match (&encryption.decoy, &encryption.decoy_password) {
+            (Some(decoy), Some(decoy_password)) => {
+                let decoy_password = match decoy_password {
+                    Some(password) => Secret::from(password.clone()),
+                    None => prompt::read_password("Decoy password: ", true)?,
+                };
+                // The decoy has header fields of its own, so the decoy password reveals
+                // nothing of the real message; encrypt_dual pads both to the same size
+                let mut decoy_header = Header::new(Kind::Plain);
+                let mut decoy = decoy.as_bytes().to_vec();
+                if let Some(compression) = requested_compression {
+                    let compressed = compression.compress(&decoy)?;
+                    if compressed.len() < decoy.len() {
+                        decoy = compressed;
+                        decoy_header.compression = Some(compression);
+                    }
+                }
+                let decoy = payload::seal_fields(&decoy_header, &decoy);
+                let decoy = if encryption.hmac {
+                    Secret::from(crypto::append_hmac(decoy_password.expose(), &decoy)?)
+                } else {
+                    Secret::from(decoy)
+                };
+                crypto::encrypt_dual(
+                    secret.expose(),
+                    message.expose(),
+                    decoy_password.expose(),
+                    decoy.expose(),
+                    encryption.cipher,
+                )?
+            }
+            _ => crypto::encrypt(secret.expose(), message.expose(), encryption.cipher)?,
+        };
+        (Kind::Password, body)
+    } else if let Some(provider) = &provider {
+        say!("🔑  Touch {} if it blinks...", provider.name());
+        let body = crypto::encrypt_with_provider(provider, &message, encryption.cipher)?;
+        (Kind::Hardware, body)
+    } else if !recipients.is_empty() {
+        let body = crypto::encrypt_to_recipients(&recipients, &message)?;
+        (Kind::Age, body)
+    } else {
+        let signer = encryption.gpg_sign.as_ref().map(Option::as_deref);
+        let body = crypto::gpg_encrypt(&encryption.gpg_recipients, signer, &message)?;
+        (Kind::OpenPgp, body)
+    };
+    Ok(payload::wrap(&Header::new(kind), &body))
+}
+
+/// Compresses `message`, or leaves it as is (returning no algorithm) when that would
+/// not make it smaller
+fn compress(message: Vec<u8>, compression: Compression) -> Result<(Vec<u8>, Option<Compression>)> {
+    if message.len() > payload::MAX_DECOMPRESSED_LEN {
+        say!("🗜️  The message is too large to decompress safely, hiding it uncompressed");
+        return Ok((message, None));
+    }
+    let compressed = compression.compress(&message)?;
+    if compressed.len() >= message.len() {
+        say!(
+            "🗜️  {} would not shrink the message, hiding it uncompressed",
+            compression.name()
+        );
+        return Ok((message, None));
+    }
+    say!(
+        "🗜️  Compressed the message with {}: {} -> {} bytes",
+        compression.name(),
+        message.len(),
+        compressed.len()
+    );
+    Ok((compressed, Some(compression)))
+}
+
+/// The message given on the command line, or the contents of `--file` along with
+/// a manifest describing the file, decoded from `--input-encoding`
+fn read_message(message: &MessageArgs) -> Result<(Vec<u8>, Option<Manifest>)> {
+    let encoding = message.input_encoding;
+    let decode = |data: &[u8]| {
+        encoding
+            .decode(data)
+            .map_err(|e| format!("Failed to decode the {} message: {}", encoding.name(), e))
+    };
+    match (&message.message, &message.file) {
+        (_, Some(file)) => {
+            let data = std::fs::read(file).map_err(|e| {
+                Failure::from(&e).error(format!("Failed to read '{}': {}", file.display(), e))
+            })?;
+            let data = decode(&data)?;
+            let name = file
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            // Not every file system records creation times
+            let created = std::fs::metadata(file)
+                .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()))
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|age| age.as_secs());
+            let manifest = Manifest {
+                mime_type: payload::guess_mime_type(&name).to_string(),
+                name,
+                size: data.len() as u64,
+                created,
+            };
+            say!(
+                "📄  Hiding '{}' ({}, {} bytes)",
+                file.display(),
+                manifest.mime_type,
+                data.len()
+            );
+            Ok((data, Some(manifest)))
+        }
+        (Some(message), None) if message == "-" => Ok((decode(&read_text("-".as_ref())?)?, None)),
+        (Some(message), None) => Ok((decode(message.as_bytes())?, None)),
+        (None, None) => match &message.message_file {
+            Some(path) => Ok((decode(&read_text(path)?)?, None)),
+            None => Err("No message given".into()),
+        },
+    }
+}
+
+/// The message text in the file at `path`, or on stdin when it is `-`, exactly as it is
+fn read_text(path: &Path) -> Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut data = vec![];
+        io::stdin().lock().read_to_end(&mut data).map_err(|e| {
+            Failure::from(&e).error(format!("Failed to read the message from stdin: {}", e))
+        })?;
+        return Ok(data);
+    }
+    fs::read(path)
+        .map_err(|e| Failure::from(&e).error(format!("Failed to read '{}': {}", path.display(), e)))
+}
+
+/// Rejects critical chunk names and names with the reserved bit set
+fn validate_chunk_type(chunk_type: &str) -> Result<()> {
+    // Check for critical PNG chunks
+    if ["IHDR", "PLTE", "IDAT", "IEND"].contains(&chunk_type) {
+        return Err(Failure::Validation.error(format!(
+            " ❌  Cannot use critical PNG chunk name '{}'. Please use a different chunk name.\n 💡 Tip: Make sure the 3rd character is uppercase (e.g., 'abCd', 'boOp', 'vaRu')",
+            chunk_type
+        )));
+    }
+
+    // Animation chunks of APNG files are ancillary but must not be reused
+    if ANIMATION_CHUNKS.contains(&chunk_type) {
+        return Err(Failure::Validation.error(format!(
+            " ❌  Cannot use APNG animation chunk name '{}'. Please use a different chunk name.",
+            chunk_type
+        )));
+    }
+
+    // Chunk types are ASCII letters, so a 4-byte name may still be fewer characters
+    if !chunk_type.is_ascii() {
+        return Err(Failure::Validation.error(format!(
+            " ❌ Invalid chunk type '{}'. Chunk types are four ASCII letters.",
+            chunk_type
+        )));
+    }
+
+    // Validate chunk type format (3rd character must be uppercase)
+    if chunk_type.len() == 4 {
+        let chars: Vec<char> = chunk_type.chars().collect();
+        if !chars[2].is_uppercase() {
+            return Err(Failure::Validation.error(format!(
+                " ❌ Invalid chunk type '{}'. The 3rd character must be uppercase.\n💡  Example: '{}{}{}{}' should be '{}{}{}{}' ",
+                chunk_type,
+                chars[0], chars[1], chars[2], chars[3],
+                chars[0], chars[1], chars[2].to_uppercase().next().unwrap(), chars[3]
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a chunk type given on the command line
+fn parse_chunk_type(chunk_type: &str) -> Result<ChunkType> {
+    ChunkType::from_str(chunk_type)
+        .map_err(|e| Failure::Validation.error(format!("Invalid chunk type: {}", e)))
+}
+
+/// Parses a `--split K/N` threshold, checking N against the number of cover images
+fn parse_split(spec: &str, images: usize) -> Result<(u8, u8)> {
+    let invalid = || {
+        Failure::Validation.error(format!(
+            "Invalid --split '{}', expected K/N such as 2/3",
+            spec
+        ))
+    };
+    let (k, n) = spec.split_once('/').ok_or_else(invalid)?;
+    let k: u8 = k.trim().parse().map_err(|_| invalid())?;
+    let n: u8 = n.trim().parse().map_err(|_| invalid())?;
+
+    if k < 2 || k > n {
+        return Err(
+            Failure::Validation.error(format!("Invalid --split {}/{}: need 2 <= K <= N", k, n))
+        );
+    }
+    if n as usize != images {
+        return Err(Failure::Validation.error(format!(
+            "--split {}/{} needs {} cover images, got {}",
+            k, n, n, images
+        )));
+    }
+    Ok((k, n))
+}
+
+/// Embeds `data` into every file matching `pattern` but backups, going on with the rest
+/// when one fails and listing the failures at the end
+fn embed_all(
+    pattern: &Path,
+    chunk_type: &str,
+    data: &[u8],
+    embedding: &EmbeddingArgs,
+    secret: Option<&Secret>,
+) -> Result<()> {
+    let paths: Vec<PathBuf> = glob::expand(pattern)?
+        .into_iter()
+        .filter(|path| atomic_file::backup_original(path).is_none())
+        .collect();
+    if paths.is_empty() {
+        return Err(format!("No files match '{}'", pattern.display()).into());
+    }
+
+    let mut failures = vec![];
+    for path in &paths {
+        if let Err(e) = embed(path, chunk_type, data.to_vec(), embedding, secret, None) {
+            let reason = output::reason(e.as_ref());
+            say!(" ❌ {}: {}", path.display(), reason);
+            failures.push((path, reason));
+        }
+    }
+
+    say!(
+        "📦  Encoded into {} of {} file(s)",
+        paths.len() - failures.len(),
+        paths.len()
+    );
+    for (path, error) in &failures {
+        say!("  • {} failed: {}", path.display(), error);
+        output::push(
+            "failed",
+            json!({
+                "file": path.to_string_lossy(),
+                "error": error.as_str(),
+            }),
+        );
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} of {} file(s) could not be encoded",
+            failures.len(),
+            paths.len()
+        )
+        .into())
+    }
+}
+
+/// Adds a chunk holding `data` before IEND, with `--idat` hides it in the compressed image
+/// data, or with `--lsb` in the pixels, scattered in an order derived from `secret` if one
+/// is given. With `output` the result is written there instead.
+fn embed(
+    path: &Path,
+    chunk_type: &str,
+    data: Vec<u8>,
+    embedding: &EmbeddingArgs,
+    secret: Option<&Secret>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?
+        .with_output(output)?
+        .journaled("encode", chunk_type);
+
+    say!(
+        "🔐  Encoding message into '{}'...",
+        handler.target_path().display()
+    );
+
+    handler.atomic_modify(|content| {
+        if let Some(mut container) = container::parse(&content)? {
+            embed_in_container(
+                container.as_mut(),
+                chunk_type,
+                &data,
+                embedding,
+                secret,
+                path,
+            )?;
+            return Ok(container.to_bytes());
+        }
+
+        // Parse PNG
+        let mut png = parse_png(&content)?;
+
+        if embedding.idat {
+            check_idat_unused(&png, path)?;
+            let before = pixels::idat_data(&png)?.len();
+            let png = idat::embed(png, chunk_type, &data)?;
+            say_encoded(&format!(
+                " into the image data stream (IDAT: {} -> {} bytes)",
+                before,
+                pixels::idat_data(&png)?.len()
+            ));
+            return Ok(png.as_bytes());
+        }
+
+        if embedding.lsb {
+            let mut pixels = Pixels::from_png(&png)?;
+            let (bits, names) = (embedding.bits, PIXEL_NAMES);
+            embed_pixels(&mut pixels, names, chunk_type, &data, bits, secret, path)?;
+            return Ok(pixels.to_png(png)?.as_bytes());
+        }
+
+        // Check for duplicate chunk
+        check_chunk_unused(&png, chunk_type, embedding, path)?;
+
+        if embedding.xmp {
+            let png = xmp::embed(png, chunk_type, &payload_text(&data)?)?;
+            say_encoded(" into the XMP packet");
+            return Ok(png.as_bytes());
+        }
+
+        // Add new chunk with message, before IEND or the first frame of an animation
+        if embedding.standard_text {
+            png.insert_ancillary(text::text_chunk(
+                chunk_type,
+                &payload_text(&data)?,
+                embedding.ztxt,
+            )?)?;
+        } else {
+            // Create and validate chunk type
+            let chunk_type_obj = parse_chunk_type(chunk_type)?;
+            let mut chunks = match embedding.max_chunk_size {
+                Some(max) if data.len() > max as usize => {
+                    let fragments = payload::fragment(&data, max as usize)?;
+                    say!(
+                        "🧩  Spreading the message over {} '{}' chunks of at most {} bytes",
+                        fragments.len(),
+                        chunk_type,
+                        max
+                    );
+                    fragments
+                        .into_iter()
+                        .map(|fragment| Ok(Chunk::new(ChunkType::from_str(chunk_type)?, fragment)))
+                        .collect::<Result<Vec<Chunk>>>()?
+                }
+                _ => vec![Chunk::new(chunk_type_obj, data)],
+            };
+
+            if let Some(count) = embedding.decoys {
+                let size = chunks[0].data().len();
+                chunks.extend(decoy_chunks(&png, chunk_type, count, size)?);
+                crypto::random_shuffle(&mut chunks)?;
+                say!("🎭  Hiding the message among {} decoy chunks", count);
+            }
+            for chunk in chunks {
+                png.insert_ancillary(chunk)?;
+            }
+        }
+
+        detail_chunks(&png, chunk_type);
+        say_encoded("");
+        Ok(png.as_bytes())
+    })
+}
+
+/// Reports that the message went where `place` says, or with `--dry-run` that it would
+fn say_encoded(place: &str) {
+    if atomic_file::is_dry_run() {
+        say!("🔍  Would encode the message{}", place);
+    } else {
+        say!(" ✅ Message encoded successfully{}", place);
+    }
+}
+
+/// Lists where the `chunk_type` chunks of `png` are, for `--verbose`
+fn detail_chunks(png: &Png, chunk_type: &str) {
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        if chunk.chunk_type().to_string() == chunk_type {
+            detail!(
+                "    Chunk '{}' at index {}, offset {}: {} bytes of data",
+                chunk_type,
+                index,
+                png.chunk_offset(index),
+                chunk.data().len()
+            );
+        }
+    }
+}
+
+/// Builds `count` chunks that look like password-encrypted payloads of about `size` bytes,
+/// under random private chunk types not already used in `png` or by `chunk_type`
+fn decoy_chunks(png: &Png, chunk_type: &str, count: u8, size: usize) -> Result<Vec<Chunk>> {
+    let mut decoys: Vec<Chunk> = Vec::with_capacity(count as usize);
+    while decoys.len() < count as usize {
+        let mut letters = [0u8; 4];
+        crypto::random_bytes(&mut letters)?;
+        // Same shape as --obfuscate-chunk names: lowercase with an uppercase third letter
+        let name: String = letters
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                let letter = b'a' + b % 26;
+                (if i == 2 {
+                    letter.to_ascii_uppercase()
+                } else {
+                    letter
+                }) as char
+            })
+            .collect();
+        if name == chunk_type
+            || png.chunk_by_type(&name).is_some()
+            || decoys.iter().any(|d| d.chunk_type().to_string() == name)
+        {
+            continue;
+        }
+
+        // A genuine payload header followed by noise, within a quarter of the real size either way
+        let mut data = payload::wrap(&Header::new(Kind::Password), &[]);
+        let target = size - size / 4 + crypto::random_index(size / 2 + 1)?;
+        let mut noise = vec![0u8; target.saturating_sub(data.len())];
+        crypto::random_bytes(&mut noise)?;
+        data.extend(noise);
+        decoys.push(Chunk::new(ChunkType::from_str(&name)?, data));
+    }
+    Ok(decoys)
+}
+
+/// The chunk branch of [`embed`] for the non-PNG formats in [`container`]
+fn embed_in_container(
+    container: &mut dyn Container,
+    chunk_type: &str,
+    data: &[u8],
+    embedding: &EmbeddingArgs,
+    secret: Option<&Secret>,
+    path: &Path,
+) -> Result<()> {
+    check_container_options(
+        container,
+        embedding.idat,
+        embedding.standard_text,
+        embedding.xmp,
+    )?;
+    if embedding.decoys.is_some() {
+        return Err("--decoys needs a PNG file".into());
+    }
+    if embedding.lsb {
+        let mut pixels = container.pixels()?;
+        let names = container.sample_names();
+        embed_pixels(
+            &mut pixels,
+            names,
+            chunk_type,
+            data,
+            embedding.bits,
+            secret,
+            path,
+        )?;
+        return container.set_pixels(&pixels);
+    }
+    check_container_unused(container, chunk_type, path)?;
+
+    let max = embedding
+        .max_chunk_size
+        .map_or(usize::MAX, |max| max as usize)
+        .min(container.max_payload(chunk_type));
+    let blocks = if data.len() > max {
+        let fragments = payload::fragment(data, max)?;
+        say!(
+            "🧩  Spreading the message over {} {}s of at most {} bytes",
+            fragments.len(),
+            container.block_kind(),
+            max
+        );
+        fragments
+    } else {
+        vec![data.to_vec()]
+    };
+    for block in &blocks {
+        container.insert(chunk_type, block)?;
+    }
+    say_encoded(&format!(" into the {} file", container.format()));
+    Ok(())
+}
+
+/// Hides `data` in the low `bits` bits of the color samples, scattered by `secret`.
+/// Messages call the samples and their channels `names`.
+fn embed_pixels(
+    pixels: &mut Pixels,
+    (samples, channel): (&str, &str),
+    chunk_type: &str,
+    data: &[u8],
+    bits: u8,
+    secret: Option<&Secret>,
+    path: &Path,
+) -> Result<()> {
+    let order = lsb::sample_order(pixels, chunk_type, secret.map(Secret::expose))?;
+    check_pixels_unused(pixels, &order, path)?;
+    if secret.is_some() {
+        say!(
+            "🎲  Scattering the message across the {} in a password-derived order",
+            samples
+        );
+    }
+    lsb::embed(pixels, &order, chunk_type, data, bits)?;
+    say_encoded(&format!(
+        " into the {} ({} bit(s) per {})",
+        samples, bits, channel
+    ));
+    Ok(())
+}
+
+fn check_container_unused(container: &dyn Container, chunk_type: &str, path: &Path) -> Result<()> {
+    if !container.payloads(chunk_type).is_empty() {
+        return Err(Failure::Validation.error(format!(
+            " ❌ '{}' already holds a message labelled '{}'. Cannot add duplicate message.\n💡  Tip: Use a different chunk name to store another hidden message",
+            path.display(), chunk_type
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if `png` already has a chunk named `chunk_type`, or with `--standard-text` a
+/// text chunk with that keyword and with `--xmp` an XMP property of that name
+fn check_chunk_unused(
+    png: &Png,
+    chunk_type: &str,
+    embedding: &EmbeddingArgs,
+    path: &Path,
+) -> Result<()> {
+    if embedding.xmp {
+        if xmp::extract(png, chunk_type)?.is_some() {
+            return Err(Failure::Validation.error(format!(
+                " ❌ '{}' already has an XMP property '{}'. Cannot add duplicate message.",
+                path.display(),
+                chunk_type
+            )));
+        }
+    } else if embedding.standard_text {
+        if text::find_text(png, chunk_type)?.is_some() {
+            return Err(Failure::Validation.error(format!(
+                " ❌ '{}' already has a text chunk with keyword '{}'. Cannot add duplicate message.",
+                path.display(), chunk_type
+            )));
+        }
+    } else if png.chunk_by_type(chunk_type).is_some() {
+        return Err(Failure::Validation.error(format!(
+            " ❌ Chunk '{}' already exists in '{}'. Cannot add duplicate message.\n💡  Tip: Use a different chunk name to store another hidden message",
+            chunk_type, path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if the image data stream of `png` already carries a message
+fn check_idat_unused(png: &Png, path: &Path) -> Result<()> {
+    if idat::read_label(png)?.is_some() {
+        return Err(Failure::Validation.error(format!(
+            " ❌ The image data of '{}' already holds a message. Cannot add another one.\n💡  Tip: Use 'restore' or a fresh copy of the image",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Fails if `pixels` already carry a message in scanline order or in `order`, which a
+/// new one would overwrite. Messages scattered with another password go unnoticed.
+fn check_pixels_unused(pixels: &Pixels, order: &[usize], path: &Path) -> Result<()> {
+    if lsb::read_header(pixels, order)?.is_some()
+        || lsb::read_header(pixels, &pixels.sample_offsets()?)?.is_some()
+    {
+        return Err(Failure::Validation.error(format!(
+            " ❌ The pixels of '{}' already hold a message. Cannot add another one.\n💡  Tip: Use 'restore' or a fresh copy of the image",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the password given on the command line, prompting for it when
+/// `--password` has no value or `--ask-pass` is set
+fn resolve_password(
+    password: &Option<Option<String>>,
+    ask_pass: bool,
+    confirm: bool,
+) -> Result<Option<Secret>> {
+    let password = match password {
+        Some(Some(password)) => Secret::from(password.clone()),
+        Some(None) => prompt::read_password("Password: ", confirm)?,
+        None if ask_pass => prompt::read_password("Password: ", confirm)?,
+        None => return Ok(None),
+    };
+    Ok(Some(password))
+}
+
+/// Returns the symmetric secret given with `--password`, `--key-file` or `--use-keyring`, if any.
+///
+/// A password given together with `--use-keyring` is saved to the keyring for later runs.
+fn read_secret(
+    password: Option<Secret>,
+    key_file: Option<&Path>,
+    keyring_name: Option<&str>,
+) -> Result<Option<Secret>> {
+    match (password, key_file, keyring_name) {
+        (Some(password), _, keyring_name) => {
+            if let Some(name) = keyring_name.filter(|_| atomic_file::is_dry_run()) {
+                say!("🔍  Would update keyring entry '{}'", name);
+            } else if let Some(name) = keyring_name {
+                keyring::set_password(name, &password)?;
+                say!("🔑  Password stored in the OS keyring as '{}'", name);
+            }
+            Ok(Some(password))
+        }
+        (None, Some(path), _) => {
+            let key = Secret::from(fs::read(path).map_err(|e| {
+                Failure::from(&e).error(format!(
+                    "Failed to read key file '{}': {}",
+                    path.display(),
+                    e
+                ))
+            })?);
+            if key.expose().is_empty() {
+                return Err(format!("Key file '{}' is empty", path.display()).into());
+            }
+            Ok(Some(key))
+        }
+        (None, None, Some(name)) => Ok(Some(keyring::get_password(name)?)),
+        (None, None, None) => Ok(None),
+    }
+}
+
+pub fn rekey(path: &Path, chunk_type: &str, secrets: &RekeyArgs) -> Result<()> {
+    let password = resolve_password(&secrets.password, false, false)?;
+    let old_secret = read_secret(
+        password,
+        secrets.key_file.as_deref(),
+        secrets.use_keyring.as_deref(),
+    )?
+    .ok_or("Missing the current password or key file")?;
+
+    let new_secret = match (&secrets.new_password, &secrets.new_key_file) {
+        (Some(Some(password)), _) => Secret::from(password.clone()),
+        (Some(None), _) => prompt::read_password("New password: ", true)?,
+        (None, Some(path)) => {
+            read_secret(None, Some(path), None)?.ok_or("Missing the new password or key file")?
+        }
+        (None, None) => return Err("Missing the new password or key file".into()),
+    };
+    if new_secret.expose() == old_secret.expose() {
+        return Err("The new password/key must differ from the current one".into());
+    }
+    // An obfuscated chunk is named after the password, so it gets a new name as well
+    let label = chunk_type;
+    let chunk_type: &str = &stored_chunk_type(label, Some(&old_secret), secrets.obfuscate_chunk)?;
+    let new_chunk_type = stored_chunk_type(label, Some(&new_secret), secrets.obfuscate_chunk)?;
+
+    let handler = AtomicFileHandler::new(path)?;
+
+    say!(
+        "🔑  Re-encrypting chunk '{}' in '{}'...",
+        label,
+        handler.target_path().display()
+    );
+
+    // The keyring entry follows the new password so that `--use-keyring` keeps working
+    let keyring_entry = secrets
+        .use_keyring
+        .as_deref()
+        .filter(|_| secrets.new_password.is_some());
+    let keyring_updated = std::cell::Cell::new(false);
+    let result = handler.atomic_modify(|content| {
+        let mut png = parse_png(&content)?;
+
+        let indices: Vec<usize> = png
+            .chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| chunk.chunk_type().to_string() == chunk_type)
+            .map(|(index, _)| index)
+            .collect();
+        let blocks: Vec<&[u8]> = indices
+            .iter()
+            .map(|&index| png.chunks()[index].data())
+            .collect();
+        let data = collect_payload(&blocks, chunk_type)?.ok_or_else(|| {
+            Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", label))
+        })?;
+        if new_chunk_type != chunk_type && png.chunk_by_type(&new_chunk_type).is_some() {
+            return Err(format!(
+                "The new password maps '{}' to chunk '{}', which the image already has",
+                label, new_chunk_type
+            )
+            .into());
+        }
+        // A message spread with --max-chunk-size is spread again over chunks as large
+        let fragment_size = is_fragment(blocks[0])
+            .then(|| blocks.iter().map(|block| block.len()).max())
+            .flatten();
+
+        let (header, body) = payload::unwrap(&data)?;
+        if header.kind != Kind::Password {
+            return Err(format!(
+                "Chunk '{}' is not password-encrypted, nothing to rekey",
+                label
+            )
+            .into());
+        }
+        let body = crypto::rekey(old_secret.expose(), new_secret.expose(), body).map_err(|e| {
+            Failure::Crypto.error(format!("Failed to decrypt chunk '{}': {}", label, e))
+        })?;
+        let data = payload::wrap(&header, &body);
+        let pieces = match fragment_size {
+            Some(size) => payload::fragment(&data, size)?,
+            None => vec![data],
+        };
+
+        // Replace the data where it is, so the chunks keep their places among the others
+        let last = indices[indices.len() - 1];
+        let mut pieces = pieces.into_iter();
+        let mut surplus = vec![];
+        for &index in &indices {
+            match pieces.next() {
+                Some(piece) => {
+                    png.chunks_mut()[index] =
+                        Chunk::new(ChunkType::from_str(&new_chunk_type)?, piece)
+                }
+                None => surplus.push(index),
+            }
+        }
+        for (offset, piece) in pieces.enumerate() {
+            png.insert_chunk_at(
+                last + 1 + offset,
+                Chunk::new(ChunkType::from_str(&new_chunk_type)?, piece),
+            )?;
+        }
+        for index in surplus.into_iter().rev() {
+            png.chunks_mut().remove(index);
+        }
+
+        // Updated last before the commit, so that a keyring failure leaves the file as is
+        if let Some(name) = keyring_entry {
+            if atomic_file::is_dry_run() {
+                say!("🔍  Would update keyring entry '{}'", name);
+            } else {
+                keyring::set_password(name, &new_secret).map_err(|e| {
+                    format!("{}; the file was left encrypted with the old password", e)
+                })?;
+                keyring_updated.set(true);
+                say!("🔑  Keyring entry '{}' updated to the new password", name);
+            }
+        }
+        say!(" ✅ Chunk re-encrypted with the new secret");
+        Ok(png.as_bytes())
+    });
+
+    // The change was rolled back, so the keyring goes back to the old password too
+    if let (Err(e), Some(name)) = (&result, keyring_entry.filter(|_| keyring_updated.get())) {
+        let failure = Failure::of(e.as_ref());
+        return Err(match keyring::set_password(name, &old_secret) {
+            Ok(()) => failure.error(format!(
+                "{}; keyring entry '{}' was put back to the old password",
+                e, name
+            )),
+            Err(restore) => failure.error(format!(
+                "{}; the file still has the old password but keyring entry '{}' holds the new one, and putting it back failed: {}",
+                e, name, restore
+            )),
+        });
+    }
+    result
+}
+
+pub fn keygen(path: &Path, signing: bool) -> Result<()> {
+    let mut public_path = path.as_os_str().to_owned();
+    public_path.push(".pub");
+    let public_path = PathBuf::from(public_path);
+    for file in [path, public_path.as_path()] {
+        if file.exists() {
+            return Err(format!(
+                "'{}' already exists. Refusing to overwrite an existing key file",
+                file.display()
+            )
+            .into());
+        }
+    }
+
+    let (private_key, public_key) = if signing {
+        crypto::generate_signing_keypair()?
+    } else {
+        crypto::generate_keypair()?
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(private_key.as_bytes()))
+        .map_err(|e| {
+            Failure::from(&e).error(format!(
+                "Failed to write private key '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+    fs::write(&public_path, format!("{}\n", public_key)).map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to write public key '{}': {}",
+            public_path.display(),
+            e
+        ))
+    })?;
+
+    say!("🔑  Generated a new key pair:");
+    say!("    Private key: {}", path.display());
+    say!("    Public key: {}", public_path.display());
+    output::set("private_key", path.to_string_lossy());
+    output::set("public_key", public_path.to_string_lossy());
+    if signing {
+        say!("    Verifying key: {}", public_key);
+        output::set("verifying_key", public_key);
+    } else {
+        say!("    Recipient: {}", public_key);
+        output::set("recipient", public_key);
+    }
+    say!("💡  Tip: Share the .pub file, keep the private key secret");
+    Ok(())
+}
+
+pub fn sign(path: &Path, key: &Path, chunk: Option<&str>) -> Result<()> {
+    if chunk == Some(SIGNATURE_CHUNK) {
+        return Err(format!(
+            " ❌  Cannot sign the signature chunk '{}' itself",
+            SIGNATURE_CHUNK
+        )
+        .into());
+    }
+
+    let handler = AtomicFileHandler::new(path)?;
+
+    say!("✍️  Signing '{}'...", handler.target_path().display());
+
+    handler.atomic_modify(|content| {
+        let mut png = parse_png(&content)?;
+
+        // Replace any existing signature with the same scope
+        let others: Vec<SignatureRecord> = signature::records(&png)?
+            .into_iter()
+            .filter(|record| record.scope.as_deref() != chunk)
+            .collect();
+        while png.remove_chunk(SIGNATURE_CHUNK).is_ok() {}
+
+        let message = signature::signed_message(&png, chunk)?;
+        let (public_key, signature) = crypto::sign_with_key_file(key, &message)?;
+        let record = SignatureRecord {
+            scope: chunk.map(str::to_string),
+            public_key,
+            signature,
+        };
+
+        for record in others.iter().chain([&record]) {
+            let chunk_type = ChunkType::from_str(SIGNATURE_CHUNK)?;
+            png.insert_ancillary(Chunk::new(chunk_type, record.as_bytes()))?;
+        }
+
+        let verifying_key = crypto::format_verifying_key(&public_key);
+        say!(" ✅ Signed {}", record.describe_scope());
+        say!("    Verifying key: {}", verifying_key);
+        output::set("file", handler.target_path().to_string_lossy());
+        output::set("scope", chunk);
+        output::set("verifying_key", verifying_key);
+        Ok(png.as_bytes())
+    })
+}
+
+/// Checks the structure and every chunk CRC of a PNG file, then the Ed25519 signatures
+/// stored in it, if any
+pub fn verify(path: &Path, key: Option<&Path>, chunk: Option<&str>) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+    require_png(&buffer)?;
+
+    say!(
+        "🧪  Checking the structure of '{}':",
+        handler.target_path().display()
+    );
+    let integrity = check_integrity(&buffer);
+    output::set("file", handler.target_path().to_string_lossy());
+    for problem in &integrity.problems {
+        say!(
+            "  • Offset {} ({:#x}): ❌ {}",
+            problem.offset,
+            problem.offset,
+            problem.description
+        );
+        output::push(
+            "problems",
+            json!({
+                "offset": problem.offset,
+                "description": problem.description.as_str(),
+            }),
+        );
+    }
+    if !integrity.problems.is_empty() {
+        return Err(format!(
+            "{} problem(s) found in '{}'",
+            integrity.problems.len(),
+            handler.target_path().display()
+        )
+        .into());
+    }
+    say!(
+        "  ✅ {} chunks, all CRCs match, IHDR first and IEND last",
+        integrity.chunks
+    );
+    output::set("problems", Vec::<Value>::new());
+    output::set("chunks", integrity.chunks);
+    output::set("trailing_bytes", integrity.trailing);
+    if integrity.trailing > 0 {
+        say!(
+            "  ⚠️  {} bytes of trailing data after IEND, which signatures do not cover",
+            integrity.trailing
+        );
+    }
+
+    let png = parse_png(&buffer)?;
+
+    let expected_key = key.map(crypto::read_verifying_key_file).transpose()?;
+
+    let records: Vec<SignatureRecord> = signature::records(&png)?
+        .into_iter()
+        .filter(|record| chunk.is_none() || record.scope.as_deref() == chunk)
+        .collect();
+    // Without --key or --chunk a file that was never signed only gets the structure check
+    if records.is_empty() && key.is_none() && chunk.is_none() {
+        return Ok(());
+    }
+    if records.is_empty() {
+        return Err(format!(
+            "No signature found in '{}'{}",
+            handler.target_path().display(),
+            chunk
+                .map(|c| format!(" for chunk '{}'", c))
+                .unwrap_or_default()
+        )
+        .into());
+    }
+
+    say!(
+        "🔏  Verifying signatures in '{}':",
+        handler.target_path().display()
+    );
+
+    let mut failures = 0;
+    for record in &records {
+        let valid = signature::signed_message(&png, record.scope.as_deref())
+            .map(|message| {
+                crypto::verify_signature(&record.public_key, &message, &record.signature)
+            })
+            .unwrap_or(false);
+        let trusted = expected_key.is_none_or(|expected| expected == record.public_key);
+
+        let status = match (valid, trusted) {
+            (true, true) => "✅ valid",
+            (true, false) => "❌ signed by a different key",
+            (false, _) => "❌ INVALID (content was modified)",
+        };
+        let verifying_key = crypto::format_verifying_key(&record.public_key);
+        say!("  • {}: {}", record.describe_scope(), status);
+        say!("    Key: {}", verifying_key);
+        output::push(
+            "signatures",
+            json!({
+                "scope": record.scope.as_deref(),
+                "verifying_key": verifying_key,
+                "valid": valid,
+                "trusted": trusted,
+            }),
+        );
+
+        if !(valid && trusted) {
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        return Err(Failure::Crypto.error(format!(
+            "{} of {} signature(s) failed verification",
+            failures,
+            records.len()
+        )));
+    }
+    if expected_key.is_none() {
+        say!("💡  Tip: Pass --key with the signer's .pub file to check who made the signature");
+    }
+    Ok(())
+}
+
+/// Writes the data of the first chunk of type `chunk_type` to `out_file`, as it is stored
+pub fn dump_chunk(
+    path: &Path,
+    chunk_type: &str,
+    out_file: &Path,
+    index: Option<usize>,
+) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let png = parse_png(&handler.read_file()?)?;
+
+    let count = png.chunks_by_type(chunk_type).count();
+    let chunk = png
+        .chunk_by_type_nth(chunk_type, index.unwrap_or(0))
+        .ok_or_else(|| missing_chunk(chunk_type, index, count))?;
+    fs::write(out_file, chunk.data()).map_err(|e| {
+        Failure::from(&e).error(format!("Failed to write '{}': {}", out_file.display(), e))
+    })?;
+
+    say!("💾  Chunk data saved:");
+    say!("    File: {}", handler.target_path().display());
+    say!("    Chunk: {}", chunk_type);
+    if let Some(index) = index {
+        say!("    Index: {} of {}", index, count);
+    }
+    say!(
+        "    Written: {} ({} bytes)",
+        out_file.display(),
+        chunk.data().len()
+    );
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("chunk", chunk_type);
+    output::set("index", index.unwrap_or(0));
+    output::set("count", count);
+    output::set("written", out_file.to_string_lossy());
+    output::set("size", chunk.data().len());
+    if count > 1 && index.is_none() {
+        say!(
+            "💡  Tip: The file has {} '{}' chunks, only the first was written; pick another with --index",
+            count,
+            chunk_type
+        );
+    }
+    Ok(())
+}
+
+/// Error for a chunk `dump-chunk` or `remove` cannot find, the `index`th of `count`
+fn missing_chunk(chunk_type: &str, index: Option<usize>, count: usize) -> Error {
+    match index {
+        Some(index) if count > 0 => Failure::ChunkNotFound.error(format!(
+            " Chunk '{}' number {} not found; the file has {} of them, counting from 0",
+            chunk_type, index, count
+        )),
+        _ => Failure::ChunkNotFound.error(format!(" Chunk type '{}' not found", chunk_type)),
+    }
+}
+
+pub fn extract_pixels(path: &Path, out_file: &Path, format: PixelFormat) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let mut png = parse_png(&handler.read_file()?)?;
+    let pixels = png.decode_pixels()?;
+
+    let bytes = match format {
+        PixelFormat::Raw => pixels.data().to_vec(),
+        PixelFormat::Ppm => pixels.to_ppm(png.chunk_by_type("PLTE").map(Chunk::data))?,
+        PixelFormat::Png => {
+            // tRNS is ancillary, but dropping it would change which pixels are transparent
+            png.strip_ancillary(&["tRNS".to_string()]);
+            pixels.to_png(png)?.as_bytes()
+        }
+    };
+    fs::write(out_file, &bytes).map_err(|e| {
+        Failure::from(&e).error(format!("Failed to write '{}': {}", out_file.display(), e))
+    })?;
+
+    let ihdr = pixels.ihdr;
+    say!("💾  Pixel data saved:");
+    say!("    File: {}", handler.target_path().display());
+    say!(
+        "    Image: {}x{}, {}",
+        ihdr.width,
+        ihdr.height,
+        ihdr.describe()
+    );
+    say!(
+        "    Written: {} ({} bytes)",
+        out_file.display(),
+        bytes.len()
+    );
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("width", ihdr.width);
+    output::set("height", ihdr.height);
+    output::set("pixel_format", ihdr.describe());
+    output::set("format", format.name());
+    output::set("written", out_file.to_string_lossy());
+    output::set("size", bytes.len());
+    if format == PixelFormat::Raw {
+        say!(
+            "💡  Tip: Each of the {} rows is {} bytes, without filter type bytes",
+            ihdr.height,
+            bytes.len() / ihdr.height as usize
+        );
+    }
+    Ok(())
+}
+
+/// Writes a new `width` x `height` PNG filled with `fill`, an RRGGBB or RRGGBBAA color;
+/// an existing file is never overwritten
+pub fn create(path: &Path, width: u32, height: u32, fill: &str) -> Result<()> {
+    let color = encoding::hex_decode(fill.trim_start_matches('#'))
+        .ok()
+        .filter(|color| matches!(color.len(), 3 | 4))
+        .ok_or_else(|| format!("Invalid fill color '{}': use RRGGBB or RRGGBBAA", fill))?;
+    let color_type = if color.len() == 4 { 6 } else { 2 };
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .filter(|count| count.checked_mul(color.len()).is_some())
+        .ok_or_else(|| format!("A {}x{} image is too large to create", width, height))?;
+    let pixels = color.repeat(count);
+    let png = Png::builder(width, height)
+        .color_type(color_type, 8)
+        .pixels(pixels)
+        .build()?;
+    let bytes = png.as_bytes();
+    let pixel_format = Ihdr::from_png(&png)?.describe();
+    output::set("file", path.to_string_lossy());
+    output::set("width", width);
+    output::set("height", height);
+    output::set("pixel_format", pixel_format.as_str());
+    output::set("size", bytes.len());
+
+    if atomic_file::is_dry_run() {
+        say!(
+            "🔍  Dry run: '{}' would be created ({}x{}, {} bytes)",
+            path.display(),
+            width,
+            height,
+            bytes.len()
+        );
+        output::set("dry_run", true);
+        return Ok(());
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+
+    say!("🖼️  Created '{}':", path.display());
+    say!("    Image: {}x{}, {}", width, height, pixel_format);
+    say!("    Size: {} bytes", bytes.len());
+    Ok(())
+}
+
+/// Adds a chunk of type `chunk_type` holding the contents of `in_file`, before the first
+/// chunk of type `before`, at `index`, or otherwise in front of IEND (or the first frame
+/// of an animation) like the chunks `encode` writes
+pub fn inject_chunk(
+    path: &Path,
+    chunk_type: &str,
+    in_file: &Path,
+    before: Option<&str>,
+    index: Option<usize>,
+) -> Result<()> {
+    let chunk_type_obj = parse_chunk_type(chunk_type)?;
+    if ["IHDR", "PLTE", "IDAT", "IEND"].contains(&chunk_type) {
+        return Err(format!(
+            " ❌  Cannot inject critical PNG chunk '{}'; it would break the image",
+            chunk_type
+        )
+        .into());
+    }
+    let data = fs::read(in_file).map_err(|e| {
+        Failure::from(&e).error(format!("Failed to read '{}': {}", in_file.display(), e))
+    })?;
+    if data.len() > Chunk::MAX_LENGTH as usize {
+        return Err(format!(
+            "'{}' holds {} bytes, more than one chunk can ({})",
+            in_file.display(),
+            data.len(),
+            Chunk::MAX_LENGTH
+        )
+        .into());
+    }
+
+    let insert = |png: &mut Png, chunk: Chunk| match (before, index) {
+        (Some(before), _) => png.insert_before(before, chunk).map(|_| ()),
+        (_, Some(index)) => png.insert_chunk_at(index, chunk),
+        (None, None) => png.insert_ancillary(chunk),
+    };
+
+    let handler = AtomicFileHandler::new(path)?;
+    // Check the image and the place before creating a backup
+    insert(
+        &mut parse_png(&handler.read_file()?)?,
+        Chunk::new(ChunkType::from_str(chunk_type)?, vec![]),
+    )?;
+
+    say!(
+        "💉  Injecting '{}' as chunk '{}' into '{}'...",
+        in_file.display(),
+        chunk_type,
+        handler.target_path().display()
+    );
+    let len = data.len();
+    handler.atomic_modify(|content| {
+        let mut png = parse_png(&content)?;
+        insert(&mut png, Chunk::new(chunk_type_obj, data))?;
+        Ok(png.as_bytes())
+    })?;
+    say!(" ✅ Chunk '{}' added ({} bytes)", chunk_type, len);
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("chunk", chunk_type);
+    output::set("source", in_file.to_string_lossy());
+    output::set("size", len);
+    Ok(())
 }
 
-pub fn encode(path: &str, chunk_type: &str, message: &str) -> Result<()> {
-    // Check for critical PNG chunks
-    if ["IHDR", "PLTE", "IDAT", "IEND"].contains(&chunk_type) {
+/// Moves the chunks of type `chunk_type` to the place `placement` gives
+pub fn move_chunk(path: &Path, chunk_type: &str, placement: &PlacementArgs) -> Result<()> {
+    let placement = match (&placement.before, &placement.after, placement.index) {
+        (Some(before), _, _) => Placement::Before(before.clone()),
+        (_, Some(after), _) => Placement::After(after.clone()),
+        (_, _, Some(index)) => Placement::Index(index),
+        (None, None, None) => return Err("Give --before, --after or --index".into()),
+    };
+
+    let handler = AtomicFileHandler::new(path)?;
+    // Check the move before creating a backup
+    parse_png(&handler.read_file()?)?.move_chunks(chunk_type, &placement)?;
+
+    handler.atomic_modify(|content| {
+        let mut png = parse_png(&content)?;
+        let index = png.move_chunks(chunk_type, &placement)?;
+        let count = png.chunks_by_type(chunk_type).count();
+        say!(
+            " ✅ Moved {} '{}' chunk(s) to position {}",
+            count,
+            chunk_type,
+            index
+        );
+        output::set("file", handler.target_path().to_string_lossy());
+        output::set("chunk", chunk_type);
+        output::set("count", count);
+        output::set("position", index);
+        Ok(png.as_bytes())
+    })
+}
+
+/// Renames the chunks of type `old_type` to `new_type`, which must follow the same naming
+/// rules as the chunk types `encode` uses
+pub fn rename_chunk(path: &Path, old_type: &str, new_type: &str) -> Result<()> {
+    if ANIMATION_CHUNKS.contains(&old_type) {
         return Err(format!(
-            " ❌  Cannot use critical PNG chunk name '{}'. Please use a different chunk name.\n 💡 Tip: Make sure the 3rd character is uppercase (e.g., 'abCd', 'boOp', 'vaRu')",
-            chunk_type
-        ).into());
+            " ❌  Cannot rename APNG animation chunk '{}'; it would break the animation",
+            old_type
+        )
+        .into());
     }
+    validate_chunk_type(new_type)?;
+    parse_chunk_type(new_type)?;
 
-    // Validate chunk type format (3rd character must be uppercase)
-    if chunk_type.len() == 4 {
-        let chars: Vec<char> = chunk_type.chars().collect();
-        if !chars[2].is_uppercase() {
-            return Err(format!(
-                " ❌ Invalid chunk type '{}'. The 3rd character must be uppercase.\n💡  Example: '{}{}{}{}' should be '{}{}{}{}' ",
-                chunk_type,
-                chars[0], chars[1], chars[2], chars[3],
-                chars[0], chars[1], chars[2].to_uppercase().next().unwrap(), chars[3]
-            ).into());
-        }
-    }
+    let handler = AtomicFileHandler::new(path)?;
+    // Check the rename before creating a backup
+    parse_png(&handler.read_file()?)?.rename_chunks(old_type, new_type)?;
+
+    handler.atomic_modify(|content| {
+        let mut png = parse_png(&content)?;
+        let count = png.rename_chunks(old_type, new_type)?;
+        say!(
+            " ✅ Renamed {} '{}' chunk(s) to '{}'",
+            count,
+            old_type,
+            new_type
+        );
+        output::set("file", handler.target_path().to_string_lossy());
+        output::set("old_type", old_type);
+        output::set("new_type", new_type);
+        output::set("count", count);
+        Ok(png.as_bytes())
+    })
+}
 
+/// Removes every ancillary chunk except the types in `keep`, e.g. before publishing
+pub fn strip(path: &Path, keep: &[String]) -> Result<()> {
     let handler = AtomicFileHandler::new(path)?;
+    // Only create a backup when there is something to strip
+    if parse_png(&handler.read_file()?)?
+        .strip_ancillary(keep)
+        .is_empty()
+    {
+        say!(
+            " ✅ No ancillary chunks to strip in '{}'",
+            handler.target_path().display()
+        );
+        output::set("file", handler.target_path().to_string_lossy());
+        output::set("stripped", Vec::<Value>::new());
+        output::set("removed", 0usize);
+        return Ok(());
+    }
 
-    println!(
-        "🔐  Encoding message into '{}'...",
+    say!(
+        "🧹  Stripping ancillary chunks from '{}':",
         handler.target_path().display()
     );
-
     handler.atomic_modify(|content| {
-        // Parse PNG
-        let mut png =
-            Png::try_from(content.as_slice()).map_err(|e| format!("Failed to parse PNG: {}", e))?;
-
-        // Check for duplicate chunk
-        if png.chunk_by_type(chunk_type).is_some() {
-            return Err(format!(
-                " ❌ Chunk '{}' already exists. Cannot add duplicate message.\n💡  Tip: Use a different chunk name to store another hidden message",
-                chunk_type
-            ).into());
+        let mut png = parse_png(&content)?;
+        let stripped = png.strip_ancillary(keep);
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for chunk in &stripped {
+            let chunk_type = chunk.chunk_type().to_string();
+            match counts.iter_mut().find(|(found, _)| *found == chunk_type) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((chunk_type, 1)),
+            }
         }
+        output::set("file", handler.target_path().to_string_lossy());
+        for (chunk_type, count) in counts {
+            say!("  • {} ({} chunk(s))", chunk_type, count);
+            output::push(
+                "stripped",
+                json!({
+                    "type": chunk_type,
+                    "count": count,
+                }),
+            );
+        }
+        say!(" ✅ Removed {} chunk(s)", stripped.len());
+        output::set("removed", stripped.len());
+        Ok(png.as_bytes())
+    })
+}
 
-        // Remove IEND chunk
-        let end = png
-            .remove_chunk("IEND")
-            .map_err(|e| format!("Failed to remove IEND chunk: {}", e))?;
+/// Fixes the damage `png::repair` can fix, backing up the original first
+pub fn optimize(path: &Path, refilter: bool) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let png = parse_png(&handler.read_file()?)?;
+    let before = pixels::idat_data(&png)?.len();
+
+    // Only create a backup when recompressing actually saves space
+    let optimized = pixels::recompress(png, refilter)?;
+    let after = pixels::idat_data(&optimized)?.len();
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("before", before);
+    if after >= before {
+        output::set("after", before);
+        output::set("saved", 0usize);
+        say!(
+            " ✅ '{}' is already as small as this can make it ({} bytes of image data)",
+            handler.target_path().display(),
+            before
+        );
+        return Ok(());
+    }
 
-        // Create and validate chunk type
-        let chunk_type_obj =
-            ChunkType::from_str(chunk_type).map_err(|e| format!("Invalid chunk type: {}", e))?;
+    say!("🗜️  Optimizing '{}'", handler.target_path().display());
+    handler.atomic_modify(|_| Ok(optimized.as_bytes()))?;
+    say!(
+        " ✅ Image data: {} -> {} bytes ({} saved)",
+        before,
+        after,
+        before - after
+    );
+    output::set("after", after);
+    output::set("saved", before - after);
+    Ok(())
+}
 
-        // Add new chunk with message
-        png.append_chunk(Chunk::new(chunk_type_obj, message.as_bytes().to_vec()));
+pub fn repair(path: &Path) -> Result<()> {
+    let handler = AtomicFileHandler::new(path)?;
+    let buffer = handler.read_file()?;
+    require_png(&buffer)?;
 
-        // Re-add IEND chunk
-        png.append_chunk(end);
+    // Only create a backup when there is something to fix
+    let (_, fixes) = png::repair(&buffer)?;
+    output::set("file", handler.target_path().to_string_lossy());
+    if fixes.is_empty() {
+        output::set("fixes", Vec::<Value>::new());
+        say!(
+            " ✅ Nothing to repair in '{}'",
+            handler.target_path().display()
+        );
+        return Ok(());
+    }
 
-        println!(" ✅ Message encoded successfully");
-        Ok(png.as_bytes())
-    })
+    say!("🩹  Repairing '{}':", handler.target_path().display());
+    handler.atomic_modify(|content| {
+        let (repaired, fixes) = png::repair(&content)?;
+        for fix in &fixes {
+            say!("  • {}", fix);
+            output::push("fixes", fix.as_str());
+        }
+        Ok(repaired)
+    })?;
+    say!(" ✅ {} problem(s) fixed", fixes.len());
+    if let Some(problem) = check_integrity(&handler.read_file()?).problems.first() {
+        output::set(
+            "remaining_problem",
+            json!({
+                "offset": problem.offset,
+                "description": problem.description.as_str(),
+            }),
+        );
+        say!(
+            "💡  Tip: Left as it was, 'verify' still reports offset {}: {}",
+            problem.offset,
+            problem.description
+        );
+    }
+    Ok(())
 }
 
-pub fn remove(path: &str, chunk_type: &str) -> Result<()> {
-    println!("🗑️  Removing the Hidden Message:");
-    println!("   File: {}", path);
-    println!("   Chunk: {}", chunk_type);
+/// Removes the message in `chunk_type` with all its fragments, or with `index` only the
+/// chunk of that type after `index` others
+pub fn remove(
+    path: &Path,
+    chunk_type: &str,
+    index: Option<usize>,
+    output: Option<&Path>,
+) -> Result<()> {
+    say!("🗑️  Removing the Hidden Message:");
+    say!("   File: {}", path.display());
+    say!("   Chunk: {}", chunk_type);
 
     // Check if it's a critical chunk before attempting modification
     if ["IHDR", "PLTE", "IDAT", "IEND"].contains(&chunk_type) {
-        println!(
-            "  Removed: ❌ Cannot remove critical PNG chunk '{}'",
-            chunk_type
+        return Err(
+            Failure::Validation.error(format!("Cannot remove critical PNG chunk '{}'", chunk_type))
         );
-        println!("💡 Tip: Use 'restore' command if you need to revert changes");
-        return Ok(());
     }
 
-    let handler = AtomicFileHandler::new(path)?;
+    let handler = AtomicFileHandler::new(path)?
+        .with_output(output)?
+        .journaled("remove", chunk_type);
+    if let Some(output) = output {
+        say!("   Output: {}", output.display());
+    }
 
     // Check if chunk exists before creating backup
     let buffer = handler.read_file()?;
-    let png =
-        Png::try_from(buffer.as_slice()).map_err(|e| format!("Failed to parse PNG: {}", e))?;
+    if let Some(container) = container::parse(&buffer)? {
+        if index.is_some() {
+            return Err(format!(
+                "--index only works with PNG files, not {} files",
+                container.format()
+            )
+            .into());
+        }
+        if container.payloads(chunk_type).is_empty() {
+            return Err(missing_chunk(chunk_type, None, 0));
+        }
+        return handler.atomic_modify_silent(|content| {
+            let mut container =
+                container::parse(&content)?.ok_or("The file is no longer in the same format")?;
+            let removed = container.remove(chunk_type);
+            if removed > 1 {
+                say!("   Fragments: {}", removed);
+            }
+            say!("   Removed: ✅ Successfully");
+            Ok(container.to_bytes())
+        });
+    }
+    let png = parse_png(&buffer)?;
 
-    if png.chunk_by_type(chunk_type).is_none() {
-        println!("   Removed: ❌ Failed to remove chunk -> chunk not found");
-        println!("💡 Tip: Use 'restore' command if you need to revert changes");
-        return Ok(());
+    if png
+        .chunk_by_type_nth(chunk_type, index.unwrap_or(0))
+        .is_none()
+    {
+        let count = png.chunks_by_type(chunk_type).count();
+        return Err(missing_chunk(chunk_type, index, count));
     }
 
     // Create backup silently and perform removal
     handler.atomic_modify_silent(|content| {
-        let mut png =
-            Png::try_from(content.as_slice()).map_err(|e| format!("Failed to parse PNG: {}", e))?;
+        let mut png = parse_png(&content)?;
+        detail_chunks(&png, chunk_type);
+
+        if let Some(index) = index {
+            png.remove_chunk_nth(chunk_type, index)
+                .map_err(|e| format!("Failed to remove chunk: {}", e))?;
+            say!("   Index: {}", index);
+            say!("   Removed: ✅ Successfully");
+            return Ok(png.as_bytes());
+        }
 
-        png.remove_chunk(chunk_type)
+        let removed = png
+            .remove_chunk(chunk_type)
             .map_err(|e| format!("Failed to remove chunk: {}", e))?;
 
-        println!("   Removed: ✅ Successfully");
+        // A message written with --max-chunk-size spans every chunk of its type
+        if is_fragment(removed.data()) {
+            let mut fragments = 1;
+            while png.remove_chunk(chunk_type).is_ok() {
+                fragments += 1;
+            }
+            say!("   Fragments: {}", fragments);
+        }
+
+        say!("   Removed: ✅ Successfully");
         Ok(png.as_bytes())
     })
 }
 
-pub fn restore_original(path: &str) -> Result<()> {
+pub fn restore_original(path: &Path, version: Option<&str>) -> Result<()> {
     // Check if the provided path is a backup file
-    if path.ends_with(".backup") {
+    if let Some(original_path) = atomic_file::backup_original(path) {
+        if version.is_some() {
+            return Err(
+                "--version picks among the backups of an image: pass the image, \
+                        not a backup file"
+                    .into(),
+            );
+        }
         // User provided backup file path, restore to original
-        let original_path = path.strip_suffix(".backup").unwrap();
-
-        println!("🔄 Restoring original file from backup...");
-        println!("  From: {}", path);
+        say!("🔄 Restoring original file from backup...");
+        say!("  From: {}", path.display());
 
-        if !std::path::Path::new(path).exists() {
-            return Err(format!("Backup file '{}' not found", path).into());
+        if !path.exists() {
+            return Err(
+                Failure::FileNotFound.error(format!("Backup file '{}' not found", path.display()))
+            );
+        }
+        if atomic_file::is_dry_run() {
+            let current = fs::read(&original_path).map_err(|e| {
+                Failure::from(&e).error(format!(
+                    "Failed to read '{}': {}",
+                    original_path.display(),
+                    e
+                ))
+            })?;
+            report_dry_run(&original_path, &current, &atomic_file::read_backup(path)?);
+            return Ok(());
         }
 
-        std::fs::copy(path, original_path)
+        let _lock = FileLock::acquire(&original_path, true)?;
+        atomic_file::restore_backup(path, &original_path)
             .map_err(|e| format!("Failed to restore from backup: {}", e))?;
 
-        println!("✅ Original file restored successfully");
+        say!("✅ Original file restored successfully");
         return Ok(());
     }
 
@@ -186,26 +3615,226 @@ pub fn restore_original(path: &str) -> Result<()> {
     let handler = AtomicFileHandler::new(path)?;
 
     if !handler.has_backup() {
-        return Err(format!(
+        return Err(Failure::FileNotFound.error(format!(
             "No backup found for '{}'. File may already be in original state.",
-            path
+            path.display()
+        )));
+    }
+
+    match version {
+        None => handler.restore_original(),
+        Some("") if output::is_json() => {
+            Err("Pass the number of the version to restore, --version N, with --json".into())
+        }
+        Some("") => {
+            let backups = handler.backups();
+            say!("🗂️  Backups of '{}':", handler.target_path().display());
+            print_backups(&backups);
+            let prompt = format!("Restore which version? [1-{}]: ", backups.len());
+            let answer = prompt::read_line(&prompt)?;
+            handler.restore_from(&handler.find_backup(answer.trim())?)
+        }
+        Some(version) => handler.restore_from(&handler.find_backup(version)?),
+    }
+}
+
+/// Reverts the most recent encode or remove recorded in the journal for the image at
+/// `path`, or for any image in the directory at `path`, from the backup made before it
+pub fn undo(path: &Path) -> Result<()> {
+    let (dir, file) = if path.is_dir() {
+        (path, None)
+    } else {
+        (atomic_file::parent_dir(path), path.file_name())
+    };
+    let entries = journal::entries(dir)?;
+    let (index, entry) = entries
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, entry)| file.is_none_or(|file| file == entry.file))
+        .ok_or_else(|| format!("No encode or remove of '{}' to undo", path.display()))?;
+
+    let target = dir.join(&entry.file);
+    say!(
+        "↩️  Undoing the {} of chunk '{}' in '{}' ({})",
+        entry.operation,
+        entry.chunk,
+        target.display(),
+        date::format_timestamp(entry.timestamp)
+    );
+
+    if !entry.backup.exists() {
+        return Err(format!(
+            "The backup made before the {}, '{}', no longer exists",
+            entry.operation,
+            entry.backup.display()
+        )
+        .into());
+    }
+    let original = atomic_file::read_backup(&entry.backup)?;
+    if journal::digest(&original) != entry.before {
+        return Err(format!(
+            "Backup '{}' is not the file as it was before the {}",
+            entry.backup.display(),
+            entry.operation
         )
         .into());
     }
 
-    handler.restore_original()
+    let handler = AtomicFileHandler::new(&target)?;
+    // Only the exact file the change produced is reverted, so that undo never throws
+    // away a change made since
+    let check_unchanged = |current: &[u8]| -> Result<()> {
+        if journal::digest(current) != entry.after {
+            return Err(format!(
+                "'{}' has changed since the {}: use 'restore --version' to pick a backup \
+                 instead",
+                target.display(),
+                entry.operation
+            )
+            .into());
+        }
+        Ok(())
+    };
+    if atomic_file::is_dry_run() {
+        let current = handler.read_file()?;
+        check_unchanged(&current)?;
+        report_dry_run(&target, &current, &original);
+        return Ok(());
+    }
+    // Like restore, undo makes no backup of its own: the file goes back to one that
+    // exists already, and pruning for a new one could delete the backups that older
+    // entries of the journal still need. Checked once the file is locked, so that no
+    // other run can change it in between.
+    let _lock = FileLock::acquire(&target, false)?;
+    check_unchanged(&handler.read_file()?)?;
+    atomic_file::restore_backup(&entry.backup, &target)?;
+    journal::remove(dir, index)?;
+    say!(" ✅ Reverted '{}'", target.display());
+    Ok(())
 }
 
-pub fn cleanup_files(path: &str) -> Result<()> {
-    let handler = AtomicFileHandler::new(path)?;
-    handler.cleanup()
+/// Lists backup versions, numbered from the newest as `restore --version` takes them,
+/// with when they were made and their size
+fn print_backups(backups: &[Backup]) {
+    for (number, backup) in backups.iter().enumerate() {
+        let size = fs::metadata(&backup.path).map(|m| m.len()).unwrap_or(0);
+        let created = match backup.created {
+            Some(created) => date::format_timestamp(created),
+            None => "made by an earlier version".to_string(),
+        };
+        let compressed = if atomic_file::is_compressed_backup(&backup.path) {
+            ", compressed"
+        } else {
+            ""
+        };
+        let verified = atomic_file::verify_backup(&backup.path);
+        let (integrity, checksum) = match verified {
+            Ok(Some(true)) => ("checksum ✅", "valid"),
+            Ok(Some(false)) => ("checksum ❌ CORRUPTED", "corrupted"),
+            Ok(None) => ("no checksum", "none"),
+            Err(_) => ("unreadable ❌", "unreadable"),
+        };
+        output::push(
+            "backups",
+            json!({
+                "path": backup.path.to_string_lossy(),
+                "created": backup.created,
+                "size": size,
+                "compressed": !compressed.is_empty(),
+                "checksum": checksum,
+            }),
+        );
+        say!(
+            "     {}. {} ({}, {} bytes{}, {})",
+            number + 1,
+            backup.path.display(),
+            created,
+            size,
+            compressed,
+            integrity
+        );
+    }
+}
+
+pub fn cleanup_files(
+    path: &Path,
+    prune: bool,
+    keep_last: Option<usize>,
+    older_than: Option<&str>,
+    trash: bool,
+) -> Result<()> {
+    if !prune {
+        let handler = AtomicFileHandler::new(path)?;
+        return handler.cleanup(trash);
+    }
+
+    let older_than = older_than.map(date::parse_age).transpose()?;
+    let files = if path.is_dir() {
+        atomic_file::backed_up_files(path)
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut total = 0;
+    output::set("trash", trash);
+    output::set("pruned", Vec::<Value>::new());
+    for file in &files {
+        let handler = AtomicFileHandler::new(file)?;
+        let before = handler.backups().len();
+        let removed = handler.prune(keep_last, older_than, trash);
+        if !removed.is_empty() {
+            let backups: Vec<Value> = removed
+                .iter()
+                .map(|backup| backup.path.to_string_lossy().into())
+                .collect();
+            output::push(
+                "pruned",
+                json!({
+                    "file": file.to_string_lossy(),
+                    "backups": backups,
+                    "kept": before - removed.len(),
+                }),
+            );
+        }
+        let removed = removed.len();
+        if removed > 0 {
+            say!(
+                " 🧹  {}: {} backup version(s) {}, {} kept",
+                file.display(),
+                removed,
+                match (atomic_file::is_dry_run(), trash) {
+                    (true, _) => "would be removed",
+                    (false, true) => "moved to the trash",
+                    (false, false) => "removed",
+                },
+                before - removed
+            );
+            total += removed;
+        }
+    }
+
+    output::set("removed", total);
+    if total == 0 {
+        say!(" ℹ️   No backups to prune");
+    } else if files.len() > 1 {
+        say!(
+            " 🧹  Pruned {} backup file(s) across {} images",
+            total,
+            files.len()
+        );
+    }
+    Ok(())
 }
 
-pub fn show_status(path: &str) -> Result<()> {
+pub fn show_status(path: &Path) -> Result<()> {
     let handler = AtomicFileHandler::new(path)?;
 
-    println!("📊  File Status:");
-    println!(
+    say!("📊  File Status:");
+    output::set("file", handler.target_path().to_string_lossy());
+    output::set("exists", handler.target_path().exists());
+    output::set("backups", Vec::<Value>::new());
+    say!(
         "   Target file: {} {}",
         handler.target_path().display(),
         if handler.target_path().exists() {
@@ -214,15 +3843,786 @@ pub fn show_status(path: &str) -> Result<()> {
             "❌"
         }
     );
-    println!(
-        "   Backup file: {} {}",
-        handler.backup_path().display(),
-        if handler.has_backup() { "✅" } else { "❌" }
-    );
+    let backups = handler.backups();
+    if backups.is_empty() {
+        say!("   Backup files: none ❌");
+    } else {
+        say!("   Backup files: {} ✅ (newest first)", backups.len());
+    }
+    print_backups(&backups);
 
-    if handler.has_backup() {
-        println!("💡 Use 'restore' command to revert to original");
+    if !backups.is_empty() {
+        say!("💡 Use 'restore' command to revert to original ('--version N' for an older one)");
     }
 
     Ok(())
 }
+
+pub fn completions(shell: Shell) -> Result<()> {
+    let command = Args::command();
+    // The script is the output itself, so it is printed as it is even with --plain
+    print!("{}", completions::script(shell, &command));
+    Ok(())
+}
+
+pub fn generate_man(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).map_err(|e| {
+        Failure::from(&e).error(format!("Failed to create '{}': {}", dir.display(), e))
+    })?;
+    let pages = man::pages(&Args::command());
+    for (name, roff) in &pages {
+        let path = dir.join(name);
+        fs::write(&path, roff).map_err(|e| {
+            Failure::from(&e).error(format!("Failed to write '{}': {}", path.display(), e))
+        })?;
+        detail!("  • {}", path.display());
+        output::push("written", path.to_string_lossy());
+    }
+    say!(
+        "📖  Wrote {} man page(s) to '{}'",
+        pages.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+pub fn complete(words: &[String]) -> Result<()> {
+    for candidate in completions::complete(&Args::command(), words) {
+        println!("{}", candidate);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zlib;
+    use clap::Parser;
+
+    /// A fresh directory for the files of test `name`
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hpv-json-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes an 8x8 RGB PNG with a tEXt chunk after its image data to `name` in `dir`
+    fn image(dir: &Path, name: &str) -> PathBuf {
+        let mut png = Png::builder(8, 8)
+            .color_type(2, 8)
+            .pixels((0..=191).collect())
+            .build()
+            .unwrap();
+        let text = Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"Comment\0hello".to_vec(),
+        );
+        png.insert_ancillary(text).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, png.as_bytes()).unwrap();
+        path
+    }
+
+    /// Parses `args` as the command line after the program name and runs the command
+    fn run_args(args: &[&str]) -> Result<()> {
+        let args =
+            crate::args::Args::try_parse_from(std::iter::once("hpv").chain(args.iter().copied()))?;
+        crate::run(&args.command)
+    }
+
+    /// Runs `run` as `command` with `--json` and gives back the result of the JSON
+    /// document it reports, parsed from its text
+    fn json_result(command: &str, run: impl FnOnce() -> Result<()>) -> Value {
+        output::set_json(true);
+        let result = run();
+        let document: Value =
+            serde_json::from_str(&output::document(command, &result).to_string()).unwrap();
+        output::set_json(false);
+        assert_eq!(document["command"], Value::from(command));
+        assert_eq!(document["ok"], Value::Bool(true), "{}", document);
+        document["result"].clone()
+    }
+
+    fn number(value: &Value) -> u64 {
+        value
+            .as_u64()
+            .unwrap_or_else(|| panic!("{} is not a number", value))
+    }
+
+    fn length(value: &Value) -> usize {
+        match value {
+            Value::Array(values) => values.len(),
+            other => panic!("{} is not an array", other),
+        }
+    }
+
+    #[test]
+    fn test_info_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("info"), "image.png");
+        let result = json_result("info", || info(&path));
+        assert_eq!(result["file"], Value::from(path.to_string_lossy()));
+        assert_eq!(result["width"], Value::from(8u32));
+        assert_eq!(result["height"], Value::from(8u32));
+        assert_eq!(result["bit_depth"], Value::from(8u32));
+        assert_eq!(result["color_type"], Value::from(2u32));
+        assert_eq!(result["interlaced"], Value::Bool(false));
+        assert_eq!(number(&result["size"]), fs::metadata(&path).unwrap().len());
+        assert_eq!(result["chunks"], Value::from(4usize));
+    }
+
+    #[test]
+    fn test_stats_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("stats"), "image.png");
+        let result = json_result("stats", || stats(&path));
+        assert_eq!(number(&result["size"]), fs::metadata(&path).unwrap().len());
+        assert_eq!(length(&result["types"]), 4);
+        assert_eq!(result["types"][2]["type"], Value::from("tEXt"));
+        assert_eq!(result["ancillary"]["count"], Value::from(1usize));
+        assert_eq!(result["vault"]["count"], Value::from(0usize));
+        assert!(result["image_data"]["percent"].is_f64());
+        assert_eq!(result["trailing_bytes"], Value::from(0usize));
+        assert_eq!(length(&result["largest"]), 3);
+    }
+
+    #[test]
+    fn test_capacity_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("capacity"), "image.png");
+        let result = json_result("capacity", || capacity(&path));
+        assert_eq!(result["pixel_format"], Value::from("8-bit RGB"));
+        assert_eq!(result["chunk_max_bytes"], Value::from(Chunk::MAX_LENGTH));
+        assert_eq!(length(&result["lsb"]), lsb::MAX_BITS as usize);
+        assert_eq!(result["lsb"][0]["bits"], Value::from(1u32));
+        assert!(number(&result["lsb"][0]["bytes"]) > 0);
+    }
+
+    #[test]
+    fn test_analyze_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("analyze");
+        let path = image(&dir, "image.png");
+        let data = dir.join("data.bin");
+        fs::write(&data, b"hidden").unwrap();
+        inject_chunk(&path, "ruSt", &data, None, None).unwrap();
+        let result = json_result("analyze", || analyze(&path));
+        assert_eq!(length(&result["chunks"]), 5);
+        assert_eq!(result["chunks"][0]["type"], Value::from("IHDR"));
+        assert_eq!(result["chunks"][0]["finding"], Value::Null);
+        assert_eq!(result["chunks"][3]["type"], Value::from("ruSt"));
+        assert_eq!(result["chunks"][3]["finding"], Value::from("unknown chunk"));
+        assert!(result["chunks"][3]["entropy"].is_f64());
+        assert!(result["chi_square"].is_f64());
+        assert_ne!(result["verdict"], Value::from("unlikely"));
+    }
+
+    #[test]
+    fn test_verify_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("verify");
+        let path = image(&dir, "image.png");
+        let key = dir.join("signing.key");
+        keygen(&key, true).unwrap();
+        sign(&path, &key, None).unwrap();
+        let public_key = dir.join("signing.key.pub");
+        let result = json_result("verify", || verify(&path, Some(&public_key), None));
+        assert_eq!(result["problems"], Value::Array(vec![]));
+        assert_eq!(result["chunks"], Value::from(5usize));
+        assert_eq!(length(&result["signatures"]), 1);
+        assert_eq!(result["signatures"][0]["scope"], Value::Null);
+        assert_eq!(result["signatures"][0]["valid"], Value::Bool(true));
+        assert_eq!(result["signatures"][0]["trusted"], Value::Bool(true));
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("diff");
+        let old = image(&dir, "old.png");
+        let new = image(&dir, "new.png");
+        let data = dir.join("data.bin");
+        fs::write(&data, b"hidden").unwrap();
+        inject_chunk(&new, "ruSt", &data, None, None).unwrap();
+        let result = json_result("diff", || diff(&old, &new));
+        assert_eq!(length(&result["changes"]), 1);
+        assert_eq!(result["changes"][0]["change"], Value::from("added"));
+        assert_eq!(result["changes"][0]["new"]["type"], Value::from("ruSt"));
+        assert_eq!(result["changes"][0]["new"]["length"], Value::from(6usize));
+        assert_eq!(result["reordered"], Value::Bool(false));
+        assert_eq!(result["pixels"], Value::from("identical"));
+    }
+
+    #[test]
+    fn test_dump_chunk_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("dump");
+        let path = image(&dir, "image.png");
+        let out = dir.join("text.bin");
+        let result = json_result("dump-chunk", || dump_chunk(&path, "tEXt", &out, None));
+        assert_eq!(result["chunk"], Value::from("tEXt"));
+        assert_eq!(result["index"], Value::from(0usize));
+        assert_eq!(result["count"], Value::from(1usize));
+        assert_eq!(result["written"], Value::from(out.to_string_lossy()));
+        assert_eq!(result["size"], Value::from(13usize));
+    }
+
+    #[test]
+    fn test_extract_pixels_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("extract");
+        let path = image(&dir, "image.png");
+        let out = dir.join("pixels.raw");
+        let result = json_result("extract-pixels", || {
+            extract_pixels(&path, &out, PixelFormat::Raw)
+        });
+        assert_eq!(result["width"], Value::from(8u32));
+        assert_eq!(result["format"], Value::from("raw"));
+        assert_eq!(result["written"], Value::from(out.to_string_lossy()));
+        assert_eq!(result["size"], Value::from(192usize));
+    }
+
+    #[test]
+    fn test_create_json() {
+        let _serial = output::serialize_tests();
+        let path = test_dir("create").join("new.png");
+        let result = json_result("create", || create(&path, 3, 2, "ff000080"));
+        assert_eq!(result["file"], Value::from(path.to_string_lossy()));
+        assert_eq!(result["width"], Value::from(3u32));
+        assert_eq!(result["height"], Value::from(2u32));
+        assert_eq!(result["pixel_format"], Value::from("8-bit RGBA"));
+        assert_eq!(number(&result["size"]), fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn test_keygen_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("keygen");
+        let key = dir.join("signing.key");
+        let result = json_result("keygen", || keygen(&key, true));
+        assert_eq!(result["private_key"], Value::from(key.to_string_lossy()));
+        let public_key = dir.join("signing.key.pub");
+        assert_eq!(
+            result["public_key"],
+            Value::from(public_key.to_string_lossy())
+        );
+        let written = fs::read_to_string(&public_key).unwrap();
+        assert_eq!(result["verifying_key"], Value::from(written.trim()));
+        assert_eq!(result["recipient"], Value::Null);
+    }
+
+    #[test]
+    fn test_sign_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("sign");
+        let path = image(&dir, "image.png");
+        let key = dir.join("signing.key");
+        keygen(&key, true).unwrap();
+        let result = json_result("sign", || sign(&path, &key, Some("tEXt")));
+        assert_eq!(result["scope"], Value::from("tEXt"));
+        let written = fs::read_to_string(dir.join("signing.key.pub")).unwrap();
+        assert_eq!(result["verifying_key"], Value::from(written.trim()));
+        assert_eq!(
+            result["changed"][0]["file"],
+            Value::from(path.to_string_lossy())
+        );
+    }
+
+    #[test]
+    fn test_strip_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("strip"), "image.png");
+        let result = json_result("strip", || strip(&path, &[]));
+        assert_eq!(result["removed"], Value::from(1usize));
+        assert_eq!(result["stripped"][0]["type"], Value::from("tEXt"));
+        assert_eq!(result["stripped"][0]["count"], Value::from(1usize));
+
+        let result = json_result("strip", || strip(&path, &[]));
+        assert_eq!(result["removed"], Value::from(0usize));
+        assert_eq!(result["stripped"], Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_optimize_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("optimize"), "image.png");
+        let result = json_result("optimize", || optimize(&path, true));
+        let (before, after) = (number(&result["before"]), number(&result["after"]));
+        assert!(after <= before);
+        assert_eq!(number(&result["saved"]), before - after);
+    }
+
+    #[test]
+    fn test_repair_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("repair"), "image.png");
+        let mut data = fs::read(&path).unwrap();
+        // The last byte of the CRC of IHDR, which ends 33 bytes into the file
+        data[32] ^= 0xff;
+        fs::write(&path, data).unwrap();
+        let result = json_result("repair", || repair(&path));
+        assert_eq!(length(&result["fixes"]), 1);
+        assert_eq!(result["remaining_problem"], Value::Null);
+
+        let result = json_result("repair", || repair(&path));
+        assert_eq!(result["fixes"], Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_move_chunk_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("move"), "image.png");
+        let placement = PlacementArgs {
+            before: Some("IDAT".to_string()),
+            after: None,
+            index: None,
+        };
+        let result = json_result("move-chunk", || move_chunk(&path, "tEXt", &placement));
+        assert_eq!(result["chunk"], Value::from("tEXt"));
+        assert_eq!(result["count"], Value::from(1usize));
+        assert_eq!(result["position"], Value::from(1usize));
+    }
+
+    #[test]
+    fn test_rename_chunk_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("rename"), "image.png");
+        let result = json_result("rename-chunk", || rename_chunk(&path, "tEXt", "ruSt"));
+        assert_eq!(result["old_type"], Value::from("tEXt"));
+        assert_eq!(result["new_type"], Value::from("ruSt"));
+        assert_eq!(result["count"], Value::from(1usize));
+    }
+
+    #[test]
+    fn test_inject_chunk_json() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("inject");
+        let path = image(&dir, "image.png");
+        let data = dir.join("data.bin");
+        fs::write(&data, b"hidden").unwrap();
+        let result = json_result("inject-chunk", || {
+            inject_chunk(&path, "ruSt", &data, None, None)
+        });
+        assert_eq!(result["file"], Value::from(path.to_string_lossy()));
+        assert_eq!(result["chunk"], Value::from("ruSt"));
+        assert_eq!(result["source"], Value::from(data.to_string_lossy()));
+        assert_eq!(result["size"], Value::from(6usize));
+    }
+
+    #[test]
+    fn test_cleanup_files_json() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("cleanup"), "image.png");
+        strip(&path, &[]).unwrap();
+        let result = json_result("cleanup-files", || {
+            cleanup_files(&path, true, Some(0), None, false)
+        });
+        assert_eq!(result["removed"], Value::from(1usize));
+        assert_eq!(
+            result["pruned"][0]["file"],
+            Value::from(path.to_string_lossy())
+        );
+        assert_eq!(length(&result["pruned"][0]["backups"]), 1);
+        assert_eq!(result["pruned"][0]["kept"], Value::from(0usize));
+
+        let data = path.with_extension("bin");
+        fs::write(&data, b"hidden").unwrap();
+        inject_chunk(&path, "ruSt", &data, None, None).unwrap();
+        let result = json_result("cleanup-files", || {
+            cleanup_files(&path, false, None, None, false)
+        });
+        assert_eq!(result["file"], Value::from(path.to_string_lossy()));
+        assert_eq!(result["temp_files"], Value::from(0usize));
+        assert_eq!(length(&result["backups"]), 1);
+    }
+
+    #[test]
+    fn test_lsb_encode_rejects_oversized_image() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("oversized");
+        let mut ihdr = Vec::new();
+        ihdr.extend(0x7FFF_FFFFu32.to_be_bytes());
+        ihdr.extend(0x7FFF_FFFFu32.to_be_bytes());
+        ihdr.extend([16, 2, 0, 0, 0]);
+        let png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), zlib::deflate(&[0])),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]);
+        let path = dir.join("huge.png");
+        fs::write(&path, png.as_bytes()).unwrap();
+
+        assert!(capacity(&path).is_err());
+        let path_arg = path.to_str().unwrap();
+        assert!(run_args(&["encode", path_arg, "teSt", "hi", "--lsb"]).is_err());
+        assert_eq!(fs::read(&path).unwrap(), png.as_bytes());
+        // No temporary file or backup left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_validate_chunk_type_rejects_non_ascii() {
+        // Four bytes, but only two characters
+        assert_eq!("éé".len(), 4);
+        assert!(validate_chunk_type("éé").is_err());
+        assert!(validate_chunk_type("ruSé").is_err());
+        assert!(validate_chunk_type("ruSt").is_ok());
+    }
+
+    #[test]
+    fn test_non_ascii_chunk_type_is_a_validation_failure() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("non-ascii"), "image.png");
+        let before = fs::read(&path).unwrap();
+        let error = run_args(&["encode", path.to_str().unwrap(), "éé", "secret"]).unwrap_err();
+        assert_eq!(Failure::of(error.as_ref()), Failure::Validation);
+        assert_eq!(Failure::of(error.as_ref()).exit_code(), 6);
+        assert_eq!(fs::read(&path).unwrap(), before);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_encode_keeps_permissions_and_times() {
+        let _serial = output::serialize_tests();
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, SystemTime};
+
+        let path = image(&test_dir("metadata"), "image.png");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_times(fs::FileTimes::new().set_modified(modified))
+            .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        run_args(&["encode", path.to_str().unwrap(), "ruSt", "secret"]).unwrap();
+        assert_ne!(fs::read(&path).unwrap(), before);
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(metadata.modified().unwrap(), modified);
+    }
+
+    /// The chunk and message of each message a decode reported in `result`
+    fn found_messages(result: &Value) -> Vec<(String, String)> {
+        let Value::Array(found) = &result["found"] else {
+            return vec![];
+        };
+        found
+            .iter()
+            .map(|found| match (&found["chunk"], &found["message"]) {
+                (Value::String(chunk), Value::String(message)) => (chunk.clone(), message.clone()),
+                other => panic!("{:?} is no message", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decoy_reveals_nothing_of_the_real_message() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("decoy");
+        let path = image(&dir, "image.png");
+        let plans = dir.join("plans-2024.pdf");
+        fs::write(&plans, "the real plans ".repeat(20)).unwrap();
+        let path_arg = path.to_str().unwrap();
+        run_args(&[
+            "encode",
+            path_arg,
+            "ruSt",
+            "--file",
+            plans.to_str().unwrap(),
+            "--compress",
+            "zstd",
+            "--expires",
+            "2999-12-31",
+            "--password",
+            "real",
+            "--decoy",
+            "shopping list",
+            "--decoy-password",
+            "decoy",
+        ])
+        .unwrap();
+
+        let result = json_result("decode", || {
+            run_args(&["decode", path_arg, "ruSt", "--password", "decoy"])
+        });
+        assert_eq!(
+            found_messages(&result),
+            [("ruSt".to_string(), "shopping list".to_string())]
+        );
+        let found = &result["found"][0];
+        assert_eq!(found["expires"], Value::Null);
+        assert_eq!(found["written"], Value::Null);
+        assert!(!found.to_string().contains("plans"));
+
+        // Both halves are as long, whichever message is longer
+        let png = parse_png(&fs::read(&path).unwrap()).unwrap();
+        let (_, body) = payload::unwrap(png.chunk_by_type("ruSt").unwrap().data()).unwrap();
+        let decoy = crypto::decrypt(b"decoy", body).unwrap();
+        let real = crypto::decrypt(b"real", body).unwrap();
+        assert!(decoy.expose().len() < real.expose().len());
+        assert!((body.len() - 13).is_multiple_of(2));
+
+        let out = dir.join("out.pdf");
+        let decode = ["decode", path_arg, "ruSt", "--password", "real", "--out"];
+        run_args(&[&decode[..], &[out.to_str().unwrap()]].concat()).unwrap();
+        assert_eq!(fs::read(&out).unwrap(), fs::read(&plans).unwrap());
+    }
+
+    #[test]
+    fn test_decode_all() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("decode-all"), "image.png");
+        let path_arg = path.to_str().unwrap();
+        run_args(&["encode", path_arg, "ruSt", "first"]).unwrap();
+        run_args(&["encode", path_arg, "teSt", "second"]).unwrap();
+        run_args(&["encode", path_arg, "seCr", "hidden", "--password", "pw"]).unwrap();
+
+        // The encrypted message is passed over without a password, the others shown
+        let result = json_result("decode", || run_args(&["decode", path_arg, "--all"]));
+        let expected = [("ruSt", "first"), ("teSt", "second")];
+        assert_eq!(
+            found_messages(&result),
+            expected.map(|(chunk, message)| (chunk.to_string(), message.to_string()))
+        );
+
+        let result = json_result("decode", || {
+            run_args(&["decode", path_arg, "--all", "--password", "pw"])
+        });
+        let expected = [("ruSt", "first"), ("teSt", "second"), ("seCr", "hidden")];
+        assert_eq!(
+            found_messages(&result),
+            expected.map(|(chunk, message)| (chunk.to_string(), message.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_update_replaces_the_chunk_in_place() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("update"), "image.png");
+        let path_arg = path.to_str().unwrap();
+        run_args(&["encode", path_arg, "ruSt", "old message"]).unwrap();
+        add_text_chunk(&path);
+        let before = parse_png(&fs::read(&path).unwrap()).unwrap();
+
+        run_args(&["update", path_arg, "ruSt", "new message"]).unwrap();
+        let after = parse_png(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(after.chunks().len(), before.chunks().len());
+        for (old, new) in before.chunks().iter().zip(after.chunks()) {
+            assert_eq!(old.chunk_type(), new.chunk_type());
+            if old.chunk_type().to_string() != "ruSt" {
+                assert_eq!(old.as_bytes(), new.as_bytes());
+            }
+        }
+        let result = json_result("decode", || run_args(&["decode", path_arg, "ruSt"]));
+        assert_eq!(
+            found_messages(&result),
+            [("ruSt".to_string(), "new message".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_encode_to_output_leaves_the_source_untouched() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("output");
+        let path = image(&dir, "image.png");
+        let out = dir.join("out.png");
+        let before = fs::read(&path).unwrap();
+
+        let (path_arg, out_arg) = (path.to_str().unwrap(), out.to_str().unwrap());
+        run_args(&["encode", path_arg, "ruSt", "secret", "--output", out_arg]).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert_eq!(
+            fs::read_dir(&dir).unwrap().count(),
+            2,
+            "no backup or temp file"
+        );
+        let result = json_result("decode", || run_args(&["decode", out_arg, "ruSt"]));
+        assert_eq!(
+            found_messages(&result),
+            [("ruSt".to_string(), "secret".to_string())]
+        );
+    }
+
+    /// Adds a tEXt chunk after the others of the PNG file at `path`
+    fn add_text_chunk(path: &Path) {
+        let text = Chunk::new(
+            ChunkType::from_str("tEXt").unwrap(),
+            b"Title\0later".to_vec(),
+        );
+        let mut png = parse_png(&fs::read(path).unwrap()).unwrap();
+        png.insert_ancillary(text).unwrap();
+        fs::write(path, png.as_bytes()).unwrap();
+    }
+
+    /// The chunk types of the PNG file at `path`, in file order
+    fn chunk_types(path: &Path) -> Vec<String> {
+        parse_png(&fs::read(path).unwrap())
+            .unwrap()
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rekey_keeps_the_chunk_in_place() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("rekey"), "image.png");
+        let path_arg = path.to_str().unwrap();
+        run_args(&["encode", path_arg, "seCr", "secret", "--password", "old"]).unwrap();
+        add_text_chunk(&path);
+        let before = chunk_types(&path);
+
+        run_args(&[
+            "rekey",
+            path_arg,
+            "seCr",
+            "--password",
+            "old",
+            "--new-password",
+            "new",
+        ])
+        .unwrap();
+        assert_eq!(chunk_types(&path), before);
+        run_args(&["decode", path_arg, "seCr", "--password", "new"]).unwrap();
+        assert!(run_args(&["decode", path_arg, "seCr", "--password", "old"]).is_err());
+    }
+
+    #[test]
+    fn test_rekey_renames_an_obfuscated_chunk() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("rekey-obfuscated"), "image.png");
+        let path_arg = path.to_str().unwrap();
+        let obfuscated = ["--obfuscate-chunk"];
+        let encode = ["encode", path_arg, "note", "secret", "--password", "old"];
+        run_args(&[&encode[..], &obfuscated].concat()).unwrap();
+        let old_name = crypto::derive_chunk_type(b"old", "note").unwrap();
+        let new_name = crypto::derive_chunk_type(b"new", "note").unwrap();
+        let position = chunk_types(&path).iter().position(|name| *name == old_name);
+        assert!(position.is_some());
+
+        let rekey = [
+            "rekey",
+            path_arg,
+            "note",
+            "--password",
+            "old",
+            "--new-password",
+            "new",
+        ];
+        run_args(&[&rekey[..], &obfuscated].concat()).unwrap();
+        let names = chunk_types(&path);
+        assert!(!names.contains(&old_name));
+        assert_eq!(names.iter().position(|name| *name == new_name), position);
+        let decode = ["decode", path_arg, "note", "--password", "new"];
+        run_args(&[&decode[..], &obfuscated].concat()).unwrap();
+    }
+
+    #[test]
+    fn test_rekey_every_fragment() {
+        let _serial = output::serialize_tests();
+        let path = image(&test_dir("rekey-fragments"), "image.png");
+        let path_arg = path.to_str().unwrap();
+        let message = "a message long enough to need several chunks ".repeat(8);
+        run_args(&[
+            "encode",
+            path_arg,
+            "seCr",
+            &message,
+            "--password",
+            "old",
+            "--max-chunk-size",
+            "64",
+        ])
+        .unwrap();
+        let before = chunk_types(&path);
+        assert!(before.iter().filter(|name| *name == "seCr").count() > 1);
+
+        run_args(&[
+            "rekey",
+            path_arg,
+            "seCr",
+            "--password",
+            "old",
+            "--new-password",
+            "new",
+        ])
+        .unwrap();
+        assert_eq!(chunk_types(&path), before);
+        let png = parse_png(&fs::read(&path).unwrap()).unwrap();
+        let blocks: Vec<&[u8]> = png
+            .chunks_by_type("seCr")
+            .map(|chunk| chunk.data())
+            .collect();
+        let data = collect_payload(&blocks, "seCr").unwrap().unwrap();
+        let (_, body) = payload::unwrap(&data).unwrap();
+        let opened = crypto::decrypt(b"new", body).unwrap();
+        let (_, opened) = payload::open_fields(opened.expose()).unwrap();
+        assert_eq!(opened, message.as_bytes());
+    }
+
+    #[test]
+    fn test_encrypted_payload_hides_its_header_fields() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("sealed-fields");
+        let path = image(&dir, "image.png");
+        let notes = dir.join("notes-2024.txt");
+        fs::write(&notes, "meet at the old mill").unwrap();
+        let path_arg = path.to_str().unwrap();
+        run_args(&[
+            "encode",
+            path_arg,
+            "ruSt",
+            "--file",
+            notes.to_str().unwrap(),
+            "--compress",
+            "gzip",
+            "--password",
+            "pw",
+        ])
+        .unwrap();
+
+        let png = parse_png(&fs::read(&path).unwrap()).unwrap();
+        let data = png.chunk_by_type("ruSt").unwrap().data();
+        let (header, _) = payload::unwrap(data).unwrap();
+        assert_eq!(header, Header::new(Kind::Password));
+        assert!(!data.windows(10).any(|window| window == b"notes-2024"));
+
+        // The restored file is created, never written over an existing one
+        let out = dir.join("out.txt");
+        let decode = ["decode", path_arg, "ruSt", "--password", "pw"];
+        run_args(&[&decode[..], &["--out", out.to_str().unwrap()]].concat()).unwrap();
+        assert_eq!(fs::read(&out).unwrap(), b"meet at the old mill");
+        fs::write(&out, "other").unwrap();
+        assert!(save_message(&out, b"message", true).is_err());
+        assert_eq!(fs::read(&out).unwrap(), b"other");
+    }
+
+    #[test]
+    fn test_undo_reverts_more_changes_than_backups_kept() {
+        let _serial = output::serialize_tests();
+        let dir = test_dir("undo");
+        let path = image(&dir, "image.png");
+        let original = fs::read(&path).unwrap();
+        let path_arg = path.to_str().unwrap();
+        let chunk_types = ["ruSa", "ruSb", "ruSc", "ruSd", "ruSe", "ruSf"];
+        let mut versions = vec![];
+        for chunk_type in chunk_types {
+            versions.push(fs::read(&path).unwrap());
+            run_args(&["encode", path_arg, chunk_type, "hello"]).unwrap();
+        }
+        // Encodes beyond --keep-backups prune the oldest backups, so only the last
+        // five can be undone
+        for version in versions.iter().skip(1).rev() {
+            undo(&path).unwrap();
+            assert_eq!(&fs::read(&path).unwrap(), version);
+        }
+        assert!(undo(&path).is_err());
+        assert_ne!(fs::read(&path).unwrap(), original);
+    }
+}
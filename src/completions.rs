@@ -0,0 +1,297 @@
+use crate::png::ChunkReader;
+use clap::{Arg, ArgAction, Command};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// Arguments that name chunk types, which are completed from the image named before them
+const CHUNK_TYPE_ARGS: [&str; 3] = ["chunk_type", "old_type", "chunk"];
+
+pub use clap_complete::Shell;
+
+/// The completion script for `shell`, for the program `root`, as clap_complete writes
+/// it. For bash, zsh and fish a hook first hands the words typed so far to the hidden
+/// `complete` command, which looks into the image for its chunk types, and leaves
+/// everything else to the generated script.
+pub fn script(shell: Shell, root: &Command) -> String {
+    let mut root = root.clone();
+    let bin = root.get_name().to_string();
+    let mut script = vec![];
+    clap_complete::generate(shell, &mut root, &bin, &mut script);
+    let script = String::from_utf8_lossy(&script).into_owned();
+    let hook = format!(
+        "_{}_chunk_types",
+        bin.to_lowercase()
+            .replace(|c: char| !c.is_alphanumeric(), "_")
+    );
+    match shell {
+        Shell::Bash => {
+            // The function name clap_complete gives the generated completion
+            let generated = format!("_{}", bin.replace('-', "__"));
+            script
+                + &format!(
+                    r#"
+{hook}() {{
+    local IFS=$'\n' output
+    output=$("{bin}" complete -- "${{COMP_WORDS[@]:1:COMP_CWORD}}" 2>/dev/null)
+    if [[ -n $output ]]; then
+        COMPREPLY=($output)
+        return 0
+    fi
+    {generated} "$@"
+}}
+complete -F {hook} -o bashdefault -o default {bin}
+"#
+                )
+        }
+        Shell::Zsh => {
+            let generated = format!("_{}", bin);
+            // Registered in place of the generated function, which it falls back on
+            let register = |function: &str| {
+                format!(
+                    "if [ \"$funcstack[1]\" = \"{generated}\" ]; then\n    {function} \"$@\"\nelse\n    compdef {function} {bin}\nfi\n"
+                )
+            };
+            script.replace(
+                &register(&generated),
+                &format!(
+                    r#"{hook}() {{
+    local -a types
+    types=("${{(@f)$("{bin}" complete -- "${{(@)words[2,CURRENT]}}" 2>/dev/null)}}")
+    if [[ -n ${{types[1]}} ]]; then
+        compadd -a types
+    else
+        {generated} "$@"
+    fi
+}}
+
+{register}"#,
+                    register = register(&hook)
+                ),
+            )
+        }
+        Shell::Fish => {
+            script
+                + &format!(
+                    r#"
+function __fish{hook}
+    set -l types ({bin} complete -- (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)
+    printf '%s\n' $types
+    set -q types[1]
+end
+complete -c {bin} -n '__fish{hook} >/dev/null' -f -a '(__fish{hook})'
+"#
+                )
+        }
+        _ => script,
+    }
+}
+
+/// Whether `arg` takes a value in the word after it; optional values, as of
+/// `--backup-dir`, must be given with `=`
+fn takes_value(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Set | ArgAction::Append)
+        && arg
+            .get_num_args()
+            .is_none_or(|range| range.min_values() > 0)
+}
+
+fn find_long<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(name))
+}
+
+fn find_short(command: &Command, name: char) -> Option<&Arg> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_short() == Some(name))
+}
+
+/// The chunk types in the PNG file at `path` besides the critical ones, which are what
+/// commands taking a chunk type are given; none when it cannot be read
+fn chunk_types(path: &Path) -> Vec<String> {
+    let mut types = BTreeSet::new();
+    let Ok(file) = File::open(path) else {
+        return vec![];
+    };
+    let Ok(mut chunks) = ChunkReader::new(BufReader::new(file)) else {
+        return vec![];
+    };
+    while let Ok(Some(header)) = chunks.next_header() {
+        if !header.chunk_type.is_critical() {
+            types.insert(header.chunk_type.to_string());
+        }
+        if chunks.skip_chunk().is_err() {
+            break;
+        }
+    }
+    types.into_iter().collect()
+}
+
+/// The chunk types in the image `positionals` start with when `arg` takes a chunk type;
+/// empty for any other argument
+fn values(arg: &Arg, positionals: &[&str]) -> Vec<String> {
+    match positionals.first() {
+        Some(path) if CHUNK_TYPE_ARGS.contains(&arg.get_id().as_str()) => {
+            chunk_types(Path::new(path))
+        }
+        _ => vec![],
+    }
+}
+
+/// The chunk types that complete the last of `words`, which are typed after the program
+/// name, for the program `root`. Empty when the word is not a chunk type, which the
+/// generated script completes instead.
+pub fn complete(root: &Command, words: &[String]) -> Vec<String> {
+    let Some((current, before)) = words.split_last() else {
+        return vec![];
+    };
+    let mut root = root.clone();
+    // Global options are only copied into the subcommands when built
+    root.build();
+
+    let mut command = &root;
+    let mut positionals: Vec<&str> = vec![];
+    let mut pending: Option<&Arg> = None;
+    let mut options_done = false;
+    for word in before {
+        if pending.take().is_some() {
+            continue;
+        }
+        if !options_done && word == "--" {
+            options_done = true;
+        } else if !options_done && let Some(long) = word.strip_prefix("--") {
+            pending = find_long(command, long).filter(|arg| takes_value(arg));
+        } else if !options_done && word.len() > 1 && word.starts_with('-') {
+            // A value may be attached to a short option, as in -ofile.png
+            for (i, short) in word.char_indices().skip(1) {
+                match find_short(command, short) {
+                    Some(arg) if takes_value(arg) => {
+                        if i + short.len_utf8() == word.len() {
+                            pending = Some(arg);
+                        }
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        } else if let Some(subcommand) = positionals
+            .is_empty()
+            .then(|| command.find_subcommand(word))
+            .flatten()
+        {
+            command = subcommand;
+        } else {
+            positionals.push(word);
+        }
+    }
+
+    let candidates = if let Some(arg) = pending {
+        values(arg, &positionals)
+    } else if let Some(value) = current
+        .strip_prefix("--")
+        .and_then(|option| option.split_once('='))
+        .and_then(|(long, _)| find_long(command, long))
+        .map(|arg| values(arg, &positionals))
+    {
+        let long = &current[..current.find('=').unwrap_or_default()];
+        value
+            .into_iter()
+            .map(|value| format!("{}={}", long, value))
+            .collect()
+    } else if (current.starts_with('-') && !options_done)
+        || (positionals.is_empty() && command.has_subcommands())
+    {
+        vec![]
+    } else {
+        let mut args = command.get_positionals();
+        let arg = args.nth(positionals.len()).or_else(|| {
+            command
+                .get_positionals()
+                .last()
+                .filter(|arg| matches!(arg.get_action(), ArgAction::Append))
+        });
+        arg.map(|arg| values(arg, &positionals)).unwrap_or_default()
+    };
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use crate::chunk::Chunk;
+    use crate::png::Png;
+    use clap::CommandFactory;
+
+    fn complete_words(words: &[&str]) -> Vec<String> {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        complete(&Args::command(), &words)
+    }
+
+    #[test]
+    fn test_leaves_other_words_to_the_script() {
+        assert!(complete_words(&["re"]).is_empty());
+        assert!(complete_words(&["--keep-backups", "3", "cleanup", "--p"]).is_empty());
+        assert!(complete_words(&["encode", "a.png", "ruSt", "hi", "--cipher", "c"]).is_empty());
+        assert!(complete_words(&["print", ""]).is_empty());
+        assert!(complete_words(&["encode", "a.png", "ruSt", "hi", "-o", ""]).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_types_from_image() {
+        let path = std::env::temp_dir().join(format!("hpv-complete-{}.png", std::process::id()));
+        let chunk =
+            |chunk_type: &str, data: &[u8]| Chunk::new(chunk_type.parse().unwrap(), data.to_vec());
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("ruSt", b"one"),
+            chunk("tEXt", b"Title\0two"),
+            chunk("ruSt", b"three"),
+            chunk("IEND", b""),
+        ]);
+        std::fs::write(&path, png.as_bytes()).unwrap();
+        let path_str = path.to_str().unwrap();
+
+        assert_eq!(complete_words(&["decode", path_str, ""]), ["ruSt", "tEXt"]);
+        assert_eq!(complete_words(&["remove", path_str, "r"]), ["ruSt"]);
+        assert_eq!(
+            complete_words(&["sign", path_str, "--key", "k", "--chunk", "t"]),
+            ["tEXt"]
+        );
+        // A new chunk type is not completed
+        assert!(complete_words(&["rename-chunk", path_str, "ruSt", ""]).is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_scripts_hook_chunk_types_into_generated_ones() {
+        let script = |shell| script(shell, &Args::command());
+        let bash = script(Shell::Bash);
+        assert!(bash.starts_with("_Hidden__Pixel__Vault() {"));
+        assert!(bash.contains("    _Hidden__Pixel__Vault \"$@\""));
+        assert!(bash.ends_with(
+            "complete -F _hidden_pixel_vault_chunk_types -o bashdefault -o default \
+             Hidden-Pixel-Vault\n"
+        ));
+
+        let zsh = script(Shell::Zsh);
+        assert!(zsh.starts_with("#compdef Hidden-Pixel-Vault\n"));
+        assert!(zsh.contains("compdef _hidden_pixel_vault_chunk_types Hidden-Pixel-Vault\n"));
+        assert!(!zsh.contains("compdef _Hidden-Pixel-Vault Hidden-Pixel-Vault"));
+
+        let fish = script(Shell::Fish);
+        assert!(fish.contains("complete -c Hidden-Pixel-Vault -n '__fish_hidden_pixel_vault"));
+
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            assert!(script(shell).contains(" complete -- "), "{:?}", shell);
+            assert!(script(shell).contains("obfuscate-chunk"), "{:?}", shell);
+        }
+        assert!(script(Shell::PowerShell).contains("Register-ArgumentCompleter"));
+    }
+}
@@ -0,0 +1,183 @@
+mod bmp;
+mod flac;
+mod gif;
+mod jpeg;
+mod riff;
+mod tiff;
+mod wav;
+mod webp;
+
+use crate::Result;
+use crate::exit::Failure;
+use crate::pixels::Pixels;
+use crate::png::Png;
+
+/// A block of a container file as listed by `print`
+pub struct Block<'a> {
+    /// Format-specific name, such as "APP0 JFIF" for a JPEG segment
+    pub name: String,
+    /// Contents of the block, only the payload for vault blocks
+    pub data: &'a [u8],
+    /// Label of a block written by `encode`, `None` for the file's own blocks
+    pub label: Option<&'a str>,
+}
+
+/// A non-PNG file that `encode`, `decode`, `print` and `remove` can hide messages in.
+///
+/// Messages live in labelled vault blocks that readers of the format skip, the
+/// counterpart of the ancillary chunks used in PNG files.
+pub trait Container {
+    /// Format name for messages, e.g. "JPEG"
+    fn format(&self) -> &'static str;
+    /// What a vault block is called in this format, e.g. "APP15 segment"
+    fn block_kind(&self) -> &'static str;
+    /// Largest payload one vault block labelled `label` can hold
+    fn max_payload(&self, label: &str) -> usize;
+    /// Every block of the file in file order, vault blocks included
+    fn blocks(&self) -> Vec<Block<'_>>;
+    /// Data of every vault block labelled `label`, in file order
+    fn payloads(&self, label: &str) -> Vec<&[u8]>;
+    /// Adds a vault block labelled `label` after any earlier ones
+    fn insert(&mut self, label: &str, data: &[u8]) -> Result<()>;
+    /// Removes every vault block labelled `label`, returning how many there were
+    fn remove(&mut self, label: &str) -> usize;
+    fn to_bytes(&self) -> Vec<u8>;
+    /// Image samples that `--lsb` can hide messages in, for formats that store them
+    /// uncompressed
+    fn pixels(&self) -> Result<Pixels> {
+        Err(format!("{} files cannot carry --lsb messages", self.format()).into())
+    }
+    /// Writes back samples returned by `pixels`
+    fn set_pixels(&mut self, _pixels: &Pixels) -> Result<()> {
+        Err(format!("{} files cannot carry --lsb messages", self.format()).into())
+    }
+    /// What `--lsb` messages call the samples and one of their channels
+    fn sample_names(&self) -> (&'static str, &'static str) {
+        PIXEL_NAMES
+    }
+}
+
+/// [`Container::sample_names`] of images
+pub const PIXEL_NAMES: (&str, &str) = ("pixels", "color channel");
+
+/// File formats recognized by their magic bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Bmp,
+    Webp,
+    Tiff,
+    Wav,
+    Flac,
+}
+
+impl Format {
+    pub const ALL: [Format; 8] = [
+        Format::Png,
+        Format::Jpeg,
+        Format::Gif,
+        Format::Bmp,
+        Format::Webp,
+        Format::Tiff,
+        Format::Wav,
+        Format::Flac,
+    ];
+
+    /// The format `data` starts like, if any
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        Format::ALL.into_iter().find(|format| match format {
+            Format::Png => data.starts_with(&Png::STANDARD_HEADER),
+            Format::Jpeg => data.starts_with(&jpeg::MAGIC),
+            Format::Gif => data.starts_with(gif::MAGIC),
+            Format::Bmp => data.starts_with(bmp::MAGIC),
+            Format::Webp => webp::detect(data),
+            Format::Tiff => tiff::detect(data),
+            Format::Wav => wav::detect(data),
+            Format::Flac => data.starts_with(flac::MAGIC),
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::Gif => "GIF",
+            Format::Bmp => "BMP",
+            Format::Webp => "WebP",
+            Format::Tiff => "TIFF",
+            Format::Wav => "WAV",
+            Format::Flac => "FLAC",
+        }
+    }
+}
+
+/// The format of `data`, or an error listing the supported formats
+pub fn detect(data: &[u8]) -> Result<Format> {
+    Format::detect(data).ok_or_else(|| {
+        let names: Vec<&str> = Format::ALL.iter().map(|format| format.name()).collect();
+        Failure::Parse.error(format!(
+            "Unrecognized file format; supported formats are {}",
+            names.join(", ")
+        ))
+    })
+}
+
+/// Parses `data` as one of the non-PNG formats, or returns `None` for a PNG file
+pub fn parse(data: &[u8]) -> Result<Option<Box<dyn Container>>> {
+    Ok(Some(match detect(data)? {
+        Format::Png => return Ok(None),
+        Format::Jpeg => Box::new(jpeg::Jpeg::parse(data)?),
+        Format::Gif => Box::new(gif::Gif::parse(data)?),
+        Format::Bmp => Box::new(bmp::Bmp::parse(data)?),
+        Format::Webp => Box::new(webp::Webp::parse(data)?),
+        Format::Tiff => Box::new(tiff::Tiff::parse(data)?),
+        Format::Wav => Box::new(wav::Wav::parse(data)?),
+        Format::Flac => Box::new(flac::Flac::parse(data)?),
+    }))
+}
+
+/// Label and payload of a vault block laid out as label length (1) | label | payload,
+/// after any format-specific identifier
+fn split_labelled(data: &[u8]) -> Option<(&str, &[u8])> {
+    let (&len, rest) = data.split_first()?;
+    let (label, payload) = rest.split_at_checked(len as usize)?;
+    Some((std::str::from_utf8(label).ok()?, payload))
+}
+
+fn labelled(label: &str, payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u8::try_from(label.len()).map_err(|_| format!("Label '{}' is too long", label))?;
+    let mut data = Vec::with_capacity(1 + label.len() + payload.len());
+    data.push(len);
+    data.extend(label.as_bytes());
+    data.extend(payload);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_parse() {
+        let mut png = Png::STANDARD_HEADER.to_vec();
+        png.extend([0; 12]);
+        assert_eq!(Format::detect(&png), Some(Format::Png));
+        assert!(parse(&png).unwrap().is_none());
+        assert_eq!(Format::detect(b"GIF89a"), Some(Format::Gif));
+        assert_eq!(Format::detect(b"RIFF\0\0\0\0WAVEfmt "), Some(Format::Wav));
+
+        let error = parse(b"%PDF-1.7").err().unwrap().to_string();
+        assert!(error.contains("PNG, JPEG, GIF"), "{}", error);
+        assert!(parse(b"\xff\xd8\xff").is_err());
+    }
+
+    #[test]
+    fn test_labelled_round_trip() {
+        let data = labelled("ruSt", b"payload").unwrap();
+        assert_eq!(split_labelled(&data), Some(("ruSt", &b"payload"[..])));
+        assert_eq!(split_labelled(b"\x09ab"), None);
+        assert!(labelled(&"x".repeat(256), b"").is_err());
+    }
+}
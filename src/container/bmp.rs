@@ -0,0 +1,359 @@
+use super::{Block, Container, labelled, split_labelled};
+use crate::Result;
+use crate::pixels::{Ihdr, Pixels};
+
+pub const MAGIC: &[u8; 2] = b"BM";
+const FILE_HEADER_LEN: usize = 14;
+const BI_RGB: u32 = 0;
+/// Starts every record written by `encode` after the pixel array
+const IDENTIFIER: &[u8] = b"HPVault\0";
+/// Identifier | record length (4, little-endian) | label length (1) | label | payload
+const RECORD_HEADER_LEN: usize = IDENTIFIER.len() + 4;
+
+/// Label and payload of a vault record
+type Record = (String, Vec<u8>);
+
+/// A BMP file with the vault records that `encode` appends after its pixel array
+pub struct Bmp {
+    /// File header, DIB header, color table and pixel array
+    image: Vec<u8>,
+    /// Label and payload of each vault record, in file order
+    records: Vec<Record>,
+    /// Bytes after the pixel array that are not vault records, kept as they were
+    trailing: Vec<u8>,
+    dib_len: usize,
+    pixel_offset: usize,
+    width: usize,
+    /// Negative heights mark top-down images; the row order does not matter here
+    height: usize,
+    bits_per_pixel: u16,
+    compression: u32,
+}
+
+impl Bmp {
+    pub fn parse(data: &[u8]) -> Result<Bmp> {
+        let u32_at = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .ok_or("BMP header is truncated")
+        };
+        let u16_at = |offset: usize| {
+            data.get(offset..offset + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .ok_or("BMP header is truncated")
+        };
+
+        let pixel_offset = u32_at(10)? as usize;
+        let dib_len = u32_at(FILE_HEADER_LEN)? as usize;
+        let dib = FILE_HEADER_LEN;
+        let (width, height, bits_per_pixel, compression, image_size) = if dib_len == 12 {
+            // BITMAPCOREHEADER: 16-bit dimensions, never compressed
+            let (width, height) = (u16_at(dib + 4)? as usize, u16_at(dib + 6)? as usize);
+            (width, height, u16_at(dib + 10)?, BI_RGB, 0)
+        } else if dib_len >= 40 {
+            let width = u32_at(dib + 4)? as i32;
+            let height = u32_at(dib + 8)? as i32;
+            if width <= 0 || height == 0 {
+                return Err("BMP declares an empty image".into());
+            }
+            (
+                width as usize,
+                height.unsigned_abs() as usize,
+                u16_at(dib + 14)?,
+                u32_at(dib + 16)?,
+                u32_at(dib + 20)? as usize,
+            )
+        } else {
+            return Err(format!("Unknown BMP header size {}", dib_len).into());
+        };
+
+        let stride = (width * bits_per_pixel as usize).div_ceil(32) * 4;
+        let pixel_len = if compression == BI_RGB {
+            stride * height
+        } else {
+            image_size
+        };
+        let pixel_end = pixel_offset + pixel_len;
+        if pixel_offset < FILE_HEADER_LEN + dib_len || pixel_len == 0 || pixel_end > data.len() {
+            return Err("BMP pixel array is missing or truncated".into());
+        }
+
+        let mut records = Vec::new();
+        let mut rest = &data[pixel_end..];
+        while let Some((record, tail)) = split_record(rest) {
+            records.push(record);
+            rest = tail;
+        }
+
+        Ok(Bmp {
+            image: data[..pixel_end].to_vec(),
+            records,
+            trailing: rest.to_vec(),
+            dib_len,
+            pixel_offset,
+            width,
+            height,
+            bits_per_pixel,
+            compression,
+        })
+    }
+
+    fn stride(&self) -> usize {
+        (self.width * self.bits_per_pixel as usize).div_ceil(32) * 4
+    }
+
+    /// The IHDR a PNG with the same pixel layout would have: BGR samples become RGB
+    /// samples and the unused fourth byte of 32-bit pixels counts as alpha
+    fn ihdr(&self) -> Result<Ihdr> {
+        let color_type = match (self.compression, self.bits_per_pixel) {
+            (BI_RGB, 24) => 2,
+            (BI_RGB, 32) => 6,
+            _ => {
+                return Err(format!(
+                    "Only uncompressed 24- and 32-bit BMP files can carry --lsb messages, this one has {} bits per pixel{}",
+                    self.bits_per_pixel,
+                    if self.compression == BI_RGB {
+                        ""
+                    } else {
+                        " and is compressed"
+                    }
+                )
+                .into());
+            }
+        };
+        Ok(Ihdr {
+            width: self.width as u32,
+            height: self.height as u32,
+            bit_depth: 8,
+            color_type,
+            interlaced: false,
+        })
+    }
+}
+
+/// The first vault record of `data` and the bytes after it
+fn split_record(data: &[u8]) -> Option<(Record, &[u8])> {
+    let rest = data.strip_prefix(IDENTIFIER)?;
+    let (length, rest) = rest.split_at_checked(4)?;
+    let length = u32::from_le_bytes(length.try_into().ok()?) as usize;
+    let (record, tail) = rest.split_at_checked(length)?;
+    let (label, payload) = split_labelled(record)?;
+    Some(((label.to_string(), payload.to_vec()), tail))
+}
+
+impl Container for Bmp {
+    fn format(&self) -> &'static str {
+        "BMP"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "record after the pixel array"
+    }
+
+    fn max_payload(&self, label: &str) -> usize {
+        u32::MAX as usize - RECORD_HEADER_LEN - 1 - label.len()
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        let dib_end = FILE_HEADER_LEN + self.dib_len;
+        let dib_name = match self.dib_len {
+            12 => "BITMAPCOREHEADER",
+            40 => "BITMAPINFOHEADER",
+            108 => "BITMAPV4HEADER",
+            124 => "BITMAPV5HEADER",
+            _ => "DIB header",
+        };
+        let mut blocks = vec![
+            Block {
+                name: "BITMAPFILEHEADER".to_string(),
+                data: &self.image[..FILE_HEADER_LEN],
+                label: None,
+            },
+            Block {
+                name: format!(
+                    "{} ({}x{}, {} bits per pixel)",
+                    dib_name, self.width, self.height, self.bits_per_pixel
+                ),
+                data: &self.image[FILE_HEADER_LEN..dib_end],
+                label: None,
+            },
+        ];
+        if self.pixel_offset > dib_end {
+            blocks.push(Block {
+                name: "Color table".to_string(),
+                data: &self.image[dib_end..self.pixel_offset],
+                label: None,
+            });
+        }
+        blocks.push(Block {
+            name: "Pixel array".to_string(),
+            data: &self.image[self.pixel_offset..],
+            label: None,
+        });
+        for (label, payload) in &self.records {
+            blocks.push(Block {
+                name: "Vault record".to_string(),
+                data: payload,
+                label: Some(label),
+            });
+        }
+        if !self.trailing.is_empty() {
+            blocks.push(Block {
+                name: "Trailing data".to_string(),
+                data: &self.trailing,
+                label: None,
+            });
+        }
+        blocks
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.records
+            .iter()
+            .filter(|(found, _)| found == label)
+            .map(|(_, payload)| payload.as_slice())
+            .collect()
+    }
+
+    fn insert(&mut self, label: &str, payload: &[u8]) -> Result<()> {
+        if payload.len() > self.max_payload(label) {
+            return Err("Message is too large for a BMP record".into());
+        }
+        // Checks the label length
+        labelled(label, &[])?;
+        self.records.push((label.to_string(), payload.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        let before = self.records.len();
+        self.records.retain(|(found, _)| found != label);
+        before - self.records.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.image.clone();
+        for (label, payload) in &self.records {
+            // insert has checked the label, so this cannot fail
+            let record = labelled(label, payload).unwrap_or_default();
+            bytes.extend(IDENTIFIER);
+            bytes.extend((record.len() as u32).to_le_bytes());
+            bytes.extend(record);
+        }
+        bytes.extend(&self.trailing);
+        // Keep the declared file size in step with the records
+        let size = u32::try_from(bytes.len()).unwrap_or(u32::MAX);
+        bytes[2..6].copy_from_slice(&size.to_le_bytes());
+        bytes
+    }
+
+    fn pixels(&self) -> Result<Pixels> {
+        let ihdr = self.ihdr()?;
+        let row_len = self.width * self.bits_per_pixel as usize / 8;
+        let data = self.image[self.pixel_offset..]
+            .chunks_exact(self.stride())
+            .flat_map(|row| &row[..row_len])
+            .copied()
+            .collect();
+        Pixels::from_raw(ihdr, data)
+    }
+
+    fn set_pixels(&mut self, pixels: &Pixels) -> Result<()> {
+        let row_len = self.width * self.bits_per_pixel as usize / 8;
+        let stride = self.stride();
+        let rows = self.image[self.pixel_offset..].chunks_exact_mut(stride);
+        for (row, pixel_row) in rows.zip(pixels.data().chunks_exact(row_len)) {
+            row[..row_len].copy_from_slice(pixel_row);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bottom-up BMP with a BITMAPINFOHEADER and padded rows
+    fn test_bmp(width: u32, height: u32, bits_per_pixel: u16) -> Vec<u8> {
+        let stride = (width as usize * bits_per_pixel as usize).div_ceil(32) * 4;
+        let pixel_offset = FILE_HEADER_LEN + 40;
+        let size = pixel_offset + stride * height as usize;
+        let mut data = Vec::with_capacity(size);
+        data.extend(MAGIC);
+        data.extend((size as u32).to_le_bytes());
+        data.extend([0; 4]);
+        data.extend((pixel_offset as u32).to_le_bytes());
+        data.extend(40u32.to_le_bytes());
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(bits_per_pixel.to_le_bytes());
+        data.extend(BI_RGB.to_le_bytes());
+        data.extend([0; 20]);
+        data.extend((0..stride * height as usize).map(|i| (i * 7) as u8));
+        data
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let original = test_bmp(5, 3, 24);
+        let mut bmp = Bmp::parse(&original).unwrap();
+        assert_eq!(bmp.to_bytes(), original);
+        bmp.insert("ruSt", b"hidden").unwrap();
+        bmp.insert("raSt", b"").unwrap();
+
+        let written = bmp.to_bytes();
+        assert_eq!(&written[..original.len()][6..], &original[6..]);
+        assert_eq!(
+            u32::from_le_bytes(written[2..6].try_into().unwrap()) as usize,
+            written.len()
+        );
+        let mut bmp = Bmp::parse(&written).unwrap();
+        assert_eq!(bmp.payloads("ruSt"), [b"hidden"]);
+        assert_eq!(bmp.payloads("raSt"), [b""]);
+        assert_eq!(bmp.blocks().len(), 5);
+        assert_eq!(bmp.remove("ruSt"), 1);
+        assert_eq!(bmp.remove("ruSt"), 0);
+    }
+
+    #[test]
+    fn test_keeps_foreign_trailing_data() {
+        let mut data = test_bmp(4, 2, 32);
+        data.extend(b"not ours");
+        let mut bmp = Bmp::parse(&data).unwrap();
+        assert_eq!(bmp.trailing, b"not ours");
+        bmp.insert("ruSt", b"x").unwrap();
+        let bmp = Bmp::parse(&bmp.to_bytes()).unwrap();
+        assert_eq!(bmp.payloads("ruSt"), [b"x"]);
+        assert!(bmp.to_bytes().ends_with(b"not ours"));
+    }
+
+    #[test]
+    fn test_pixels_skip_row_padding() {
+        let data = test_bmp(5, 3, 24);
+        let mut bmp = Bmp::parse(&data).unwrap();
+        let mut pixels = bmp.pixels().unwrap();
+        assert_eq!(pixels.data().len(), 5 * 3 * 3);
+        assert_eq!(pixels.data()[15], data[FILE_HEADER_LEN + 40 + 16]);
+
+        pixels.set_byte(15, 0xee);
+        bmp.set_pixels(&pixels).unwrap();
+        let written = bmp.to_bytes();
+        assert_eq!(written[FILE_HEADER_LEN + 40 + 16], 0xee);
+        // Padding is untouched
+        assert_eq!(
+            written[FILE_HEADER_LEN + 40 + 15],
+            data[FILE_HEADER_LEN + 40 + 15]
+        );
+
+        let palette = Bmp::parse(&test_bmp(4, 4, 8)).unwrap();
+        assert!(palette.pixels().is_err());
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let data = test_bmp(5, 3, 24);
+        assert!(Bmp::parse(&data[..data.len() - 1]).is_err());
+        assert!(Bmp::parse(&data[..20]).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use super::{Block, Container, labelled, split_labelled};
+use crate::Result;
+
+pub const MAGIC: &[u8; 4] = b"fLaC";
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const APPLICATION: u8 = 2;
+const LAST_BLOCK: u8 = 0x80;
+/// Block type (1) and length (3)
+const BLOCK_HEADER_LEN: usize = 4;
+/// Block lengths are 24-bit
+const MAX_BLOCK_LEN: usize = 0xff_ffff;
+/// Application ID of the blocks written by `encode`
+const APPLICATION_ID: &[u8; 4] = b"HPVt";
+
+struct MetadataBlock {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+impl MetadataBlock {
+    /// Label and payload of a vault block
+    fn vault(&self) -> Option<(&str, &[u8])> {
+        if self.kind != APPLICATION {
+            return None;
+        }
+        split_labelled(self.data.strip_prefix(APPLICATION_ID)?)
+    }
+}
+
+/// A FLAC file split into its metadata blocks and the audio frames after them, which
+/// are kept as they are
+pub struct Flac {
+    blocks: Vec<MetadataBlock>,
+    audio: Vec<u8>,
+}
+
+impl Flac {
+    pub fn parse(data: &[u8]) -> Result<Flac> {
+        let mut blocks = Vec::new();
+        let mut position = MAGIC.len();
+        loop {
+            let header = data
+                .get(position..position + BLOCK_HEADER_LEN)
+                .ok_or("FLAC metadata is truncated")?;
+            let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            let start = position + BLOCK_HEADER_LEN;
+            let block = data
+                .get(start..start + len)
+                .ok_or("FLAC metadata block is truncated")?;
+            blocks.push(MetadataBlock {
+                kind: header[0] & !LAST_BLOCK,
+                data: block.to_vec(),
+            });
+            position = start + len;
+            if header[0] & LAST_BLOCK != 0 {
+                break;
+            }
+        }
+        if blocks[0].kind != STREAMINFO {
+            return Err("FLAC file does not start with a STREAMINFO block".into());
+        }
+        Ok(Flac {
+            blocks,
+            audio: data[position..].to_vec(),
+        })
+    }
+
+    fn padding(&mut self) -> Option<&mut MetadataBlock> {
+        self.blocks.iter_mut().find(|block| block.kind == PADDING)
+    }
+}
+
+/// Block name as `print` shows it, such as "STREAMINFO (44100 Hz, 2 channels, 16-bit)"
+fn block_name(block: &MetadataBlock) -> String {
+    match block.kind {
+        STREAMINFO if block.data.len() >= 14 => {
+            let data = &block.data;
+            let sample_rate =
+                (data[10] as u32) << 12 | (data[11] as u32) << 4 | (data[12] as u32) >> 4;
+            let channels = ((data[12] >> 1) & 0x07) + 1;
+            let bits = (((data[12] & 1) << 4) | (data[13] >> 4)) + 1;
+            format!(
+                "STREAMINFO ({} Hz, {} channel(s), {}-bit)",
+                sample_rate, channels, bits
+            )
+        }
+        STREAMINFO => "STREAMINFO".to_string(),
+        PADDING => "PADDING".to_string(),
+        APPLICATION => format!(
+            "APPLICATION {}",
+            String::from_utf8_lossy(block.data.get(..4).unwrap_or_default())
+        ),
+        3 => "SEEKTABLE".to_string(),
+        4 => "VORBIS_COMMENT".to_string(),
+        5 => "CUESHEET".to_string(),
+        6 => "PICTURE".to_string(),
+        kind => format!("Block type {}", kind),
+    }
+}
+
+impl Container for Flac {
+    fn format(&self) -> &'static str {
+        "FLAC"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "APPLICATION block"
+    }
+
+    fn max_payload(&self, label: &str) -> usize {
+        MAX_BLOCK_LEN - APPLICATION_ID.len() - 1 - label.len()
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        let mut blocks: Vec<Block> = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let vault = block.vault();
+                Block {
+                    name: block_name(block),
+                    data: vault.map_or(&block.data, |(_, payload)| payload),
+                    label: vault.map(|(label, _)| label),
+                }
+            })
+            .collect();
+        blocks.push(Block {
+            name: "Audio frames".to_string(),
+            data: &self.audio,
+            label: None,
+        });
+        blocks
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.blocks
+            .iter()
+            .filter_map(MetadataBlock::vault)
+            .filter(|&(found, _)| found == label)
+            .map(|(_, payload)| payload)
+            .collect()
+    }
+
+    fn insert(&mut self, label: &str, payload: &[u8]) -> Result<()> {
+        if payload.len() > self.max_payload(label) {
+            return Err(format!(
+                "{} bytes do not fit in one FLAC metadata block (at most {})",
+                payload.len(),
+                self.max_payload(label)
+            )
+            .into());
+        }
+        let mut data = APPLICATION_ID.to_vec();
+        data.extend(labelled(label, payload)?);
+
+        // Take the room from the padding encoders leave for tag edits, if there is
+        // enough, so the audio stays where it was
+        let needed = BLOCK_HEADER_LEN + data.len();
+        if let Some(padding) = self
+            .padding()
+            .filter(|padding| padding.data.len() >= needed)
+        {
+            padding.data.truncate(padding.data.len() - needed);
+        }
+        // After earlier vault blocks so fragments stay in order, otherwise before the
+        // padding, which conventionally comes last
+        let position = match self
+            .blocks
+            .iter()
+            .rposition(|block| block.vault().is_some())
+        {
+            Some(last) => last + 1,
+            None => self
+                .blocks
+                .iter()
+                .position(|block| block.kind == PADDING)
+                .unwrap_or(self.blocks.len()),
+        };
+        self.blocks.insert(
+            position,
+            MetadataBlock {
+                kind: APPLICATION,
+                data,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        let before = self.blocks.len();
+        let mut freed = 0;
+        self.blocks.retain(|block| match block.vault() {
+            Some((found, _)) if found == label => {
+                freed += BLOCK_HEADER_LEN + block.data.len();
+                false
+            }
+            _ => true,
+        });
+        // Give the room back to the padding as zeros
+        if let Some(padding) = self.padding() {
+            let len = (padding.data.len() + freed).min(MAX_BLOCK_LEN);
+            padding.data.resize(len, 0);
+        }
+        before - self.blocks.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let last = if i + 1 == self.blocks.len() {
+                LAST_BLOCK
+            } else {
+                0
+            };
+            bytes.push(block.kind | last);
+            bytes.extend(&(block.data.len() as u32).to_be_bytes()[1..]);
+            bytes.extend(&block.data);
+        }
+        bytes.extend(&self.audio);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// STREAMINFO for 44.1 kHz 16-bit stereo, a 100-byte PADDING block and a few bytes
+    /// standing in for the audio frames
+    fn test_flac(padding: usize) -> Vec<u8> {
+        let mut streaminfo = vec![0; 34];
+        streaminfo[10..14].copy_from_slice(&[0x0a, 0xc4, 0x42, 0xf0]);
+        let mut data = MAGIC.to_vec();
+        data.push(STREAMINFO);
+        data.extend(&(streaminfo.len() as u32).to_be_bytes()[1..]);
+        data.extend(streaminfo);
+        data.push(PADDING | LAST_BLOCK);
+        data.extend(&(padding as u32).to_be_bytes()[1..]);
+        data.extend(vec![0; padding]);
+        data.extend([0xff, 0xf8, 0x69, 0x08]);
+        data
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let data = test_flac(100);
+        let flac = Flac::parse(&data).unwrap();
+        assert_eq!(flac.to_bytes(), data);
+        let names: Vec<String> = flac.blocks().into_iter().map(|block| block.name).collect();
+        assert_eq!(
+            names,
+            [
+                "STREAMINFO (44100 Hz, 2 channel(s), 16-bit)",
+                "PADDING",
+                "Audio frames"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_uses_padding() {
+        let original = test_flac(100);
+        let mut flac = Flac::parse(&original).unwrap();
+        flac.insert("ruSt", b"first").unwrap();
+        flac.insert("ruSt", b"second").unwrap();
+        flac.insert("big!", &[1; 200]).unwrap();
+
+        let written = flac.to_bytes();
+        // The two small blocks came out of the padding, the large one did not fit
+        assert_eq!(
+            written.len(),
+            original.len() + BLOCK_HEADER_LEN + 4 + 5 + 200
+        );
+        assert!(written.ends_with(&[0xff, 0xf8, 0x69, 0x08]));
+        let mut flac = Flac::parse(&written).unwrap();
+        assert_eq!(flac.payloads("ruSt"), [&b"first"[..], b"second"]);
+        let blocks = flac.blocks();
+        assert_eq!(blocks[1].name, "APPLICATION HPVt");
+        assert_eq!(blocks[1].label, Some("ruSt"));
+        assert_eq!(blocks[4].name, "PADDING");
+
+        assert_eq!(flac.remove("big!"), 1);
+        assert_eq!(flac.remove("ruSt"), 2);
+        let cleaned = flac.to_bytes();
+        assert_eq!(
+            cleaned.len(),
+            original.len() + BLOCK_HEADER_LEN + 4 + 5 + 200
+        );
+        assert!(Flac::parse(&cleaned).unwrap().payloads("ruSt").is_empty());
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let data = test_flac(10);
+        assert!(Flac::parse(&data[..50]).is_err());
+        assert!(Flac::parse(b"fLaC\x81\0\0\0").is_err());
+    }
+}
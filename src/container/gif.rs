@@ -0,0 +1,345 @@
+use super::{Block, Container, labelled, split_labelled};
+use crate::Result;
+
+pub const MAGIC: &[u8; 3] = b"GIF";
+/// Signature, version and the logical screen descriptor
+const HEADER_LEN: usize = 13;
+const EXTENSION: u8 = 0x21;
+const IMAGE: u8 = 0x2c;
+const TRAILER: u8 = 0x3b;
+const GRAPHIC_CONTROL: u8 = 0xf9;
+const COMMENT: u8 = 0xfe;
+const PLAIN_TEXT: u8 = 0x01;
+const APPLICATION: u8 = 0xff;
+/// Application identifier (8) and authentication code (3) of the extensions written by
+/// `encode`, laid out like NETSCAPE2.0
+const APPLICATION_ID: &[u8; 11] = b"HPVAULT 1.0";
+/// Data sub-blocks are at most 255 bytes and chain without limit
+const MAX_SUB_BLOCK: usize = 255;
+
+enum Part {
+    /// An extension or image exactly as it was read, introducer included
+    Raw(Vec<u8>),
+    /// Label and payload of a vault application extension
+    Vault(String, Vec<u8>),
+}
+
+/// A GIF file split into its extensions and images, the vault application extensions
+/// written by `encode` among them
+pub struct Gif {
+    /// Header, logical screen descriptor and global color table
+    head: Vec<u8>,
+    parts: Vec<Part>,
+    /// The trailer and anything after it
+    tail: Vec<u8>,
+}
+
+impl Gif {
+    pub fn parse(data: &[u8]) -> Result<Gif> {
+        let packed = *data.get(10).ok_or("GIF header is truncated")?;
+        let mut position = HEADER_LEN + color_table_len(packed);
+        let head = data
+            .get(..position)
+            .ok_or("GIF header is truncated")?
+            .to_vec();
+
+        let mut parts = Vec::new();
+        loop {
+            let start = position;
+            match data.get(position) {
+                Some(&EXTENSION) => {
+                    let kind = *data.get(position + 1).ok_or("GIF file is truncated")?;
+                    position = skip_sub_blocks(data, position + 2)?;
+                    let raw = &data[start..position];
+                    let vault = (kind == APPLICATION)
+                        .then(|| sub_blocks(&raw[2..]))
+                        .and_then(|blocks| {
+                            let (id, rest) = blocks.split_first()?;
+                            if id.as_slice() != APPLICATION_ID {
+                                return None;
+                            }
+                            let data = rest.concat();
+                            let (label, payload) = split_labelled(&data)?;
+                            Some(Part::Vault(label.to_string(), payload.to_vec()))
+                        });
+                    parts.push(vault.unwrap_or_else(|| Part::Raw(raw.to_vec())));
+                }
+                Some(&IMAGE) => {
+                    let packed = *data.get(position + 9).ok_or("GIF file is truncated")?;
+                    // Descriptor, local color table, LZW minimum code size
+                    position += 10 + color_table_len(packed) + 1;
+                    position = skip_sub_blocks(data, position)?;
+                    parts.push(Part::Raw(data[start..position].to_vec()));
+                }
+                Some(&TRAILER) => {
+                    return Ok(Gif {
+                        head,
+                        parts,
+                        tail: data[position..].to_vec(),
+                    });
+                }
+                Some(&byte) => {
+                    return Err(format!(
+                        "Unexpected GIF block {:#04x} at offset {}",
+                        byte, position
+                    )
+                    .into());
+                }
+                None => return Err("GIF file ends without a trailer".into()),
+            }
+        }
+    }
+}
+
+/// Size of the color table announced by a packed field: its flag is the top bit, its
+/// size the low three
+fn color_table_len(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        0
+    } else {
+        3 << ((packed & 0x07) + 1)
+    }
+}
+
+/// Offset after the terminator of the sub-blocks starting at `position`
+fn skip_sub_blocks(data: &[u8], mut position: usize) -> Result<usize> {
+    loop {
+        let len = *data.get(position).ok_or("GIF file is truncated")? as usize;
+        position += 1 + len;
+        if len == 0 {
+            return Ok(position);
+        }
+    }
+}
+
+/// Contents of each sub-block of a well-formed sequence
+fn sub_blocks(mut data: &[u8]) -> Vec<Vec<u8>> {
+    let mut blocks = Vec::new();
+    while let Some((&len, rest)) = data.split_first() {
+        if len == 0 {
+            break;
+        }
+        let Some((block, rest)) = rest.split_at_checked(len as usize) else {
+            break;
+        };
+        blocks.push(block.to_vec());
+        data = rest;
+    }
+    blocks
+}
+
+/// Block name as `print` shows it, such as "Application Extension NETSCAPE2.0"
+fn part_name(raw: &[u8]) -> String {
+    if raw[0] == IMAGE {
+        let width = u16::from_le_bytes([raw[5], raw[6]]);
+        let height = u16::from_le_bytes([raw[7], raw[8]]);
+        return format!("Image ({}x{})", width, height);
+    }
+    match raw[1] {
+        GRAPHIC_CONTROL => "Graphic Control Extension".to_string(),
+        COMMENT => "Comment Extension".to_string(),
+        PLAIN_TEXT => "Plain Text Extension".to_string(),
+        APPLICATION => {
+            let id = sub_blocks(&raw[2..]).into_iter().next().unwrap_or_default();
+            format!("Application Extension {}", String::from_utf8_lossy(&id))
+        }
+        kind => format!("Extension {:#04x}", kind),
+    }
+}
+
+impl Container for Gif {
+    fn format(&self) -> &'static str {
+        "GIF"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "application extension"
+    }
+
+    fn max_payload(&self, label: &str) -> usize {
+        u32::MAX as usize - 1 - label.len()
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        let mut blocks = vec![Block {
+            name: format!(
+                "Header {} ({}x{})",
+                String::from_utf8_lossy(&self.head[..6]),
+                u16::from_le_bytes([self.head[6], self.head[7]]),
+                u16::from_le_bytes([self.head[8], self.head[9]])
+            ),
+            data: &self.head[..HEADER_LEN],
+            label: None,
+        }];
+        if self.head.len() > HEADER_LEN {
+            blocks.push(Block {
+                name: "Global Color Table".to_string(),
+                data: &self.head[HEADER_LEN..],
+                label: None,
+            });
+        }
+        for part in &self.parts {
+            blocks.push(match part {
+                Part::Raw(raw) => Block {
+                    name: part_name(raw),
+                    data: raw,
+                    label: None,
+                },
+                Part::Vault(label, payload) => Block {
+                    name: format!(
+                        "Application Extension {}",
+                        String::from_utf8_lossy(APPLICATION_ID)
+                    ),
+                    data: payload,
+                    label: Some(label),
+                },
+            });
+        }
+        blocks.push(Block {
+            name: "Trailer".to_string(),
+            data: &self.tail,
+            label: None,
+        });
+        blocks
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Vault(found, payload) if found == label => Some(payload.as_slice()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn insert(&mut self, label: &str, payload: &[u8]) -> Result<()> {
+        // Checks the label length
+        labelled(label, &[])?;
+        // After earlier vault extensions so fragments stay in order, otherwise before the
+        // first image and the graphic control extension that belongs to it
+        let position = match self
+            .parts
+            .iter()
+            .rposition(|part| matches!(part, Part::Vault(..)))
+        {
+            Some(last) => last + 1,
+            None => self
+                .parts
+                .iter()
+                .position(|part| {
+                    matches!(part, Part::Raw(raw) if raw[0] == IMAGE || raw[1] == GRAPHIC_CONTROL)
+                })
+                .unwrap_or(self.parts.len()),
+        };
+        self.parts
+            .insert(position, Part::Vault(label.to_string(), payload.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        let before = self.parts.len();
+        self.parts
+            .retain(|part| !matches!(part, Part::Vault(found, _) if found == label));
+        before - self.parts.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.head.clone();
+        let mut has_extensions = false;
+        for part in &self.parts {
+            match part {
+                Part::Raw(raw) => bytes.extend(raw),
+                Part::Vault(label, payload) => {
+                    bytes.extend([EXTENSION, APPLICATION, APPLICATION_ID.len() as u8]);
+                    bytes.extend(APPLICATION_ID);
+                    // insert has checked the label, so this cannot fail
+                    let data = labelled(label, payload).unwrap_or_default();
+                    for block in data.chunks(MAX_SUB_BLOCK) {
+                        bytes.push(block.len() as u8);
+                        bytes.extend(block);
+                    }
+                    bytes.push(0);
+                    has_extensions = true;
+                }
+            }
+        }
+        bytes.extend(&self.tail);
+        // Extensions are a GIF89a feature
+        if has_extensions {
+            bytes[3..6].copy_from_slice(b"89a");
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// GIF87a with a two-color global table, a NETSCAPE2.0 loop extension and one
+    /// 1x1 image preceded by its graphic control extension
+    fn test_gif() -> Vec<u8> {
+        let mut data = b"GIF87a".to_vec();
+        data.extend([1, 0, 1, 0, 0x80, 0, 0]);
+        data.extend([0, 0, 0, 255, 255, 255]);
+        data.extend([EXTENSION, APPLICATION, 11]);
+        data.extend(b"NETSCAPE2.0");
+        data.extend([3, 1, 0, 0, 0]);
+        data.extend([EXTENSION, GRAPHIC_CONTROL, 4, 0, 0, 0, 0, 0]);
+        data.extend([IMAGE, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+        data.extend([2, 2, 0x4c, 0x01, 0]);
+        data.push(TRAILER);
+        data
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let data = test_gif();
+        let gif = Gif::parse(&data).unwrap();
+        assert_eq!(gif.to_bytes(), data);
+        let names: Vec<String> = gif.blocks().into_iter().map(|block| block.name).collect();
+        assert_eq!(
+            names,
+            [
+                "Header GIF87a (1x1)",
+                "Global Color Table",
+                "Application Extension NETSCAPE2.0",
+                "Graphic Control Extension",
+                "Image (1x1)",
+                "Trailer"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_payloads_remove() {
+        let mut gif = Gif::parse(&test_gif()).unwrap();
+        let long = vec![7; 600];
+        gif.insert("ruSt", &long).unwrap();
+        gif.insert("ruSt", b"second").unwrap();
+        gif.insert("raSt", b"").unwrap();
+
+        let written = gif.to_bytes();
+        assert!(written.starts_with(b"GIF89a"));
+        let mut gif = Gif::parse(&written).unwrap();
+        assert_eq!(gif.payloads("ruSt"), [&long[..], b"second"]);
+        assert_eq!(gif.payloads("raSt"), [b""]);
+        let blocks = gif.blocks();
+        // Vault extensions come before the image's graphic control extension
+        assert_eq!(blocks[3].label, Some("ruSt"));
+        assert_eq!(blocks[6].name, "Graphic Control Extension");
+
+        assert_eq!(gif.remove("ruSt"), 2);
+        assert!(gif.payloads("ruSt").is_empty());
+        assert_eq!(gif.payloads("raSt"), [b""]);
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let data = test_gif();
+        assert!(Gif::parse(&data[..data.len() - 1]).is_err());
+        assert!(Gif::parse(&data[..30]).is_err());
+        assert!(Gif::parse(b"GIF89a").is_err());
+    }
+}
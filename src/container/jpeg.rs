@@ -0,0 +1,261 @@
+use super::{Block, Container, labelled, split_labelled};
+use crate::Result;
+
+/// SOI marker followed by the start of the first segment
+pub const MAGIC: [u8; 3] = [0xff, 0xd8, 0xff];
+const SOS: u8 = 0xda;
+const EOI: u8 = 0xd9;
+const APP0: u8 = 0xe0;
+const APP15: u8 = 0xef;
+const COM: u8 = 0xfe;
+/// Marks APP15 segments written by `encode`; other programs use APP15 rarely and
+/// never with this prefix
+const IDENTIFIER: &[u8] = b"HPVault\0";
+/// The segment length field is 16 bits and counts itself
+const MAX_SEGMENT_DATA: usize = u16::MAX as usize - 2;
+
+struct Segment {
+    marker: u8,
+    data: Vec<u8>,
+}
+
+impl Segment {
+    /// Label and payload of a vault segment
+    fn vault(&self) -> Option<(&str, &[u8])> {
+        if self.marker != APP15 {
+            return None;
+        }
+        split_labelled(self.data.strip_prefix(IDENTIFIER)?)
+    }
+}
+
+/// A JPEG file split into the marker segments before the first scan, which is kept
+/// as is along with everything after it
+pub struct Jpeg {
+    segments: Vec<Segment>,
+    /// From the first SOS marker on: scan data, later tables and scans, EOI and any
+    /// trailing bytes
+    scan: Vec<u8>,
+}
+
+impl Jpeg {
+    pub fn parse(data: &[u8]) -> Result<Jpeg> {
+        let mut segments = Vec::new();
+        let mut position = 2;
+        loop {
+            if data.get(position) != Some(&0xff) {
+                return Err(format!("Invalid JPEG marker at offset {}", position).into());
+            }
+            let start = position;
+            // Any number of fill bytes may precede a marker
+            while data.get(position) == Some(&0xff) {
+                position += 1;
+            }
+            let marker = *data.get(position).ok_or("JPEG file is truncated")?;
+            position += 1;
+            match marker {
+                SOS | EOI => {
+                    return Ok(Jpeg {
+                        segments,
+                        scan: data[start..].to_vec(),
+                    });
+                }
+                0x01 | 0xd0..=0xd7 => {
+                    return Err(format!("Unexpected JPEG marker {:#04x}", marker).into());
+                }
+                _ => {}
+            }
+            let length = data
+                .get(position..position + 2)
+                .map(|length| u16::from_be_bytes([length[0], length[1]]) as usize)
+                .filter(|&length| length >= 2)
+                .ok_or("JPEG segment length is invalid")?;
+            let segment = data
+                .get(position + 2..position + length)
+                .ok_or("JPEG file is truncated")?;
+            segments.push(Segment {
+                marker,
+                data: segment.to_vec(),
+            });
+            position += length;
+        }
+    }
+}
+
+impl Container for Jpeg {
+    fn format(&self) -> &'static str {
+        "JPEG"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "APP15 segment"
+    }
+
+    fn max_payload(&self, label: &str) -> usize {
+        MAX_SEGMENT_DATA - IDENTIFIER.len() - 1 - label.len()
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        let mut blocks: Vec<Block> = self
+            .segments
+            .iter()
+            .map(|segment| {
+                let vault = segment.vault();
+                Block {
+                    name: segment_name(segment),
+                    data: vault.map_or(&segment.data, |(_, payload)| payload),
+                    label: vault.map(|(label, _)| label),
+                }
+            })
+            .collect();
+        blocks.push(Block {
+            name: "SOS + image data".to_string(),
+            data: &self.scan,
+            label: None,
+        });
+        blocks
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.segments
+            .iter()
+            .filter_map(Segment::vault)
+            .filter(|&(found, _)| found == label)
+            .map(|(_, payload)| payload)
+            .collect()
+    }
+
+    fn insert(&mut self, label: &str, payload: &[u8]) -> Result<()> {
+        if payload.len() > self.max_payload(label) {
+            return Err(format!(
+                "{} bytes do not fit in one JPEG segment (at most {})",
+                payload.len(),
+                self.max_payload(label)
+            )
+            .into());
+        }
+        let mut data = IDENTIFIER.to_vec();
+        data.extend(labelled(label, payload)?);
+        // After the APPn segments, which JFIF and Exif require to come first, and
+        // after earlier vault segments so fragments stay in order
+        let position = self
+            .segments
+            .iter()
+            .position(|segment| !(APP0..=APP15).contains(&segment.marker))
+            .unwrap_or(self.segments.len());
+        self.segments.insert(
+            position,
+            Segment {
+                marker: APP15,
+                data,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        let before = self.segments.len();
+        self.segments
+            .retain(|segment| !matches!(segment.vault(), Some((found, _)) if found == label));
+        before - self.segments.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xd8];
+        for segment in &self.segments {
+            bytes.extend([0xff, segment.marker]);
+            bytes.extend((segment.data.len() as u16 + 2).to_be_bytes());
+            bytes.extend(&segment.data);
+        }
+        bytes.extend(&self.scan);
+        bytes
+    }
+}
+
+/// Marker name, with the identifier of APPn segments such as "APP1 Exif"
+fn segment_name(segment: &Segment) -> String {
+    let name = match segment.marker {
+        APP0..=APP15 => format!("APP{}", segment.marker - APP0),
+        COM => "COM".to_string(),
+        0xdb => "DQT".to_string(),
+        0xc4 => "DHT".to_string(),
+        0xcc => "DAC".to_string(),
+        0xdd => "DRI".to_string(),
+        0xc0..=0xcf => format!("SOF{}", segment.marker - 0xc0),
+        marker => format!("marker {:#04x}", marker),
+    };
+    if !(APP0..=APP15).contains(&segment.marker) {
+        return name;
+    }
+    let identifier: String = segment
+        .data
+        .iter()
+        .take_while(|&&byte| byte.is_ascii_graphic())
+        .map(|&byte| byte as char)
+        .collect();
+    if identifier.is_empty() {
+        name
+    } else {
+        format!("{} {}", name, identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SOI, APP0 JFIF, DQT, SOS with a few bytes of scan data, EOI
+    fn test_jpeg() -> Vec<u8> {
+        let mut data = vec![0xff, 0xd8];
+        data.extend([0xff, APP0, 0, 7]);
+        data.extend(b"JFIF\0");
+        data.extend([0xff, 0xdb, 0, 4, 0, 1]);
+        data.extend([0xff, SOS, 0, 3, 1, 0x12, 0xff, 0x00, 0x34, 0xff, EOI]);
+        data
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let data = test_jpeg();
+        let jpeg = Jpeg::parse(&data).unwrap();
+        assert_eq!(jpeg.to_bytes(), data);
+        let names: Vec<String> = jpeg.blocks().into_iter().map(|block| block.name).collect();
+        assert_eq!(names, ["APP0 JFIF", "DQT", "SOS + image data"]);
+    }
+
+    #[test]
+    fn test_insert_payloads_remove() {
+        let mut jpeg = Jpeg::parse(&test_jpeg()).unwrap();
+        jpeg.insert("ruSt", b"first").unwrap();
+        jpeg.insert("ruSt", b"second").unwrap();
+        jpeg.insert("raSt", b"other").unwrap();
+
+        let jpeg = &mut Jpeg::parse(&jpeg.to_bytes()).unwrap();
+        assert_eq!(jpeg.payloads("ruSt"), [&b"first"[..], b"second"]);
+        let blocks = jpeg.blocks();
+        // Vault segments follow APP0 and come before the tables
+        assert_eq!(blocks[1].name, "APP15 HPVault");
+        assert_eq!(blocks[1].label, Some("ruSt"));
+        assert_eq!(blocks[4].name, "DQT");
+
+        assert_eq!(jpeg.remove("ruSt"), 2);
+        assert!(jpeg.payloads("ruSt").is_empty());
+        assert_eq!(jpeg.payloads("raSt"), [b"other"]);
+        assert!(
+            jpeg.insert("big!", &vec![0; jpeg.max_payload("big!") + 1])
+                .is_err()
+        );
+        jpeg.insert("big!", &vec![0; jpeg.max_payload("big!")])
+            .unwrap();
+        assert!(Jpeg::parse(&jpeg.to_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let data = test_jpeg();
+        assert!(Jpeg::parse(&data[..8]).is_err());
+        assert!(Jpeg::parse(&[0xff, 0xd8, 0xff, APP0, 0, 1]).is_err());
+        assert!(Jpeg::parse(&[0xff, 0xd8, 0x00]).is_err());
+        assert!(Jpeg::parse(&[0xff, 0xd8, 0xff, 0xd0]).is_err());
+    }
+}
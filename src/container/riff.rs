@@ -0,0 +1,189 @@
+use super::Block;
+use crate::Result;
+
+const RIFF: &[u8; 4] = b"RIFF";
+/// "RIFF", the size of everything after it and the form type
+const HEADER_LEN: usize = 12;
+
+/// True for RIFF files of the form type `form`, e.g. "WEBP"
+pub fn detect(data: &[u8], form: &[u8; 4]) -> bool {
+    data.starts_with(RIFF) && data.get(8..HEADER_LEN) == Some(form)
+}
+
+pub struct Chunk {
+    pub fourcc: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// The FourCC as text, which is how messages are labelled
+    pub fn name(&self) -> &str {
+        std::str::from_utf8(&self.fourcc).unwrap_or("????")
+    }
+}
+
+/// The chunk list of a RIFF file. Chunks the format does not define are messages, named
+/// by their label, as ancillary chunk types are in PNG.
+pub struct Riff {
+    form: [u8; 4],
+    /// Name of the format for messages, e.g. "WebP"
+    format: &'static str,
+    /// Chunks defined by the format, which are never messages
+    standard: &'static [&'static [u8; 4]],
+    pub chunks: Vec<Chunk>,
+    /// Bytes after the end the RIFF header declares, kept as they were
+    trailing: Vec<u8>,
+}
+
+impl Riff {
+    pub fn parse(
+        data: &[u8],
+        format: &'static str,
+        standard: &'static [&'static [u8; 4]],
+    ) -> Result<Riff> {
+        let size = data
+            .get(4..8)
+            .map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize)
+            .ok_or_else(|| format!("{} header is truncated", format))?;
+        let end = 8 + size;
+        if size < 4 || end > data.len() {
+            return Err(format!("{} file is shorter than its RIFF header declares", format).into());
+        }
+
+        let mut chunks = Vec::new();
+        let mut position = HEADER_LEN;
+        while position < end {
+            let header = data
+                .get(position..position + 8)
+                .filter(|_| position + 8 <= end)
+                .ok_or_else(|| format!("{} chunk header is truncated", format))?;
+            let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let start = position + 8;
+            let chunk = data
+                .get(start..start + len)
+                .filter(|_| start + len <= end)
+                .ok_or_else(|| {
+                    format!(
+                        "{} chunk '{}' is truncated",
+                        format,
+                        String::from_utf8_lossy(&header[..4])
+                    )
+                })?;
+            chunks.push(Chunk {
+                fourcc: [header[0], header[1], header[2], header[3]],
+                data: chunk.to_vec(),
+            });
+            // Chunks are padded to an even length
+            position = start + len + len % 2;
+        }
+
+        Ok(Riff {
+            form: [data[8], data[9], data[10], data[11]],
+            format,
+            standard,
+            chunks,
+            trailing: data[end..].to_vec(),
+        })
+    }
+
+    pub fn is_standard(&self, chunk: &Chunk) -> bool {
+        self.standard.contains(&&chunk.fourcc)
+    }
+
+    /// Every chunk, described by `describe` if the format defines it
+    pub fn blocks(&self, describe: fn(&Chunk) -> Option<String>) -> Vec<Block<'_>> {
+        let mut blocks: Vec<Block> = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let standard = self.is_standard(chunk);
+                let name = match (standard, describe(chunk)) {
+                    (false, _) => "Custom chunk".to_string(),
+                    (true, Some(description)) => {
+                        format!("{} ({})", chunk.name().trim_end(), description)
+                    }
+                    (true, None) => chunk.name().trim_end().to_string(),
+                };
+                Block {
+                    name,
+                    data: &chunk.data,
+                    label: (!standard).then(|| chunk.name()),
+                }
+            })
+            .collect();
+        if !self.trailing.is_empty() {
+            blocks.push(Block {
+                name: "Trailing data".to_string(),
+                data: &self.trailing,
+                label: None,
+            });
+        }
+        blocks
+    }
+
+    pub fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.chunks
+            .iter()
+            .filter(|chunk| !self.is_standard(chunk) && chunk.fourcc == label.as_bytes())
+            .map(|chunk| chunk.data.as_slice())
+            .collect()
+    }
+
+    /// Checks that `label` can name a message chunk and returns its FourCC
+    pub fn fourcc(&self, label: &str) -> Result<[u8; 4]> {
+        let fourcc: [u8; 4] = label
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|fourcc: &[u8; 4]| fourcc.iter().all(|byte| (0x20..0x7f).contains(byte)))
+            .ok_or_else(|| {
+                format!(
+                    "{} chunk names are 4 printable ASCII characters, got '{}'",
+                    self.format, label
+                )
+            })?;
+        if self.standard.contains(&&fourcc) {
+            return Err(format!("'{}' is a standard {} chunk", label, self.format).into());
+        }
+        Ok(fourcc)
+    }
+
+    /// Appends a chunk labelled `label`
+    pub fn insert(&mut self, label: &str, data: &[u8]) -> Result<()> {
+        let fourcc = self.fourcc(label)?;
+        if data.len() > u32::MAX as usize - 1 {
+            return Err("Message is too large for a RIFF chunk".into());
+        }
+        self.chunks.push(Chunk {
+            fourcc,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, label: &str) -> usize {
+        let before = self.chunks.len();
+        let standard = self.standard;
+        self.chunks
+            .retain(|chunk| standard.contains(&&chunk.fourcc) || chunk.fourcc != label.as_bytes());
+        before - self.chunks.len()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = RIFF.to_vec();
+        bytes.extend([0; 4]);
+        bytes.extend(self.form);
+        for chunk in &self.chunks {
+            bytes.extend(chunk.fourcc);
+            bytes.extend((chunk.data.len() as u32).to_le_bytes());
+            bytes.extend(&chunk.data);
+            if chunk.data.len() % 2 == 1 {
+                bytes.push(0);
+            }
+        }
+        let size = (bytes.len() - 8) as u32;
+        bytes[4..8].copy_from_slice(&size.to_le_bytes());
+        bytes.extend(&self.trailing);
+        bytes
+    }
+}
@@ -0,0 +1,492 @@
+use super::{Block, Container, labelled, split_labelled};
+use crate::Result;
+use std::ops::Range;
+
+const LITTLE_ENDIAN: &[u8; 4] = b"II*\0";
+const BIG_ENDIAN: &[u8; 4] = b"MM\0*";
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 12;
+const UNDEFINED: u16 = 7;
+/// Tags from 65000 on are reusable private tags that need no registration
+const PRIVATE_TAGS: Range<u16> = 65000..u16::MAX;
+/// Starts the value of every private tag written by `encode`
+const IDENTIFIER: &[u8] = b"HPVault\0";
+
+/// True for classic (not Big) TIFF files in either byte order
+pub fn detect(data: &[u8]) -> bool {
+    data.starts_with(LITTLE_ENDIAN) || data.starts_with(BIG_ENDIAN)
+}
+
+/// An entry of the first IFD: tag, field type, value count and either the value itself
+/// or the offset of the value, as stored in the file
+#[derive(Clone, Copy)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value: [u8; 4],
+}
+
+struct Vault {
+    tag: u16,
+    label: String,
+    payload: Vec<u8>,
+}
+
+/// A TIFF file with its first IFD (the main image) parsed into entries, the private tags
+/// written by `encode` among them. Everything else is kept byte for byte; writing appends
+/// a new IFD, as TIFF editors commonly do, so no offset elsewhere in the file changes.
+pub struct Tiff {
+    data: Vec<u8>,
+    big_endian: bool,
+    /// Position and length of the first IFD in `data`
+    ifd: Range<usize>,
+    /// Entries of the first IFD other than vault tags
+    entries: Vec<Entry>,
+    vaults: Vec<Vault>,
+    next_ifd: [u8; 4],
+    /// Values of the vault tags in `data`, cleared when writing
+    vault_values: Vec<Range<usize>>,
+    changed: bool,
+}
+
+impl Tiff {
+    pub fn parse(data: &[u8]) -> Result<Tiff> {
+        let big_endian = data.starts_with(BIG_ENDIAN);
+        let mut tiff = Tiff {
+            data: data.to_vec(),
+            big_endian,
+            ifd: 0..0,
+            entries: Vec::new(),
+            vaults: Vec::new(),
+            next_ifd: [0; 4],
+            vault_values: Vec::new(),
+            changed: false,
+        };
+
+        let start = tiff.u32_at(4)? as usize;
+        if start < HEADER_LEN {
+            return Err("TIFF file has no image directory".into());
+        }
+        let count = tiff.u16_at(start)? as usize;
+        let entries_start = start + 2;
+        let end = entries_start + count * ENTRY_LEN + 4;
+        if end > data.len() {
+            return Err("TIFF image directory is truncated".into());
+        }
+        tiff.ifd = start..end;
+        tiff.next_ifd.copy_from_slice(&data[end - 4..end]);
+
+        for i in 0..count {
+            let position = entries_start + i * ENTRY_LEN;
+            let entry = Entry {
+                tag: tiff.u16_at(position)?,
+                kind: tiff.u16_at(position + 2)?,
+                count: tiff.u32_at(position + 4)?,
+                value: data[position + 8..position + 12].try_into()?,
+            };
+            let range = tiff.value_range(&entry);
+            if range.as_ref().is_some_and(|range| range.end > data.len()) {
+                return Err(
+                    format!("Value of TIFF tag {} lies outside the file", entry.tag).into(),
+                );
+            }
+            let vault = range
+                .clone()
+                .filter(|_| PRIVATE_TAGS.contains(&entry.tag) && entry.kind == UNDEFINED)
+                .and_then(|range| split_labelled(data[range].strip_prefix(IDENTIFIER)?));
+            match (vault, range) {
+                (Some((label, payload)), Some(range)) => {
+                    tiff.vaults.push(Vault {
+                        tag: entry.tag,
+                        label: label.to_string(),
+                        payload: payload.to_vec(),
+                    });
+                    tiff.vault_values.push(range);
+                }
+                _ => tiff.entries.push(entry),
+            }
+        }
+        Ok(tiff)
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16> {
+        let bytes: [u8; 2] = self
+            .data
+            .get(offset..offset + 2)
+            .ok_or("TIFF file is truncated")?
+            .try_into()?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32> {
+        let bytes: [u8; 4] = self
+            .data
+            .get(offset..offset + 4)
+            .ok_or("TIFF file is truncated")?
+            .try_into()?;
+        Ok(self.u32_from(bytes))
+    }
+
+    fn u32_from(&self, bytes: [u8; 4]) -> u32 {
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    /// Where the value of `entry` is stored, or `None` if it fits in the four bytes of
+    /// the entry itself
+    fn value_range(&self, entry: &Entry) -> Option<Range<usize>> {
+        let len = entry.count as usize * type_size(entry.kind);
+        let offset = self.u32_from(entry.value) as usize;
+        (len > 4).then_some(offset..offset + len)
+    }
+
+    fn value<'a>(&'a self, entry: &'a Entry) -> &'a [u8] {
+        match self.value_range(entry) {
+            Some(range) => &self.data[range],
+            None => &entry.value[..entry.count as usize * type_size(entry.kind)],
+        }
+    }
+}
+
+/// Bytes per value of a TIFF field type
+fn type_size(kind: u16) -> usize {
+    match kind {
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        // BYTE, ASCII, SBYTE, UNDEFINED and unknown types, which readers skip
+        _ => 1,
+    }
+}
+
+/// Name of the baseline and common extension tags
+fn tag_name(tag: u16) -> Option<&'static str> {
+    Some(match tag {
+        254 => "NewSubfileType",
+        256 => "ImageWidth",
+        257 => "ImageLength",
+        258 => "BitsPerSample",
+        259 => "Compression",
+        262 => "PhotometricInterpretation",
+        266 => "FillOrder",
+        269 => "DocumentName",
+        270 => "ImageDescription",
+        271 => "Make",
+        272 => "Model",
+        273 => "StripOffsets",
+        274 => "Orientation",
+        277 => "SamplesPerPixel",
+        278 => "RowsPerStrip",
+        279 => "StripByteCounts",
+        282 => "XResolution",
+        283 => "YResolution",
+        284 => "PlanarConfiguration",
+        285 => "PageName",
+        296 => "ResolutionUnit",
+        297 => "PageNumber",
+        305 => "Software",
+        306 => "DateTime",
+        315 => "Artist",
+        317 => "Predictor",
+        318 => "WhitePoint",
+        319 => "PrimaryChromaticities",
+        320 => "ColorMap",
+        322 => "TileWidth",
+        323 => "TileLength",
+        324 => "TileOffsets",
+        325 => "TileByteCounts",
+        338 => "ExtraSamples",
+        339 => "SampleFormat",
+        700 => "XMP",
+        33432 => "Copyright",
+        34665 => "ExifIFD",
+        34675 => "ICCProfile",
+        34853 => "GPSInfo",
+        _ => return None,
+    })
+}
+
+impl Container for Tiff {
+    fn format(&self) -> &'static str {
+        "TIFF"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "private tag"
+    }
+
+    fn max_payload(&self, label: &str) -> usize {
+        u32::MAX as usize - IDENTIFIER.len() - 1 - label.len()
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        let mut blocks = vec![Block {
+            name: format!(
+                "Header ({})",
+                if self.big_endian {
+                    "big-endian"
+                } else {
+                    "little-endian"
+                }
+            ),
+            data: &self.data[..HEADER_LEN],
+            label: None,
+        }];
+        for entry in &self.entries {
+            blocks.push(Block {
+                name: match tag_name(entry.tag) {
+                    Some(name) => format!("Tag {} {}", entry.tag, name),
+                    None => format!("Tag {}", entry.tag),
+                },
+                data: self.value(entry),
+                label: None,
+            });
+        }
+        for vault in &self.vaults {
+            blocks.push(Block {
+                name: format!("Tag {} (private)", vault.tag),
+                data: &vault.payload,
+                label: Some(&vault.label),
+            });
+        }
+        blocks
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.vaults
+            .iter()
+            .filter(|vault| vault.label == label)
+            .map(|vault| vault.payload.as_slice())
+            .collect()
+    }
+
+    fn insert(&mut self, label: &str, payload: &[u8]) -> Result<()> {
+        // Checks the label length
+        labelled(label, &[])?;
+        if payload.len() > self.max_payload(label) {
+            return Err("Message is too large for a TIFF tag".into());
+        }
+        // The next free private tag after earlier vault tags, so fragments stay in order
+        let first = self
+            .vaults
+            .iter()
+            .map(|vault| vault.tag + 1)
+            .max()
+            .unwrap_or(PRIVATE_TAGS.start);
+        let tag = (first..PRIVATE_TAGS.end)
+            .find(|&tag| self.entries.iter().all(|entry| entry.tag != tag))
+            .ok_or("No private TIFF tags are left for another message")?;
+        self.vaults.push(Vault {
+            tag,
+            label: label.to_string(),
+            payload: payload.to_vec(),
+        });
+        self.changed = true;
+        Ok(())
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        let before = self.vaults.len();
+        self.vaults.retain(|vault| vault.label != label);
+        let removed = before - self.vaults.len();
+        self.changed |= removed > 0;
+        removed
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        if !self.changed {
+            return self.data.clone();
+        }
+        let mut bytes = self.data.clone();
+
+        // Clear the old directory and the old messages, and drop them altogether if
+        // they are at the end, where the previous write put them
+        let mut cleared = self.vault_values.clone();
+        cleared.push(self.ifd.clone());
+        for range in &cleared {
+            bytes[range.clone()].fill(0);
+        }
+        let mut end = bytes.len();
+        // Values and directories start on word boundaries, so a padding byte may follow
+        while let Some(range) = cleared
+            .iter()
+            .find(|range| range.start < end && (range.end == end || range.end + 1 == end))
+        {
+            end = range.start;
+        }
+        bytes.truncate(end.max(HEADER_LEN));
+
+        let mut entries = self.entries.clone();
+        for vault in &self.vaults {
+            if bytes.len() % 2 == 1 {
+                bytes.push(0);
+            }
+            let offset = bytes.len() as u32;
+            bytes.extend(IDENTIFIER);
+            // insert has checked the label, so this cannot fail
+            let value = labelled(&vault.label, &vault.payload).unwrap_or_default();
+            bytes.extend(&value);
+            entries.push(Entry {
+                tag: vault.tag,
+                kind: UNDEFINED,
+                count: (IDENTIFIER.len() + value.len()) as u32,
+                value: self.u32_bytes(offset),
+            });
+        }
+        entries.sort_by_key(|entry| entry.tag);
+
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        let ifd = bytes.len() as u32;
+        bytes.extend(self.u16_bytes(entries.len() as u16));
+        for entry in &entries {
+            bytes.extend(self.u16_bytes(entry.tag));
+            bytes.extend(self.u16_bytes(entry.kind));
+            bytes.extend(self.u32_bytes(entry.count));
+            bytes.extend(entry.value);
+        }
+        bytes.extend(self.next_ifd);
+        bytes[4..8].copy_from_slice(&self.u32_bytes(ifd));
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x1 8-bit grayscale TIFF: header, pixel strip, a description and the IFD
+    fn test_tiff(big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut data = if big_endian {
+            BIG_ENDIAN
+        } else {
+            LITTLE_ENDIAN
+        }
+        .to_vec();
+        data.extend(u32_bytes(20));
+        data.extend([0x10, 0xf0]);
+        data.extend(b"a test\0\0\0\0");
+        let entries: [(u16, u16, u32, [u8; 4]); 5] = [
+            (
+                256,
+                3,
+                1,
+                [u16_bytes(2), [0, 0]].concat().try_into().unwrap(),
+            ),
+            (
+                257,
+                3,
+                1,
+                [u16_bytes(1), [0, 0]].concat().try_into().unwrap(),
+            ),
+            (270, 2, 7, u32_bytes(10)),
+            (273, 4, 1, u32_bytes(8)),
+            (279, 4, 1, u32_bytes(2)),
+        ];
+        data.extend(u16_bytes(entries.len() as u16));
+        for (tag, kind, count, value) in entries {
+            data.extend(u16_bytes(tag));
+            data.extend(u16_bytes(kind));
+            data.extend(u32_bytes(count));
+            data.extend(value);
+        }
+        data.extend([0; 4]);
+        data
+    }
+
+    #[test]
+    fn test_parse_unchanged() {
+        for big_endian in [false, true] {
+            let data = test_tiff(big_endian);
+            assert!(detect(&data));
+            let tiff = Tiff::parse(&data).unwrap();
+            assert_eq!(tiff.to_bytes(), data);
+            let blocks = tiff.blocks();
+            assert_eq!(blocks.len(), 6);
+            assert_eq!(blocks[3].name, "Tag 270 ImageDescription");
+            assert_eq!(blocks[3].data, b"a test\0");
+            let width = if big_endian { [0, 2] } else { [2, 0] };
+            assert_eq!(blocks[1].data, width);
+        }
+    }
+
+    #[test]
+    fn test_insert_payloads_remove() {
+        for big_endian in [false, true] {
+            let original = test_tiff(big_endian);
+            let mut tiff = Tiff::parse(&original).unwrap();
+            tiff.insert("ruSt", b"first").unwrap();
+            tiff.insert("ruSt", b"second").unwrap();
+
+            let mut tiff = Tiff::parse(&tiff.to_bytes()).unwrap();
+            assert_eq!(tiff.payloads("ruSt"), [&b"first"[..], b"second"]);
+            let blocks = tiff.blocks();
+            assert_eq!(blocks[6].name, "Tag 65000 (private)");
+            assert_eq!(blocks[7].label, Some("ruSt"));
+            // The image and its other tags are untouched
+            assert_eq!(blocks[3].data, b"a test\0");
+            assert_eq!(&tiff.to_bytes()[8..10], [0x10, 0xf0]);
+
+            tiff.insert("raSt", b"other").unwrap();
+            let mut tiff = Tiff::parse(&tiff.to_bytes()).unwrap();
+            assert_eq!(tiff.blocks()[8].name, "Tag 65002 (private)");
+            assert_eq!(tiff.remove("ruSt"), 2);
+            assert_eq!(tiff.remove("raSt"), 1);
+            let cleaned = tiff.to_bytes();
+            // Earlier writes are dropped from the end, the original directory is cleared
+            assert!(cleaned.len() <= original.len() + 2);
+            assert!(!cleaned.windows(IDENTIFIER.len()).any(|w| w == IDENTIFIER));
+            let tiff = Tiff::parse(&cleaned).unwrap();
+            assert_eq!(tiff.blocks().len(), 6);
+        }
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let data = test_tiff(false);
+        assert!(Tiff::parse(&data[..data.len() - 1]).is_err());
+        assert!(Tiff::parse(&data[..20]).is_err());
+        let mut bad_offset = data.clone();
+        bad_offset[22 + 2 * ENTRY_LEN + 8] = 200;
+        assert!(Tiff::parse(&bad_offset).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use super::riff::{self, Chunk, Riff};
+use super::{Block, Container};
+use crate::Result;
+use crate::pixels::{Ihdr, Pixels};
+
+const WAVE: &[u8; 4] = b"WAVE";
+const PCM: u16 = 1;
+/// WAVE_FORMAT_EXTENSIBLE, whose sub-format GUID starts with the actual format code
+const EXTENSIBLE: u16 = 0xfffe;
+/// Chunks defined by the WAVE format and its common extensions (Broadcast Wave, ID3,
+/// sampler and cue data); any other FourCC is free for messages
+const STANDARD_CHUNKS: &[&[u8; 4]] = &[
+    b"fmt ", b"data", b"fact", b"LIST", b"cue ", b"plst", b"smpl", b"inst", b"bext", b"iXML",
+    b"axml", b"cart", b"ds64", b"id3 ", b"ID3 ", b"PEAK", b"acid", b"JUNK", b"PAD ", b"wavl",
+    b"slnt",
+];
+
+pub fn detect(data: &[u8]) -> bool {
+    riff::detect(data, WAVE)
+}
+
+/// The fields of the fmt chunk that decide where sample bytes are
+struct Format {
+    code: u16,
+    channels: u16,
+    sample_rate: u32,
+    block_align: u16,
+    bits_per_sample: u16,
+}
+
+impl Format {
+    fn read(data: &[u8]) -> Option<Format> {
+        let u16_at = |offset: usize| {
+            Some(u16::from_le_bytes(
+                data.get(offset..offset + 2)?.try_into().ok()?,
+            ))
+        };
+        let mut code = u16_at(0)?;
+        if code == EXTENSIBLE {
+            code = u16_at(24)?;
+        }
+        Some(Format {
+            code,
+            channels: u16_at(2)?,
+            sample_rate: u32::from_le_bytes(data.get(4..8)?.try_into().ok()?),
+            block_align: u16_at(12)?,
+            bits_per_sample: u16_at(14)?,
+        })
+    }
+}
+
+/// A WAV file as its list of RIFF chunks. Messages are either chunks of their own, named
+/// by the message label, or with `--lsb` the low bits of the PCM samples.
+pub struct Wav {
+    riff: Riff,
+}
+
+impl Wav {
+    pub fn parse(data: &[u8]) -> Result<Wav> {
+        Ok(Wav {
+            riff: Riff::parse(data, "WAV", STANDARD_CHUNKS)?,
+        })
+    }
+
+    fn chunk(&self, fourcc: &[u8; 4]) -> Result<&Chunk> {
+        self.riff
+            .chunks
+            .iter()
+            .find(|chunk| &chunk.fourcc == fourcc)
+            .ok_or_else(|| {
+                format!(
+                    "WAV file has no '{}' chunk",
+                    String::from_utf8_lossy(fourcc)
+                )
+                .into()
+            })
+    }
+
+    /// Bytes per sample, checking that the samples are integer PCM
+    fn sample_len(&self) -> Result<usize> {
+        let format =
+            Format::read(&self.chunk(b"fmt ")?.data).ok_or("WAV format chunk is truncated")?;
+        if format.code != PCM {
+            return Err(format!(
+                "Only integer PCM WAV files can carry --lsb messages, this one has format {:#06x}",
+                format.code
+            )
+            .into());
+        }
+        let channels = format.channels.max(1) as usize;
+        let len = format.block_align as usize / channels;
+        if !(1..=4).contains(&len) {
+            return Err(format!("Unsupported WAV sample size of {} bytes", len).into());
+        }
+        Ok(len)
+    }
+}
+
+/// Description of the chunks the format defines, such as the sample format for fmt
+fn describe(chunk: &Chunk) -> Option<String> {
+    Some(match &chunk.fourcc {
+        b"fmt " => {
+            let format = Format::read(&chunk.data)?;
+            format!(
+                "{}, {} channel(s), {} Hz, {}-bit",
+                match format.code {
+                    PCM => "PCM".to_string(),
+                    3 => "IEEE float".to_string(),
+                    code => format!("format {:#06x}", code),
+                },
+                format.channels,
+                format.sample_rate,
+                format.bits_per_sample
+            )
+        }
+        b"data" => "audio samples".to_string(),
+        b"LIST" => format!(
+            "{} list",
+            String::from_utf8_lossy(chunk.data.get(..4)?).trim_end()
+        ),
+        b"bext" => "Broadcast Wave metadata".to_string(),
+        b"id3 " | b"ID3 " => "ID3 tags".to_string(),
+        b"JUNK" | b"PAD " => "padding".to_string(),
+        _ => return None,
+    })
+}
+
+impl Container for Wav {
+    fn format(&self) -> &'static str {
+        "WAV"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "RIFF chunk"
+    }
+
+    fn max_payload(&self, _label: &str) -> usize {
+        u32::MAX as usize - 1
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        self.riff.blocks(describe)
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.riff.payloads(label)
+    }
+
+    fn insert(&mut self, label: &str, data: &[u8]) -> Result<()> {
+        self.riff.insert(label, data)
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        self.riff.remove(label)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.riff.to_bytes()
+    }
+
+    /// The low byte of every sample (samples are little-endian) as one row of 8-bit
+    /// gray pixels, so `--lsb` changes only the quietest bits of the audio
+    fn pixels(&self) -> Result<Pixels> {
+        let len = self.sample_len()?;
+        let low_bytes: Vec<u8> = self
+            .chunk(b"data")?
+            .data
+            .iter()
+            .step_by(len)
+            .copied()
+            .collect();
+        let ihdr = Ihdr {
+            width: u32::try_from(low_bytes.len())?,
+            height: 1,
+            bit_depth: 8,
+            color_type: 0,
+            interlaced: false,
+        };
+        Pixels::from_raw(ihdr, low_bytes)
+    }
+
+    fn set_pixels(&mut self, pixels: &Pixels) -> Result<()> {
+        let len = self.sample_len()?;
+        let chunk = self
+            .riff
+            .chunks
+            .iter_mut()
+            .find(|chunk| &chunk.fourcc == b"data")
+            .ok_or("WAV file has no 'data' chunk")?;
+        for (sample, &low) in chunk.data.iter_mut().step_by(len).zip(pixels.data()) {
+            *sample = low;
+        }
+        Ok(())
+    }
+
+    fn sample_names(&self) -> (&'static str, &'static str) {
+        ("audio samples", "sample")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16-bit stereo PCM WAV with four frames
+    fn test_wav() -> Vec<u8> {
+        let mut fmt = PCM.to_le_bytes().to_vec();
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend(32000u32.to_le_bytes());
+        fmt.extend(4u16.to_le_bytes());
+        fmt.extend(16u16.to_le_bytes());
+        let samples: Vec<u8> = (0..16).map(|i| i * 16 + 1).collect();
+
+        let mut data = b"RIFF".to_vec();
+        data.extend((4 + 8 + fmt.len() as u32 + 8 + samples.len() as u32).to_le_bytes());
+        data.extend(WAVE);
+        data.extend(b"fmt ");
+        data.extend((fmt.len() as u32).to_le_bytes());
+        data.extend(fmt);
+        data.extend(b"data");
+        data.extend((samples.len() as u32).to_le_bytes());
+        data.extend(samples);
+        data
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let data = test_wav();
+        assert!(detect(&data));
+        let wav = Wav::parse(&data).unwrap();
+        assert_eq!(wav.to_bytes(), data);
+        let names: Vec<String> = wav.blocks().into_iter().map(|block| block.name).collect();
+        assert_eq!(
+            names,
+            [
+                "fmt (PCM, 2 channel(s), 8000 Hz, 16-bit)",
+                "data (audio samples)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pixels_are_low_sample_bytes() {
+        let mut wav = Wav::parse(&test_wav()).unwrap();
+        let mut pixels = wav.pixels().unwrap();
+        assert_eq!(pixels.ihdr.width, 8);
+        assert_eq!(pixels.data(), [1, 33, 65, 97, 129, 161, 193, 225]);
+
+        pixels.set_byte(1, 0);
+        wav.set_pixels(&pixels).unwrap();
+        let samples = &wav.chunk(b"data").unwrap().data;
+        assert_eq!(samples[2..4], [0, 49]);
+        assert_eq!(samples[0..2], [1, 17]);
+    }
+
+    #[test]
+    fn test_chunks_and_unsupported_formats() {
+        let mut wav = Wav::parse(&test_wav()).unwrap();
+        wav.insert("ruSt", b"hidden").unwrap();
+        assert!(wav.insert("data", b"x").is_err());
+        let mut wav = Wav::parse(&wav.to_bytes()).unwrap();
+        assert_eq!(wav.payloads("ruSt"), [b"hidden"]);
+        assert_eq!(wav.remove("ruSt"), 1);
+
+        let mut float = test_wav();
+        float[20] = 3;
+        assert!(Wav::parse(&float).unwrap().pixels().is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use super::riff::{self, Chunk, Riff};
+use super::{Block, Container};
+use crate::Result;
+
+const WEBP: &[u8; 4] = b"WEBP";
+const VP8X: &[u8; 4] = b"VP8X";
+/// Canvas flags (1), reserved (3), canvas width - 1 (3) and height - 1 (3)
+const VP8X_LEN: usize = 10;
+const ALPHA_FLAG: u8 = 0x10;
+/// Chunks defined by the WebP container specification; any other FourCC is free for
+/// messages
+const STANDARD_CHUNKS: &[&[u8; 4]] = &[
+    b"VP8 ", b"VP8L", b"VP8X", b"ALPH", b"ANIM", b"ANMF", b"ICCP", b"EXIF", b"XMP ",
+];
+
+pub fn detect(data: &[u8]) -> bool {
+    riff::detect(data, WEBP)
+}
+
+/// A WebP file as its list of RIFF chunks. Messages are chunks of their own, named by
+/// the message label.
+pub struct Webp {
+    riff: Riff,
+}
+
+impl Webp {
+    pub fn parse(data: &[u8]) -> Result<Webp> {
+        let riff = Riff::parse(data, "WebP", STANDARD_CHUNKS)?;
+        if riff.chunks.is_empty() {
+            return Err("WebP file has no image data".into());
+        }
+        Ok(Webp { riff })
+    }
+
+    /// Turns a simple (single-bitstream) file into the extended format, which is the
+    /// one that allows chunks of other types
+    fn make_extended(&mut self) -> Result<()> {
+        let chunks = &mut self.riff.chunks;
+        if chunks.iter().any(|chunk| &chunk.fourcc == VP8X) {
+            return Ok(());
+        }
+        let image = &chunks[0];
+        let (width, height, alpha) = match &image.fourcc {
+            // Frame tag (3), start code (3), then 14-bit width and height
+            b"VP8 " if image.data.get(3..6) == Some(&[0x9d, 0x01, 0x2a]) => {
+                let size = |offset: usize| {
+                    u16::from_le_bytes([image.data[offset], image.data[offset + 1]]) & 0x3fff
+                };
+                if image.data.len() < 10 {
+                    return Err("VP8 bitstream is truncated".into());
+                }
+                (size(6) as u32, size(8) as u32, false)
+            }
+            // Signature byte, then width - 1 and height - 1 in 14 bits each and the
+            // alpha hint
+            b"VP8L" if image.data.len() >= 5 && image.data[0] == 0x2f => {
+                let bits = u32::from_le_bytes([
+                    image.data[1],
+                    image.data[2],
+                    image.data[3],
+                    image.data[4],
+                ]);
+                (
+                    (bits & 0x3fff) + 1,
+                    ((bits >> 14) & 0x3fff) + 1,
+                    (bits >> 28) & 1 == 1,
+                )
+            }
+            _ => {
+                return Err(format!(
+                    "Cannot read the canvas size of a WebP file starting with a '{}' chunk",
+                    image.name()
+                )
+                .into());
+            }
+        };
+
+        let mut data = vec![if alpha { ALPHA_FLAG } else { 0 }, 0, 0, 0];
+        data.extend(&(width - 1).to_le_bytes()[..3]);
+        data.extend(&(height - 1).to_le_bytes()[..3]);
+        chunks.insert(
+            0,
+            Chunk {
+                fourcc: *VP8X,
+                data,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Description of the chunks the format defines, such as "lossless bitstream" for VP8L
+fn describe(chunk: &Chunk) -> Option<String> {
+    Some(match &chunk.fourcc {
+        b"VP8 " => "lossy bitstream".to_string(),
+        b"VP8L" => "lossless bitstream".to_string(),
+        b"VP8X" if chunk.data.len() >= VP8X_LEN => {
+            let size = |offset: usize| {
+                u32::from_le_bytes([
+                    chunk.data[offset],
+                    chunk.data[offset + 1],
+                    chunk.data[offset + 2],
+                    0,
+                ]) + 1
+            };
+            format!("extended format, {}x{} canvas", size(4), size(7))
+        }
+        b"ALPH" => "alpha".to_string(),
+        b"ANIM" => "animation parameters".to_string(),
+        b"ANMF" => "animation frame".to_string(),
+        b"ICCP" => "color profile".to_string(),
+        b"EXIF" => "Exif metadata".to_string(),
+        b"XMP " => "XMP metadata".to_string(),
+        _ => return None,
+    })
+}
+
+impl Container for Webp {
+    fn format(&self) -> &'static str {
+        "WebP"
+    }
+
+    fn block_kind(&self) -> &'static str {
+        "RIFF chunk"
+    }
+
+    fn max_payload(&self, _label: &str) -> usize {
+        u32::MAX as usize - 1
+    }
+
+    fn blocks(&self) -> Vec<Block<'_>> {
+        self.riff.blocks(describe)
+    }
+
+    fn payloads(&self, label: &str) -> Vec<&[u8]> {
+        self.riff.payloads(label)
+    }
+
+    fn insert(&mut self, label: &str, data: &[u8]) -> Result<()> {
+        self.riff.fourcc(label)?;
+        self.make_extended()?;
+        self.riff.insert(label, data)
+    }
+
+    fn remove(&mut self, label: &str) -> usize {
+        self.riff.remove(label)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.riff.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A simple lossless WebP: one VP8L chunk for a 3x2 image with alpha
+    fn test_webp() -> Vec<u8> {
+        let mut bitstream = vec![0x2f];
+        bitstream.extend((2 | (1 << 14) | (1 << 28) as u32).to_le_bytes());
+        bitstream.extend([0xaa, 0xbb]);
+        let mut data = b"RIFF".to_vec();
+        data.extend((4 + 8 + bitstream.len() as u32 + 1).to_le_bytes());
+        data.extend(WEBP);
+        data.extend(b"VP8L");
+        data.extend((bitstream.len() as u32).to_le_bytes());
+        data.extend(bitstream);
+        data.push(0);
+        data
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let data = test_webp();
+        assert!(detect(&data));
+        let webp = Webp::parse(&data).unwrap();
+        assert_eq!(webp.to_bytes(), data);
+        assert_eq!(webp.blocks()[0].name, "VP8L (lossless bitstream)");
+        assert!(!detect(b"RIFF\0\0\0\0WAVE"));
+    }
+
+    #[test]
+    fn test_insert_makes_file_extended() {
+        let mut webp = Webp::parse(&test_webp()).unwrap();
+        webp.insert("ruSt", b"odd").unwrap();
+        webp.insert("ruSt", b"even").unwrap();
+        assert!(webp.insert("EXIF", b"x").is_err());
+        assert!(webp.insert("long", b"x").is_ok());
+        assert!(webp.insert("toolong", b"x").is_err());
+
+        let mut webp = Webp::parse(&webp.to_bytes()).unwrap();
+        let blocks = webp.blocks();
+        assert_eq!(blocks[0].name, "VP8X (extended format, 3x2 canvas)");
+        assert_eq!(blocks[0].data[0], ALPHA_FLAG);
+        assert_eq!(blocks[2].label, Some("ruSt"));
+        assert_eq!(webp.payloads("ruSt"), [&b"odd"[..], b"even"]);
+        assert!(webp.payloads("VP8L").is_empty());
+
+        assert_eq!(webp.remove("ruSt"), 2);
+        assert_eq!(webp.remove("VP8L"), 0);
+        assert_eq!(webp.payloads("long"), [b"x"]);
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let data = test_webp();
+        assert!(Webp::parse(&data[..data.len() - 1]).is_err());
+        let mut short_chunk = data.clone();
+        short_chunk[16] = 200;
+        assert!(Webp::parse(&short_chunk).is_err());
+        assert!(Webp::parse(b"RIFF\x04\0\0\0WEBP").is_err());
+    }
+}
@@ -0,0 +1,1083 @@
+use crate::exit::Failure;
+use crate::{Error, Result};
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead as _, KeyInit};
+use age::secrecy::ExposeSecret;
+use chacha20::ChaCha20;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20poly1305::ChaCha20Poly1305;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hardware::CHALLENGE_LEN;
+use hmac::{Hmac, Mac};
+use kdf::Params;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+pub use secret::Secret;
+
+mod age_file;
+mod bech32;
+mod gpg;
+mod hardware;
+mod kdf;
+mod secret;
+mod shamir;
+
+pub const SALT_LEN: usize = 16;
+const SIGNING_KEY_HRP: &str = "vault-signing-key-";
+const VERIFYING_KEY_HRP: &str = "vaultpub";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const PARAMS_LEN: usize = 12;
+const HEADER_LEN: usize = 1 + PARAMS_LEN + SALT_LEN + NONCE_LEN;
+const HMAC_MAGIC: &[u8; 8] = b"HPVHMAC\x01";
+const HMAC_HEADER_LEN: usize = HMAC_MAGIC.len() + PARAMS_LEN + SALT_LEN;
+const HMAC_TAG_LEN: usize = 32;
+const HARDWARE_MAGIC: &[u8; 8] = b"HPVHWKY\x01";
+const HARDWARE_HEADER_LEN: usize = HARDWARE_MAGIC.len() + 2 + CHALLENGE_LEN + 1;
+const HARDWARE_KEK_DOMAIN: &[u8] = b"hidden-pixel-vault/hardware-kek";
+/// The one error every failed tag or MAC check reports, so wrong keys and
+/// tampered data are indistinguishable
+const AUTHENTICATION_FAILED: &str = "Authentication failed: wrong password/key or corrupted data";
+const CHUNK_NAME_DOMAIN: &[u8] = b"hidden-pixel-vault/chunk-name\0";
+const PIXEL_ORDER_DOMAIN: &[u8] = b"hidden-pixel-vault/pixel-order\0";
+/// Fixed so chunk names and pixel orders derived from a label stay stable if the payload defaults ever change
+const LABEL_KEY_PARAMS: Params = Params {
+    memory_kib: 19 * 1024,
+    iterations: 2,
+    parallelism: 1,
+};
+const SHARE_SET_ID_LEN: usize = 8;
+const SHARE_HEADER_LEN: usize = SHARE_SET_ID_LEN + 2;
+const SHARE_CHECKSUM_LEN: usize = 16;
+
+/// AEAD used for password-protected payloads, recorded as the first payload byte
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Cipher {
+    /// AES-256-GCM (fast with AES-NI)
+    #[default]
+    AesGcm,
+    /// ChaCha20-Poly1305 (fast without AES hardware support)
+    #[value(name = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::AesGcm => 1,
+            Cipher::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Cipher> {
+        match id {
+            1 => Ok(Cipher::AesGcm),
+            2 => Ok(Cipher::ChaCha20Poly1305),
+            _ => Err(format!("Unknown cipher ID {} in encrypted payload", id).into()),
+        }
+    }
+
+    fn with_key(self, key: &Secret) -> Result<Aead> {
+        let key = key.expose();
+        Ok(match self {
+            Cipher::AesGcm => Aead::AesGcm(Box::new(
+                Aes256Gcm::new_from_slice(key).map_err(|_| "Invalid AES-256-GCM key length")?,
+            )),
+            Cipher::ChaCha20Poly1305 => Aead::ChaCha20Poly1305(
+                ChaCha20Poly1305::new_from_slice(key)
+                    .map_err(|_| "Invalid ChaCha20-Poly1305 key length")?,
+            ),
+        })
+    }
+}
+
+/// AEAD implementations from the RustCrypto crates; `aes-gcm` picks AES-NI and CLMUL
+/// at runtime and falls back to a bitsliced, constant-time software path
+enum Aead {
+    AesGcm(Box<Aes256Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+impl Aead {
+    /// Encrypts `plaintext` and returns ciphertext followed by the 16-byte tag
+    fn seal(&self, nonce: &[u8; NONCE_LEN], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let sealed = match self {
+            Aead::AesGcm(cipher) => cipher.encrypt(nonce.into(), plaintext),
+            Aead::ChaCha20Poly1305(cipher) => cipher.encrypt(nonce.into(), plaintext),
+        };
+        Ok(sealed.map_err(|_| "Message is too large to encrypt")?)
+    }
+
+    /// Verifies the tag on `ciphertext` (ciphertext || tag) and returns the plaintext
+    fn open(&self, nonce: &[u8; NONCE_LEN], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let opened = match self {
+            Aead::AesGcm(cipher) => cipher.decrypt(nonce.into(), ciphertext),
+            Aead::ChaCha20Poly1305(cipher) => cipher.decrypt(nonce.into(), ciphertext),
+        };
+        Ok(opened.map_err(|_| AUTHENTICATION_FAILED)?)
+    }
+}
+
+impl Drop for Aead {
+    fn drop(&mut self) {
+        // `aes` wipes its round keys and ChaCha20Poly1305 its key, but polyval's
+        // CPU-detecting backend never wipes the GHASH key, so wipe the whole AES-GCM state
+        if let Aead::AesGcm(cipher) = self {
+            // SAFETY: the AES-GCM state is key material and zero-sized markers, with no
+            // pointers or niches, so all zero bytes is a valid value for its own drop glue
+            unsafe { zeroize::zeroize_flat_type(&mut **cipher as *mut Aes256Gcm) };
+        }
+    }
+}
+
+impl ZeroizeOnDrop for Aead {}
+
+/// Password-protected payload as stored in a chunk.
+///
+/// Layout (integers big-endian):
+/// cipher ID (1) | Argon2 memory KiB (4) | iterations (4) | parallelism (4) | salt (16) | nonce (12) | ciphertext || tag (16)
+pub struct EncryptedPayload {
+    cipher: Cipher,
+    params: Params,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for EncryptedPayload {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() < HEADER_LEN + TAG_LEN {
+            return Err("Encrypted payload is too short".into());
+        }
+        let cipher = Cipher::from_id(value[0])?;
+        let value = &value[1..];
+
+        let read_u32 =
+            |offset: usize| u32::from_be_bytes(value[offset..offset + 4].try_into().unwrap());
+        let params = Params::from_payload(read_u32(0), read_u32(4), read_u32(8))?;
+
+        Ok(EncryptedPayload {
+            cipher,
+            params,
+            salt: value[PARAMS_LEN..PARAMS_LEN + SALT_LEN].try_into()?,
+            nonce: value[PARAMS_LEN + SALT_LEN..HEADER_LEN - 1].try_into()?,
+            ciphertext: value[HEADER_LEN - 1..].to_vec(),
+        })
+    }
+}
+
+impl EncryptedPayload {
+    /// Encrypts `plaintext` with `cipher` using an Argon2id key derived from `secret`
+    pub fn seal(secret: &[u8], plaintext: &[u8], cipher: Cipher) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        random_bytes(&mut salt)?;
+        random_bytes(&mut nonce)?;
+
+        let params = Params::default();
+        let key = derive_key(secret, &salt, params)?;
+        let ciphertext = cipher.with_key(&key)?.seal(&nonce, plaintext)?;
+
+        Ok(EncryptedPayload {
+            cipher,
+            params,
+            salt,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Re-derives the key from `secret` for this payload's salt and parameters
+    fn aead(&self, secret: &[u8]) -> Result<Aead> {
+        let key = derive_key(secret, &self.salt, self.params)?;
+        self.cipher.with_key(&key)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut container = Vec::with_capacity(HEADER_LEN + self.ciphertext.len());
+        container.push(self.cipher.id());
+        container.extend(self.params.memory_kib.to_be_bytes());
+        container.extend(self.params.iterations.to_be_bytes());
+        container.extend(self.params.parallelism.to_be_bytes());
+        container.extend(self.salt);
+        container.extend(self.nonce);
+        container.extend(&self.ciphertext);
+        container
+    }
+}
+
+fn derive_key(secret: &[u8], salt: &[u8], params: Params) -> Result<Secret> {
+    Ok(Secret::from(kdf::argon2id(secret, salt, params, KEY_LEN)?))
+}
+
+/// Encrypts a message and returns the serialized [`EncryptedPayload`].
+///
+/// `secret` is either a password or the contents of a key file.
+pub fn encrypt(secret: &[u8], plaintext: &[u8], cipher: Cipher) -> Result<Vec<u8>> {
+    Ok(EncryptedPayload::seal(secret, plaintext, cipher)?.as_bytes())
+}
+
+/// Parses a serialized [`EncryptedPayload`] (or a payload from [`encrypt_dual`]) and decrypts it
+pub fn decrypt(secret: &[u8], payload: &[u8]) -> Result<Secret> {
+    Ok(open_payload(secret, payload)?.0)
+}
+
+/// Re-encrypts a payload from `old_secret` to `new_secret`, keeping its cipher.
+///
+/// An HMAC frame inside is re-keyed as well. For a dual payload only the slot
+/// `old_secret` opens is replaced; the other message is left untouched.
+pub fn rekey(old_secret: &[u8], new_secret: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    let (message, opened) = open_payload(old_secret, payload)?;
+    let message = if has_hmac(message.expose()) {
+        let message = verify_hmac(old_secret, message.expose())?;
+        Secret::from(append_hmac(new_secret, message.expose())?)
+    } else {
+        message
+    };
+
+    let single = EncryptedPayload::try_from(payload)?;
+    match opened {
+        Opened::Single => encrypt(new_secret, message.expose(), single.cipher),
+        Opened::Slot(index) => {
+            let header_len = 1 + PARAMS_LEN;
+            let slot_len = (payload.len() - header_len) / 2;
+            let padded_len = slot_len - DUAL_SLOT_OVERHEAD + 4;
+            let slot = seal_slot(
+                new_secret,
+                single.params,
+                single.cipher,
+                message.expose(),
+                padded_len,
+            )?;
+
+            let mut rekeyed = payload.to_vec();
+            let offset = header_len + index * slot_len;
+            rekeyed[offset..offset + slot_len].copy_from_slice(&slot);
+            Ok(rekeyed)
+        }
+    }
+}
+
+/// Which part of a payload a secret opened
+enum Opened {
+    Single,
+    Slot(usize),
+}
+
+fn open_payload(secret: &[u8], payload: &[u8]) -> Result<(Secret, Opened)> {
+    let single = EncryptedPayload::try_from(payload)?;
+    let aead = single.aead(secret)?;
+    let error = match aead.open(&single.nonce, &single.ciphertext) {
+        Ok(plaintext) => return Ok((Secret::from(plaintext), Opened::Single)),
+        Err(e) => e,
+    };
+
+    // Not a single message; try both halves of a dual payload. The first slot
+    // starts where a single payload's salt does, so its key is already derived.
+    let slots = &payload[1 + PARAMS_LEN..];
+    if !slots.len().is_multiple_of(2) || slots.len() / 2 < DUAL_SLOT_OVERHEAD {
+        return Err(error);
+    }
+    let (first, second) = slots.split_at(slots.len() / 2);
+    if let Ok(message) = open_slot(&aead, first) {
+        return Ok((message, Opened::Slot(0)));
+    }
+    let key = derive_key(secret, &second[..SALT_LEN], single.params)?;
+    let message = open_slot(&single.cipher.with_key(&key)?, second).map_err(|_| error)?;
+    Ok((message, Opened::Slot(1)))
+}
+
+/// Encrypts `message` under `secret` and `decoy` under `decoy_secret` into one deniable payload.
+///
+/// Layout: cipher ID (1) | Argon2 params (12) | slot | slot, each slot being salt (16) |
+/// nonce (12) | ciphertext || tag (16) over length (4) | message | zero padding. Both slots
+/// are padded to the same size and stored in random order, and the first slot lines up
+/// with the header of an [`EncryptedPayload`], so the payload reveals neither that it
+/// holds two messages nor which password opens the real one.
+pub fn encrypt_dual(
+    secret: &[u8],
+    message: &[u8],
+    decoy_secret: &[u8],
+    decoy: &[u8],
+    cipher: Cipher,
+) -> Result<Vec<u8>> {
+    if secret == decoy_secret {
+        return Err("The decoy password must differ from the real one".into());
+    }
+
+    let params = Params::default();
+    let padded_len = 4 + message.len().max(decoy.len());
+    let mut slots = [
+        seal_slot(secret, params, cipher, message, padded_len)?,
+        seal_slot(decoy_secret, params, cipher, decoy, padded_len)?,
+    ];
+    let mut coin = [0u8; 1];
+    random_bytes(&mut coin)?;
+    if coin[0] & 1 == 1 {
+        slots.swap(0, 1);
+    }
+
+    let mut payload = Vec::with_capacity(1 + PARAMS_LEN + 2 * slots[0].len());
+    payload.push(cipher.id());
+    payload.extend(params.memory_kib.to_be_bytes());
+    payload.extend(params.iterations.to_be_bytes());
+    payload.extend(params.parallelism.to_be_bytes());
+    payload.extend(slots.concat());
+    Ok(payload)
+}
+
+const DUAL_SLOT_OVERHEAD: usize = SALT_LEN + NONCE_LEN + 4 + TAG_LEN;
+
+fn seal_slot(
+    secret: &[u8],
+    params: Params,
+    cipher: Cipher,
+    message: &[u8],
+    padded_len: usize,
+) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    random_bytes(&mut salt)?;
+    random_bytes(&mut nonce)?;
+    let key = derive_key(secret, &salt, params)?;
+
+    let mut padded = Secret::from(vec![0u8; padded_len]);
+    let buf = padded.expose_mut();
+    buf[..4].copy_from_slice(&(message.len() as u32).to_be_bytes());
+    buf[4..4 + message.len()].copy_from_slice(message);
+
+    let mut slot = Vec::with_capacity(padded_len + DUAL_SLOT_OVERHEAD - 4);
+    slot.extend(salt);
+    slot.extend(nonce);
+    slot.extend(cipher.with_key(&key)?.seal(&nonce, padded.expose())?);
+    Ok(slot)
+}
+
+fn open_slot(aead: &Aead, slot: &[u8]) -> Result<Secret> {
+    let nonce = slot[SALT_LEN..SALT_LEN + NONCE_LEN].try_into()?;
+    let padded = Secret::from(aead.open(&nonce, &slot[SALT_LEN + NONCE_LEN..])?);
+    let (length, rest) = padded.expose().split_at(4);
+    let length = u32::from_be_bytes(length.try_into()?) as usize;
+    if length > rest.len() {
+        return Err("Invalid message length in dual payload".into());
+    }
+    Ok(Secret::from(rest[..length].to_vec()))
+}
+
+/// Frames `message` with an HMAC-SHA256 tag keyed from `secret`.
+///
+/// Layout: magic (8) | Argon2 params (12) | salt (16) | message | tag (32),
+/// where the tag covers everything before it.
+pub fn append_hmac(secret: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    random_bytes(&mut salt)?;
+    let params = Params::default();
+    let key = derive_key(secret, &salt, params)?;
+
+    let mut framed = Vec::with_capacity(HMAC_HEADER_LEN + message.len() + HMAC_TAG_LEN);
+    framed.extend(HMAC_MAGIC);
+    framed.extend(params.memory_kib.to_be_bytes());
+    framed.extend(params.iterations.to_be_bytes());
+    framed.extend(params.parallelism.to_be_bytes());
+    framed.extend(salt);
+    framed.extend(message);
+    let tag = hmac_sha256(key.expose(), &framed);
+    framed.extend(tag);
+    Ok(framed)
+}
+
+/// Whether `data` was framed by [`append_hmac`]
+pub fn has_hmac(data: &[u8]) -> bool {
+    data.starts_with(HMAC_MAGIC)
+}
+
+/// Checks the HMAC tag on a framed message and returns the message
+pub fn verify_hmac(secret: &[u8], framed: &[u8]) -> Result<Secret> {
+    if !has_hmac(framed) || framed.len() < HMAC_HEADER_LEN + HMAC_TAG_LEN {
+        return Err("Message does not carry a valid HMAC frame".into());
+    }
+
+    let read_u32 =
+        |offset: usize| u32::from_be_bytes(framed[offset..offset + 4].try_into().unwrap());
+    let params = Params::from_payload(read_u32(8), read_u32(12), read_u32(16))?;
+    let salt = &framed[20..HMAC_HEADER_LEN];
+    let key = derive_key(secret, salt, params)?;
+
+    let (body, tag) = framed.split_at(framed.len() - HMAC_TAG_LEN);
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key.expose())?;
+    mac.update(body);
+    mac.verify_slice(tag).map_err(|_| AUTHENTICATION_FAILED)?;
+    Ok(Secret::from(body[HMAC_HEADER_LEN..].to_vec()))
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac =
+        <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Encrypts a message to one or more age recipients (`age1...`)
+pub fn encrypt_to_recipients(recipients: &[String], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let recipients = recipients
+        .iter()
+        .map(|r| age_file::parse_recipient(r))
+        .collect::<Result<Vec<_>>>()?;
+    age_file::encrypt(&recipients, plaintext)
+}
+
+/// Decrypts an age payload with the identities stored in `identity_file`
+pub fn decrypt_with_identity_file(identity_file: &Path, payload: &[u8]) -> Result<Secret> {
+    let contents = Secret::from(fs::read(identity_file).map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to read identity file '{}': {}",
+            identity_file.display(),
+            e
+        ))
+    })?);
+    let identities = age_file::parse_identities(contents.as_str()?)?;
+    Ok(Secret::from(age_file::decrypt(&identities, payload)?))
+}
+
+pub use gpg::encrypt as gpg_encrypt;
+
+/// Decrypts (and verifies, if signed) an armored OpenPGP message with gpg
+pub fn gpg_decrypt(payload: &[u8]) -> Result<Secret> {
+    Ok(Secret::from(gpg::decrypt(payload)?))
+}
+
+pub use hardware::{KeyProvider, YubiKey};
+
+/// Encrypts a message under a random data key wrapped with a key from `provider`.
+///
+/// Layout: magic (8) | provider descriptor (2) | challenge (32) | cipher ID (1) |
+/// wrap nonce (12) | wrapped data key (32) || tag (16) | nonce (12) | ciphertext || tag (16)
+pub fn encrypt_with_provider(
+    provider: &dyn KeyProvider,
+    plaintext: &[u8],
+    cipher: Cipher,
+) -> Result<Vec<u8>> {
+    let mut challenge = [0u8; CHALLENGE_LEN];
+    let mut data_key = Secret::from(vec![0u8; KEY_LEN]);
+    let mut wrap_nonce = [0u8; NONCE_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    random_bytes(&mut challenge)?;
+    random_bytes(data_key.expose_mut())?;
+    random_bytes(&mut wrap_nonce)?;
+    random_bytes(&mut nonce)?;
+
+    let kek = hardware_kek(provider, &challenge)?;
+
+    let mut payload = HARDWARE_MAGIC.to_vec();
+    payload.extend(provider.descriptor());
+    payload.extend(challenge);
+    payload.push(cipher.id());
+    payload.extend(wrap_nonce);
+    payload.extend(
+        cipher
+            .with_key(&kek)?
+            .seal(&wrap_nonce, data_key.expose())?,
+    );
+    payload.extend(nonce);
+    payload.extend(cipher.with_key(&data_key)?.seal(&nonce, plaintext)?);
+    Ok(payload)
+}
+
+/// Whether `data` was produced by [`encrypt_with_provider`]
+pub fn is_hardware_payload(data: &[u8]) -> bool {
+    data.starts_with(HARDWARE_MAGIC)
+}
+
+/// Returns the key provider a hardware-wrapped payload needs
+pub fn hardware_provider(payload: &[u8]) -> Result<Box<dyn KeyProvider>> {
+    if !is_hardware_payload(payload) || payload.len() < HARDWARE_HEADER_LEN {
+        return Err("Not a hardware-wrapped payload".into());
+    }
+    hardware::from_descriptor(payload[8..10].try_into()?)
+}
+
+/// Unwraps the data key of a hardware-wrapped payload with `provider` and decrypts it
+pub fn decrypt_with_provider(provider: &dyn KeyProvider, payload: &[u8]) -> Result<Secret> {
+    const WRAPPED_LEN: usize = NONCE_LEN + KEY_LEN + TAG_LEN;
+    if !is_hardware_payload(payload)
+        || payload.len() < HARDWARE_HEADER_LEN + WRAPPED_LEN + NONCE_LEN + TAG_LEN
+    {
+        return Err("Hardware-wrapped payload is too short".into());
+    }
+
+    let challenge = payload[10..10 + CHALLENGE_LEN].try_into()?;
+    let cipher = Cipher::from_id(payload[HARDWARE_HEADER_LEN - 1])?;
+    let (wrapped, body) = payload[HARDWARE_HEADER_LEN..].split_at(WRAPPED_LEN);
+
+    let kek = hardware_kek(provider, &challenge)?;
+    let data_key = Secret::from(
+        cipher
+            .with_key(&kek)?
+            .open(wrapped[..NONCE_LEN].try_into()?, &wrapped[NONCE_LEN..])
+            .map_err(|_| AUTHENTICATION_FAILED)?,
+    );
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .with_key(&data_key)?
+        .open(nonce.try_into()?, ciphertext)?;
+    Ok(Secret::from(plaintext))
+}
+
+fn hardware_kek(provider: &dyn KeyProvider, challenge: &[u8; CHALLENGE_LEN]) -> Result<Secret> {
+    let response = provider.respond(challenge)?;
+    Ok(Secret::from(
+        hmac_sha256(response.expose(), HARDWARE_KEK_DOMAIN).to_vec(),
+    ))
+}
+
+/// Derives a private, ancillary, safe-to-copy chunk type (like `qzKv`) from `secret` and `label`.
+///
+/// Uses Argon2id rather than a plain hash so the visible chunk name can't be used
+/// to test password guesses any faster than the payload itself.
+pub fn derive_chunk_type(secret: &[u8], label: &str) -> Result<String> {
+    let key = derive_label_key(secret, CHUNK_NAME_DOMAIN, label)?;
+
+    let letters: Vec<u8> = key.expose()[..4].iter().map(|b| b'a' + b % 26).collect();
+    // Lowercase first, second and fourth letters; the third must be uppercase (reserved bit)
+    Ok([
+        letters[0],
+        letters[1],
+        letters[2].to_ascii_uppercase(),
+        letters[3],
+    ]
+    .iter()
+    .map(|&b| b as char)
+    .collect())
+}
+
+/// Shuffles `items` into an order only holders of `secret` can reproduce, such as the
+/// order in which LSB mode visits pixel samples.
+///
+/// Fisher-Yates driven by a ChaCha20 keystream keyed from `secret` and `label` with Argon2id.
+pub fn keyed_shuffle<T>(secret: &[u8], label: &str, items: &mut [T]) -> Result<()> {
+    let key = derive_label_key(secret, PIXEL_ORDER_DOMAIN, label)?;
+    let mut rng = ChaCha20::new_from_slices(key.expose(), &[0; NONCE_LEN])
+        .map_err(|_| "Invalid ChaCha20 key length")?;
+
+    let mut next_u64 = || {
+        let mut bytes = [0u8; 8];
+        rng.apply_keystream(&mut bytes);
+        u64::from_le_bytes(bytes)
+    };
+    for i in (1..items.len()).rev() {
+        // Multiply-shift maps a u64 onto 0..=i; the bias is below 2^-32 for any image size
+        let j = ((next_u64() as u128 * (i as u128 + 1)) >> 64) as usize;
+        items.swap(i, j);
+    }
+    Ok(())
+}
+
+/// Shuffles `items` uniformly at random with the operating system's CSPRNG
+pub fn random_shuffle<T>(items: &mut [T]) -> Result<()> {
+    for i in (1..items.len()).rev() {
+        items.swap(i, random_index(i + 1)?);
+    }
+    Ok(())
+}
+
+/// Uniformly random index below `bound`, which must not be zero
+pub fn random_index(bound: usize) -> Result<usize> {
+    let mut bytes = [0u8; 8];
+    random_bytes(&mut bytes)?;
+    // Multiply-shift as in `keyed_shuffle`; the bias is negligible for small bounds
+    Ok(((u64::from_le_bytes(bytes) as u128 * bound as u128) >> 64) as usize)
+}
+
+/// Argon2id key for `label`, separated per use by `domain`
+fn derive_label_key(secret: &[u8], domain: &[u8], label: &str) -> Result<Secret> {
+    let mut salt_input = domain.to_vec();
+    salt_input.extend(label.as_bytes());
+    let salt = sha256(&salt_input);
+    derive_key(secret, &salt[..SALT_LEN], LABEL_KEY_PARAMS)
+}
+
+/// Splits `secret` into `n` Shamir shares over GF(256), any `k` of which reconstruct it.
+///
+/// Layout of each share: set ID (8) | k (1) | n (1) | index (1) | share bytes.
+/// A truncated SHA-256 checksum is split along with the secret, so [`combine_shares`]
+/// detects corrupted or mismatched shares without the checksum leaking from fewer than k.
+pub fn split_secret(secret: &[u8], k: u8, n: u8) -> Result<Vec<Vec<u8>>> {
+    let mut set_id = [0u8; SHARE_SET_ID_LEN];
+    random_bytes(&mut set_id)?;
+
+    let mut checked = Vec::with_capacity(secret.len() + SHARE_CHECKSUM_LEN);
+    checked.extend(secret);
+    checked.extend(&sha256(secret)[..SHARE_CHECKSUM_LEN]);
+    let checked = Secret::from(checked);
+
+    Ok(shamir::split(checked.expose(), k, n)?
+        .into_iter()
+        .map(|share| {
+            let mut framed = Vec::with_capacity(SHARE_HEADER_LEN + share.len());
+            framed.extend(set_id);
+            framed.extend([k, n]);
+            framed.extend(share);
+            framed
+        })
+        .collect())
+}
+
+/// Returns the threshold k, share count n and index of a share from [`split_secret`]
+pub fn share_info(share: &[u8]) -> Result<(u8, u8, u8)> {
+    if share.len() < SHARE_HEADER_LEN + 1 + SHARE_CHECKSUM_LEN {
+        return Err("Share is too short".into());
+    }
+    Ok((
+        share[SHARE_SET_ID_LEN],
+        share[SHARE_SET_ID_LEN + 1],
+        share[SHARE_HEADER_LEN],
+    ))
+}
+
+/// Reconstructs a secret from at least k shares produced by [`split_secret`]
+pub fn combine_shares(shares: &[&[u8]]) -> Result<Secret> {
+    let first = shares.first().ok_or("No shares given")?;
+    let (k, _, _) = share_info(first)?;
+    for share in shares {
+        share_info(share)?;
+        if share[..SHARE_HEADER_LEN] != first[..SHARE_HEADER_LEN] {
+            return Err("Shares belong to different splits".into());
+        }
+    }
+    if shares.len() < k as usize {
+        return Err(format!("Need {} shares, only {} given", k, shares.len()).into());
+    }
+
+    let points: Vec<&[u8]> = shares.iter().map(|s| &s[SHARE_HEADER_LEN..]).collect();
+    let checked = Secret::from(shamir::combine(&points)?);
+    let (secret, checksum) = checked
+        .expose()
+        .split_at(checked.expose().len() - SHARE_CHECKSUM_LEN);
+    if !secret::ct_eq(&sha256(secret)[..SHARE_CHECKSUM_LEN], checksum) {
+        return Err("Shares are corrupted or do not belong together".into());
+    }
+    Ok(Secret::from(secret.to_vec()))
+}
+
+/// Generates a new X25519 key pair, returning the identity file contents and public key
+pub fn generate_keypair() -> Result<(String, String)> {
+    let identity = age::x25519::Identity::generate();
+    let public_key = identity.to_public().to_string();
+    let contents = format!(
+        "# public key: {}\n{}\n",
+        public_key,
+        identity.to_string().expose_secret()
+    );
+    Ok((contents, public_key))
+}
+
+/// Reads the `age1...` recipients stored in a public key file
+pub fn read_public_key_file(path: &Path) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to read public key file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let recipients = age_file::parse_recipients(&contents)
+        .map_err(|e| format!("Invalid public key file '{}': {}", path.display(), e))?;
+    Ok(recipients.iter().map(ToString::to_string).collect())
+}
+
+/// Generates a new Ed25519 signing key, returning the key file contents and verifying key
+pub fn generate_signing_keypair() -> Result<(String, String)> {
+    let mut seed = [0u8; 32];
+    random_bytes(&mut seed)?;
+
+    let verifying_key =
+        format_verifying_key(&SigningKey::from_bytes(&seed).verifying_key().to_bytes());
+    let signing_key = bech32::encode(SIGNING_KEY_HRP, &seed).to_uppercase();
+    let contents = format!("# verifying key: {}\n{}\n", verifying_key, signing_key);
+    Ok((contents, verifying_key))
+}
+
+/// Encodes an Ed25519 public key as `vaultpub1...`
+pub fn format_verifying_key(public_key: &[u8; 32]) -> String {
+    bech32::encode(VERIFYING_KEY_HRP, public_key)
+}
+
+/// Signs `message` with the key in a signing key file, returning the public key and signature
+pub fn sign_with_key_file(path: &Path, message: &[u8]) -> Result<([u8; 32], [u8; 64])> {
+    let mut seed = read_key_file(path, SIGNING_KEY_HRP)?;
+    let signing_key = SigningKey::from_bytes(&seed);
+    seed.zeroize();
+    Ok((
+        signing_key.verifying_key().to_bytes(),
+        signing_key.sign(message).to_bytes(),
+    ))
+}
+
+/// Reads the Ed25519 public key from a verifying key file (`vaultpub1...`)
+pub fn read_verifying_key_file(path: &Path) -> Result<[u8; 32]> {
+    read_key_file(path, VERIFYING_KEY_HRP)
+}
+
+pub fn verify_signature(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    VerifyingKey::from_bytes(public_key)
+        .and_then(|key| key.verify_strict(message, &Signature::from_bytes(signature)))
+        .is_ok()
+}
+
+/// Reads the first non-comment line of a key file and decodes it as a 32-byte bech32 key
+fn read_key_file(path: &Path, hrp: &str) -> Result<[u8; 32]> {
+    let contents = fs::read_to_string(path).map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to read key file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let line = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| format!("Key file '{}' is empty", path.display()))?;
+
+    let (found_hrp, key) = bech32::decode(line)
+        .map_err(|e| format!("Invalid key file '{}': {}", path.display(), e))?;
+    if found_hrp != hrp {
+        return Err(format!(
+            "Invalid key file '{}': expected a {}1... key",
+            path.display(),
+            hrp
+        )
+        .into());
+    }
+    key.try_into()
+        .map_err(|_| format!("Invalid key file '{}': wrong key length", path.display()).into())
+}
+
+/// Fills `buf` with bytes from the operating system's CSPRNG
+#[cfg(unix)]
+pub fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    use std::io::Read;
+
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(buf))
+        .map_err(|e| Failure::from(&e).error(format!("Failed to read random bytes: {}", e)))
+}
+
+/// Fills `buf` with bytes from the operating system's CSPRNG
+#[cfg(windows)]
+pub fn random_bytes(buf: &mut [u8]) -> Result<()> {
+    #[link(name = "bcrypt")]
+    unsafe extern "system" {
+        fn BCryptGenRandom(
+            algorithm: *mut std::ffi::c_void,
+            buffer: *mut u8,
+            length: u32,
+            flags: u32,
+        ) -> i32;
+    }
+    const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 0x0000_0002;
+
+    for chunk in buf.chunks_mut(u32::MAX as usize) {
+        let status = unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                chunk.as_mut_ptr(),
+                chunk.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        };
+        if status != 0 {
+            return Err(format!("Failed to read random bytes: NTSTATUS {:#x}", status).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_round_trip() {
+        let payload = encrypt(b"hunter2", b"This is a secret message", Cipher::AesGcm).unwrap();
+        let message = decrypt(b"hunter2", &payload).unwrap();
+        assert_eq!(message.expose(), b"This is a secret message");
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_password() {
+        let payload = encrypt(b"hunter2", b"This is a secret message", Cipher::AesGcm).unwrap();
+        assert!(decrypt(b"hunter3", &payload).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_uses_fresh_nonce() {
+        let first = encrypt(b"hunter2", b"same message", Cipher::AesGcm).unwrap();
+        let second = encrypt(b"hunter2", b"same message", Cipher::AesGcm).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_decrypt_truncated_payload() {
+        assert!(decrypt(b"hunter2", &[0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn test_payload_layout_round_trip() {
+        let payload = EncryptedPayload::seal(b"hunter2", b"layout", Cipher::AesGcm).unwrap();
+        let bytes = payload.as_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + b"layout".len() + TAG_LEN);
+
+        let parsed = EncryptedPayload::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(parsed.params, Params::default());
+        assert_eq!(parsed.salt, payload.salt);
+        assert_eq!(parsed.nonce, payload.nonce);
+        assert_eq!(decrypt(b"hunter2", &bytes).unwrap().expose(), b"layout");
+    }
+
+    #[test]
+    fn test_chacha20_poly1305_round_trip() {
+        let payload = encrypt(b"hunter2", b"no AES-NI here", Cipher::ChaCha20Poly1305).unwrap();
+        assert_eq!(payload[0], Cipher::ChaCha20Poly1305.id());
+        assert_eq!(
+            decrypt(b"hunter2", &payload).unwrap().expose(),
+            b"no AES-NI here"
+        );
+        assert!(decrypt(b"hunter3", &payload).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_unknown_cipher_id() {
+        let mut payload = encrypt(b"hunter2", b"message", Cipher::AesGcm).unwrap();
+        // ID 0 included: there are no payloads without an ID to fall back to
+        for id in [0, 0xff] {
+            payload[0] = id;
+            assert!(decrypt(b"hunter2", &payload).is_err());
+        }
+    }
+
+    #[test]
+    fn test_dual_payload_reveals_message_per_password() {
+        let payload = encrypt_dual(
+            b"real",
+            b"attack at dawn",
+            b"decoy",
+            b"hi",
+            Cipher::ChaCha20Poly1305,
+        )
+        .unwrap();
+        assert_eq!(
+            decrypt(b"real", &payload).unwrap().expose(),
+            b"attack at dawn"
+        );
+        assert_eq!(decrypt(b"decoy", &payload).unwrap().expose(), b"hi");
+        assert!(decrypt(b"other", &payload).is_err());
+    }
+
+    #[test]
+    fn test_dual_payload_slots_have_equal_size() {
+        let payload = encrypt_dual(
+            b"real",
+            b"a much longer message",
+            b"decoy",
+            b"",
+            Cipher::AesGcm,
+        )
+        .unwrap();
+        assert_eq!(
+            payload.len(),
+            1 + PARAMS_LEN + 2 * (DUAL_SLOT_OVERHEAD + b"a much longer message".len())
+        );
+        assert!(encrypt_dual(b"same", b"a", b"same", b"b", Cipher::AesGcm).is_err());
+    }
+
+    #[test]
+    fn test_rekey_single_payload() {
+        let payload = encrypt(b"old", b"rotate me", Cipher::ChaCha20Poly1305).unwrap();
+        let rekeyed = rekey(b"old", b"new", &payload).unwrap();
+        assert_eq!(rekeyed[0], Cipher::ChaCha20Poly1305.id());
+        assert_eq!(decrypt(b"new", &rekeyed).unwrap().expose(), b"rotate me");
+        assert!(decrypt(b"old", &rekeyed).is_err());
+        assert!(rekey(b"wrong", b"new", &payload).is_err());
+    }
+
+    #[test]
+    fn test_rekey_dual_payload_keeps_other_slot() {
+        let payload =
+            encrypt_dual(b"real", b"secret", b"decoy", b"boring", Cipher::AesGcm).unwrap();
+        let rekeyed = rekey(b"decoy", b"decoy2", &payload).unwrap();
+        assert_eq!(rekeyed.len(), payload.len());
+        assert_eq!(decrypt(b"real", &rekeyed).unwrap().expose(), b"secret");
+        assert_eq!(decrypt(b"decoy2", &rekeyed).unwrap().expose(), b"boring");
+        assert!(decrypt(b"decoy", &rekeyed).is_err());
+    }
+
+    #[test]
+    fn test_rekey_rekeys_hmac() {
+        let framed = append_hmac(b"old", b"tagged").unwrap();
+        let payload = encrypt(b"old", &framed, Cipher::AesGcm).unwrap();
+        let rekeyed = rekey(b"old", b"new", &payload).unwrap();
+        let message = decrypt(b"new", &rekeyed).unwrap();
+        assert_eq!(
+            verify_hmac(b"new", message.expose()).unwrap().expose(),
+            b"tagged"
+        );
+    }
+
+    /// Stand-in for a hardware token: an HMAC key that never leaves the "device"
+    struct TestProvider([u8; 16]);
+
+    impl KeyProvider for TestProvider {
+        fn descriptor(&self) -> [u8; 2] {
+            [0xfe, 0]
+        }
+
+        fn name(&self) -> String {
+            "test token".to_string()
+        }
+
+        fn respond(&self, challenge: &[u8; CHALLENGE_LEN]) -> Result<Secret> {
+            Ok(Secret::from(hmac_sha256(&self.0, challenge).to_vec()))
+        }
+    }
+
+    #[test]
+    fn test_provider_wrapped_round_trip() {
+        let token = TestProvider([7; 16]);
+        let payload = encrypt_with_provider(&token, b"needs the token", Cipher::AesGcm).unwrap();
+        assert!(is_hardware_payload(&payload));
+        assert_eq!(
+            decrypt_with_provider(&token, &payload).unwrap().expose(),
+            b"needs the token"
+        );
+        assert!(decrypt_with_provider(&TestProvider([8; 16]), &payload).is_err());
+        assert!(hardware_provider(&payload).is_err());
+    }
+
+    #[test]
+    fn test_provider_wrapped_truncated_payload() {
+        let token = TestProvider([7; 16]);
+        let payload = encrypt_with_provider(&token, b"", Cipher::ChaCha20Poly1305).unwrap();
+        assert!(decrypt_with_provider(&token, &payload).is_ok());
+        assert!(decrypt_with_provider(&token, &payload[..payload.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_derive_chunk_type() {
+        let name = derive_chunk_type(b"hunter2", "RuSt").unwrap();
+        assert_eq!(name, derive_chunk_type(b"hunter2", "RuSt").unwrap());
+        assert_ne!(name, derive_chunk_type(b"hunter3", "RuSt").unwrap());
+        assert_ne!(name, derive_chunk_type(b"hunter2", "other").unwrap());
+
+        let bytes = name.as_bytes();
+        assert_eq!(bytes.len(), 4);
+        assert!(bytes[0].is_ascii_lowercase() && bytes[1].is_ascii_lowercase());
+        assert!(bytes[2].is_ascii_uppercase() && bytes[3].is_ascii_lowercase());
+    }
+
+    #[test]
+    fn test_keyed_shuffle() {
+        let mut order: Vec<u32> = (0..1000).collect();
+        keyed_shuffle(b"hunter2", "RuSt", &mut order).unwrap();
+        let mut again: Vec<u32> = (0..1000).collect();
+        keyed_shuffle(b"hunter2", "RuSt", &mut again).unwrap();
+        assert_eq!(order, again);
+        assert_ne!(order, (0..1000).collect::<Vec<u32>>());
+
+        let mut other: Vec<u32> = (0..1000).collect();
+        keyed_shuffle(b"hunter3", "RuSt", &mut other).unwrap();
+        assert_ne!(order, other);
+
+        order.sort();
+        assert_eq!(order, (0..1000).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_random_shuffle() {
+        let mut order: Vec<u32> = (0..1000).collect();
+        random_shuffle(&mut order).unwrap();
+        assert_ne!(order, (0..1000).collect::<Vec<u32>>());
+        order.sort();
+        assert_eq!(order, (0..1000).collect::<Vec<u32>>());
+
+        assert!((0..100).all(|_| random_index(3).unwrap() < 3));
+        assert_eq!(random_index(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_split_combine_shares() {
+        let shares = split_secret(b"one of three", 2, 3).unwrap();
+        assert_eq!(share_info(&shares[2]).unwrap(), (2, 3, 3));
+        assert_eq!(
+            combine_shares(&[&shares[2], &shares[0]]).unwrap().expose(),
+            b"one of three"
+        );
+        assert!(combine_shares(&[&shares[1]]).is_err());
+
+        let mut corrupted = shares[1].clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(combine_shares(&[&shares[0], &corrupted]).is_err());
+
+        let other = split_secret(b"one of three", 2, 3).unwrap();
+        assert!(combine_shares(&[&shares[0], &other[1]]).is_err());
+    }
+
+    #[test]
+    fn test_hmac_round_trip() {
+        let framed = append_hmac(b"hunter2", b"tamper evident").unwrap();
+        assert!(has_hmac(&framed));
+        assert_eq!(
+            verify_hmac(b"hunter2", &framed).unwrap().expose(),
+            b"tamper evident"
+        );
+        assert!(verify_hmac(b"hunter3", &framed).is_err());
+    }
+
+    #[test]
+    fn test_hmac_detects_modified_message() {
+        let mut framed = append_hmac(b"hunter2", b"tamper evident").unwrap();
+        framed[HMAC_HEADER_LEN] ^= 1;
+        assert!(verify_hmac(b"hunter2", &framed).is_err());
+        assert!(verify_hmac(b"hunter2", &framed[..HMAC_HEADER_LEN]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_costly_payload_params() {
+        let mut payload = encrypt(b"hunter2", b"hello", Cipher::AesGcm).unwrap();
+        // Iterations, then memory, as a crafted image could set them
+        payload[5..9].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decrypt(b"hunter2", &payload).is_err());
+        assert!(rekey(b"hunter2", b"other", &payload).is_err());
+        payload[5..9].copy_from_slice(&2u32.to_be_bytes());
+        payload[1..5].copy_from_slice(&(4 * 1024 * 1024u32).to_be_bytes());
+        assert!(decrypt(b"hunter2", &payload).is_err());
+
+        let mut framed = append_hmac(b"hunter2", b"tamper evident").unwrap();
+        framed[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(verify_hmac(b"hunter2", &framed).is_err());
+    }
+
+    #[test]
+    fn test_salt_changes_derived_key() {
+        let params = Params {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let first = derive_key(b"hunter2", &[1u8; SALT_LEN], params).unwrap();
+        let second = derive_key(b"hunter2", &[2u8; SALT_LEN], params).unwrap();
+        assert_ne!(first.expose(), second.expose());
+    }
+}
@@ -0,0 +1,203 @@
+use super::AUTHENTICATION_FAILED;
+use crate::Result;
+use age::x25519::{Identity, Recipient};
+use age::{DecryptError, Decryptor, Encryptor};
+use std::io::{Read, Write};
+
+/// Parses an age X25519 recipient (`age1...`)
+pub fn parse_recipient(s: &str) -> Result<Recipient> {
+    s.parse().map_err(|e| {
+        format!(
+            "Invalid recipient '{}': {}, expected an age1... public key",
+            s, e
+        )
+        .into()
+    })
+}
+
+/// Parses an identity file: one key per line, `#` comments and blank lines ignored
+pub fn parse_identities(contents: &str) -> Result<Vec<Identity>> {
+    let identities = key_lines(contents)
+        .map(|line| {
+            line.parse::<Identity>().map_err(|e| {
+                format!(
+                    "Invalid identity: {}, expected an AGE-SECRET-KEY-1... key",
+                    e
+                )
+                .into()
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if identities.is_empty() {
+        return Err("Identity file does not contain any AGE-SECRET-KEY-1... keys".into());
+    }
+    Ok(identities)
+}
+
+/// Parses a public key file: one `age1...` recipient per line, `#` comments and blank lines ignored
+pub fn parse_recipients(contents: &str) -> Result<Vec<Recipient>> {
+    let recipients = key_lines(contents)
+        .map(parse_recipient)
+        .collect::<Result<Vec<_>>>()?;
+
+    if recipients.is_empty() {
+        return Err("Public key file does not contain any age1... keys".into());
+    }
+    Ok(recipients)
+}
+
+fn key_lines(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// Encrypts `plaintext` to the given recipients in the age v1 format
+pub fn encrypt(recipients: &[Recipient], plaintext: &[u8]) -> Result<Vec<u8>> {
+    if recipients.is_empty() {
+        return Err("At least one recipient is required".into());
+    }
+
+    let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+        .map_err(|e| format!("Failed to encrypt to the recipients: {}", e))?;
+    let mut output = vec![];
+    let mut writer = encryptor.wrap_output(&mut output)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+    Ok(output)
+}
+
+/// Decrypts an age v1 file using any matching identity
+pub fn decrypt(identities: &[Identity], data: &[u8]) -> Result<Vec<u8>> {
+    let decryptor = Decryptor::new_buffered(data).map_err(describe)?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|id| id as &dyn age::Identity))
+        .map_err(describe)?;
+
+    let mut plaintext = vec![];
+    reader
+        .read_to_end(&mut plaintext)
+        .map_err(|_| "age payload is corrupted")?;
+    Ok(plaintext)
+}
+
+fn describe(error: DecryptError) -> crate::Error {
+    match error {
+        DecryptError::NoMatchingKeys => "No matching identity found for this payload".into(),
+        DecryptError::InvalidMac | DecryptError::DecryptionFailed => AUTHENTICATION_FAILED.into(),
+        DecryptError::InvalidHeader | DecryptError::UnknownFormat => {
+            "Not an age-encrypted payload".into()
+        }
+        other => format!("Failed to decrypt age payload: {}", other).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    #[test]
+    fn test_recipient_round_trip() {
+        let recipient = Identity::generate().to_public();
+        let encoded = recipient.to_string();
+        assert!(encoded.starts_with("age1"));
+        assert_eq!(parse_recipient(&encoded).unwrap().to_string(), encoded);
+        assert!(parse_recipient("age1notakey").is_err());
+    }
+
+    #[test]
+    fn test_identity_file_parsing() {
+        let identity = Identity::generate();
+        let file = format!(
+            "# created: today\n# public key: {}\n{}\n",
+            identity.to_public(),
+            identity.to_string().expose_secret()
+        );
+
+        let parsed = parse_identities(&file).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(
+            parsed[0].to_public().to_string(),
+            identity.to_public().to_string()
+        );
+    }
+
+    #[test]
+    fn test_public_key_file_parsing() {
+        let first = Identity::generate().to_public();
+        let second = Identity::generate().to_public();
+        let file = format!("# vault key\n{}\n\n{}\n", first, second);
+
+        let parsed = parse_recipients(&file).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].to_string(), second.to_string());
+        assert!(parse_recipients("# nothing here\n").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_single_recipient() {
+        let identity = Identity::generate();
+        let encrypted = encrypt(&[identity.to_public()], b"hidden in plain sight").unwrap();
+
+        assert!(encrypted.starts_with(b"age-encryption.org/v1\n"));
+        assert_eq!(
+            decrypt(&[identity], &encrypted).unwrap(),
+            b"hidden in plain sight"
+        );
+    }
+
+    #[test]
+    fn test_decrypt_payload_from_earlier_versions() {
+        // Written by the tool's own age encoder before it moved to the age crate
+        let identity = "AGE-SECRET-KEY-1GFPYYSJZGFPYYSJZGFPYYSJZGFPYYSJZGFPYYSJZGFPYYSJZGFPQ4EGAEX";
+        let encrypted = crate::encoding::hex_decode(
+            "6167652d656e6372797074696f6e2e6f72672f76310a2d3e20583235353139206b63634553514131593246476a6e636242447a70687150377342336a6e4d336a4f47796e676e363333686b0a4d58643331447255534c5533596a73794f68765279374f734f54364854423649435165644c7237595765670a2d2d2d203750746668796e61455270376f626b6f616e56463972446277597454366a5042664476337663636d3931380a948b657c2fb95ef231d34372c94833eba7bcf119c99dea6a1c97068a7e72688e448341208f9f137fca",
+        )
+        .unwrap();
+        let identities = parse_identities(identity).unwrap();
+        assert_eq!(decrypt(&identities, &encrypted).unwrap(), b"hello age");
+    }
+
+    #[test]
+    fn test_multiple_recipients() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let encrypted = encrypt(&[alice.to_public(), bob.to_public()], b"team secret").unwrap();
+
+        assert_eq!(decrypt(&[bob], &encrypted).unwrap(), b"team secret");
+        assert_eq!(decrypt(&[alice], &encrypted).unwrap(), b"team secret");
+        assert!(decrypt(&[Identity::generate()], &encrypted).is_err());
+    }
+
+    #[test]
+    fn test_multi_chunk_payload() {
+        let identity = Identity::generate();
+        let message: Vec<u8> = (0..64 * 1024 * 2 + 17).map(|i| i as u8).collect();
+        let encrypted = encrypt(&[identity.to_public()], &message).unwrap();
+        assert_eq!(decrypt(&[identity], &encrypted).unwrap(), message);
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        let identity = Identity::generate();
+        let encrypted = encrypt(&[identity.to_public()], b"").unwrap();
+        assert!(decrypt(&[identity], &encrypted).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tampered_header_is_rejected() {
+        let identity = Identity::generate();
+        let encrypted = encrypt(&[identity.to_public()], b"message").unwrap();
+
+        // Inject an extra stanza so the header no longer matches its MAC
+        let position = encrypted.windows(4).position(|w| w == b"--- ").unwrap();
+        let mut tampered = encrypted[..position].to_vec();
+        tampered.extend_from_slice(b"-> grease\n\n");
+        tampered.extend_from_slice(&encrypted[position..]);
+
+        assert!(decrypt(&[identity], &tampered).is_err());
+    }
+}
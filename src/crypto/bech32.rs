@@ -0,0 +1,137 @@
+use crate::Result;
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ff_ffff) << 5) ^ u32::from(value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|b| b & 31));
+    expanded
+}
+
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>> {
+    let mut accumulator = 0u32;
+    let mut bits = 0u32;
+    let mut output = vec![];
+    let max = (1u32 << to) - 1;
+
+    for &value in data {
+        accumulator = (accumulator << from) | u32::from(value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            output.push(((accumulator >> bits) & max) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            output.push(((accumulator << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (accumulator << (to - bits)) & max != 0 {
+        return Err("Invalid bech32 padding".into());
+    }
+    Ok(output)
+}
+
+/// Encodes `data` as lowercase Bech32 (BIP 173) with the given human-readable part
+pub fn encode(hrp: &str, data: &[u8]) -> String {
+    let hrp = hrp.to_lowercase();
+    let values = convert_bits(data, 8, 5, true).expect("padding conversion cannot fail");
+
+    let checksum_input = hrp_expand(&hrp)
+        .into_iter()
+        .chain(values.iter().copied())
+        .chain([0u8; 6]);
+    let checksum = polymod(checksum_input) ^ 1;
+
+    let mut output = hrp;
+    output.push('1');
+    for value in values {
+        output.push(CHARSET[value as usize] as char);
+    }
+    for i in 0..6 {
+        output.push(CHARSET[((checksum >> (5 * (5 - i))) & 31) as usize] as char);
+    }
+    output
+}
+
+/// Decodes a Bech32 string into its lowercase human-readable part and data bytes
+pub fn decode(input: &str) -> Result<(String, Vec<u8>)> {
+    if input.chars().any(|c| c.is_ascii_lowercase())
+        && input.chars().any(|c| c.is_ascii_uppercase())
+    {
+        return Err("Bech32 string must not mix upper and lower case".into());
+    }
+    let input = input.to_lowercase();
+
+    let separator = input
+        .rfind('1')
+        .ok_or("Bech32 string is missing the '1' separator")?;
+    let (hrp, data) = (&input[..separator], &input[separator + 1..]);
+    if hrp.is_empty() || data.len() < 6 {
+        return Err("Bech32 string is too short".into());
+    }
+
+    let values = data
+        .bytes()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|&a| a == c)
+                .map(|p| p as u8)
+                .ok_or_else(|| format!("Invalid bech32 character '{}'", c as char))
+        })
+        .collect::<std::result::Result<Vec<u8>, String>>()?;
+
+    if polymod(hrp_expand(hrp).into_iter().chain(values.iter().copied())) != 1 {
+        return Err("Invalid bech32 checksum".into());
+    }
+
+    let bytes = convert_bits(&values[..values.len() - 6], 5, 8, false)?;
+    Ok((hrp.to_string(), bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bip173_valid_checksum() {
+        let (hrp, data) = decode("A12UEL5L").unwrap();
+        assert_eq!(hrp, "a");
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..32).collect();
+        let encoded = encode("age", &data);
+        assert!(encoded.starts_with("age1"));
+        assert_eq!(decode(&encoded).unwrap(), ("age".to_string(), data));
+    }
+
+    #[test]
+    fn test_rejects_bad_checksum_and_mixed_case() {
+        let mut encoded = encode("age", &[1, 2, 3]);
+        encoded.pop();
+        encoded.push('q');
+        assert!(decode(&encoded).is_err());
+        assert!(decode("A12uEL5L").is_err());
+    }
+}
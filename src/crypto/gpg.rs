@@ -0,0 +1,62 @@
+use crate::Result;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Encrypts to `recipients` and/or signs `plaintext` with gpg, producing an armored message.
+///
+/// `signer` is `Some(None)` to sign with gpg's default key, or `Some(Some(key))` for a specific one.
+pub fn encrypt(
+    recipients: &[String],
+    signer: Option<Option<&str>>,
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let mut args = vec!["--armor"];
+    if !recipients.is_empty() {
+        args.push("--encrypt");
+        for recipient in recipients {
+            args.extend(["--recipient", recipient.as_str()]);
+        }
+    }
+    if let Some(key) = signer {
+        args.push("--sign");
+        if let Some(key) = key {
+            args.extend(["--local-user", key]);
+        }
+    }
+
+    run(&args, plaintext)
+}
+
+/// Decrypts (and verifies, if signed) an armored OpenPGP message with gpg
+pub fn decrypt(payload: &[u8]) -> Result<Vec<u8>> {
+    run(&["--decrypt"], payload)
+}
+
+/// Runs gpg with `input` on stdin and returns its stdout; gpg's own diagnostics
+/// (passphrase prompts, signature status) go straight to the terminal
+fn run(args: &[&str], input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("gpg")
+        .arg("--quiet")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| format!("Failed to run gpg (is GnuPG installed?): {}", e))?;
+
+    // Feed stdin from another thread so a large output can't deadlock the pipes
+    let mut stdin = child.stdin.take().ok_or("Failed to open gpg stdin")?;
+    let output = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(input));
+        let output = child.wait_with_output();
+        // gpg may exit before reading everything (e.g. bad input), so a broken pipe is not fatal
+        let _ = writer.join();
+        output
+    })
+    .map_err(|e| format!("Failed to run gpg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("gpg exited with {}", output.status).into());
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,108 @@
+use super::Secret;
+use crate::Result;
+use std::process::{Command, Stdio};
+
+pub const CHALLENGE_LEN: usize = 32;
+
+const YUBIKEY_CHALLENGE_RESPONSE: u8 = 1;
+
+/// Source of key-wrapping secrets held outside the process, such as a hardware token.
+///
+/// Responses must be deterministic: the same challenge always yields the same secret,
+/// so the key wrapped at encode time can be unwrapped again at decode time.
+pub trait KeyProvider {
+    /// Provider ID and provider-specific parameter (e.g. a slot), stored in the payload
+    fn descriptor(&self) -> [u8; 2];
+
+    /// Human-readable name shown while waiting for the token
+    fn name(&self) -> String;
+
+    fn respond(&self, challenge: &[u8; CHALLENGE_LEN]) -> Result<Secret>;
+}
+
+/// Looks up the provider a payload was wrapped with
+pub fn from_descriptor(descriptor: [u8; 2]) -> Result<Box<dyn KeyProvider>> {
+    match descriptor {
+        [YUBIKEY_CHALLENGE_RESPONSE, slot] => Ok(Box::new(YubiKey::new(slot)?)),
+        [id, _] => Err(format!("Unknown key provider ID {} in payload", id).into()),
+    }
+}
+
+/// HMAC-SHA1 challenge-response slot of a YubiKey, queried with `ykchalresp` (yubikey-personalization)
+pub struct YubiKey {
+    slot: u8,
+}
+
+impl YubiKey {
+    pub fn new(slot: u8) -> Result<Self> {
+        if !(1..=2).contains(&slot) {
+            return Err(format!("Invalid YubiKey slot {}, expected 1 or 2", slot).into());
+        }
+        Ok(YubiKey { slot })
+    }
+}
+
+impl KeyProvider for YubiKey {
+    fn descriptor(&self) -> [u8; 2] {
+        [YUBIKEY_CHALLENGE_RESPONSE, self.slot]
+    }
+
+    fn name(&self) -> String {
+        format!("YubiKey slot {}", self.slot)
+    }
+
+    fn respond(&self, challenge: &[u8; CHALLENGE_LEN]) -> Result<Secret> {
+        let challenge: String = challenge.iter().map(|b| format!("{:02x}", b)).collect();
+        let output = Command::new("ykchalresp")
+            .arg(format!("-{}", self.slot))
+            .args(["-x", &challenge])
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| {
+                format!(
+                    "Failed to run ykchalresp (is yubikey-personalization installed?): {}",
+                    e
+                )
+            })?;
+
+        if !output.status.success() {
+            return Err(format!("ykchalresp exited with {}", output.status).into());
+        }
+        let response = Secret::from(output.stdout);
+        decode_hex(response.as_str()?.trim())
+    }
+}
+
+fn decode_hex(hex: &str) -> Result<Secret> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err("Hardware token returned an invalid response".into());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| "Hardware token returned an invalid response")?;
+    Ok(Secret::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("00ff10").unwrap().expose(), [0x00, 0xff, 0x10]);
+        assert!(decode_hex("abc").is_err());
+        assert!(decode_hex("zz").is_err());
+        assert!(decode_hex("").is_err());
+    }
+
+    #[test]
+    fn test_from_descriptor() {
+        let provider = from_descriptor([YUBIKEY_CHALLENGE_RESPONSE, 2]).unwrap();
+        assert_eq!(provider.descriptor(), [YUBIKEY_CHALLENGE_RESPONSE, 2]);
+        assert_eq!(provider.name(), "YubiKey slot 2");
+        assert!(from_descriptor([YUBIKEY_CHALLENGE_RESPONSE, 3]).is_err());
+        assert!(from_descriptor([0xff, 1]).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::Result;
+use argon2::{Algorithm, Argon2, Version};
+
+/// Ceilings for parameters read back from a payload: well above anything this tool writes,
+/// but low enough that a crafted image cannot make a decode hang or run out of memory
+const MAX_PAYLOAD_MEMORY_KIB: u32 = 256 * 1024;
+const MAX_PAYLOAD_ITERATIONS: u32 = 16;
+const MAX_PAYLOAD_PARALLELISM: u32 = 64;
+
+/// Cost parameters for Argon2id (RFC 9106)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    /// Memory size in KiB
+    pub memory_kib: u32,
+    /// Number of passes over memory
+    pub iterations: u32,
+    /// Number of lanes
+    pub parallelism: u32,
+}
+
+impl Default for Params {
+    /// OWASP's recommended minimum: 19 MiB, 2 passes, 1 lane
+    fn default() -> Self {
+        Params {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl Params {
+    /// Parameters stored in an untrusted payload, refused before any key is derived if they
+    /// are invalid or cost more than the payload limits
+    pub fn from_payload(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Params> {
+        let params = Params {
+            memory_kib,
+            iterations,
+            parallelism,
+        };
+        params.validate()?;
+        if memory_kib > MAX_PAYLOAD_MEMORY_KIB {
+            return Err(format!(
+                "Payload asks for {} MiB of Argon2 memory, more than the {} MiB limit",
+                memory_kib / 1024,
+                MAX_PAYLOAD_MEMORY_KIB / 1024
+            )
+            .into());
+        }
+        if iterations > MAX_PAYLOAD_ITERATIONS {
+            return Err(format!(
+                "Payload asks for {} Argon2 iterations, more than the limit of {}",
+                iterations, MAX_PAYLOAD_ITERATIONS
+            )
+            .into());
+        }
+        if parallelism > MAX_PAYLOAD_PARALLELISM {
+            return Err(format!(
+                "Payload asks for {} Argon2 lanes, more than the limit of {}",
+                parallelism, MAX_PAYLOAD_PARALLELISM
+            )
+            .into());
+        }
+        Ok(params)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.parallelism == 0 || self.parallelism > 0x00ff_ffff {
+            return Err("Argon2 parallelism must be between 1 and 2^24-1".into());
+        }
+        if self.iterations == 0 {
+            return Err("Argon2 iterations must be at least 1".into());
+        }
+        if self.memory_kib < 8 * self.parallelism {
+            return Err("Argon2 memory must be at least 8 KiB per lane".into());
+        }
+        // Refuse absurd parameters read back from untrusted payloads
+        if self.memory_kib > 4 * 1024 * 1024 {
+            return Err("Argon2 memory cost exceeds the 4 GiB limit".into());
+        }
+        Ok(())
+    }
+}
+
+/// Derives `output_len` bytes from `password` and `salt` with Argon2id
+pub fn argon2id(
+    password: &[u8],
+    salt: &[u8],
+    params: Params,
+    output_len: usize,
+) -> Result<Vec<u8>> {
+    params.validate()?;
+    if salt.len() < 8 {
+        return Err("Argon2 salt must be at least 8 bytes".into());
+    }
+
+    let params = argon2::Params::new(
+        params.memory_kib,
+        params.iterations,
+        params.parallelism,
+        Some(output_len),
+    )
+    .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+    let mut output = vec![0u8; output_len];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password, salt, &mut output)
+        .map_err(|e| format!("Argon2 key derivation failed: {}", e))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_argon2id_single_lane() {
+        let params = Params {
+            memory_kib: 64,
+            iterations: 2,
+            parallelism: 1,
+        };
+        let key = argon2id(b"password", b"somesaltsomesalt", params, 32).unwrap();
+        assert_eq!(
+            hex(&key),
+            "1a98fba8e6394425c9bdc333a9c750aaf811df070f5fb4a5788e1cf4db5793a1"
+        );
+    }
+
+    #[test]
+    fn test_argon2id_multiple_lanes() {
+        let params = Params {
+            memory_kib: 32,
+            iterations: 3,
+            parallelism: 4,
+        };
+        let key = argon2id(b"password", b"somesaltsomesalt", params, 32).unwrap();
+        assert_eq!(
+            hex(&key),
+            "f25048ec48311a804ea9edd74e08c30765aa0f8d47c2a5b5a6097243cdf2e5ce"
+        );
+    }
+
+    #[test]
+    fn test_argon2id_long_output() {
+        let params = Params {
+            memory_kib: 16,
+            iterations: 1,
+            parallelism: 2,
+        };
+        let key = argon2id(b"pw", b"saltsaltsaltsalt", params, 80).unwrap();
+        assert_eq!(
+            hex(&key),
+            "535f3225b084107086c3774f50738dc7581b0c1241e4f77838c28eb63916ac6772e15b27d95346434bbb716475e2dd87db962bb5a7bf54340189f78770de2d2b56a41377daa840c14535f8c0338001cf"
+        );
+    }
+
+    #[test]
+    fn test_argon2id_rejects_invalid_params() {
+        let params = Params {
+            memory_kib: 4,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(argon2id(b"pw", b"saltsaltsaltsalt", params, 32).is_err());
+    }
+
+    #[test]
+    fn test_payload_params_limits() {
+        assert_eq!(
+            Params::from_payload(19 * 1024, 2, 1).unwrap(),
+            Params::default()
+        );
+        assert!(Params::from_payload(MAX_PAYLOAD_MEMORY_KIB, MAX_PAYLOAD_ITERATIONS, 4).is_ok());
+        assert!(Params::from_payload(4 * 1024 * 1024, 2, 1).is_err());
+        assert!(Params::from_payload(MAX_PAYLOAD_MEMORY_KIB + 1, 2, 1).is_err());
+        assert!(Params::from_payload(19 * 1024, u32::MAX, 1).is_err());
+        assert!(Params::from_payload(19 * 1024, 2, 1000).is_err());
+        assert!(Params::from_payload(4, 1, 1).is_err());
+    }
+}
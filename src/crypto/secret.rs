@@ -0,0 +1,84 @@
+use std::fmt::{Debug, Formatter};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Byte buffer for passwords, keys and decrypted plaintext that is wiped when dropped,
+/// including any spare capacity left over from earlier growth
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn expose_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
+    /// Interprets the secret as UTF-8 text, e.g. to hand a password to the keyring
+    pub fn as_str(&self) -> crate::Result<&str> {
+        Ok(std::str::from_utf8(&self.0)?)
+    }
+}
+
+impl From<Vec<u8>> for Secret {
+    fn from(value: Vec<u8>) -> Self {
+        Secret(value)
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(value.into_bytes())
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret([REDACTED; {} bytes])", self.0.len())
+    }
+}
+
+/// Compares two byte strings in time that depends only on their lengths, for checking
+/// authentication tags without leaking how many leading bytes matched
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+
+    #[test]
+    fn test_key_material_is_wiped_on_drop() {
+        assert_zeroize_on_drop::<Secret>();
+        assert_zeroize_on_drop::<aes::Aes256>();
+        assert_zeroize_on_drop::<chacha20poly1305::ChaCha20Poly1305>();
+        assert_zeroize_on_drop::<chacha20::ChaCha20>();
+    }
+
+    #[test]
+    fn test_zeroize() {
+        let mut secret = Secret::from(vec![0xaau8; 32]);
+        secret.zeroize();
+        assert!(secret.expose().is_empty());
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"tag", b"tag"));
+        assert!(!ct_eq(b"tag", b"tab"));
+        assert!(!ct_eq(b"tag", b"tags"));
+        assert!(ct_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let secret = Secret::from("hunter2".to_string());
+        assert_eq!(secret.expose(), b"hunter2");
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED; 7 bytes])");
+    }
+}
@@ -0,0 +1,139 @@
+use super::random_bytes;
+use crate::Result;
+use zeroize::Zeroize;
+
+/// Multiplication in GF(2^8) with the AES polynomial, without data-dependent branches
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse as a^254 (0 maps to 0)
+fn inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Splits `secret` into `n` shares, any `k` of which reconstruct it.
+///
+/// Each share is its x coordinate (1..=n) followed by one y byte per secret byte.
+pub fn split(secret: &[u8], k: u8, n: u8) -> Result<Vec<Vec<u8>>> {
+    if k < 2 || k > n {
+        return Err(format!("Invalid threshold {}/{}: need 2 <= k <= n", k, n).into());
+    }
+
+    let mut shares: Vec<Vec<u8>> = (1..=n)
+        .map(|x| {
+            let mut share = Vec::with_capacity(1 + secret.len());
+            share.push(x);
+            share
+        })
+        .collect();
+
+    // One random polynomial of degree k - 1 per secret byte, with the byte as constant term
+    let mut coefficients = vec![0u8; k as usize - 1];
+    for &byte in secret {
+        random_bytes(&mut coefficients)?;
+        for share in shares.iter_mut() {
+            let x = share[0];
+            // Horner's rule
+            let y = coefficients.iter().rev().fold(0, |acc, &c| mul(acc, x) ^ c);
+            share.push(mul(y, x) ^ byte);
+        }
+    }
+    coefficients.zeroize();
+    Ok(shares)
+}
+
+/// Reconstructs the secret from shares produced by [`split`] by Lagrange interpolation at x = 0.
+///
+/// Given fewer than the threshold, the result is meaningless rather than an error.
+pub fn combine(shares: &[&[u8]]) -> Result<Vec<u8>> {
+    let len = shares.first().ok_or("No shares given")?.len();
+    if len < 1 || shares.iter().any(|share| share.len() != len) {
+        return Err("Shares have different lengths".into());
+    }
+    let xs: Vec<u8> = shares.iter().map(|share| share[0]).collect();
+    if xs.contains(&0) || (1..xs.len()).any(|i| xs[..i].contains(&xs[i])) {
+        return Err("Shares must have distinct, non-zero indices".into());
+    }
+
+    // Lagrange basis values at x = 0: prod x_j / (x_j - x_i), subtraction being XOR
+    let basis: Vec<u8> = xs
+        .iter()
+        .enumerate()
+        .map(|(i, &xi)| {
+            let (numerator, denominator) = xs
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .fold((1, 1), |(num, den), (_, &xj)| {
+                    (mul(num, xj), mul(den, xj ^ xi))
+                });
+            mul(numerator, inv(denominator))
+        })
+        .collect();
+
+    Ok((1..len)
+        .map(|index| {
+            shares
+                .iter()
+                .zip(&basis)
+                .fold(0, |acc, (share, &b)| acc ^ mul(share[index], b))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_inverse() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inv(a)), 1);
+        }
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+    }
+
+    #[test]
+    fn test_any_k_shares_reconstruct() {
+        let secret = b"split across images";
+        let shares = split(secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for combination in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let chosen: Vec<&[u8]> = combination.iter().map(|&i| shares[i].as_slice()).collect();
+            assert_eq!(combine(&chosen).unwrap(), secret);
+        }
+
+        let too_few: Vec<&[u8]> = shares[..2].iter().map(Vec::as_slice).collect();
+        assert_ne!(combine(&too_few).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_rejects_invalid_input() {
+        assert!(split(b"x", 1, 3).is_err());
+        assert!(split(b"x", 4, 3).is_err());
+
+        let shares = split(b"xy", 2, 2).unwrap();
+        assert!(combine(&[&shares[0], &shares[0]]).is_err());
+        assert!(combine(&[&shares[0], &shares[1][..2]]).is_err());
+        assert!(combine(&[]).is_err());
+    }
+}
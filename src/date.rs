@@ -0,0 +1,222 @@
+use crate::Result;
+use crate::exit::Failure;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Current time as seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses a `YYYY-MM-DD` date into the last second of that day (UTC)
+pub fn parse_end_of_day(date: &str) -> Result<u64> {
+    let invalid =
+        || Failure::Validation.error(format!("Invalid date '{}', expected YYYY-MM-DD", date));
+    let mut parts = date.trim().splitn(3, '-');
+    let mut next = |max: u32| -> Result<u32> {
+        let value: u32 = parts
+            .next()
+            .and_then(|part| part.parse().ok())
+            .ok_or_else(invalid)?;
+        if value == 0 || value > max {
+            return Err(invalid());
+        }
+        Ok(value)
+    };
+    let (year, month, day) = (next(9999)?, next(12)?, next(31)?);
+    if year < 1970 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    Ok((days_from_civil(year, month, day) + 1) * SECONDS_PER_DAY - 1)
+}
+
+/// Parses an age such as `30d` into seconds: a number followed by `s`, `m`, `h`, `d` or
+/// `w` for seconds, minutes, hours, days or weeks
+pub fn parse_age(age: &str) -> Result<u64> {
+    let invalid = || {
+        Failure::Validation.error(format!(
+            "Invalid age '{}', expected a number and a unit such as 30d",
+            age
+        ))
+    };
+    let age = age.trim();
+    let unit = match age.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => SECONDS_PER_DAY,
+        Some('w') => 7 * SECONDS_PER_DAY,
+        _ => return Err(invalid()),
+    };
+    let count: u64 = age[..age.len() - 1].parse().map_err(|_| invalid())?;
+    count.checked_mul(unit).ok_or_else(invalid)
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / SECONDS_PER_DAY);
+    let seconds = timestamp % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Formats seconds since the Unix epoch as `YYYYMMDDTHHMMSS` (UTC), for file names
+pub fn compact_timestamp(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / SECONDS_PER_DAY);
+    let seconds = timestamp % SECONDS_PER_DAY;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parses a timestamp written by `compact_timestamp`
+pub fn parse_compact_timestamp(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once('T')?;
+    if date.len() != 8 || time.len() != 6 || !text.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let number = |digits: &str| digits.parse::<u32>().ok();
+    let (year, month, day) = (
+        number(&date[..4])?,
+        number(&date[4..6])?,
+        number(&date[6..])?,
+    );
+    let (hour, minute, second) = (
+        number(&time[..2])?,
+        number(&time[2..4])?,
+        number(&time[4..])?,
+    );
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+    Some(
+        days_from_civil(year, month, day) * SECONDS_PER_DAY
+            + (hour * 3600 + minute * 60 + second) as u64,
+    )
+}
+
+fn is_leap_year(year: u32) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: u32, month: u32, day: u32) -> u64 {
+    let days_before_year: u64 = (1970..year)
+        .map(|y| if is_leap_year(y) { 366 } else { 365 })
+        .sum();
+    let days_before_month: u32 = (1..month).map(|m| days_in_month(year, m)).sum();
+    days_before_year + (days_before_month + day - 1) as u64
+}
+
+fn civil_from_days(mut days: u64) -> (u32, u32, u32) {
+    let mut year = 1970;
+    loop {
+        let length = if is_leap_year(year) { 366 } else { 365 };
+        if days < length {
+            break;
+        }
+        days -= length;
+        year += 1;
+    }
+    let mut month = 1;
+    while days >= days_in_month(year, month) as u64 {
+        days -= days_in_month(year, month) as u64;
+        month += 1;
+    }
+    (year, month, days as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_end_of_day() {
+        assert_eq!(parse_end_of_day("1970-01-01").unwrap(), 86_399);
+        assert_eq!(parse_end_of_day("2025-12-31").unwrap(), 1_767_225_599);
+        assert_eq!(parse_end_of_day("2024-02-29").unwrap(), 1_709_251_199);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_dates() {
+        for date in [
+            "2025-13-01",
+            "2025-02-29",
+            "2025-00-10",
+            "1969-12-31",
+            "tomorrow",
+            "2025-1",
+        ] {
+            assert!(parse_end_of_day(date).is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d").unwrap(), 30 * 86_400);
+        assert_eq!(parse_age("12h").unwrap(), 43_200);
+        assert_eq!(parse_age("2w").unwrap(), 14 * 86_400);
+        assert_eq!(parse_age("90s").unwrap(), 90);
+        for age in ["30", "d", "-1d", "1.5h", "3y", "99999999999999999999w"] {
+            assert!(parse_age(age).is_err(), "{}", age);
+        }
+    }
+
+    #[test]
+    fn test_compact_timestamp_round_trip() {
+        assert_eq!(compact_timestamp(1_709_164_800), "20240229T000000");
+        for timestamp in [0, 1_767_225_599, 1_709_251_199] {
+            let text = compact_timestamp(timestamp);
+            assert_eq!(parse_compact_timestamp(&text), Some(timestamp));
+        }
+        for text in [
+            "20250229T000000",
+            "20240101T240000",
+            "2024011T1200000",
+            "backup",
+        ] {
+            assert_eq!(parse_compact_timestamp(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_767_225_599), "2025-12-31 23:59:59 UTC");
+        assert_eq!(format_timestamp(1_709_164_800), "2024-02-29 00:00:00 UTC");
+    }
+}
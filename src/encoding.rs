@@ -0,0 +1,186 @@
+use crate::Result;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Text encoding of messages passed to `encode` or shown by `decode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Encoding {
+    /// The bytes as they are
+    #[default]
+    Raw,
+    /// Standard base64 with padding
+    Base64,
+    /// Hexadecimal, two digits per byte
+    Hex,
+}
+
+impl Encoding {
+    /// Decodes `data` given in this encoding. Whitespace is ignored so that wrapped
+    /// lines and trailing newlines from files or other tools are accepted.
+    pub fn decode(self, data: &[u8]) -> Result<Vec<u8>> {
+        if self == Encoding::Raw {
+            return Ok(data.to_vec());
+        }
+        let text: String = std::str::from_utf8(data)
+            .map_err(|_| format!("Input is not valid {}", self.name()))?
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        match self {
+            Encoding::Base64 => base64_decode(&text),
+            _ => hex_decode(&text),
+        }
+    }
+
+    /// Encodes `data` in this encoding
+    pub fn encode(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Raw => data.to_vec(),
+            Encoding::Base64 => base64_encode(data, true).into_bytes(),
+            Encoding::Hex => hex_encode(data).into_bytes(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Raw => "raw",
+            Encoding::Base64 => "base64",
+            Encoding::Hex => "hex",
+        }
+    }
+}
+
+/// Encodes bytes as lowercase hexadecimal
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes hexadecimal in either case
+pub fn hex_decode(input: &str) -> Result<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return Err("Hex input has an odd number of digits".into());
+    }
+    input
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            // from_str_radix alone would also accept a sign
+            if !pair.iter().all(u8::is_ascii_hexdigit) {
+                let pair = String::from_utf8_lossy(pair);
+                return Err(format!("Invalid hex digits '{}'", pair).into());
+            }
+            Ok(u8::from_str_radix(std::str::from_utf8(pair)?, 16)?)
+        })
+        .collect()
+}
+
+/// Encodes bytes as standard base64, with or without `=` padding
+pub fn base64_encode(data: &[u8], padding: bool) -> String {
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for group in data.chunks(3) {
+        let mut buffer = [0u8; 3];
+        buffer[..group.len()].copy_from_slice(group);
+        let bits = u32::from_be_bytes([0, buffer[0], buffer[1], buffer[2]]);
+
+        let symbols = group.len() + 1;
+        for i in 0..4 {
+            if i < symbols {
+                let index = (bits >> (18 - 6 * i)) & 0x3f;
+                output.push(BASE64_ALPHABET[index as usize] as char);
+            } else if padding {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Decodes standard base64, accepting input with or without `=` padding.
+///
+/// Non-canonical encodings (stray bits in the final symbol) are rejected.
+pub fn base64_decode(input: &str) -> Result<Vec<u8>> {
+    let trimmed = input.trim_end_matches('=');
+    if input.len() - trimmed.len() > 2
+        || (trimmed.len() != input.len() && !input.len().is_multiple_of(4))
+    {
+        return Err("Invalid base64 padding".into());
+    }
+    if trimmed.len() % 4 == 1 {
+        return Err("Invalid base64 length".into());
+    }
+
+    let mut output = Vec::with_capacity(trimmed.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in trimmed.bytes() {
+        let value = BASE64_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| format!("Invalid base64 character '{}'", c as char))?;
+
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    if buffer & ((1 << bits) - 1) != 0 {
+        return Err("Non-canonical base64 encoding".into());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b"", true), "");
+        assert_eq!(base64_encode(b"f", true), "Zg==");
+        assert_eq!(base64_encode(b"fo", true), "Zm8=");
+        assert_eq!(base64_encode(b"foo", true), "Zm9v");
+        assert_eq!(base64_encode(b"foobar", true), "Zm9vYmFy");
+        assert_eq!(base64_encode(b"fooba", false), "Zm9vYmE");
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode("Zm9vYmE=").unwrap(), b"fooba");
+        assert_eq!(base64_decode("Zm9vYmE").unwrap(), b"fooba");
+        assert_eq!(base64_decode("Zg").unwrap(), b"f");
+        assert_eq!(base64_decode("").unwrap(), b"");
+    }
+
+    #[test]
+    fn test_base64_decode_rejects_invalid_input() {
+        assert!(base64_decode("Zm9v!").is_err());
+        assert!(base64_decode("Z").is_err());
+        assert!(base64_decode("Zh").is_err());
+        assert!(base64_decode("Zg=").is_err());
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        assert_eq!(hex_encode(b"\x00\xffHi"), "00ff4869");
+        assert_eq!(hex_decode("00FF4869").unwrap(), b"\x00\xffHi");
+        assert_eq!(hex_decode("").unwrap(), b"");
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+        assert!(hex_decode("+1").is_err());
+    }
+
+    #[test]
+    fn test_encoding_ignores_whitespace() {
+        assert_eq!(Encoding::Base64.decode(b"Zm9v\nYmFy\n").unwrap(), b"foobar");
+        assert_eq!(Encoding::Hex.decode(b" 66 6f 6f\n").unwrap(), b"foo");
+        assert_eq!(Encoding::Raw.decode(b" foo\n").unwrap(), b" foo\n");
+        assert_eq!(Encoding::Hex.encode(b"foo"), b"666f6f");
+        assert!(Encoding::Base64.decode(b"\xff").is_err());
+    }
+}
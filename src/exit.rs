@@ -0,0 +1,94 @@
+use crate::Error;
+use std::fmt;
+use std::io;
+
+/// What kind of failure ended a run, which decides its exit status so that scripts can
+/// tell them apart. Usage errors exit with 2, as clap reports them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Anything not sorted into the kinds below, such as a batch in which some files failed
+    Other = 1,
+    /// The image, backup or another file named on the command line does not exist
+    FileNotFound = 3,
+    /// The file is not a valid image, or a chunk does not hold a readable message
+    Parse = 4,
+    /// The image has no chunk or message of the given type
+    ChunkNotFound = 5,
+    /// An argument such as a chunk type or date was rejected
+    Validation = 6,
+    /// Decryption or an integrity check failed, or the key needed for it was not given
+    Crypto = 7,
+    /// Reading or writing a file failed
+    Io = 8,
+}
+
+/// An error that carries the kind of failure it is
+#[derive(Debug)]
+struct Tagged {
+    failure: Failure,
+    message: String,
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Tagged {}
+
+impl Failure {
+    /// An error of this kind with `message`
+    pub fn error(self, message: impl Into<String>) -> Error {
+        Box::new(Tagged {
+            failure: self,
+            message: message.into(),
+        })
+    }
+
+    /// The kind of `error`: what it was tagged with, for I/O errors whether the file was
+    /// missing, and otherwise `Other`
+    pub fn of(error: &(dyn std::error::Error + 'static)) -> Failure {
+        if let Some(tagged) = error.downcast_ref::<Tagged>() {
+            tagged.failure
+        } else if let Some(error) = error.downcast_ref::<io::Error>() {
+            Failure::from(error)
+        } else {
+            Failure::Other
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        self as i32
+    }
+}
+
+impl From<&io::Error> for Failure {
+    fn from(error: &io::Error) -> Failure {
+        match error.kind() {
+            io::ErrorKind::NotFound => Failure::FileNotFound,
+            _ => Failure::Io,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds() {
+        let error = Failure::ChunkNotFound.error("Chunk type 'ruSt' not found");
+        assert_eq!(error.to_string(), "Chunk type 'ruSt' not found");
+        assert_eq!(Failure::of(error.as_ref()), Failure::ChunkNotFound);
+
+        let missing: Error = io::Error::from(io::ErrorKind::NotFound).into();
+        assert_eq!(Failure::of(missing.as_ref()), Failure::FileNotFound);
+        let denied: Error = io::Error::from(io::ErrorKind::PermissionDenied).into();
+        assert_eq!(Failure::of(denied.as_ref()), Failure::Io);
+
+        let untagged: Error = "Something else".into();
+        assert_eq!(Failure::of(untagged.as_ref()), Failure::Other);
+        assert_eq!(Failure::of(untagged.as_ref()).exit_code(), 1);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::Result;
+
+/// Most parity bytes per data byte accepted by `--fec`
+pub const MAX_RATIO: f64 = 4.0;
+/// Parity bytes | data length (4, big-endian), stored three times for majority voting
+const PARAMS_LEN: usize = 5;
+const CODEWORD_LEN: usize = 255;
+
+const fn tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    (exp, log)
+}
+
+/// Exponent and logarithm tables of GF(2^8) with the polynomial 0x11d and generator 2
+const TABLES: ([u8; 512], [u8; 256]) = tables();
+const EXP: &[u8; 512] = &TABLES.0;
+const LOG: &[u8; 256] = &TABLES.1;
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + 255 - LOG[b as usize] as usize]
+}
+
+fn inverse(a: u8) -> u8 {
+    EXP[255 - LOG[a as usize] as usize]
+}
+
+// Polynomials are stored highest degree first
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut sum = vec![0; len];
+    for (i, &c) in p.iter().enumerate() {
+        sum[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        sum[i + len - q.len()] ^= c;
+    }
+    sum
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut product = vec![0; p.len() + q.len() - 1];
+    for (i, &a) in p.iter().enumerate() {
+        for (j, &b) in q.iter().enumerate() {
+            product[i + j] ^= mul(a, b);
+        }
+    }
+    product
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().fold(0, |y, &c| mul(y, x) ^ c)
+}
+
+fn generator(parity: usize) -> Vec<u8> {
+    (0..parity).fold(vec![1], |g, i| poly_mul(&g, &[1, EXP[i]]))
+}
+
+/// Appends `parity` Reed-Solomon check bytes to `message`
+fn encode_block(message: &[u8], parity: usize, generator: &[u8]) -> Vec<u8> {
+    let mut codeword = message.to_vec();
+    codeword.resize(message.len() + parity, 0);
+    for i in 0..message.len() {
+        let coefficient = codeword[i];
+        if coefficient != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                codeword[i + j] ^= mul(g, coefficient);
+            }
+        }
+    }
+    codeword[..message.len()].copy_from_slice(message);
+    codeword
+}
+
+/// Syndromes with a leading zero, so index `i + 1` holds the codeword evaluated at 2^i
+fn syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+    std::iter::once(0)
+        .chain((0..parity).map(|i| poly_eval(codeword, EXP[i])))
+        .collect()
+}
+
+/// Corrects up to `parity / 2` byte errors in place, returning how many were fixed
+fn decode_block(codeword: &mut [u8], parity: usize) -> Result<usize> {
+    let uncorrectable = || "Too many damaged bytes to correct";
+    let syndrome = syndromes(codeword, parity);
+    if syndrome.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    // Berlekamp-Massey: find the error locator polynomial
+    let mut locator = vec![1u8];
+    let mut previous = vec![1u8];
+    for i in 0..parity {
+        let k = i + 1;
+        let mut delta = syndrome[k];
+        for j in 1..locator.len().min(k + 1) {
+            delta ^= mul(locator[locator.len() - 1 - j], syndrome[k - j]);
+        }
+        previous.push(0);
+        if delta != 0 {
+            if previous.len() > locator.len() {
+                let next = poly_scale(&previous, delta);
+                previous = poly_scale(&locator, inverse(delta));
+                locator = next;
+            }
+            locator = poly_add(&locator, &poly_scale(&previous, delta));
+        }
+    }
+    let leading = locator.iter().take_while(|&&c| c == 0).count();
+    let locator = &locator[leading..];
+    let errors = locator.len() - 1;
+    if errors * 2 > parity {
+        return Err(uncorrectable().into());
+    }
+
+    // Chien search: the roots of the reversed locator give the error positions
+    let reversed: Vec<u8> = locator.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..codeword.len())
+        .filter(|&i| poly_eval(&reversed, EXP[i % 255]) == 0)
+        .map(|i| codeword.len() - 1 - i)
+        .collect();
+    if positions.len() != errors {
+        return Err(uncorrectable().into());
+    }
+
+    // Forney: compute the error magnitudes
+    let coefficient_positions: Vec<usize> =
+        positions.iter().map(|&p| codeword.len() - 1 - p).collect();
+    let errata_locator = coefficient_positions
+        .iter()
+        .fold(vec![1], |l, &i| poly_mul(&l, &poly_add(&[1], &[EXP[i], 0])));
+    let reversed_syndrome: Vec<u8> = syndrome.iter().rev().copied().collect();
+    let product = poly_mul(&reversed_syndrome, &errata_locator);
+    let evaluator = product[product.len() - errata_locator.len()..].to_vec();
+
+    let roots: Vec<u8> = coefficient_positions.iter().map(|&i| EXP[i]).collect();
+    for (i, &root) in roots.iter().enumerate() {
+        let root_inverse = inverse(root);
+        let derivative = roots
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |d, (_, &other)| mul(d, 1 ^ mul(root_inverse, other)));
+        if derivative == 0 {
+            return Err(uncorrectable().into());
+        }
+        let y = mul(root, poly_eval(&evaluator, root_inverse));
+        codeword[positions[i]] ^= div(y, derivative);
+    }
+
+    if syndromes(codeword, parity).iter().any(|&s| s != 0) {
+        return Err(uncorrectable().into());
+    }
+    Ok(errors)
+}
+
+/// Number of parity bytes per 255-byte codeword for `ratio` parity bytes per data byte
+fn parity_len(ratio: f64) -> Result<usize> {
+    if !(ratio > 0.0 && ratio <= MAX_RATIO) {
+        return Err(format!(
+            "Invalid FEC ratio {}, expected a value above 0 and at most {}",
+            ratio, MAX_RATIO
+        )
+        .into());
+    }
+    let parity = (CODEWORD_LEN as f64 * ratio / (1.0 + ratio)).round() as usize;
+    Ok(parity.clamp(2, CODEWORD_LEN - 1))
+}
+
+/// Protects `data` with Reed-Solomon parity, about `ratio` parity bytes per data byte.
+///
+/// Layout: parameters (5, stored three times) | codewords interleaved byte by byte, so a
+/// run of damaged bytes is spread over all of them. Each codeword corrects up to half
+/// its parity bytes.
+pub fn encode(data: &[u8], ratio: f64) -> Result<Vec<u8>> {
+    let parity = parity_len(ratio)?;
+    let length = u32::try_from(data.len()).map_err(|_| "Message is too large for --fec")?;
+    let blocks = data.len().div_ceil(CODEWORD_LEN - parity).max(1);
+    let block_len = data.len().div_ceil(blocks).max(1);
+
+    let mut padded = data.to_vec();
+    padded.resize(blocks * block_len, 0);
+    let generator = generator(parity);
+    let codewords: Vec<Vec<u8>> = padded
+        .chunks(block_len)
+        .map(|block| encode_block(block, parity, &generator))
+        .collect();
+
+    let mut params = vec![parity as u8];
+    params.extend(length.to_be_bytes());
+    let mut encoded = params.repeat(3);
+    for j in 0..block_len + parity {
+        encoded.extend(codewords.iter().map(|codeword| codeword[j]));
+    }
+    Ok(encoded)
+}
+
+/// Recovers the data protected by [`encode`], returning it with the number of corrected bytes
+pub fn decode(encoded: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let copies = encoded
+        .get(..PARAMS_LEN * 3)
+        .ok_or("Error-corrected payload is truncated")?;
+    // Majority vote per byte over the three copies
+    let params: Vec<u8> = (0..PARAMS_LEN)
+        .map(|i| {
+            let (a, b, c) = (
+                copies[i],
+                copies[i + PARAMS_LEN],
+                copies[i + 2 * PARAMS_LEN],
+            );
+            if a == b || a == c { a } else { b }
+        })
+        .collect();
+    let parity = params[0] as usize;
+    let length = u32::from_be_bytes(params[1..].try_into()?) as usize;
+    if !(2..CODEWORD_LEN).contains(&parity) {
+        return Err("Error-corrected payload has invalid parameters".into());
+    }
+
+    let blocks = length.div_ceil(CODEWORD_LEN - parity).max(1);
+    let block_len = length.div_ceil(blocks).max(1);
+    let interleaved = &encoded[PARAMS_LEN * 3..];
+    if interleaved.len() != blocks * (block_len + parity) {
+        return Err("Error-corrected payload has the wrong length".into());
+    }
+
+    let mut data = Vec::with_capacity(blocks * block_len);
+    let mut corrected = 0;
+    for b in 0..blocks {
+        let mut codeword: Vec<u8> = interleaved
+            .iter()
+            .skip(b)
+            .step_by(blocks)
+            .copied()
+            .collect();
+        corrected += decode_block(&mut codeword, parity)
+            .map_err(|e| format!("Codeword {} of {}: {}", b + 1, blocks, e))?;
+        data.extend(&codeword[..block_len]);
+    }
+    data.truncate(length);
+    Ok((data, corrected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudorandom bytes for corrupting test data
+    fn noise(seed: u32, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_without_damage() {
+        for len in [0, 1, 10, 200, 1000] {
+            let data = noise(len as u32, len);
+            let encoded = encode(&data, 0.25).unwrap();
+            assert_eq!(decode(&encoded).unwrap(), (data, 0));
+        }
+    }
+
+    #[test]
+    fn test_corrects_scattered_errors() {
+        let data = noise(7, 600);
+        let encoded = encode(&data, 0.5).unwrap();
+        let parity = parity_len(0.5).unwrap();
+        let blocks = data.len().div_ceil(CODEWORD_LEN - parity);
+
+        let n = (encoded.len() - PARAMS_LEN * 3) / blocks;
+        let step = n / (parity / 2);
+
+        // Exactly parity / 2 errors in every codeword, at shifting positions
+        for shift in 0..step {
+            let mut damaged = encoded.clone();
+            for b in 0..blocks {
+                for j in (shift..n).step_by(step).take(parity / 2) {
+                    damaged[PARAMS_LEN * 3 + j * blocks + b] ^= noise(j as u32, 1)[0] | 1;
+                }
+            }
+            let (decoded, corrected) = decode(&damaged).unwrap();
+            assert_eq!(decoded, data);
+            assert_eq!(corrected, blocks * (parity / 2));
+        }
+    }
+
+    #[test]
+    fn test_corrects_a_burst_and_damaged_params() {
+        let data = noise(3, 2000);
+        let mut encoded = encode(&data, 0.25).unwrap();
+        // A burst is spread over all interleaved codewords
+        for byte in &mut encoded[500..700] {
+            *byte = 0;
+        }
+        encoded[1] ^= 0xff;
+        let (decoded, corrected) = decode(&encoded).unwrap();
+        assert_eq!(decoded, data);
+        assert!(corrected >= 190);
+    }
+
+    #[test]
+    fn test_too_much_damage_is_detected() {
+        let data = noise(5, 100);
+        let mut encoded = encode(&data, 0.1).unwrap();
+        let len = encoded.len();
+        for byte in &mut encoded[PARAMS_LEN * 3..len / 2] {
+            *byte ^= 0xa5;
+        }
+        assert!(decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_invalid_ratio() {
+        assert!(encode(b"x", 0.0).is_err());
+        assert!(encode(b"x", -1.0).is_err());
+        assert!(encode(b"x", f64::NAN).is_err());
+        assert!(encode(b"x", MAX_RATIO + 0.1).is_err());
+        assert!(encode(b"x", MAX_RATIO).is_ok());
+    }
+}
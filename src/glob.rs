@@ -0,0 +1,212 @@
+use crate::Result;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Whether `text` has any of the wildcards `*`, `?` or `[`
+pub fn is_pattern(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+/// Whether the file name `name` matches `pattern`, where `*` stands for any characters,
+/// `?` for one and `[abc]`, `[a-z]` or `[!abc]` for one of (or none of) a set
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_from(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), class(&pattern[1..])) {
+            (Some(&c), Some((set, negated, rest))) => {
+                in_class(set, c) != negated && matches_from(rest, &name[1..])
+            }
+            // An unclosed [ is an ordinary character
+            (Some('['), None) => matches_from(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some(&c) => name.first() == Some(&c) && matches_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// The set of a `[...]` class whose `[` is already taken, whether it is negated, and the
+/// pattern after its `]`; `None` if it is not closed
+fn class(pattern: &[char]) -> Option<(&[char], bool, &[char])> {
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    let start = usize::from(negated);
+    // A ] right at the start belongs to the set
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    Some((&pattern[start..end], negated, &pattern[end + 1..]))
+}
+
+fn in_class(set: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            if (set[i]..=set[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// The files matching `pattern`, sorted. `**` matches any number of directories, so
+/// `photos/**/*.png` finds the images in every folder under photos. Hidden files and
+/// directories only match a part of the pattern starting with a dot.
+pub fn expand(pattern: &Path) -> Result<Vec<PathBuf>> {
+    let mut base = PathBuf::new();
+    let mut parts = vec![];
+    for component in pattern.components() {
+        let text = component.as_os_str().to_string_lossy();
+        if parts.is_empty() && !is_pattern(&text) {
+            base.push(component);
+        } else if let Component::Normal(_) = component {
+            parts.push(text.into_owned());
+        } else {
+            return Err(format!("'{}' is not a usable pattern", pattern.display()).into());
+        }
+    }
+    let mut found = vec![];
+    walk(&base, &parts, &mut found);
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+/// Every file in `dir` and the directories below it, sorted, leaving out hidden ones
+/// like `expand` does for `dir/**/*`
+pub fn files_under(dir: &Path) -> Vec<PathBuf> {
+    let mut found = vec![];
+    walk(dir, &["**".to_string(), "*".to_string()], &mut found);
+    found.sort();
+    found
+}
+
+fn walk(dir: &Path, parts: &[String], found: &mut Vec<PathBuf>) {
+    let Some((part, rest)) = parts.split_first() else {
+        if dir.is_file() {
+            found.push(dir.to_path_buf());
+        }
+        return;
+    };
+    let Ok(entries) = fs::read_dir(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }) else {
+        return;
+    };
+    if part == "**" {
+        // No directories at all, then each one below
+        walk(dir, rest, found);
+    }
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') && !part.starts_with('.') {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        if part == "**" {
+            // Not through symbolic links, which may lead back up and never end
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                walk(&path, parts, found);
+            }
+        } else if matches(part, &name) {
+            walk(&path, rest, found);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("*.png", "holiday.png"));
+        assert!(matches("*.png", ".png"));
+        assert!(!matches("*.png", "holiday.png.backup"));
+        assert!(matches("img_??.png", "img_01.png"));
+        assert!(!matches("img_??.png", "img_1.png"));
+        assert!(matches("[a-c]*", "beach.png"));
+        assert!(!matches("[!a-c]*", "beach.png"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("a[b", "a[b"));
+        assert!(matches("*é*", "café.png"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn test_is_pattern() {
+        assert!(is_pattern("photos/*.png"));
+        assert!(is_pattern("img[12].png"));
+        assert!(!is_pattern("photos/holiday.png"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("hpv-glob-{}", std::process::id()));
+        for file in [
+            "a.png",
+            "b.png",
+            "c.jpg",
+            ".hidden.png",
+            "sub/d.png",
+            "sub/deep/e.png",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let names = |pattern: &str| -> Vec<String> {
+            expand(&dir.join(pattern))
+                .unwrap()
+                .iter()
+                .map(|path| {
+                    let relative = path.strip_prefix(&dir).unwrap();
+                    relative.to_string_lossy().replace('\\', "/")
+                })
+                .collect()
+        };
+
+        assert_eq!(names("*.png"), ["a.png", "b.png"]);
+        assert_eq!(names(".*.png"), [".hidden.png"]);
+        assert_eq!(names("*/*.png"), ["sub/d.png"]);
+        assert_eq!(
+            names("**/*.png"),
+            ["a.png", "b.png", "sub/d.png", "sub/deep/e.png"]
+        );
+        assert!(names("*.gif").is_empty());
+        assert_eq!(files_under(&dir).len(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recursion_skips_symlinked_directories() {
+        let dir = std::env::temp_dir().join(format!("hpv-glob-loop-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/a.png"), b"").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+
+        assert_eq!(files_under(&dir), [dir.join("sub/a.png")]);
+        assert_eq!(
+            expand(&dir.join("**/*.png")).unwrap(),
+            [dir.join("sub/a.png")]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+use crate::pixels::{Ihdr, idat_data, replace_idat};
+use crate::png::Png;
+use crate::{Result, zlib};
+
+const LABEL_LEN: usize = 4;
+/// Label | payload length (4, big-endian)
+const HEADER_LEN: usize = LABEL_LEN + 4;
+
+/// Returns `png` with its image data recompressed so that padding inside the zlib stream
+/// carries `data`, labelled so `extract` can find it. The pixels stay exactly the same.
+pub fn embed(png: Png, label: &str, data: &[u8]) -> Result<Png> {
+    let label: [u8; LABEL_LEN] = label
+        .as_bytes()
+        .try_into()
+        .map_err(|_| format!("Label '{}' must be 4 bytes long", label))?;
+    let length = u32::try_from(data.len()).map_err(|_| "Message is too large")?;
+    let size = Ihdr::from_png(&png)?.inflated_size()?;
+    let raw =
+        zlib::inflate(&idat_data(&png)?, size).map_err(|e| format!("Corrupt image data: {}", e))?;
+
+    let mut hidden = Vec::with_capacity(HEADER_LEN + data.len());
+    hidden.extend(label);
+    hidden.extend(length.to_be_bytes());
+    hidden.extend(data);
+    replace_idat(png, zlib::deflate_hiding(&raw, &hidden))
+}
+
+/// Label of the message hidden in the image data stream, if there is one
+pub fn read_label(png: &Png) -> Result<Option<[u8; LABEL_LEN]>> {
+    let hidden = zlib::read_hidden(&idat_data(png)?)?;
+    Ok(hidden
+        .get(..LABEL_LEN)
+        .map(|label| label.try_into().unwrap()))
+}
+
+/// Returns the data stored under `label` in the image data stream, or `None` if there is none
+pub fn extract(png: &Png, label: &str) -> Result<Option<Vec<u8>>> {
+    let hidden = zlib::read_hidden(&idat_data(png)?)?;
+    let Some(header) = hidden.get(..HEADER_LEN) else {
+        return Ok(None);
+    };
+    if &header[..LABEL_LEN] != label.as_bytes() {
+        return Ok(None);
+    }
+    let length = u32::from_be_bytes(header[LABEL_LEN..].try_into()?) as usize;
+    let data = hidden
+        .get(HEADER_LEN..HEADER_LEN + length)
+        .ok_or("Message in the image data stream is truncated")?;
+    Ok(Some(data.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::Pixels;
+    use crate::pixels::tests::test_png;
+
+    #[test]
+    fn test_embed_extract_keeps_pixels() {
+        let png = test_png(20, 10, 2, 8);
+        let before: Vec<u8> = {
+            let pixels = Pixels::from_png(&png).unwrap();
+            (0..20 * 10 * 3).map(|i| pixels.byte(i)).collect()
+        };
+        let chunk_count = png.chunks().len();
+
+        let png = embed(png, "ruSt", b"in the zlib stream").unwrap();
+        assert_eq!(png.chunks().len(), chunk_count);
+        assert_eq!(read_label(&png).unwrap(), Some(*b"ruSt"));
+        assert_eq!(
+            extract(&png, "ruSt").unwrap(),
+            Some(b"in the zlib stream".to_vec())
+        );
+        assert_eq!(extract(&png, "abCd").unwrap(), None);
+
+        let pixels = Pixels::from_png(&png).unwrap();
+        assert!((0..before.len()).all(|i| pixels.byte(i) == before[i]));
+    }
+
+    #[test]
+    fn test_clean_image_has_no_message() {
+        let png = test_png(20, 10, 2, 8);
+        assert_eq!(read_label(&png).unwrap(), None);
+        assert_eq!(extract(&png, "ruSt").unwrap(), None);
+        assert!(embed(png, "rust!", b"x").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::atomic_file;
+use crate::exit::Failure;
+use crate::{Result, crypto, encoding};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Name of the journal in each directory of images, unless backups go to a central
+/// backup directory, which then holds the journals too
+const JOURNAL_NAME: &str = ".hpv-journal";
+
+/// A change recorded in the journal, which `undo` can revert
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// When it was made, as seconds since the Unix epoch
+    pub timestamp: u64,
+    /// The command that made it, such as "encode"
+    pub operation: String,
+    pub chunk: String,
+    /// SHA-256 of the file before and after the change, in hex
+    pub before: String,
+    pub after: String,
+    /// Name of the file within the journal's directory
+    pub file: PathBuf,
+    /// The backup made of the file before the change
+    pub backup: PathBuf,
+}
+
+impl Entry {
+    /// The entry as a line of tab-separated fields, with the paths as they are even
+    /// when they are not valid UTF-8
+    fn to_line(&self) -> Vec<u8> {
+        let text = format!(
+            "{}\t{}\t{}\t{}\t{}\t",
+            self.timestamp, self.operation, self.chunk, self.before, self.after
+        );
+        [
+            text.as_bytes(),
+            &atomic_file::path_bytes(&self.file),
+            b"\t",
+            &atomic_file::path_bytes(&self.backup),
+            b"\n",
+        ]
+        .concat()
+    }
+
+    /// Parses a line written by `to_line`, without its newline
+    fn parse(line: &[u8]) -> Option<Entry> {
+        let mut fields = line.splitn(7, |&byte| byte == b'\t');
+        let mut text = || Some(std::str::from_utf8(fields.next()?).ok()?.to_string());
+        let (timestamp, operation, chunk, before, after) =
+            (text()?.parse().ok()?, text()?, text()?, text()?, text()?);
+        Some(Entry {
+            timestamp,
+            operation,
+            chunk,
+            before,
+            after,
+            file: atomic_file::path_from_bytes(fields.next()?),
+            backup: atomic_file::path_from_bytes(fields.next()?),
+        })
+    }
+}
+
+/// SHA-256 of `data` in hex, as the journal records files
+pub fn digest(data: &[u8]) -> String {
+    encoding::hex_encode(&crypto::sha256(data))
+}
+
+/// The journal of the images in `dir`
+fn journal_path(dir: &Path) -> Result<PathBuf> {
+    match atomic_file::central_journal(dir)? {
+        Some(path) => Ok(path),
+        None => Ok(dir.join(JOURNAL_NAME)),
+    }
+}
+
+/// Adds `entry` to the end of the journal of `dir`
+pub fn record(dir: &Path, entry: &Entry) -> Result<()> {
+    let path = journal_path(dir)?;
+    // A central backup directory may not exist yet
+    fs::create_dir_all(atomic_file::parent_dir(&path))
+        .and_then(|()| OpenOptions::new().create(true).append(true).open(&path))
+        .and_then(|mut file| file.write_all(&entry.to_line()))
+        .map_err(|e| {
+            Failure::from(&e).error(format!(
+                "Failed to write journal '{}': {}",
+                path.display(),
+                e
+            ))
+        })
+}
+
+/// Every change recorded for the images in `dir`, oldest first; lines that cannot be
+/// read are skipped
+pub fn entries(dir: &Path) -> Result<Vec<Entry>> {
+    let path = journal_path(dir)?;
+    match fs::read(&path) {
+        Ok(data) => Ok(data
+            .split(|&byte| byte == b'\n')
+            .filter_map(Entry::parse)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(Failure::from(&e).error(format!(
+            "Failed to read journal '{}': {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Drops the entry at `index` from the journal of `dir`, removing the journal once it
+/// is empty
+pub fn remove(dir: &Path, index: usize) -> Result<()> {
+    let path = journal_path(dir)?;
+    let mut entries = entries(dir)?;
+    if index < entries.len() {
+        entries.remove(index);
+    }
+    let written = if entries.is_empty() {
+        fs::remove_file(&path)
+    } else {
+        fs::write(
+            &path,
+            entries.iter().flat_map(Entry::to_line).collect::<Vec<_>>(),
+        )
+    };
+    written.map_err(|e| {
+        Failure::from(&e).error(format!(
+            "Failed to write journal '{}': {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_round_trip() {
+        let entry = Entry {
+            timestamp: 1_709_164_800,
+            operation: "encode".to_string(),
+            chunk: "ruSt".to_string(),
+            before: digest(b"before"),
+            after: digest(b"after"),
+            file: PathBuf::from("holiday photo.png"),
+            backup: PathBuf::from("/photos/holiday photo.png.backup.20240229T000000"),
+        };
+        let line = entry.to_line();
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(Entry::parse(&line[..line.len() - 1]), Some(entry));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_lines() {
+        for line in [
+            "",
+            "not a journal line",
+            "x\tencode\truSt\ta\tb\tf.png\tf.backup",
+        ] {
+            assert_eq!(Entry::parse(line.as_bytes()), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::Result;
+use crate::crypto::Secret;
+
+/// Service name for keyring entries; the user-chosen name is the account.
+/// Backed by the Secret Service (`secret-tool`) on Linux/BSD, the login keychain
+/// (Security framework) on macOS, and the Credential Manager on Windows.
+const SERVICE: &str = "hidden-pixel-vault";
+
+/// Looks up the password stored under `name`
+pub fn get_password(name: &str) -> Result<Secret> {
+    platform::get_password(name)
+        .map_err(|e| format!("Failed to read '{}' from the keyring: {}", name, e).into())
+}
+
+/// Stores (or replaces) the password under `name`
+pub fn set_password(name: &str, password: &Secret) -> Result<()> {
+    platform::set_password(name, password)
+        .map_err(|e| format!("Failed to store '{}' in the keyring: {}", name, e).into())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use super::SERVICE;
+    use crate::Result;
+    use crate::crypto::Secret;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    pub fn get_password(name: &str) -> Result<Secret> {
+        let output = Command::new("secret-tool")
+            .args(["lookup", "service", SERVICE, "account", name])
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("could not run secret-tool (is libsecret installed?): {}", e))?;
+
+        let password = Secret::from(output.stdout);
+        if !output.status.success() || password.expose().is_empty() {
+            return Err("no password stored under this name".into());
+        }
+        Ok(password)
+    }
+
+    pub fn set_password(name: &str, password: &Secret) -> Result<()> {
+        // The password goes through stdin so it never shows up in the process list
+        let mut child = Command::new("secret-tool")
+            .arg("store")
+            .arg(format!("--label=Hidden Pixel Vault: {}", name))
+            .args(["service", SERVICE, "account", name])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not run secret-tool (is libsecret installed?): {}", e))?;
+
+        child
+            .stdin
+            .take()
+            .ok_or("could not open secret-tool stdin")?
+            .write_all(password.expose())?;
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(format!("secret-tool exited with {}", status).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::SERVICE;
+    use crate::Result;
+    use crate::crypto::Secret;
+    use security_framework::passwords::{get_generic_password, set_generic_password};
+
+    /// `errSecItemNotFound`
+    const ITEM_NOT_FOUND: i32 = -25300;
+
+    pub fn get_password(name: &str) -> Result<Secret> {
+        let password = get_generic_password(SERVICE, name).map_err(|e| {
+            if e.code() == ITEM_NOT_FOUND {
+                "no password stored under this name".to_string()
+            } else {
+                e.to_string()
+            }
+        })?;
+        Ok(Secret::from(password))
+    }
+
+    pub fn set_password(name: &str, password: &Secret) -> Result<()> {
+        // Through the Security framework rather than the `security` tool, which only
+        // takes the password as an argument and so would show it in the process list
+        set_generic_password(SERVICE, name, password.expose())?;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::SERVICE;
+    use crate::Result;
+    use crate::crypto::Secret;
+    use std::ffi::c_void;
+
+    const CRED_TYPE_GENERIC: u32 = 1;
+    const CRED_PERSIST_LOCAL_MACHINE: u32 = 2;
+
+    #[repr(C)]
+    struct Credential {
+        flags: u32,
+        kind: u32,
+        target_name: *mut u16,
+        comment: *mut u16,
+        last_written: [u32; 2],
+        credential_blob_size: u32,
+        credential_blob: *mut u8,
+        persist: u32,
+        attribute_count: u32,
+        attributes: *mut c_void,
+        target_alias: *mut u16,
+        user_name: *mut u16,
+    }
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn CredReadW(
+            target_name: *const u16,
+            kind: u32,
+            flags: u32,
+            credential: *mut *mut Credential,
+        ) -> i32;
+        fn CredWriteW(credential: *const Credential, flags: u32) -> i32;
+        fn CredFree(buffer: *mut c_void);
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain([0]).collect()
+    }
+
+    fn target(name: &str) -> Vec<u16> {
+        wide(&format!("{}:{}", SERVICE, name))
+    }
+
+    pub fn get_password(name: &str) -> Result<Secret> {
+        let target = target(name);
+        let mut credential: *mut Credential = std::ptr::null_mut();
+        let found = unsafe { CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) };
+        if found == 0 {
+            return Err("no password stored under this name".into());
+        }
+
+        let blob = unsafe {
+            let credential = &*credential;
+            std::slice::from_raw_parts(
+                credential.credential_blob,
+                credential.credential_blob_size as usize,
+            )
+            .to_vec()
+        };
+        unsafe { CredFree(credential.cast()) };
+
+        Ok(Secret::from(blob))
+    }
+
+    pub fn set_password(name: &str, password: &Secret) -> Result<()> {
+        let mut target = target(name);
+        let mut user_name = wide(name);
+        let mut blob = Secret::from(password.expose().to_vec());
+
+        let credential = Credential {
+            flags: 0,
+            kind: CRED_TYPE_GENERIC,
+            target_name: target.as_mut_ptr(),
+            comment: std::ptr::null_mut(),
+            last_written: [0; 2],
+            credential_blob_size: blob.expose().len() as u32,
+            credential_blob: blob.expose_mut().as_mut_ptr(),
+            persist: CRED_PERSIST_LOCAL_MACHINE,
+            attribute_count: 0,
+            attributes: std::ptr::null_mut(),
+            target_alias: std::ptr::null_mut(),
+            user_name: user_name.as_mut_ptr(),
+        };
+
+        if unsafe { CredWriteW(&credential, 0) } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+use crate::pixels::{Ihdr, Pixels};
+use crate::{Result, crypto};
+
+/// Most low bits per sample that may be replaced; beyond this the noise becomes visible
+pub const MAX_BITS: u8 = 4;
+const LABEL_LEN: usize = 4;
+/// Label | bits per sample (1) | payload length (4, big-endian)
+const HEADER_LEN: usize = LABEL_LEN + 1 + 4;
+/// The header is always written one bit per sample so it can be read before the depth is known
+const HEADER_SAMPLES: usize = HEADER_LEN * 8;
+
+/// Header found at the start of the pixel data
+#[derive(Debug, PartialEq, Eq)]
+pub struct Header {
+    pub label: [u8; LABEL_LEN],
+    pub bits: u8,
+    pub length: usize,
+}
+
+/// Bytes that fit in an image at `bits` per color sample, after the header
+pub fn capacity(ihdr: &Ihdr, bits: u8) -> Result<usize> {
+    let samples = ihdr.color_samples()?;
+    let bits = samples
+        .saturating_sub(HEADER_SAMPLES)
+        .checked_mul(bits as usize)
+        .ok_or("Image is too large to compute its capacity")?;
+    Ok(bits / 8)
+}
+
+/// Color sample offsets in the order bits are written: scanline order, or with a secret
+/// a pseudorandom order derived from it and `label`
+pub fn sample_order(pixels: &Pixels, label: &str, secret: Option<&[u8]>) -> Result<Vec<usize>> {
+    let mut offsets = pixels.sample_offsets()?;
+    if let Some(secret) = secret {
+        crypto::keyed_shuffle(secret, label, &mut offsets)?;
+    }
+    Ok(offsets)
+}
+
+/// Hides `data` in the low `bits` bits of the samples at `order`, labelled so `extract` can find it
+pub fn embed(
+    pixels: &mut Pixels,
+    order: &[usize],
+    label: &str,
+    data: &[u8],
+    bits: u8,
+) -> Result<()> {
+    if !(1..=MAX_BITS).contains(&bits) {
+        return Err(format!("Invalid bit depth {}, expected 1 to {}", bits, MAX_BITS).into());
+    }
+    let label: [u8; LABEL_LEN] = label
+        .as_bytes()
+        .try_into()
+        .map_err(|_| format!("Label '{}' must be 4 bytes long", label))?;
+    let available = capacity(&pixels.ihdr, bits)?;
+    if data.len() > available {
+        return Err(format!(
+            "Message needs {} bytes but the image holds only {} at {} bit(s) per channel; try more --bits or a larger image",
+            data.len(),
+            available,
+            bits
+        )
+        .into());
+    }
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend(label);
+    header.push(bits);
+    header.extend((data.len() as u32).to_be_bytes());
+
+    write_bits(pixels, &order[..HEADER_SAMPLES], &header, 1);
+    write_bits(pixels, &order[HEADER_SAMPLES..], data, bits);
+    Ok(())
+}
+
+/// Reads the header from the samples at `order`, if the pixels carry one in that order
+pub fn read_header(pixels: &Pixels, order: &[usize]) -> Result<Option<Header>> {
+    if order.len() < HEADER_SAMPLES {
+        return Ok(None);
+    }
+    let header = read_bits(pixels, &order[..HEADER_SAMPLES], HEADER_LEN, 1);
+    let bits = header[LABEL_LEN];
+    let length = u32::from_be_bytes(header[LABEL_LEN + 1..].try_into()?) as usize;
+    // Random pixel noise rarely passes these checks
+    if !(1..=MAX_BITS).contains(&bits) || length > capacity(&pixels.ihdr, bits)? {
+        return Ok(None);
+    }
+    Ok(Some(Header {
+        label: header[..LABEL_LEN].try_into()?,
+        bits,
+        length,
+    }))
+}
+
+/// Returns the bit depth and data stored under `label` in `order`, or `None` if there is none
+pub fn extract(pixels: &Pixels, order: &[usize], label: &str) -> Result<Option<(u8, Vec<u8>)>> {
+    let header = match read_header(pixels, order)? {
+        Some(header) if header.label == label.as_bytes() => header,
+        _ => return Ok(None),
+    };
+    let data = read_bits(pixels, &order[HEADER_SAMPLES..], header.length, header.bits);
+    Ok(Some((header.bits, data)))
+}
+
+/// Writes `data` most significant bit first, `bits` bits into each listed byte
+fn write_bits(pixels: &mut Pixels, offsets: &[usize], data: &[u8], bits: u8) {
+    let mut stream = data
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    for &offset in offsets {
+        let mut value = 0;
+        let mut taken = 0;
+        for bit in stream.by_ref().take(bits as usize) {
+            value = (value << 1) | bit;
+            taken += 1;
+        }
+        if taken == 0 {
+            return;
+        }
+        // A final partial group keeps the sample's remaining low bits
+        let shift = bits - taken;
+        let field = ((1u8 << taken) - 1) << shift;
+        let byte = pixels.byte(offset);
+        pixels.set_byte(offset, (byte & !field) | (value << shift));
+    }
+}
+
+fn read_bits(pixels: &Pixels, offsets: &[usize], len: usize, bits: u8) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    let mut stream = offsets.iter().flat_map(|&offset| {
+        let byte = pixels.byte(offset);
+        (0..bits).rev().map(move |i| (byte >> i) & 1)
+    });
+    for _ in 0..len {
+        data.push(
+            stream
+                .by_ref()
+                .take(8)
+                .fold(0, |byte, bit| (byte << 1) | bit),
+        );
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::test_png;
+
+    #[test]
+    fn test_embed_extract_each_depth() {
+        let message = b"pixel-domain payload";
+        for bits in 1..=MAX_BITS {
+            let mut pixels = Pixels::from_png(&test_png(20, 10, 2, 8)).unwrap();
+            let original: Vec<u8> = (0..20 * 10 * 3).map(|i| pixels.byte(i)).collect();
+            let order = sample_order(&pixels, "ruSt", None).unwrap();
+            embed(&mut pixels, &order, "ruSt", message, bits).unwrap();
+
+            assert_eq!(
+                extract(&pixels, &order, "ruSt").unwrap(),
+                Some((bits, message.to_vec()))
+            );
+            assert_eq!(extract(&pixels, &order, "abCd").unwrap(), None);
+
+            // Only the low `bits` bits of any sample change
+            for (i, &before) in original.iter().enumerate() {
+                assert_eq!(before >> bits, pixels.byte(i) >> bits);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let pixels = Pixels::from_png(&test_png(20, 10, 6, 16)).unwrap();
+        // 600 color samples (alpha excluded), 72 of them used by the header
+        assert_eq!(capacity(&pixels.ihdr, 1).unwrap(), 66);
+        assert_eq!(capacity(&pixels.ihdr, 4).unwrap(), 264);
+
+        let mut pixels = pixels;
+        let order = pixels.sample_offsets().unwrap();
+        assert!(embed(&mut pixels, &order, "ruSt", &[0; 67], 1).is_err());
+        assert!(embed(&mut pixels, &order, "ruSt", &[0; 67], 2).is_ok());
+        assert!(embed(&mut pixels, &order, "ruSt", b"x", 5).is_err());
+        assert!(embed(&mut pixels, &order, "rust!", b"x", 1).is_err());
+    }
+
+    #[test]
+    fn test_clean_image_has_no_header() {
+        let pixels = Pixels::from_png(&test_png(20, 10, 2, 8)).unwrap();
+        let order = pixels.sample_offsets().unwrap();
+        assert_eq!(read_header(&pixels, &order).unwrap(), None);
+    }
+
+    #[test]
+    fn test_scattered_order_needs_the_secret() {
+        let mut pixels = Pixels::from_png(&test_png(20, 10, 2, 8)).unwrap();
+        let order = sample_order(&pixels, "ruSt", Some(b"hunter2")).unwrap();
+        assert_ne!(order, pixels.sample_offsets().unwrap());
+        embed(&mut pixels, &order, "ruSt", b"scattered", 1).unwrap();
+
+        assert_eq!(
+            extract(&pixels, &order, "ruSt").unwrap(),
+            Some((1, b"scattered".to_vec()))
+        );
+        let sequential = sample_order(&pixels, "ruSt", None).unwrap();
+        assert_eq!(extract(&pixels, &sequential, "ruSt").unwrap(), None);
+        let wrong = sample_order(&pixels, "ruSt", Some(b"hunter3")).unwrap();
+        assert_eq!(extract(&pixels, &wrong, "ruSt").unwrap(), None);
+    }
+}
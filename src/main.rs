@@ -1,43 +1,264 @@
-use crate::args::Args;
-use crate::args::Commands::{Cleanup, Decode, Encode, Print, Remove, Restore, Status};
+use crate::args::Commands::{
+    Analyze, Capacity, Cleanup, Complete, Completions, Create, Decode, Diff, DumpChunk, Encode,
+    ExtractPixels, GenerateMan, Info, InjectChunk, Interactive, Join, Keygen, MoveChunk, Optimize,
+    Preview, Print, Rekey, Remove, RenameChunk, Repair, Restore, Scan, Sign, Stats, Status, Strip,
+    Undo, Update, Verify, Watermark,
+};
+use crate::args::{Args, Commands};
 use crate::commands::{
-    cleanup_files, decode, encode, print, remove, restore_original, show_status,
+    analyze, capacity, cleanup_files, complete, completions, create, decode, diff, dump_chunk,
+    encode, extract_pixels, generate_man, info, inject_chunk, join, keygen, manage_watermark,
+    move_chunk, optimize, preview, print, rekey, remove, rename_chunk, repair, restore_original,
+    scan, show_status, sign, stats, strip, undo, update, verify,
 };
-use clap::Parser;
+use crate::exit::Failure;
+use crate::output::Verbosity;
+use clap::{CommandFactory, FromArgMatches};
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
+mod analysis;
 mod args;
 mod atomic_file;
 mod chunk;
 mod chunk_type;
 mod commands;
+mod completions;
+mod container;
+mod crypto;
+mod date;
+mod encoding;
+mod exit;
+mod fec;
+mod glob;
+mod idat;
+mod journal;
+mod keyring;
+mod lsb;
+mod man;
+mod output;
+mod payload;
+mod pixels;
 mod png;
+mod preview;
+mod prompt;
+mod signature;
+mod text;
+mod trash;
+mod tui;
+mod watermark;
+mod xmp;
+mod zlib;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let command = matches.subcommand_name().unwrap_or_default();
+    if args.json {
+        output::set_json(true);
+    }
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    if args.plain || no_color || !std::io::stdout().is_terminal() {
+        output::set_plain();
+    }
+    if args.quiet {
+        output::set_verbosity(Verbosity::Quiet);
+    } else if args.verbose {
+        output::set_verbosity(Verbosity::Verbose);
+    }
+    let result = configure(&args).and_then(|()| run(&args.command));
+
+    if result.is_ok() && args.dry_run {
+        output::say!("🔍  Dry run: no files were changed");
+    }
+    output::finish(command, &result);
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) if args.json => std::process::exit(Failure::of(e.as_ref()).exit_code()),
+        Err(e) => {
+            let error = format!(
+                " ❌ Error: {}\n💡  Tip: Use 'restore' command if you need to revert changes",
+                e
+            );
+            if output::is_plain() {
+                eprintln!("{}", output::plain(&error));
+            } else {
+                eprintln!("{}", error);
+            }
+            std::process::exit(Failure::of(e.as_ref()).exit_code());
+        }
+    }
+}
+
+/// Applies the global options of `args`
+fn configure(args: &Args) -> Result<()> {
+    atomic_file::set_keep_backups(args.keep_backups as usize);
+    atomic_file::set_compress_backups(args.compress_backups);
+    let backup_dir = args.backup_dir.clone().or_else(|| {
+        std::env::var_os("HPV_BACKUP_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    });
+    match backup_dir {
+        None => {}
+        // --backup-dir without a value
+        Some(dir) if dir.as_os_str().is_empty() => atomic_file::set_backup_dir(
+            atomic_file::default_backup_dir()
+                .ok_or("No home directory to keep backups in; pass --backup-dir DIR")?,
+        ),
+        Some(dir) => atomic_file::set_backup_dir(dir),
+    }
 
-    let result = match &args.command {
+    // Flags win over the environment, whose values are checked like theirs
+    let suffix = |arg: &Option<String>, variable| -> Result<Option<String>> {
+        match std::env::var(variable) {
+            _ if arg.is_some() => Ok(arg.clone()),
+            Ok(value) if !value.is_empty() => args::parse_suffix(&value)
+                .map(Some)
+                .map_err(|e| format!("Invalid {}: {}", variable, e).into()),
+            _ => Ok(None),
+        }
+    };
+    if let Some(suffix) = suffix(&args.backup_suffix, "HPV_BACKUP_SUFFIX")? {
+        atomic_file::set_backup_suffix(suffix);
+    }
+    if let Some(suffix) = suffix(&args.temp_suffix, "HPV_TEMP_SUFFIX")? {
+        atomic_file::set_temp_suffix(suffix);
+    }
+
+    if args.dry_run {
+        atomic_file::set_dry_run(commands::report_dry_run);
+    }
+    Ok(())
+}
+
+/// Runs the subcommand `command`
+fn run(command: &Commands) -> Result<()> {
+    match command {
         Encode {
             path,
             chunk_type,
             message,
-        } => encode(path, chunk_type, message),
-        Decode { path, chunk_type } => decode(path, chunk_type),
-        Remove { path, chunk_type } => remove(path, chunk_type),
-        Print { path } => print(path),
-        Restore { path } => restore_original(path),
-        Cleanup { path } => cleanup_files(path),
+            expires,
+            output,
+            embedding,
+            encryption,
+        } => encode(
+            path,
+            chunk_type,
+            message,
+            embedding,
+            encryption,
+            expires.as_deref(),
+            output.as_deref(),
+        ),
+        Update {
+            path,
+            chunk_type,
+            message,
+            expires,
+            encryption,
+        } => update(path, chunk_type, message, encryption, expires.as_deref()),
+        Decode {
+            path,
+            chunk_type,
+            more_paths,
+            location,
+            decryption,
+            shard: _,
+            all: _,
+            output,
+        } => decode(
+            path,
+            chunk_type.as_deref(),
+            more_paths,
+            location,
+            decryption,
+            output,
+        ),
+        Join {
+            chunk_type,
+            paths,
+            location,
+            decryption,
+            output,
+        } => join(chunk_type, paths, location, decryption, output),
+        Rekey {
+            path,
+            chunk_type,
+            rekey: secrets,
+        } => rekey(path, chunk_type, secrets),
+        Keygen { path, signing } => keygen(path, *signing),
+        Sign { path, key, chunk } => sign(path, key, chunk.as_deref()),
+        Verify { path, key, chunk } => verify(path, key.as_deref(), chunk.as_deref()),
+        DumpChunk {
+            path,
+            chunk_type,
+            out_file,
+            index,
+        } => dump_chunk(path, chunk_type, out_file, *index),
+        ExtractPixels {
+            path,
+            out_file,
+            format,
+        } => extract_pixels(path, out_file, *format),
+        Create {
+            path,
+            width,
+            height,
+            fill,
+        } => create(path, *width, *height, fill),
+        InjectChunk {
+            path,
+            chunk_type,
+            in_file,
+            before,
+            index,
+        } => inject_chunk(path, chunk_type, in_file, before.as_deref(), *index),
+        RenameChunk {
+            path,
+            old_type,
+            new_type,
+        } => rename_chunk(path, old_type, new_type),
+        MoveChunk {
+            path,
+            chunk_type,
+            placement,
+        } => move_chunk(path, chunk_type, placement),
+        Strip { path, keep } => strip(path, keep),
+        Optimize { path, refilter } => optimize(path, *refilter),
+        Repair { path } => repair(path),
+        Remove {
+            path,
+            chunk_type,
+            index,
+            output,
+        } => remove(path, chunk_type, *index, output.as_deref()),
+        Print { path, detailed } => print(path, *detailed),
+        Diff { old, new } => diff(old, new),
+        Info { path } => info(path),
+        Preview { path, width, ascii } => preview(path, *width, *ascii || output::is_plain()),
+        Stats { path } => stats(path),
+        Capacity { path } => capacity(path),
+        Analyze { path } => analyze(path),
+        Scan { dir } => scan(dir),
+        Watermark { action } => manage_watermark(action),
+        Restore { path, version } => restore_original(path, version.as_deref()),
+        Undo { path } => undo(path),
+        Cleanup {
+            path,
+            prune,
+            keep_last,
+            older_than,
+            trash,
+        } => cleanup_files(path, *prune, *keep_last, older_than.as_deref(), *trash),
         Status { path } => show_status(path),
-    };
-
-    match result {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            eprintln!(" ❌ Error: {}", e);
-            eprintln!("💡  Tip: Use 'restore' command if you need to revert changes");
-            std::process::exit(1);
-        }
+        Interactive { path } => tui::interactive(path),
+        Completions { shell } => completions(*shell),
+        Complete { words } => complete(words),
+        GenerateMan { dir } => generate_man(dir),
     }
 }
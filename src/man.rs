@@ -0,0 +1,105 @@
+use clap::Command;
+use clap_mangen::Man;
+
+/// The man pages of `root` and each of its subcommands, nested ones included, as file
+/// names such as `Hidden-Pixel-Vault-encode.1` with their roff source. Hidden commands
+/// and options are left out, like in `--help`.
+pub fn pages(root: &Command) -> Vec<(String, String)> {
+    // Building copies the global options into the subcommands and names each of them
+    // after its parents, which is what the pages are named after
+    let mut root = root.clone().disable_help_subcommand(true);
+    root.build();
+    let mut pages = vec![];
+    add_pages(&root, &mut pages);
+    pages
+}
+
+fn add_pages(command: &Command, pages: &mut Vec<(String, String)>) {
+    let man = Man::new(command.clone());
+    let mut roff = vec![];
+    man.render(&mut roff)
+        .expect("rendering into memory does not fail");
+    pages.push((
+        man.get_filename(),
+        String::from_utf8_lossy(&roff).into_owned(),
+    ));
+    for subcommand in command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+    {
+        add_pages(subcommand, pages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Args;
+    use clap::CommandFactory;
+
+    fn find_page<'a>(pages: &'a [(String, String)], name: &str) -> &'a str {
+        pages
+            .iter()
+            .find(|(file, _)| file == name)
+            .map(|(_, roff)| roff.as_str())
+            .unwrap_or_else(|| panic!("no page {}", name))
+    }
+
+    /// How roff shows `text` in bold
+    fn bold(text: &str) -> String {
+        format!("\\fB{}\\fR", text.replace('-', "\\-"))
+    }
+
+    #[test]
+    fn test_pages() {
+        let pages = pages(&Args::command());
+        let root = find_page(&pages, "Hidden-Pixel-Vault.1");
+        assert!(root.contains(".TH Hidden-Pixel-Vault 1 "));
+        assert!(root.contains(".SH SUBCOMMANDS\n"));
+        assert!(root.contains("Hidden\\-Pixel\\-Vault\\-encode(1)"));
+        assert!(root.contains("Exit status:"));
+
+        let encode = find_page(&pages, "Hidden-Pixel-Vault-encode.1");
+        assert!(encode.contains("\\fBHidden\\-Pixel\\-Vault encode\\fR "));
+        assert!(encode.contains("<\\fIPATH\\fR> <\\fICHUNK_TYPE\\fR> [\\fIMESSAGE\\fR]"));
+        assert!(encode.contains(".TP\n\\fB\\-o\\fR, \\fB\\-\\-output\\fR \\fI<FILE>\\fR\n"));
+        // Global options are documented on every page
+        assert!(encode.contains(&bold("--backup-dir")));
+        assert!(encode.contains("Possible values:"));
+
+        // Nested subcommands get pages of their own, hidden ones none
+        find_page(&pages, "Hidden-Pixel-Vault-watermark-embed.1");
+        assert!(!pages.iter().any(|(file, _)| file.contains("complete.")));
+        assert!(!pages.iter().any(|(file, _)| file.contains("-help.")));
+    }
+
+    fn check_page(command: &Command, name: &str, pages: &[(String, String)]) {
+        let roff = find_page(pages, &format!("{}.1", name));
+        for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+            let label = match (arg.get_long(), arg.get_short()) {
+                (Some(long), _) => bold(&format!("--{}", long)),
+                (None, Some(short)) => bold(&format!("-{}", short)),
+                (None, None) => format!("\\fI{}\\fR", arg.get_id().as_str().to_uppercase()),
+            };
+            assert!(roff.contains(&label), "{} misses {}", name, label);
+        }
+        for subcommand in command
+            .get_subcommands()
+            .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help")
+        {
+            check_page(
+                subcommand,
+                &format!("{}-{}", name, subcommand.get_name()),
+                pages,
+            );
+        }
+    }
+
+    #[test]
+    fn test_every_subcommand_has_a_page_with_its_options() {
+        let mut root = Args::command();
+        root.build();
+        let pages = pages(&root);
+        check_page(&root, root.get_name(), &pages);
+    }
+}
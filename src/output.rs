@@ -0,0 +1,323 @@
+use crate::Result;
+use crate::exit::Failure;
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+
+/// How much commands print, set by `-q/--quiet` and `-v/--verbose`
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    /// Nothing but errors, on stderr, and decoded messages
+    Quiet,
+    Normal,
+    /// Also each step, with byte counts and offsets
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Prints output in plain ASCII, without emoji or box-drawing characters, for logs,
+/// legacy consoles and screen readers
+pub fn set_plain() {
+    PLAIN.store(true, Ordering::Relaxed);
+}
+
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// The ASCII that `plain` puts in place of a symbol; symbols without one are dropped
+fn ascii_symbol(c: char) -> Option<&'static str> {
+    match c {
+        '✅' => Some("[ok]"),
+        '❌' => Some("[failed]"),
+        '⚠' => Some("[warning]"),
+        '•' => Some("-"),
+        '…' => Some("..."),
+        '←' => Some("<-"),
+        '×' => Some("x"),
+        '➕' => Some("+"),
+        '➖' => Some("-"),
+        _ => None,
+    }
+}
+
+fn is_symbol(c: char) -> bool {
+    ascii_symbol(c).is_some()
+        || matches!(c as u32,
+            // Arrows, technical symbols, box drawing, block elements, other symbols and
+            // dingbats
+            0x2190..=0x21ff | 0x2300..=0x23ff | 0x2500..=0x27bf | 0x2b00..=0x2bff
+            // Emoji, the presentation selector and joiner between their parts
+            | 0x1f000..=0x1faff | 0xfe0f | 0x200d
+        )
+        || c == 'ℹ'
+}
+
+/// The first line of `error` without its leading symbol, to list it beside a file name
+pub fn reason(error: &dyn std::error::Error) -> String {
+    let text = error.to_string();
+    let first = text.lines().find(|line| !line.trim().is_empty());
+    first
+        .unwrap_or_default()
+        .trim_start_matches(|c: char| c.is_whitespace() || is_symbol(c))
+        .to_string()
+}
+
+/// `text` with its emoji and box-drawing characters replaced by ASCII, or dropped along
+/// with the spaces after them when they are only decoration. Other text, such as
+/// accented letters, is kept.
+pub fn plain(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !is_symbol(c) {
+            plain.push(c);
+            continue;
+        }
+        let replacement = ascii_symbol(c);
+        plain.push_str(replacement.unwrap_or_default());
+        while chars.next_if(|&c| c == '\u{fe0f}').is_some() {}
+        let mut spaced = false;
+        while chars.next_if(|&c| c == ' ').is_some() {
+            spaced = true;
+        }
+        if spaced && replacement.is_some() {
+            plain.push(' ');
+        }
+    }
+    plain
+}
+
+/// What a run reports with `--json`: fields of its result and the lines it would
+/// otherwise have printed
+#[derive(Default)]
+struct Report {
+    result: Map<String, Value>,
+    messages: Vec<Value>,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static REPORT: LazyLock<Mutex<Report>> = LazyLock::new(Mutex::default);
+
+/// With `json`, collects what commands report into one JSON document on stdout,
+/// printed by `finish`, instead of printing it as it happens
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+fn report() -> MutexGuard<'static, Report> {
+    REPORT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn with_report(update: impl FnOnce(&mut Report)) {
+    if is_json() {
+        update(&mut report());
+    }
+}
+
+/// Resets the output settings and report for a test that runs commands, and keeps
+/// other such tests from running until the guard is dropped, as they share both
+#[cfg(test)]
+pub fn serialize_tests() -> MutexGuard<'static, ()> {
+    static RUNNING: Mutex<()> = Mutex::new(());
+    let guard = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    set_verbosity(Verbosity::Normal);
+    PLAIN.store(false, Ordering::Relaxed);
+    set_json(false);
+    *report() = Report::default();
+    guard
+}
+
+/// Prints a line of output at `level`, which `--quiet` or a lack of `--verbose` leaves
+/// out; with `--json` it is added to the "messages" of the document instead
+pub fn line(level: Verbosity, text: String) {
+    if is_plain() {
+        emit(level, plain(&text));
+    } else {
+        emit(level, text);
+    }
+}
+
+/// Prints `label` followed by `data`, such as a decoded message, which `--plain` leaves
+/// as it is. `data` is what was asked for, so `--quiet` prints it too, on its own;
+/// `--json` has it in the result already.
+pub fn labelled(label: &str, data: &str) {
+    if verbosity() == Verbosity::Quiet && !is_json() {
+        emit(Verbosity::Quiet, data.to_string());
+        return;
+    }
+    let label = if is_plain() {
+        plain(label)
+    } else {
+        label.to_string()
+    };
+    emit(Verbosity::Normal, label + data);
+}
+
+fn emit(level: Verbosity, text: String) {
+    if verbosity() < level {
+        return;
+    }
+    if !is_json() {
+        println!("{}", text);
+        return;
+    }
+    let text = text.trim();
+    if !text.is_empty() {
+        with_report(|report| report.messages.push(text.into()));
+    }
+}
+
+/// Prints a line of output, see [`line`]
+macro_rules! say {
+    () => {
+        $crate::output::line($crate::output::Verbosity::Normal, String::new())
+    };
+    ($($arg:tt)*) => {
+        $crate::output::line($crate::output::Verbosity::Normal, format!($($arg)*))
+    };
+}
+pub(crate) use say;
+
+/// Prints a line of detail, such as a byte count, only with `--verbose`
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        $crate::output::line($crate::output::Verbosity::Verbose, format!($($arg)*))
+    };
+}
+pub(crate) use detail;
+
+/// Sets the field `name` of the JSON result; does nothing without `--json`
+pub fn set(name: &str, value: impl Into<Value>) {
+    with_report(|report| {
+        report.result.insert(name.to_string(), value.into());
+    });
+}
+
+/// Adds `value` to the list in the field `name` of the JSON result, for results such as
+/// every file changed or every message found
+pub fn push(name: &str, value: impl Into<Value>) {
+    with_report(|report| {
+        let value = value.into();
+        match report.result.get_mut(name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(field) => *field = Value::Array(vec![value]),
+            None => {
+                report
+                    .result
+                    .insert(name.to_string(), Value::Array(vec![value]));
+            }
+        }
+    });
+}
+
+/// Prints the JSON document of a run of `command` that ended with `result`; does nothing
+/// without `--json`
+pub fn finish(command: &str, result: &Result<()>) {
+    if is_json() {
+        println!("{}", document(command, result));
+    }
+}
+
+/// The JSON document of a run of `command` that ended with `result`: whether it
+/// succeeded, its result, its error if it failed, and the messages it reported. Takes
+/// the report, so the next run starts afresh.
+pub fn document(command: &str, result: &Result<()>) -> Value {
+    let report = std::mem::take(&mut *report());
+    let mut fields = Map::new();
+    fields.insert("command".to_string(), command.into());
+    fields.insert("ok".to_string(), result.is_ok().into());
+    // A command that fails part way, as a batch, still reports what it got done
+    if result.is_ok() || !report.result.is_empty() {
+        fields.insert("result".to_string(), Value::Object(report.result));
+    }
+    if let Err(e) = result {
+        fields.insert("error".to_string(), e.to_string().trim().into());
+        let exit_code = Failure::of(e.as_ref()).exit_code();
+        fields.insert("exit_code".to_string(), exit_code.into());
+    }
+    fields.insert("messages".to_string(), Value::Array(report.messages));
+    Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain() {
+        for (text, expected) in [
+            (
+                " ✅ Message encoded successfully",
+                " [ok] Message encoded successfully",
+            ),
+            (
+                "🔐  Encoding message into 'a.png'...",
+                "Encoding message into 'a.png'...",
+            ),
+            (
+                "  ⚠️  12 bytes of trailing data",
+                "  [warning] 12 bytes of trailing data",
+            ),
+            ("   Target file: a.png ✅", "   Target file: a.png [ok]"),
+            ("  • IHDR (13 bytes)", "  - IHDR (13 bytes)"),
+            (" ℹ️   No files to clean up", " No files to clean up"),
+            ("    Message: café 日本", "    Message: café 日本"),
+            ("▀▀▀", ""),
+        ] {
+            assert_eq!(plain(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_document() {
+        let _serial = serialize_tests();
+        set_json(true);
+        set("size", 558u64);
+        push("chunks", "IHDR");
+        push("chunks", "ruSt");
+        set("expires", None::<u64>);
+        set("entropy", 3.0);
+        set("size", 559u64);
+        say!("  Found 2 chunks  ");
+        detail!("Left out without --verbose");
+        assert_eq!(
+            document("info", &Ok(())).to_string(),
+            r#"{"command":"info","ok":true,"result":{"size":559,"chunks":["IHDR","ruSt"],"expires":null,"entropy":3.0},"messages":["Found 2 chunks"]}"#
+        );
+
+        let failed: Result<()> = Err(Failure::ChunkNotFound.error("No chunk 'ruSt'"));
+        let document = document("decode", &failed);
+        assert_eq!(document["exit_code"], Value::from(5));
+        assert_eq!(document.get("result"), None);
+        set_json(false);
+    }
+
+    #[test]
+    fn test_reason() {
+        let error: crate::Error =
+            " ❌ Chunk 'ruSt' already exists\n💡  Tip: Use a different chunk name".into();
+        assert_eq!(reason(error.as_ref()), "Chunk 'ruSt' already exists");
+        let error: crate::Error = "Invalid chunk type".into();
+        assert_eq!(reason(error.as_ref()), "Invalid chunk type");
+    }
+}
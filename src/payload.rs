@@ -0,0 +1,703 @@
+use crate::Result;
+use crate::zlib;
+use std::io::Read;
+
+/// Magic at the start of every chunk written by `encode`
+pub const MAGIC: &[u8; 3] = b"HPV";
+/// Current header version; bump it for incompatible layout changes
+pub const VERSION: u8 = 2;
+const HEADER_LEN: usize = MAGIC.len() + 3;
+const FLAG_EXPIRES: u8 = 0x01;
+const FLAG_MANIFEST: u8 = 0x02;
+const FLAG_COMPRESSED: u8 = 0x04;
+/// Piece index and piece count of a fragment or shard, both big-endian u16
+const FRAGMENT_INDEX_LEN: usize = 4;
+/// Most bytes a compressed message may inflate to; encode leaves larger messages
+/// uncompressed, so only a crafted payload can go beyond it
+pub const MAX_DECOMPRESSED_LEN: usize = 256 << 20;
+/// Smallest chunk that still carries one payload byte per fragment
+pub const MIN_FRAGMENT_CHUNK: usize = HEADER_LEN + FRAGMENT_INDEX_LEN + 1;
+
+/// How the body following the header is encoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// The message itself
+    Plain,
+    /// [`crate::crypto::encrypt`] or [`crate::crypto::encrypt_dual`] output
+    Password,
+    /// age-encrypted to one or more recipients
+    Age,
+    /// Armored OpenPGP message produced by gpg
+    OpenPgp,
+    /// Data key wrapped by a hardware key provider
+    Hardware,
+    /// One Shamir share of another payload, see [`crate::crypto::split_secret`]
+    Share,
+    /// One piece of another payload spread over several chunks, see [`fragment`]
+    Fragment,
+    /// One piece of another payload spread over several images, see [`shard`]
+    Shard,
+    /// Another payload protected by Reed-Solomon parity, see [`crate::fec::encode`]
+    Fec,
+}
+
+impl Kind {
+    fn id(self) -> u8 {
+        match self {
+            Kind::Plain => 0,
+            Kind::Password => 1,
+            Kind::Age => 2,
+            Kind::OpenPgp => 3,
+            Kind::Hardware => 4,
+            Kind::Share => 5,
+            Kind::Fragment => 6,
+            Kind::Shard => 7,
+            Kind::Fec => 8,
+        }
+    }
+
+    /// Whether the body is encrypted; such payloads seal their header fields inside it
+    /// with [`seal_fields`]
+    pub fn is_encrypted(self) -> bool {
+        matches!(
+            self,
+            Kind::Password | Kind::Age | Kind::OpenPgp | Kind::Hardware
+        )
+    }
+
+    /// Short human-readable description for `print --detailed`
+    pub fn describe(self) -> &'static str {
+        match self {
+            Kind::Plain => "plain text",
+            Kind::Password => "password-encrypted",
+            Kind::Age => "age-encrypted",
+            Kind::OpenPgp => "OpenPGP",
+            Kind::Hardware => "hardware-wrapped",
+            Kind::Share => "Shamir share",
+            Kind::Fragment => "fragment",
+            Kind::Shard => "shard",
+            Kind::Fec => "error-corrected",
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Kind> {
+        match id {
+            0 => Ok(Kind::Plain),
+            1 => Ok(Kind::Password),
+            2 => Ok(Kind::Age),
+            3 => Ok(Kind::OpenPgp),
+            4 => Ok(Kind::Hardware),
+            5 => Ok(Kind::Share),
+            6 => Ok(Kind::Fragment),
+            7 => Ok(Kind::Shard),
+            8 => Ok(Kind::Fec),
+            _ => Err(format!("Unknown payload kind {}", id).into()),
+        }
+    }
+}
+
+/// zstd level used for messages; they are small, so a high level costs little time
+const ZSTD_LEVEL: i32 = 19;
+
+/// Algorithm the message was compressed with before any encryption
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// zstd, which shrinks text further than gzip; readable by `unzstd` once extracted
+    Zstd,
+    /// gzip (RFC 1952), readable by `gunzip` once extracted
+    Gzip,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::Gzip => 1,
+            Compression::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Compression> {
+        match id {
+            1 => Ok(Compression::Gzip),
+            2 => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression algorithm {} in payload", id).into()),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Zstd => Ok(zstd::encode_all(data, ZSTD_LEVEL)?),
+            Compression::Gzip => Ok(zlib::gzip(data)),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Zstd => {
+                // Read one byte past the limit to tell a full message from a longer one
+                let mut output = Vec::new();
+                zstd::Decoder::new(data)?
+                    .take(MAX_DECOMPRESSED_LEN as u64 + 1)
+                    .read_to_end(&mut output)?;
+                if output.len() > MAX_DECOMPRESSED_LEN {
+                    return Err(format!(
+                        "Decompressed data exceeds {} bytes",
+                        MAX_DECOMPRESSED_LEN
+                    )
+                    .into());
+                }
+                Ok(output)
+            }
+            Compression::Gzip => zlib::gunzip(data, MAX_DECOMPRESSED_LEN),
+        }
+    }
+}
+
+/// Describes a file hidden with `encode --file`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// Original file name, without directories
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// Unix time (seconds) the file was created, if the file system knew
+    pub created: Option<u64>,
+}
+
+impl Manifest {
+    /// Layout: name length (1) | name | MIME type length (1) | MIME type | size (8) |
+    /// creation time (8, zero if unknown), integers big-endian
+    fn write(&self, data: &mut Vec<u8>) {
+        for text in [&self.name, &self.mime_type] {
+            let bytes = truncate_utf8(text, u8::MAX as usize);
+            data.push(bytes.len() as u8);
+            data.extend(bytes);
+        }
+        data.extend(self.size.to_be_bytes());
+        data.extend(self.created.unwrap_or(0).to_be_bytes());
+    }
+
+    /// Parses a manifest from the start of `data`, returning it and the rest
+    fn read(data: &[u8]) -> Result<(Manifest, &[u8])> {
+        let truncated = || "Payload manifest is truncated";
+        let mut rest = data;
+        let mut texts = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let (&len, tail) = rest.split_first().ok_or_else(truncated)?;
+            let (text, tail) = tail.split_at_checked(len as usize).ok_or_else(truncated)?;
+            texts.push(String::from_utf8(text.to_vec())?);
+            rest = tail;
+        }
+        let (numbers, rest) = rest.split_at_checked(16).ok_or_else(truncated)?;
+        let created = u64::from_be_bytes(numbers[8..].try_into()?);
+        let mime_type = texts.pop().unwrap_or_default();
+        let manifest = Manifest {
+            name: texts.pop().unwrap_or_default(),
+            mime_type,
+            size: u64::from_be_bytes(numbers[..8].try_into()?),
+            created: (created != 0).then_some(created),
+        };
+        Ok((manifest, rest))
+    }
+}
+
+/// At most `max` bytes of `text`, cut at a character boundary
+fn truncate_utf8(text: &str, max: usize) -> &[u8] {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text.as_bytes()[..end]
+}
+
+/// MIME type guessed from a file name's extension
+pub fn guess_mime_type(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "md" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "asc" | "gpg" | "pgp" => "application/pgp-encrypted",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Parsed payload header
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub kind: Kind,
+    /// Unix time (seconds) after which `decode` refuses the message
+    pub expires: Option<u64>,
+    /// Set when the message is a file hidden with `encode --file`
+    pub manifest: Option<Manifest>,
+    /// Set when the message was compressed before being encrypted
+    pub compression: Option<Compression>,
+}
+
+impl Header {
+    pub fn new(kind: Kind) -> Self {
+        Header {
+            version: VERSION,
+            kind,
+            expires: None,
+            manifest: None,
+            compression: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| now > expires)
+    }
+}
+
+/// Prefixes `body` with the payload header, always in the current version.
+///
+/// Layout: magic "HPV" (3) | version (1) | kind (1) | flags (1) |
+/// expiry as big-endian Unix seconds (8, only with the expiry flag) |
+/// [`Manifest`] (only with the manifest flag) |
+/// [`Compression`] ID (1, only with the compression flag) | body.
+/// Encrypted kinds have no flags here; their fields are sealed with [`seal_fields`].
+pub fn wrap(header: &Header, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_LEN + 8 + body.len());
+    data.extend(MAGIC);
+    data.push(VERSION);
+    data.push(header.kind.id());
+    let mut flags = 0;
+    if header.expires.is_some() {
+        flags |= FLAG_EXPIRES;
+    }
+    if header.manifest.is_some() {
+        flags |= FLAG_MANIFEST;
+    }
+    if header.compression.is_some() {
+        flags |= FLAG_COMPRESSED;
+    }
+    data.push(flags);
+    if let Some(expires) = header.expires {
+        data.extend(expires.to_be_bytes());
+    }
+    if let Some(manifest) = &header.manifest {
+        manifest.write(&mut data);
+    }
+    if let Some(compression) = header.compression {
+        data.push(compression.id());
+    }
+    data.extend(body);
+    data
+}
+
+/// Splits a chunk into its payload header and body.
+///
+/// Chunks without the magic hold a plain message, as written before the header existed,
+/// and are reported as version 0.
+pub fn unwrap(data: &[u8]) -> Result<(Header, &[u8])> {
+    if !data.starts_with(MAGIC) {
+        let header = Header {
+            version: 0,
+            ..Header::new(Kind::Plain)
+        };
+        return Ok((header, data));
+    }
+    if data.len() < HEADER_LEN {
+        return Err("Payload header is truncated".into());
+    }
+    let kind = Kind::from_id(data[MAGIC.len() + 1])?;
+    match data[MAGIC.len()] {
+        VERSION => unwrap_v2(kind, data),
+        version => Err(format!(
+            "Payload format version {} is not supported (this build reads version {}); try a newer release",
+            version, VERSION
+        )
+        .into()),
+    }
+}
+
+fn unwrap_v2(kind: Kind, data: &[u8]) -> Result<(Header, &[u8])> {
+    let flags = data[HEADER_LEN - 1];
+    if flags & !(FLAG_EXPIRES | FLAG_MANIFEST | FLAG_COMPRESSED) != 0 {
+        return Err(format!("Unknown payload header flags {:#04x}", flags).into());
+    }
+    if kind.is_encrypted() && flags != 0 {
+        return Err("Encrypted payloads keep their header fields inside the ciphertext".into());
+    }
+
+    let mut rest = &data[HEADER_LEN..];
+    let mut expires = None;
+    if flags & FLAG_EXPIRES != 0 {
+        let (timestamp, body) = rest
+            .split_at_checked(8)
+            .ok_or("Payload header is truncated")?;
+        expires = Some(u64::from_be_bytes(timestamp.try_into()?));
+        rest = body;
+    }
+    let mut manifest = None;
+    if flags & FLAG_MANIFEST != 0 {
+        let (parsed, body) = Manifest::read(rest)?;
+        manifest = Some(parsed);
+        rest = body;
+    }
+    let mut compression = None;
+    if flags & FLAG_COMPRESSED != 0 {
+        let (&id, body) = rest.split_first().ok_or("Payload header is truncated")?;
+        compression = Some(Compression::from_id(id)?);
+        rest = body;
+    }
+
+    let header = Header {
+        version: VERSION,
+        kind,
+        expires,
+        manifest,
+        compression,
+    };
+    Ok((header, rest))
+}
+
+/// The plaintext an encrypted payload seals: the optional fields of `header` followed by
+/// `message`, laid out as a plain payload, so that nothing but the kind and the cipher
+/// shows outside the ciphertext
+pub fn seal_fields(header: &Header, message: &[u8]) -> Vec<u8> {
+    let fields = Header {
+        kind: Kind::Plain,
+        ..header.clone()
+    };
+    wrap(&fields, message)
+}
+
+/// Splits the decrypted plaintext of an encrypted payload into the header fields sealed
+/// by [`seal_fields`] and the message
+pub fn open_fields(plaintext: &[u8]) -> Result<(Header, &[u8])> {
+    match unwrap(plaintext)? {
+        (fields, message) if fields.version == VERSION && fields.kind == Kind::Plain => {
+            Ok((fields, message))
+        }
+        _ => Err("The decrypted payload has no header".into()),
+    }
+}
+
+/// Splits `data` into wrapped `Fragment` payloads of at most `max_chunk_size` bytes each.
+///
+/// Fragment body: index (2) | fragment count (2) | piece of `data`.
+pub fn fragment(data: &[u8], max_chunk_size: usize) -> Result<Vec<Vec<u8>>> {
+    if max_chunk_size < MIN_FRAGMENT_CHUNK {
+        return Err(format!(
+            "Chunk size {} is too small, use at least {} bytes",
+            max_chunk_size, MIN_FRAGMENT_CHUNK
+        )
+        .into());
+    }
+    let pieces: Vec<&[u8]> = data
+        .chunks(max_chunk_size - HEADER_LEN - FRAGMENT_INDEX_LEN)
+        .collect();
+    if pieces.len() > u16::MAX as usize {
+        return Err("The message needs more than 65535 chunks; raise --max-chunk-size".into());
+    }
+    Ok(number_pieces(Kind::Fragment, &pieces))
+}
+
+/// Splits `data` into `count` wrapped `Shard` payloads of near-equal size, one per cover
+/// image. The body layout matches [`fragment`].
+pub fn shard(data: &[u8], count: usize) -> Result<Vec<Vec<u8>>> {
+    if !(2..=u16::MAX as usize).contains(&count) {
+        return Err(format!("Cannot shard a message over {} images", count).into());
+    }
+    let pieces: Vec<&[u8]> = (0..count)
+        .map(|i| &data[i * data.len() / count..(i + 1) * data.len() / count])
+        .collect();
+    Ok(number_pieces(Kind::Shard, &pieces))
+}
+
+fn number_pieces(kind: Kind, pieces: &[&[u8]]) -> Vec<Vec<u8>> {
+    let count = pieces.len() as u16;
+    pieces
+        .iter()
+        .enumerate()
+        .map(|(index, piece)| {
+            let mut body = Vec::with_capacity(FRAGMENT_INDEX_LEN + piece.len());
+            body.extend((index as u16).to_be_bytes());
+            body.extend(count.to_be_bytes());
+            body.extend(*piece);
+            wrap(&Header::new(kind), &body)
+        })
+        .collect()
+}
+
+/// Zero-based index and total piece count from a `Fragment` or `Shard` body
+pub fn piece_position(body: &[u8]) -> Result<(u16, u16)> {
+    let index = body
+        .get(..FRAGMENT_INDEX_LEN)
+        .ok_or("Fragment is truncated")?;
+    Ok((
+        u16::from_be_bytes([index[0], index[1]]),
+        u16::from_be_bytes([index[2], index[3]]),
+    ))
+}
+
+/// Reassembles the payload from the bodies of all its `Fragment` (or all its `Shard`)
+/// payloads, in any order
+pub fn reassemble(fragments: &[&[u8]]) -> Result<Vec<u8>> {
+    let mut pieces: Vec<(u16, u16, &[u8])> = fragments
+        .iter()
+        .map(|body| {
+            let (index, count) = piece_position(body)?;
+            Ok((index, count, &body[FRAGMENT_INDEX_LEN..]))
+        })
+        .collect::<Result<_>>()?;
+    pieces.sort_by_key(|&(index, _, _)| index);
+
+    let count = pieces.first().ok_or("No fragments given")?.1;
+    if pieces.len() != count as usize
+        || pieces
+            .iter()
+            .enumerate()
+            .any(|(i, &(index, total, _))| index as usize != i || total != count)
+    {
+        return Err(format!(
+            "Found {} of the {} pieces of the message; some are missing or duplicated",
+            pieces.len(),
+            count
+        )
+        .into());
+    }
+    Ok(pieces
+        .iter()
+        .flat_map(|&(_, _, piece)| piece)
+        .copied()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_unwrap_round_trip() {
+        for kind in [
+            Kind::Plain,
+            Kind::Password,
+            Kind::Age,
+            Kind::OpenPgp,
+            Kind::Hardware,
+            Kind::Share,
+            Kind::Fragment,
+            Kind::Shard,
+            Kind::Fec,
+        ] {
+            let data = wrap(&Header::new(kind), b"body");
+            assert_eq!(&data[..4], b"HPV\x02");
+            assert_eq!(
+                unwrap(&data).unwrap(),
+                (Header::new(kind), b"body".as_slice())
+            );
+        }
+    }
+
+    #[test]
+    fn test_expiry_round_trip() {
+        let header = Header {
+            expires: Some(1_767_225_599),
+            ..Header::new(Kind::Plain)
+        };
+        let data = wrap(&header, b"body");
+        let (parsed, body) = unwrap(&data).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(body, b"body");
+        assert!(!parsed.is_expired(1_767_225_599));
+        assert!(parsed.is_expired(1_767_225_600));
+        assert!(!Header::new(Kind::Plain).is_expired(u64::MAX));
+        assert!(unwrap(&data[..10]).is_err());
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let header = Header {
+            expires: Some(1_767_225_599),
+            manifest: Some(Manifest {
+                name: "résumé.pdf".to_string(),
+                mime_type: "application/pdf".to_string(),
+                size: 4,
+                created: Some(1_700_000_000),
+            }),
+            ..Header::new(Kind::Plain)
+        };
+        let data = wrap(&header, b"body");
+        let (parsed, body) = unwrap(&data).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(body, b"body");
+
+        let header = Header {
+            manifest: Some(Manifest {
+                name: "x".repeat(300),
+                mime_type: String::new(),
+                size: 0,
+                created: None,
+            }),
+            ..Header::new(Kind::Plain)
+        };
+        let data = wrap(&header, b"");
+        let (parsed, body) = unwrap(&data).unwrap();
+        let manifest = parsed.manifest.unwrap();
+        assert_eq!(manifest.name.len(), 255);
+        assert_eq!(manifest.created, None);
+        assert!(body.is_empty());
+        assert!(unwrap(b"HPV\x02\x00\x02\x05ab").is_err());
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        let message = b"a message that repeats, a message that repeats".repeat(5);
+        for compression in [Compression::Zstd, Compression::Gzip] {
+            let header = Header {
+                expires: Some(1_767_225_599),
+                compression: Some(compression),
+                ..Header::new(Kind::Plain)
+            };
+            let data = wrap(&header, &compression.compress(&message).unwrap());
+            assert!(data.len() < message.len() / 2);
+            let (parsed, body) = unwrap(&data).unwrap();
+            assert_eq!(parsed, header);
+            assert_eq!(compression.decompress(body).unwrap(), message);
+        }
+        assert!(unwrap(b"HPV\x02\x00\x04\x09body").is_err());
+        assert!(Compression::Zstd.decompress(b"not zstd").is_err());
+        assert!(unwrap(b"HPV\x02\x00\x04").is_err());
+    }
+
+    #[test]
+    fn test_sealed_fields_round_trip() {
+        let header = Header {
+            expires: Some(1_767_225_599),
+            manifest: Some(Manifest {
+                name: "notes.txt".to_string(),
+                mime_type: "text/plain".to_string(),
+                size: 7,
+                created: None,
+            }),
+            compression: Some(Compression::Gzip),
+            ..Header::new(Kind::Password)
+        };
+        let plaintext = seal_fields(&header, b"message");
+        let (fields, message) = open_fields(&plaintext).unwrap();
+        assert_eq!(fields.kind, Kind::Plain);
+        assert_eq!(
+            (fields.expires, fields.manifest, fields.compression),
+            (header.expires, header.manifest.clone(), header.compression)
+        );
+        assert_eq!(message, b"message");
+        assert!(open_fields(b"message").is_err());
+
+        // Outside the ciphertext an encrypted payload carries no fields
+        assert!(unwrap(&wrap(&header, b"ciphertext")).is_err());
+        let (outer, _) = unwrap(&wrap(&Header::new(Kind::Password), b"ciphertext")).unwrap();
+        assert_eq!(outer, Header::new(Kind::Password));
+    }
+
+    #[test]
+    fn test_guess_mime_type() {
+        assert_eq!(guess_mime_type("notes.TXT"), "text/plain");
+        assert_eq!(guess_mime_type("scan.jpeg"), "image/jpeg");
+        assert_eq!(guess_mime_type("archive.tar.gz"), "application/gzip");
+        assert_eq!(guess_mime_type("README"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_fragment_reassemble() {
+        let data: Vec<u8> = (0..=255).collect();
+        let fragments = fragment(&data, 64).unwrap();
+        assert_eq!(fragments.len(), 5);
+        assert!(fragments.iter().all(|f| f.len() <= 64));
+
+        let mut bodies: Vec<&[u8]> = fragments
+            .iter()
+            .map(|f| {
+                let (header, body) = unwrap(f).unwrap();
+                assert_eq!(header.kind, Kind::Fragment);
+                body
+            })
+            .collect();
+        bodies.reverse();
+        assert_eq!(reassemble(&bodies).unwrap(), data);
+
+        assert!(reassemble(&bodies[1..]).is_err());
+        bodies[0] = bodies[1];
+        assert!(reassemble(&bodies).is_err());
+        assert!(fragment(&data, MIN_FRAGMENT_CHUNK - 1).is_err());
+    }
+
+    #[test]
+    fn test_shard_reassemble() {
+        let data = b"sharded over three images".to_vec();
+        let shards = shard(&data, 3).unwrap();
+        assert_eq!(shards.len(), 3);
+
+        let bodies: Vec<&[u8]> = shards
+            .iter()
+            .map(|s| {
+                let (header, body) = unwrap(s).unwrap();
+                assert_eq!(header.kind, Kind::Shard);
+                body
+            })
+            .collect();
+        assert_eq!(piece_position(bodies[2]).unwrap(), (2, 3));
+        assert_eq!(
+            reassemble(&[bodies[1], bodies[2], bodies[0]]).unwrap(),
+            data
+        );
+        assert!(reassemble(&bodies[..2]).is_err());
+
+        // More images than bytes still gives every image a shard
+        assert_eq!(shard(b"ab", 4).unwrap().len(), 4);
+        assert!(shard(&data, 1).is_err());
+    }
+
+    #[test]
+    fn test_unwrap_rejects_unknown_version_kind_and_flags() {
+        assert!(unwrap(b"HPV\x03\x00body").is_err());
+        assert!(unwrap(b"HPV\x02\xff\x00body").is_err());
+        assert!(unwrap(b"HPV\x02\x00\x80body").is_err());
+    }
+
+    #[test]
+    fn test_unwrap_reads_headerless_chunks_as_plain() {
+        let (header, body) = unwrap(b"hello").unwrap();
+        assert_eq!((header.version, header.kind), (0, Kind::Plain));
+        assert_eq!(body, b"hello");
+        assert_eq!(unwrap(b"\x00\x01").unwrap().0.kind, Kind::Plain);
+        assert_eq!(
+            unwrap(b"age-encryption.org/v1\n").unwrap().0.kind,
+            Kind::Plain
+        );
+        // Version 1 and payloads cut off inside the header are not read
+        assert!(unwrap(b"HPV\x01\x01body").is_err());
+        assert!(unwrap(b"HPV\x02\x01").is_err());
+    }
+}
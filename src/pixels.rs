@@ -0,0 +1,839 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Result, zlib};
+use std::str::FromStr;
+
+/// File formats `extract-pixels` writes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PixelFormat {
+    /// Unfiltered scanlines back to back, samples as stored in the PNG
+    #[default]
+    Raw,
+    /// Netpbm image: PGM for grayscale, PPM for everything else
+    Ppm,
+    /// A PNG with freshly compressed image data and no metadata or hidden chunks
+    Png,
+}
+
+impl PixelFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Raw => "raw",
+            PixelFormat::Ppm => "ppm",
+            PixelFormat::Png => "png",
+        }
+    }
+}
+
+/// Largest width or height the PNG specification allows
+const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+/// Start column, start row, column step and row step of the seven Adam7 passes
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Image properties from the IHDR chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub interlaced: bool,
+}
+
+impl Ihdr {
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let data = png
+            .chunk_by_type("IHDR")
+            .ok_or("PNG has no IHDR chunk")?
+            .data();
+        if data.len() != 13 {
+            return Err("IHDR chunk has an invalid length".into());
+        }
+        let ihdr = Ihdr {
+            width: u32::from_be_bytes(data[0..4].try_into()?),
+            height: u32::from_be_bytes(data[4..8].try_into()?),
+            bit_depth: data[8],
+            color_type: data[9],
+            interlaced: data[12] == 1,
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+
+    /// Checks the dimensions and the combination of color type and bit depth
+    fn validate(&self) -> Result<()> {
+        let ihdr = self;
+        if ihdr.width == 0 || ihdr.height == 0 {
+            return Err("IHDR declares an empty image".into());
+        }
+        if ihdr.width > MAX_DIMENSION || ihdr.height > MAX_DIMENSION {
+            return Err(format!(
+                "IHDR declares a {}x{} image, beyond the PNG limit of {} pixels per side",
+                ihdr.width, ihdr.height, MAX_DIMENSION
+            )
+            .into());
+        }
+        let valid_depths: &[u8] = match ihdr.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(format!("Unknown PNG color type {}", ihdr.color_type).into()),
+        };
+        if !valid_depths.contains(&ihdr.bit_depth) {
+            return Err(format!(
+                "Invalid bit depth {} for color type {}",
+                ihdr.bit_depth, ihdr.color_type
+            )
+            .into());
+        }
+        ihdr.filtered_size()?;
+        Ok(())
+    }
+
+    /// The IHDR chunk declaring these properties, with the only defined compression
+    /// and filter methods
+    fn to_chunk(self) -> Result<Chunk> {
+        let mut data = Vec::with_capacity(13);
+        data.extend(self.width.to_be_bytes());
+        data.extend(self.height.to_be_bytes());
+        data.extend([self.bit_depth, self.color_type, 0, 0, self.interlaced as u8]);
+        Ok(Chunk::new(ChunkType::from_str("IHDR")?, data))
+    }
+
+    /// Samples per pixel: gray, RGB, palette index, gray + alpha, RGBA
+    pub fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self.color_type, 4 | 6)
+    }
+
+    /// Number of color samples (alpha excluded) that can carry pixel-domain data.
+    /// Only non-interlaced 8- and 16-bit images without a palette qualify.
+    pub fn color_samples(&self) -> Result<usize> {
+        if self.color_type == 3 {
+            return Err("Palette images cannot carry pixel-domain data".into());
+        }
+        if self.bit_depth < 8 {
+            return Err(format!(
+                "{}-bit images cannot carry pixel-domain data; use an 8- or 16-bit PNG",
+                self.bit_depth
+            )
+            .into());
+        }
+        if self.interlaced {
+            return Err("Interlaced (Adam7) PNGs are not supported for pixel access".into());
+        }
+        let color_channels = self.channels() - self.has_alpha() as usize;
+        self.pixel_count()?
+            .checked_mul(color_channels)
+            .ok_or_else(|| self.too_large())
+    }
+
+    /// Number of pixels, width times height
+    pub fn pixel_count(&self) -> Result<usize> {
+        (self.width as usize)
+            .checked_mul(self.height as usize)
+            .ok_or_else(|| self.too_large())
+    }
+
+    /// Short description such as "8-bit RGBA" for status output
+    pub fn describe(&self) -> String {
+        let interlace = if self.interlaced { ", interlaced" } else { "" };
+        format!("{}-bit {}{}", self.bit_depth, self.color_name(), interlace)
+    }
+
+    pub fn color_name(&self) -> &'static str {
+        match self.color_type {
+            0 => "grayscale",
+            2 => "RGB",
+            3 => "palette",
+            4 => "grayscale + alpha",
+            _ => "RGBA",
+        }
+    }
+
+    /// Bytes per complete pixel, rounded up to 1 as the filters require
+    fn filter_unit(&self) -> usize {
+        (self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// Bytes per scanline, excluding the filter type byte
+    fn stride(&self) -> Result<usize> {
+        let bits = (self.width as usize)
+            .checked_mul(self.channels() * self.bit_depth as usize)
+            .ok_or_else(|| self.too_large())?;
+        Ok(bits.div_ceil(8))
+    }
+
+    /// Bytes of unfiltered image data: every scanline without its filter type byte
+    fn data_size(&self) -> Result<usize> {
+        self.stride()?
+            .checked_mul(self.height as usize)
+            .ok_or_else(|| self.too_large())
+    }
+
+    /// Bytes of filtered image data: every scanline after its filter type byte
+    fn filtered_size(&self) -> Result<usize> {
+        self.data_size()?
+            .checked_add(self.height as usize)
+            .ok_or_else(|| self.too_large())
+    }
+
+    /// Bytes the image data stream inflates to, interlaced or not
+    pub fn inflated_size(&self) -> Result<usize> {
+        if !self.interlaced {
+            return self.filtered_size();
+        }
+        let mut size = 0usize;
+        for (x, y, dx, dy) in ADAM7_PASSES {
+            let pass = Ihdr {
+                width: self.width.saturating_sub(x).div_ceil(dx),
+                height: self.height.saturating_sub(y).div_ceil(dy),
+                ..*self
+            };
+            if pass.width == 0 || pass.height == 0 {
+                continue;
+            }
+            size = size
+                .checked_add(pass.filtered_size()?)
+                .ok_or_else(|| self.too_large())?;
+        }
+        Ok(size)
+    }
+
+    fn too_large(&self) -> crate::Error {
+        format!(
+            "A {}x{} {} image is too large to process",
+            self.width,
+            self.height,
+            self.describe()
+        )
+        .into()
+    }
+}
+
+/// The zlib stream split across the IDAT chunks of `png`
+pub fn idat_data(png: &Png) -> Result<Vec<u8>> {
+    let compressed: Vec<u8> = png
+        .chunks_by_type("IDAT")
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect();
+    if compressed.is_empty() {
+        return Err("PNG has no IDAT chunk".into());
+    }
+    Ok(compressed)
+}
+
+/// Returns `png` with its IDAT chunks replaced by one holding `compressed`
+pub fn replace_idat(mut png: Png, compressed: Vec<u8>) -> Result<Png> {
+    let mut idat = Some(Chunk::new(ChunkType::from_str("IDAT")?, compressed));
+    let chunks = png.chunks_mut();
+    for chunk in std::mem::take(chunks) {
+        if chunk.chunk_type().to_string() != "IDAT" {
+            chunks.push(chunk);
+        } else if let Some(idat) = idat.take() {
+            // The first IDAT is replaced, the rest dropped
+            chunks.push(idat);
+        }
+    }
+    Ok(png)
+}
+
+/// Returns `png` with its image data compressed by [`zlib::deflate_best`], with
+/// `refilter` after choosing new scanline filters. Pixels and a message hidden in the
+/// compressed data by `encode --idat` stay the same.
+pub fn recompress(png: Png, refilter: bool) -> Result<Png> {
+    let compressed = idat_data(&png)?;
+    let hidden = zlib::read_hidden(&compressed)?;
+    let raw = if refilter {
+        let mut pixels = Pixels::from_png(&png)?;
+        pixels.choose_filters();
+        pixels.filtered()
+    } else {
+        let size = Ihdr::from_png(&png)?.inflated_size()?;
+        zlib::inflate(&compressed, size).map_err(|e| format!("Corrupt image data: {}", e))?
+    };
+    replace_idat(png, zlib::deflate_best(&raw, &hidden))
+}
+
+/// Creates a minimal PNG (IHDR, IDAT and IEND) from scratch, see [`Png::builder`]
+pub struct PngBuilder {
+    ihdr: Ihdr,
+    data: Option<Vec<u8>>,
+}
+
+impl PngBuilder {
+    /// An 8-bit RGB image of the given size
+    pub fn new(width: u32, height: u32) -> Self {
+        PngBuilder {
+            ihdr: Ihdr {
+                width,
+                height,
+                bit_depth: 8,
+                color_type: 2,
+                interlaced: false,
+            },
+            data: None,
+        }
+    }
+
+    pub fn color_type(mut self, color_type: u8, bit_depth: u8) -> Self {
+        self.ihdr.color_type = color_type;
+        self.ihdr.bit_depth = bit_depth;
+        self
+    }
+
+    /// Unfiltered scanlines back to back, as `extract-pixels` writes them, with 16-bit
+    /// samples big-endian. Without them every sample is zero: black, or transparent
+    /// with alpha.
+    pub fn pixels(mut self, data: Vec<u8>) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn build(self) -> Result<Png> {
+        let ihdr = self.ihdr;
+        ihdr.validate()?;
+        if ihdr.color_type == 3 {
+            return Err(
+                "Palette images need a PLTE chunk, which the builder does not write".into(),
+            );
+        }
+        let data = match self.data {
+            Some(data) => data,
+            None => vec![0; ihdr.data_size()?],
+        };
+
+        let png = Png::from_chunks(vec![
+            ihdr.to_chunk()?,
+            Chunk::new(ChunkType::from_str("IDAT")?, vec![]),
+            Chunk::new(ChunkType::from_str("IEND")?, vec![]),
+        ]);
+        let mut pixels = Pixels::from_raw(ihdr, data)?;
+        pixels.choose_filters();
+        pixels.to_png(png)
+    }
+}
+
+/// Unfiltered image data of a non-interlaced PNG, ready for sample-level edits
+pub struct Pixels {
+    pub ihdr: Ihdr,
+    /// Bytes per scanline, excluding the filter type byte
+    stride: usize,
+    /// Filter type of each scanline, reused when writing so the file size stays similar
+    filters: Vec<u8>,
+    /// Scanlines back to back, without their filter type bytes
+    data: Vec<u8>,
+}
+
+impl Pixels {
+    pub fn from_png(png: &Png) -> Result<Self> {
+        let ihdr = Ihdr::from_png(png)?;
+        if ihdr.interlaced {
+            return Err("Interlaced (Adam7) PNGs are not supported for pixel access".into());
+        }
+
+        let raw = zlib::inflate(&idat_data(png)?, ihdr.filtered_size()?)
+            .map_err(|e| format!("Corrupt image data: {}", e))?;
+
+        let stride = ihdr.stride()?;
+        let height = ihdr.height as usize;
+        if raw.len() < ihdr.filtered_size()? {
+            return Err("Image data is shorter than IHDR declares".into());
+        }
+
+        let unit = ihdr.filter_unit();
+        let mut filters = Vec::with_capacity(height);
+        let mut data = vec![0u8; ihdr.data_size()?];
+        for (row, line) in raw.chunks_exact(stride + 1).take(height).enumerate() {
+            let (done, rest) = data.split_at_mut(row * stride);
+            let previous = done.get(done.len().saturating_sub(stride)..).unwrap_or(&[]);
+            let current = &mut rest[..stride];
+            current.copy_from_slice(&line[1..]);
+            unfilter(line[0], current, previous, unit)?;
+            filters.push(line[0]);
+        }
+
+        Ok(Pixels {
+            ihdr,
+            stride,
+            filters,
+            data,
+        })
+    }
+
+    /// Pixels of another format laid out like unfiltered PNG scanlines for `ihdr`
+    pub fn from_raw(ihdr: Ihdr, data: Vec<u8>) -> Result<Self> {
+        ihdr.validate()?;
+        let size = ihdr.data_size()?;
+        if data.len() != size {
+            return Err(format!(
+                "A {}x{} {} image has {} bytes of pixel data, not {}",
+                ihdr.width,
+                ihdr.height,
+                ihdr.describe(),
+                size,
+                data.len()
+            )
+            .into());
+        }
+        Ok(Pixels {
+            stride: ihdr.stride()?,
+            filters: vec![0; ihdr.height as usize],
+            ihdr,
+            data,
+        })
+    }
+
+    /// Every pixel as 8-bit RGBA, row by row. Palette indices are looked up in
+    /// `palette` (the PLTE chunk data), 16-bit samples keep their high byte and
+    /// samples below 8 bits are scaled up.
+    pub fn to_rgba(&self, palette: Option<&[u8]>) -> Result<Vec<[u8; 4]>> {
+        let ihdr = &self.ihdr;
+        let depth = ihdr.bit_depth as usize;
+        let channels = ihdr.channels();
+        let max = (1u16 << depth.min(8)) - 1;
+        let mut rgba = Vec::with_capacity(ihdr.pixel_count()?);
+        for line in self.data.chunks_exact(self.stride) {
+            for x in 0..ihdr.width as usize {
+                let sample = |channel: usize| {
+                    let index = x * channels + channel;
+                    match depth {
+                        16 => line[index * 2],
+                        8 => line[index],
+                        _ => {
+                            let bit = index * depth;
+                            (line[bit / 8] >> (8 - depth - bit % 8)) & max as u8
+                        }
+                    }
+                };
+                let scaled = |value: u8| (value as u16 * 255 / max) as u8;
+                rgba.push(match ihdr.color_type {
+                    0 => {
+                        let gray = scaled(sample(0));
+                        [gray, gray, gray, 255]
+                    }
+                    2 => [sample(0), sample(1), sample(2), 255],
+                    3 => {
+                        let index = sample(0) as usize;
+                        let color = palette
+                            .ok_or("Palette image has no PLTE chunk")?
+                            .get(index * 3..index * 3 + 3)
+                            .ok_or_else(|| format!("Palette index {} is out of range", index))?;
+                        [color[0], color[1], color[2], 255]
+                    }
+                    4 => [sample(0), sample(0), sample(0), sample(1)],
+                    _ => [sample(0), sample(1), sample(2), sample(3)],
+                });
+            }
+        }
+        Ok(rgba)
+    }
+
+    /// The pixels as a binary Netpbm file. 8- and 16-bit grayscale and RGB images keep
+    /// their samples as they are; other images become 8-bit RGB, dropping alpha.
+    pub fn to_ppm(&self, palette: Option<&[u8]>) -> Result<Vec<u8>> {
+        let ihdr = &self.ihdr;
+        let max = (1u32 << ihdr.bit_depth) - 1;
+        let (magic, max, samples) = match (ihdr.color_type, ihdr.bit_depth) {
+            (0, 8 | 16) => ("P5", max, self.data.clone()),
+            (2, _) => ("P6", max, self.data.clone()),
+            _ => {
+                let rgb = self.to_rgba(palette)?.into_iter();
+                ("P6", 255, rgb.flat_map(|[r, g, b, _]| [r, g, b]).collect())
+            }
+        };
+        let mut ppm = format!("{}\n{} {}\n{}\n", magic, ihdr.width, ihdr.height, max).into_bytes();
+        ppm.extend(samples);
+        Ok(ppm)
+    }
+
+    /// Scanlines back to back, for writing into formats other than PNG
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns `png` with its IDAT chunks replaced by one holding the current pixels
+    pub fn to_png(&self, png: Png) -> Result<Png> {
+        replace_idat(png, zlib::deflate(&self.filtered()))
+    }
+
+    /// Scanlines filtered with their filter types, each after its filter type byte
+    fn filtered(&self) -> Vec<u8> {
+        let stride = self.stride;
+        let unit = self.ihdr.filter_unit();
+        let mut raw = Vec::with_capacity((stride + 1) * self.filters.len());
+        for (row, &filter) in self.filters.iter().enumerate() {
+            let current = &self.data[row * stride..(row + 1) * stride];
+            let previous = if row == 0 {
+                &[][..]
+            } else {
+                &self.data[(row - 1) * stride..row * stride]
+            };
+            raw.push(filter);
+            raw.extend(apply_filter(filter, current, previous, unit));
+        }
+        raw
+    }
+
+    /// Picks the filter type of every scanline with the heuristic the PNG specification
+    /// suggests: none for palette and sub-byte images, otherwise the filter whose output
+    /// has the smallest sum of absolute (signed) byte values
+    pub fn choose_filters(&mut self) {
+        if self.ihdr.color_type == 3 || self.ihdr.bit_depth < 8 {
+            self.filters.fill(0);
+            return;
+        }
+        let stride = self.stride;
+        let unit = self.ihdr.filter_unit();
+        for row in 0..self.filters.len() {
+            let current = &self.data[row * stride..(row + 1) * stride];
+            let previous = if row == 0 {
+                &[][..]
+            } else {
+                &self.data[(row - 1) * stride..row * stride]
+            };
+            self.filters[row] = (0..=4)
+                .min_by_key(|&filter| {
+                    apply_filter(filter, current, previous, unit)
+                        .iter()
+                        .map(|&byte| (byte as i8).unsigned_abs() as u32)
+                        .sum::<u32>()
+                })
+                .unwrap();
+        }
+    }
+
+    /// Byte offsets of the least significant byte of every color sample (alpha excluded),
+    /// in scanline order
+    pub fn sample_offsets(&self) -> Result<Vec<usize>> {
+        let ihdr = &self.ihdr;
+        ihdr.color_samples()?;
+
+        let sample_bytes = ihdr.bit_depth as usize / 8;
+        let channels = ihdr.channels();
+        let color_channels = channels - ihdr.has_alpha() as usize;
+        Ok((0..ihdr.pixel_count()?)
+            .flat_map(|pixel| {
+                (0..color_channels).map(move |channel| {
+                    // Big-endian samples: the last byte holds the low bits
+                    (pixel * channels + channel + 1) * sample_bytes - 1
+                })
+            })
+            .collect())
+    }
+
+    pub fn byte(&self, offset: usize) -> u8 {
+        self.data[offset]
+    }
+
+    pub fn set_byte(&mut self, offset: usize, value: u8) {
+        self.data[offset] = value;
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Predictor for byte `i` of a scanline from the already reconstructed bytes
+fn predict(filter: u8, line: &[u8], previous: &[u8], i: usize, unit: usize) -> u8 {
+    let left = if i >= unit { line[i - unit] } else { 0 };
+    let up = previous.get(i).copied().unwrap_or(0);
+    let up_left = if i >= unit {
+        previous.get(i - unit).copied().unwrap_or(0)
+    } else {
+        0
+    };
+    match filter {
+        1 => left,
+        2 => up,
+        3 => ((left as u16 + up as u16) / 2) as u8,
+        4 => paeth(left, up, up_left),
+        _ => 0,
+    }
+}
+
+/// Reverses a scanline filter in place; `previous` is empty for the first row
+fn unfilter(filter: u8, line: &mut [u8], previous: &[u8], unit: usize) -> Result<()> {
+    if filter > 4 {
+        return Err(format!("Unknown PNG filter type {}", filter).into());
+    }
+    for i in 0..line.len() {
+        line[i] = line[i].wrapping_add(predict(filter, line, previous, i, unit));
+    }
+    Ok(())
+}
+
+fn apply_filter(filter: u8, line: &[u8], previous: &[u8], unit: usize) -> Vec<u8> {
+    (0..line.len())
+        .map(|i| line[i].wrapping_sub(predict(filter, line, previous, i, unit)))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::idat;
+
+    /// Builds a PNG whose rows use every filter type
+    pub fn test_png(width: u32, height: u32, color_type: u8, bit_depth: u8) -> Png {
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([bit_depth, color_type, 0, 0, 0]);
+        let info = Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+
+        let stride = info.stride().unwrap();
+        let mut raw = Vec::new();
+        for row in 0..height as usize {
+            raw.push((row % 5) as u8);
+            raw.extend((0..stride).map(|i| (i * 31 + row * 17) as u8));
+        }
+
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), zlib::deflate(&raw)),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_filters_round_trip() {
+        let previous: Vec<u8> = (0..12).map(|i| i * 20).collect();
+        let line: Vec<u8> = (0..12).map(|i| 255 - i * 7).collect();
+        for filter in 0..=4 {
+            let mut filtered = apply_filter(filter, &line, &previous, 3);
+            unfilter(filter, &mut filtered, &previous, 3).unwrap();
+            assert_eq!(filtered, line, "filter {}", filter);
+        }
+        assert!(unfilter(5, &mut [0], &[], 1).is_err());
+    }
+
+    #[test]
+    fn test_pixels_round_trip() {
+        let png = test_png(7, 5, 6, 8);
+        let mut pixels = Pixels::from_png(&png).unwrap();
+        let offsets = pixels.sample_offsets().unwrap();
+        assert_eq!(offsets.len(), 7 * 5 * 3);
+        // Alpha bytes are skipped
+        assert_eq!(&offsets[..4], [0, 1, 2, 4]);
+
+        pixels.set_byte(offsets[10], 0xab);
+        let written = pixels.to_png(png).unwrap();
+        let reread = Pixels::from_png(&written).unwrap();
+        assert_eq!(reread.data, pixels.data);
+        assert_eq!(reread.byte(offsets[10]), 0xab);
+        assert_eq!(written.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_builder() {
+        let png = Png::builder(3, 2).build().unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+        let pixels = Pixels::from_png(&png).unwrap();
+        assert_eq!(pixels.ihdr.describe(), "8-bit RGB");
+        assert_eq!(pixels.data(), [0; 18]);
+        assert!(Png::try_from(png.as_bytes().as_slice()).is_ok());
+
+        let data: Vec<u8> = (0..32).collect();
+        let png = Png::builder(2, 2)
+            .color_type(6, 16)
+            .pixels(data.clone())
+            .build()
+            .unwrap();
+        let pixels = Pixels::from_png(&png).unwrap();
+        assert_eq!(pixels.ihdr.describe(), "16-bit RGBA");
+        assert_eq!(pixels.data(), data);
+
+        assert!(Png::builder(0, 1).build().is_err());
+        assert!(Png::builder(1, 1).color_type(2, 4).build().is_err());
+        assert!(Png::builder(1, 1).color_type(3, 8).build().is_err());
+        assert!(Png::builder(2, 2).pixels(vec![0; 11]).build().is_err());
+    }
+
+    #[test]
+    fn test_recompress_keeps_pixels() {
+        let png = test_png(64, 32, 2, 8);
+        let before = idat_data(&png).unwrap().len();
+        let pixels = Pixels::from_png(&png).unwrap();
+
+        let png = recompress(test_png(64, 32, 2, 8), false).unwrap();
+        assert!(idat_data(&png).unwrap().len() <= before);
+        assert_eq!(Pixels::from_png(&png).unwrap().data, pixels.data);
+
+        // The filter heuristic does not promise a smaller stream, only the same pixels
+        let png = recompress(test_png(64, 32, 2, 8), true).unwrap();
+        assert_eq!(Pixels::from_png(&png).unwrap().data, pixels.data);
+
+        let mut refiltered = Pixels::from_png(&test_png(4, 4, 3, 8)).unwrap();
+        refiltered.choose_filters();
+        assert_eq!(refiltered.filters, [0; 4]);
+    }
+
+    #[test]
+    fn test_recompress_keeps_hidden_data() {
+        let png = idat::embed(test_png(16, 16, 0, 8), "ruSt", b"hidden").unwrap();
+        let png = recompress(png, true).unwrap();
+        assert_eq!(idat::extract(&png, "ruSt").unwrap().unwrap(), b"hidden");
+    }
+
+    #[test]
+    fn test_sample_offsets_of_16_bit_gray() {
+        let pixels = Pixels::from_png(&test_png(3, 2, 0, 16)).unwrap();
+        assert_eq!(pixels.sample_offsets().unwrap(), [1, 3, 5, 7, 9, 11]);
+    }
+
+    #[test]
+    fn test_rejects_unsupported_images() {
+        let palette = Pixels::from_png(&test_png(4, 4, 3, 8)).unwrap();
+        assert!(palette.sample_offsets().is_err());
+        let low_depth = Pixels::from_png(&test_png(4, 4, 0, 4)).unwrap();
+        assert!(low_depth.sample_offsets().is_err());
+    }
+
+    #[test]
+    fn test_color_samples_and_description() {
+        let ihdr = Ihdr::from_png(&test_png(20, 10, 6, 16)).unwrap();
+        assert_eq!(ihdr.color_samples().unwrap(), 600);
+        assert_eq!(ihdr.describe(), "16-bit RGBA");
+
+        let interlaced = Ihdr {
+            interlaced: true,
+            ..ihdr
+        };
+        assert!(interlaced.color_samples().is_err());
+        assert_eq!(interlaced.describe(), "16-bit RGBA, interlaced");
+
+        let gray_alpha = Ihdr::from_png(&test_png(4, 4, 4, 8)).unwrap();
+        assert_eq!(gray_alpha.color_name(), "grayscale + alpha");
+    }
+
+    #[test]
+    fn test_rejects_oversized_dimensions() {
+        let huge = Ihdr {
+            width: u32::MAX,
+            height: u32::MAX,
+            bit_depth: 16,
+            color_type: 2,
+            interlaced: false,
+        };
+        assert!(huge.validate().is_err());
+        assert!(huge.color_samples().is_err());
+        assert!(Pixels::from_raw(huge, vec![]).is_err());
+
+        let mut ihdr = Vec::new();
+        ihdr.extend(u32::MAX.to_be_bytes());
+        ihdr.extend(u32::MAX.to_be_bytes());
+        ihdr.extend([16, 2, 0, 0, 0]);
+        let png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), zlib::deflate(&[0])),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]),
+        ]);
+        assert!(Ihdr::from_png(&png).is_err());
+        assert!(Pixels::from_png(&png).is_err());
+
+        let widest = Ihdr {
+            width: MAX_DIMENSION,
+            height: MAX_DIMENSION,
+            bit_depth: 8,
+            color_type: 0,
+            ..huge
+        };
+        assert!(widest.validate().is_ok());
+        assert!(widest.color_samples().is_ok());
+    }
+
+    #[test]
+    fn test_to_ppm() {
+        let gray = Pixels::from_raw(
+            Ihdr::from_png(&test_png(2, 1, 0, 16)).unwrap(),
+            vec![1, 2, 3, 4],
+        )
+        .unwrap();
+        assert_eq!(
+            gray.to_ppm(None).unwrap(),
+            b"P5\n2 1\n65535\n\x01\x02\x03\x04"
+        );
+
+        let rgba = Pixels::from_raw(
+            Ihdr::from_png(&test_png(1, 1, 6, 8)).unwrap(),
+            vec![10, 20, 30, 40],
+        )
+        .unwrap();
+        assert_eq!(rgba.to_ppm(None).unwrap(), b"P6\n1 1\n255\n\x0a\x14\x1e");
+    }
+
+    #[test]
+    fn test_to_rgba() {
+        let rgb = Pixels::from_raw(
+            Ihdr::from_png(&test_png(2, 1, 2, 16)).unwrap(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        )
+        .unwrap();
+        assert_eq!(
+            rgb.to_rgba(None).unwrap(),
+            [[1, 3, 5, 255], [7, 9, 11, 255]]
+        );
+
+        // 2-bit gray 0, 1, 2, 3 packed into one byte
+        let gray = Pixels::from_raw(
+            Ihdr::from_png(&test_png(4, 1, 0, 2)).unwrap(),
+            vec![0b00_01_10_11],
+        )
+        .unwrap();
+        let levels: Vec<u8> = gray.to_rgba(None).unwrap().iter().map(|p| p[0]).collect();
+        assert_eq!(levels, [0, 85, 170, 255]);
+
+        let indexed =
+            Pixels::from_raw(Ihdr::from_png(&test_png(2, 1, 3, 8)).unwrap(), vec![1, 0]).unwrap();
+        let palette = [10, 20, 30, 40, 50, 60];
+        assert_eq!(
+            indexed.to_rgba(Some(&palette)).unwrap(),
+            [[40, 50, 60, 255], [10, 20, 30, 255]]
+        );
+        assert!(indexed.to_rgba(Some(&palette[..3])).is_err());
+        assert!(indexed.to_rgba(None).is_err());
+    }
+}
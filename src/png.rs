@@ -1,46 +1,663 @@
 use crate::chunk::Chunk;
-use crate::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::exit::Failure;
+use crate::pixels::{Pixels, PngBuilder};
+use crate::{Error, Result, zlib};
 use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str::FromStr;
+
+/// Most bytes an iCCP profile is inflated to when describing it
+const MAX_ICC_PROFILE_LEN: usize = 16 << 20;
 
 pub struct Png {
     chunks: Vec<Chunk>,
+    /// Bytes after IEND, such as data appended by other tools; written back unchanged
+    trailing: Vec<u8>,
 }
 
 impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
+        Png::parse(value, true)
+    }
+}
+
+impl Png {
+    fn parse(value: &[u8], check_crc: bool) -> Result<Png> {
+        let mut reader = ChunkReader::open(value, check_crc)?;
+        // Files held in memory are taken as they are, even when built without IEND
+        reader.lenient_end = true;
+        let mut chunks = vec![];
+        while reader.next_header()?.is_some() {
+            chunks.push(reader.read_chunk()?);
+        }
+
+        let mut trailing = Vec::new();
+        reader.into_remainder()?.read_to_end(&mut trailing)?;
+        Ok(Png { chunks, trailing })
+    }
+
+    /// Reads only the chunks whose type `keep` accepts from `reader`, so memory use stays
+    /// at the size of the kept chunks. Other chunks are skipped after checking their CRCs,
+    /// except IDAT chunks: the image data is seeked past unread, so huge images cost no
+    /// time. Trailing data is skipped as well.
+    pub fn read_filtered(reader: impl Read + Seek, keep: impl Fn(&str) -> bool) -> Result<Png> {
+        let mut reader = ChunkReader::new(reader)?;
         let mut chunks = vec![];
-        let mut chunks_iter = value.iter();
-        let header_bytes: Vec<u8> = chunks_iter.by_ref().take(8).copied().collect();
+        while let Some(header) = reader.next_header()? {
+            let chunk_type = header.chunk_type.to_string();
+            if keep(&chunk_type) {
+                chunks.push(reader.read_chunk()?);
+            } else if chunk_type == "IDAT" {
+                reader.skip_chunk()?;
+            }
+        }
+        Ok(Png {
+            chunks,
+            trailing: Vec::new(),
+        })
+    }
+}
+
+/// Type and position of a chunk, read by [`ChunkReader`] ahead of its data
+pub struct ChunkHeader {
+    /// Where the chunk starts in the file
+    pub offset: u64,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+/// Reads the chunks of a PNG file one at a time from any `Read` source, so no more than
+/// one chunk is held in memory. Chunks whose data is not read are skipped, with their
+/// CRCs still checked unless the reader ignores them.
+pub struct ChunkReader<R> {
+    reader: R,
+    check_crc: bool,
+    /// Bytes consumed so far
+    offset: u64,
+    /// Type and length of the chunk whose data and CRC have not been consumed yet
+    pending: Option<([u8; 4], u32)>,
+    /// Set once IEND or the end of the input is reached
+    ended: bool,
+    /// Whether the input may end without IEND, keeping a cut-off header in `partial`
+    lenient_end: bool,
+    /// Start of a chunk header cut off by the end of the input
+    partial: Vec<u8>,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Checks the PNG signature at the start of `reader`
+    pub fn new(reader: R) -> Result<Self> {
+        Self::open(reader, true)
+    }
+
+    /// Like `new`, for damaged files whose chunk CRCs may not match their contents
+    pub fn ignoring_crc(reader: R) -> Result<Self> {
+        Self::open(reader, false)
+    }
+
+    fn open(mut reader: R, check_crc: bool) -> Result<Self> {
+        let mut signature = [0; 8];
+        let read = read_up_to(&mut reader, &mut signature)?;
+        if read < signature.len() || signature != Png::STANDARD_HEADER {
+            return Err(Failure::Parse.error("INVALID HEADER BYTES"));
+        }
+        Ok(ChunkReader {
+            reader,
+            check_crc,
+            offset: signature.len() as u64,
+            pending: None,
+            ended: false,
+            lenient_end: false,
+            partial: Vec::new(),
+        })
+    }
+
+    /// Header of the next chunk, skipping the data of the previous one if it was not
+    /// read; `None` after IEND. Input that ends before IEND is an error.
+    pub fn next_header(&mut self) -> Result<Option<ChunkHeader>> {
+        self.skip_pending()?;
+        if self.ended {
+            return Ok(None);
+        }
+        let mut header = [0; 8];
+        let read = read_up_to(&mut self.reader, &mut header)?;
+        if read < header.len() && !self.lenient_end {
+            return Err(Failure::Parse.error(if read == 0 {
+                "The file ends without an IEND chunk"
+            } else {
+                "A chunk header is cut off by the end of the file"
+            }));
+        }
+        if read < header.len() {
+            self.partial = header[..read].to_vec();
+            self.ended = true;
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes(header[..4].try_into()?);
+        let type_bytes: [u8; 4] = header[4..].try_into()?;
+        let chunk_type = ChunkType::try_from(type_bytes)?;
+        if length > Chunk::MAX_LENGTH {
+            return Err(format!(
+                "Chunk '{}' declares {} bytes, more than PNG allows",
+                chunk_type, length
+            )
+            .into());
+        }
+        let offset = self.offset;
+        self.offset += header.len() as u64;
+        self.pending = Some((type_bytes, length));
+        // The CRC covers the type, so a damaged type byte is reported as the CRC error it
+        // is before anyone shows the type
+        if self.check_crc && !type_bytes.iter().all(u8::is_ascii_alphabetic) {
+            self.skip_pending()?;
+            return Err(Failure::Parse.error(format!(
+                "Invalid chunk type '{}' at offset {}",
+                chunk_type, offset
+            )));
+        }
+        self.ended = &type_bytes == b"IEND";
+        Ok(Some(ChunkHeader {
+            offset,
+            length,
+            chunk_type,
+        }))
+    }
+
+    /// The chunk whose header was returned last, with its data
+    pub fn read_chunk(&mut self) -> Result<Chunk> {
+        let (type_bytes, length) = self.pending.take().ok_or("No chunk header to read")?;
+        // The declared length is not trusted with a large allocation up front
+        let mut data = Vec::with_capacity(length.min(1 << 20) as usize);
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        let mut crc = [0; 4];
+        if data.len() != length as usize || self.reader.read_exact(&mut crc).is_err() {
+            return Err(truncated(&type_bytes));
+        }
+        self.offset += length as u64 + 4;
+
+        Chunk::from_parts(
+            ChunkType::try_from(type_bytes)?,
+            data,
+            u32::from_be_bytes(crc),
+            self.check_crc,
+        )
+    }
+
+    /// Consumes the data and CRC of the chunk whose header was returned last, checking
+    /// the CRC on the way
+    fn skip_pending(&mut self) -> Result<()> {
+        let Some((type_bytes, length)) = self.pending.take() else {
+            return Ok(());
+        };
+        let mut digest = Chunk::digest();
+        digest.update(&type_bytes);
+        let mut buffer = [0; 8192];
+        let mut remaining = length as usize;
+        while remaining > 0 {
+            let piece = &mut buffer[..remaining.min(8192)];
+            self.reader
+                .read_exact(piece)
+                .map_err(|_| truncated(&type_bytes))?;
+            digest.update(piece);
+            remaining -= piece.len();
+        }
+        let mut crc = [0; 4];
+        self.reader
+            .read_exact(&mut crc)
+            .map_err(|_| truncated(&type_bytes))?;
+        self.offset += length as u64 + 4;
+
+        if self.check_crc && u32::from_be_bytes(crc) != digest.finalize() {
+            return Err(Error::from(Chunk::INVALID_CRC));
+        }
+        Ok(())
+    }
+
+    /// Whatever follows the chunks read so far, such as data appended after IEND
+    pub fn into_remainder(mut self) -> Result<impl Read> {
+        self.skip_pending()?;
+        Ok(io::Cursor::new(self.partial).chain(self.reader))
+    }
+}
+
+impl<R: Read + Seek> ChunkReader<R> {
+    /// Skips the chunk whose header was returned last by seeking past its data, without
+    /// reading it or checking its CRC
+    pub fn skip_chunk(&mut self) -> Result<()> {
+        if let Some((type_bytes, length)) = self.pending.take() {
+            let skipped = length as u64 + 4;
+            self.reader.seek_relative(skipped as i64)?;
+            // Seeking past the end succeeds, so a cut-off chunk only shows in the position
+            let position = self.reader.stream_position()?;
+            if position > self.reader.seek(SeekFrom::End(0))? {
+                return Err(truncated(&type_bytes));
+            }
+            self.reader.seek(SeekFrom::Start(position))?;
+            self.offset += skipped;
+        }
+        Ok(())
+    }
+}
+
+fn truncated(chunk_type: &[u8; 4]) -> Error {
+    format!(
+        "Chunk '{}' is cut off by the end of the file",
+        String::from_utf8_lossy(chunk_type)
+    )
+    .into()
+}
+
+/// Fills as much of `buffer` as `reader` has left, returning how much that was
+fn read_up_to(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Where [`Png::move_chunks`] puts chunks
+pub enum Placement {
+    /// Before the first chunk of this type
+    Before(String),
+    /// After the last chunk of this type
+    After(String),
+    /// At this index of the chunk list without the moved chunks
+    Index(usize),
+}
+
+/// Something wrong with a PNG file, found by [`check_integrity`]
+pub struct Problem {
+    /// Where in the file the problem is
+    pub offset: usize,
+    pub description: String,
+}
+
+/// What [`check_integrity`] found
+pub struct Integrity {
+    /// Number of complete chunks
+    pub chunks: usize,
+    pub problems: Vec<Problem>,
+    /// Number of bytes after IEND, which PNG decoders ignore
+    pub trailing: usize,
+}
+
+/// A chunk as stored in the file, before any checks
+struct RawChunk<'a> {
+    offset: usize,
+    chunk_type: [u8; 4],
+    data: &'a [u8],
+    crc: u32,
+}
+
+impl RawChunk<'_> {
+    fn name(&self) -> String {
+        String::from_utf8_lossy(&self.chunk_type).to_string()
+    }
+
+    fn has_valid_type(&self) -> bool {
+        self.chunk_type.iter().all(u8::is_ascii_alphabetic)
+    }
+}
+
+/// How far [`walk`] got through a file
+struct Walk<'a> {
+    /// Complete chunks up to and including IEND
+    chunks: Vec<RawChunk<'a>>,
+    /// Offset right after the last complete chunk
+    end: usize,
+    /// Why the last chunk could not be read, if the file ends in the middle of one
+    truncated: Option<String>,
+}
 
-        if Png::STANDARD_HEADER != header_bytes.as_slice() {
-            return Err(Error::from("INVALID HEADER BYTES"));
+/// Splits the PNG file `data` into chunks without checking them, stopping after IEND or
+/// at a chunk the file ends in the middle of
+fn walk(data: &[u8]) -> Walk<'_> {
+    let mut chunks = Vec::new();
+    let mut position = Png::STANDARD_HEADER.len();
+    let mut truncated = None;
+    while position < data.len() {
+        let Some(header) = data.get(position..position + 8) else {
+            truncated = Some(format!(
+                "Truncated chunk header ({} bytes left)",
+                data.len() - position
+            ));
+            break;
+        };
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let chunk_type = [header[4], header[5], header[6], header[7]];
+        let name = String::from_utf8_lossy(&chunk_type);
+        let Some(chunk_data) = data.get(position + 8..position + 8 + len) else {
+            truncated = Some(format!(
+                "Chunk '{}' declares {} bytes but only {} remain",
+                name,
+                len,
+                data.len() - position - 8
+            ));
+            break;
+        };
+        let Some(crc) = data.get(position + 8 + len..position + 12 + len) else {
+            truncated = Some(format!("Chunk '{}' is missing its CRC", name));
+            break;
+        };
+        chunks.push(RawChunk {
+            offset: position,
+            chunk_type,
+            data: chunk_data,
+            crc: u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]),
+        });
+        position += 12 + len;
+        if &chunk_type == b"IEND" {
+            break;
         }
+    }
+    Walk {
+        chunks,
+        end: position,
+        truncated,
+    }
+}
 
-        while chunks_iter.len() >= 12 {
-            let first4: [u8; 4] = chunks_iter
-                .clone()
-                .take(4)
-                .copied()
-                .collect::<Vec<u8>>()
-                .as_slice()
-                .try_into()?;
+/// Walks every chunk of the PNG file `data` without stopping at the first error,
+/// checking the file signature, each chunk's CRC and that IHDR comes first and IEND
+/// closes the file
+pub fn check_integrity(data: &[u8]) -> Integrity {
+    let mut problems = Vec::new();
+    let mut problem = |offset: usize, description: String| {
+        problems.push(Problem {
+            offset,
+            description,
+        })
+    };
+    if !data.starts_with(&Png::STANDARD_HEADER) {
+        problem(0, "File does not start with the PNG signature".to_string());
+    }
 
-            let length = u32::from_be_bytes(first4);
+    let walk = walk(data);
+    for (i, chunk) in walk.chunks.iter().enumerate() {
+        if !chunk.has_valid_type() {
+            problem(
+                chunk.offset,
+                format!("Invalid chunk type {:?}", chunk.name()),
+            );
+        }
+        if i == 0 && &chunk.chunk_type != b"IHDR" {
+            problem(
+                chunk.offset,
+                format!("First chunk is '{}' instead of IHDR", chunk.name()),
+            );
+        }
+        let computed = Chunk::checksum(&chunk.chunk_type, chunk.data);
+        if chunk.crc != computed {
+            problem(
+                chunk.offset,
+                format!(
+                    "CRC mismatch in chunk '{}': stored {:08x}, computed {:08x}",
+                    chunk.name(),
+                    chunk.crc,
+                    computed
+                ),
+            );
+        }
+    }
 
-            let chunk_bytes: Vec<u8> = chunks_iter
-                .by_ref()
-                .copied()
-                .take(length as usize + 12)
-                .collect();
+    let ended = walk
+        .chunks
+        .last()
+        .is_some_and(|chunk| &chunk.chunk_type == b"IEND");
+    match walk.truncated {
+        Some(truncated) => problem(walk.end, truncated),
+        None if !ended => problem(walk.end, "File does not end with an IEND chunk".to_string()),
+        None => {}
+    }
+    Integrity {
+        chunks: walk.chunks.len(),
+        problems,
+        trailing: if ended { data.len() - walk.end } else { 0 },
+    }
+}
 
-            let chunk: Chunk = Chunk::try_from(chunk_bytes.as_slice())?;
-            chunks.push(chunk);
+/// Fixes what can be fixed in the PNG file `data`: recomputes wrong CRCs, drops a
+/// truncated last chunk and everything from the first chunk with an invalid type on,
+/// and appends a missing IEND. Returns the repaired file with a description of each
+/// fix; data after IEND is kept as it is.
+pub fn repair(data: &[u8]) -> Result<(Vec<u8>, Vec<String>)> {
+    if !data.starts_with(&Png::STANDARD_HEADER) {
+        return Err("File does not start with the PNG signature, it cannot be repaired".into());
+    }
+    let walk = walk(data);
+    let mut fixes = Vec::new();
+    let mut repaired = Png::STANDARD_HEADER.to_vec();
+    let mut end = None;
+    let mut cut = false;
+    for chunk in &walk.chunks {
+        if !chunk.has_valid_type() {
+            fixes.push(format!(
+                "Dropped {} unreadable bytes from offset {}, starting with invalid chunk type {:?}",
+                data.len() - chunk.offset,
+                chunk.offset,
+                chunk.name()
+            ));
+            cut = true;
+            break;
+        }
+        let rebuilt = Chunk::new(ChunkType::try_from(chunk.chunk_type)?, chunk.data.to_vec());
+        if Chunk::checksum(&chunk.chunk_type, chunk.data) != chunk.crc {
+            fixes.push(format!(
+                "Recomputed the CRC of chunk '{}' at offset {}",
+                chunk.name(),
+                chunk.offset
+            ));
+        }
+        repaired.extend(rebuilt.as_bytes());
+        if &chunk.chunk_type == b"IEND" {
+            end = Some(walk.end);
         }
+    }
 
-        Ok(Png { chunks })
+    if let Some(truncated) = walk.truncated.filter(|_| !cut) {
+        fixes.push(format!(
+            "Dropped {} bytes from offset {}: {}",
+            data.len() - walk.end,
+            walk.end,
+            truncated
+        ));
+    }
+    match end {
+        Some(end) => repaired.extend(&data[end..]),
+        None => {
+            repaired.extend(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()).as_bytes());
+            fixes.push("Appended the missing IEND chunk".to_string());
+        }
     }
+    Ok((repaired, fixes))
+}
+
+/// Chunk types of animated PNGs (APNG)
+pub const ANIMATION_CHUNKS: [&str; 3] = ["acTL", "fcTL", "fdAT"];
+
+/// What an APNG chunk does, for `print`, such as "frame control: sequence 1, 32x32 at (0, 0)"
+pub fn describe_animation_chunk(chunk: &Chunk) -> Option<String> {
+    let data = chunk.data();
+    let u32_at = |offset: usize| {
+        Some(u32::from_be_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+    Some(match chunk.chunk_type().to_string().as_str() {
+        "acTL" => {
+            let plays = match u32_at(4)? {
+                0 => "loops forever".to_string(),
+                plays => format!("plays {} time(s)", plays),
+            };
+            format!("animation control: {} frame(s), {}", u32_at(0)?, plays)
+        }
+        "fcTL" => format!(
+            "frame control: sequence {}, {}x{} at ({}, {})",
+            u32_at(0)?,
+            u32_at(4)?,
+            u32_at(8)?,
+            u32_at(12)?,
+            u32_at(16)?
+        ),
+        "fdAT" => format!("frame data: sequence {}", u32_at(0)?),
+        _ => return None,
+    })
+}
+
+/// How a chunk differs between two files, found by [`diff`]. Indices are positions in
+/// the file the chunk comes from; for changed chunks, the newer file.
+pub enum ChunkChange<'a> {
+    Added {
+        index: usize,
+        chunk: &'a Chunk,
+    },
+    Removed {
+        index: usize,
+        chunk: &'a Chunk,
+    },
+    Changed {
+        index: usize,
+        old: &'a Chunk,
+        new: &'a Chunk,
+    },
+}
+
+/// What [`diff`] found
+pub struct Diff<'a> {
+    pub changes: Vec<ChunkChange<'a>>,
+    /// Whether the chunks both files have appear in a different order
+    pub reordered: bool,
+}
+
+/// Compares two files chunk by chunk. Chunks are matched by type and by their position
+/// among the chunks of that type, so the second IDAT of `old` pairs with the second IDAT
+/// of `new`.
+pub fn diff<'a>(old: &'a Png, new: &'a Png) -> Diff<'a> {
+    let old_keys = occurrences(old);
+    let new_keys = occurrences(new);
+
+    let mut changes = Vec::new();
+    for (index, key) in old_keys.iter().enumerate() {
+        let old_chunk = &old.chunks[index];
+        match new_keys.iter().position(|other| other == key) {
+            None => changes.push(ChunkChange::Removed {
+                index,
+                chunk: old_chunk,
+            }),
+            Some(new_index) if new.chunks[new_index].data() != old_chunk.data() => {
+                changes.push(ChunkChange::Changed {
+                    index: new_index,
+                    old: old_chunk,
+                    new: &new.chunks[new_index],
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for (index, key) in new_keys.iter().enumerate() {
+        if !old_keys.contains(key) {
+            changes.push(ChunkChange::Added {
+                index,
+                chunk: &new.chunks[index],
+            });
+        }
+    }
+
+    let common = |keys: &[(String, usize)], other: &[(String, usize)]| {
+        keys.iter()
+            .filter(|key| other.contains(key))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let reordered = common(&old_keys, &new_keys) != common(&new_keys, &old_keys);
+    Diff { changes, reordered }
+}
+
+/// Type of every chunk with how many chunks of that type come before it
+fn occurrences(png: &Png) -> Vec<(String, usize)> {
+    let mut keys: Vec<(String, usize)> = Vec::with_capacity(png.chunks.len());
+    for chunk in &png.chunks {
+        let chunk_type = chunk.chunk_type().to_string();
+        let count = keys
+            .iter()
+            .filter(|(other, _)| *other == chunk_type)
+            .count();
+        keys.push((chunk_type, count));
+    }
+    keys
+}
+
+/// What a color-management chunk declares, for `print --detailed`, such as "gamma 0.45455
+/// (display gamma 2.20)"
+pub fn describe_color_chunk(chunk: &Chunk) -> Option<String> {
+    let data = chunk.data();
+    // gAMA and cHRM store their values times 100000
+    let fixed = |offset: usize| {
+        Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as f64 / 100000.0)
+    };
+    Some(match chunk.chunk_type().to_string().as_str() {
+        "gAMA" => {
+            let gamma = fixed(0)?;
+            if gamma == 0.0 {
+                return Some("gamma 0 (invalid)".to_string());
+            }
+            format!("gamma {:.5} (display gamma {:.2})", gamma, 1.0 / gamma)
+        }
+        "sRGB" => {
+            let intent = match data.first()? {
+                0 => "perceptual",
+                1 => "relative colorimetric",
+                2 => "saturation",
+                3 => "absolute colorimetric",
+                _ => "unknown",
+            };
+            format!("sRGB color space, rendering intent: {}", intent)
+        }
+        "cHRM" => {
+            let point = |index: usize| -> Option<String> {
+                Some(format!(
+                    "({:.4}, {:.4})",
+                    fixed(index * 8)?,
+                    fixed(index * 8 + 4)?
+                ))
+            };
+            format!(
+                "chromaticities: white {}, red {}, green {}, blue {}",
+                point(0)?,
+                point(1)?,
+                point(2)?,
+                point(3)?
+            )
+        }
+        "iCCP" => {
+            let separator = data.iter().position(|&b| b == 0)?;
+            let name: String = data[..separator].iter().map(|&b| b as char).collect();
+            let compressed = data.get(separator + 2..)?;
+            let size = match zlib::inflate(compressed, MAX_ICC_PROFILE_LEN) {
+                Ok(profile) => format!("{} bytes", profile.len()),
+                Err(_) => "unreadable".to_string(),
+            };
+            format!(
+                "ICC profile '{}', {} ({} compressed)",
+                name,
+                size,
+                compressed.len()
+            )
+        }
+        _ => return None,
+    })
 }
 
 impl Display for Png {
@@ -52,24 +669,198 @@ impl Display for Png {
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
-    fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { chunks }
+    /// Starts a new 8-bit RGB image of the given size; color type and pixels can be set
+    /// before building it
+    pub fn builder(width: u32, height: u32) -> PngBuilder {
+        PngBuilder::new(width, height)
     }
 
+    /// Parses a PNG file whose chunk CRCs may not match their contents
+    pub fn from_bytes_ignoring_crc(value: &[u8]) -> Result<Png> {
+        Png::parse(value, false)
+    }
+
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
+        Png {
+            chunks,
+            trailing: Vec::new(),
+        }
+    }
+
+    /// Bytes after the IEND chunk (or after the last chunk when there is no IEND)
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing
+    }
+
+    /// Adds a chunk at the very end; `insert_ancillary` keeps chunks in front of IEND
+    #[cfg(test)]
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
+    /// True for animated PNGs (APNG), which have an acTL chunk
+    pub fn is_animated(&self) -> bool {
+        self.chunk_by_type("acTL").is_some()
+    }
+
+    /// Adds an ancillary chunk after any added before it, in front of IEND or, in an
+    /// animated PNG, in front of the first frame, since some APNG decoders stop at
+    /// unknown chunks between frames
+    pub fn insert_ancillary(&mut self, chunk: Chunk) -> Result<()> {
+        let anchors: &[&str] = if self.is_animated() {
+            &["fcTL", "IDAT"]
+        } else {
+            &["IEND"]
+        };
+        let position = self
+            .chunks
+            .iter()
+            .position(|c| anchors.contains(&c.chunk_type().to_string().as_str()))
+            .ok_or_else(|| format!("No {} chunk to insert before", anchors.join(" or ")))?;
+        self.chunks.insert(position, chunk);
+        Ok(())
+    }
+
+    /// Inserts `chunk` at `index` of the chunk list, which must be after IHDR and not
+    /// after IEND
+    pub fn insert_chunk_at(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        let position_of = |chunk_type: &str| {
+            self.chunks
+                .iter()
+                .position(|c| c.chunk_type().to_string() == chunk_type)
+        };
+        let first = position_of("IHDR").map_or(0, |i| i + 1);
+        let last = position_of("IEND").unwrap_or(self.chunks.len());
+        if !(first..=last).contains(&index) {
+            return Err(format!(
+                "Chunks can only be inserted between IHDR and IEND (positions {} to {})",
+                first, last
+            )
+            .into());
+        }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Inserts `chunk` right before the first chunk of type `chunk_type`, returning its
+    /// index
+    pub fn insert_before(&mut self, chunk_type: &str, chunk: Chunk) -> Result<usize> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == chunk_type)
+            .ok_or_else(|| {
+                Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", chunk_type))
+            })?;
+        self.insert_chunk_at(index, chunk)?;
+        Ok(index)
+    }
+
+    /// Moves every chunk of type `chunk_type`, keeping their order, to `placement`
+    /// among the other chunks, which must be after IHDR and before IEND. Returns the new
+    /// index of the first moved chunk.
+    pub fn move_chunks(&mut self, chunk_type: &str, placement: &Placement) -> Result<usize> {
+        let chunk_type_obj = ChunkType::from_str(chunk_type)?;
+        if chunk_type_obj.is_critical() {
+            return Err(format!("Critical chunk '{}' cannot be moved", chunk_type).into());
+        }
+        let is_moved = |c: &Chunk| c.chunk_type().to_string() == chunk_type;
+        if !self.chunks.iter().any(is_moved) {
+            return Err(
+                Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", chunk_type))
+            );
+        }
+
+        // Positions among the chunks that stay
+        let rest: Vec<String> = self
+            .chunks
+            .iter()
+            .filter(|c| !is_moved(c))
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        let find = |target: &str| {
+            rest.iter().position(|t| t == target).ok_or_else(|| {
+                Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", target))
+            })
+        };
+        let position = match placement {
+            Placement::Before(target) => find(target)?,
+            Placement::After(target) => {
+                rest.iter().rposition(|t| t == target).ok_or_else(|| {
+                    Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", target))
+                })? + 1
+            }
+            Placement::Index(index) => *index,
+        };
+        let first = rest.iter().position(|t| t == "IHDR").map_or(0, |i| i + 1);
+        let last = rest.iter().position(|t| t == "IEND").unwrap_or(rest.len());
+        if !(first..=last).contains(&position) {
+            return Err(format!(
+                "Chunks can only be moved between IHDR and IEND (positions {} to {})",
+                first, last
+            )
+            .into());
+        }
+
+        let (moved, mut chunks): (Vec<Chunk>, Vec<Chunk>) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(is_moved);
+        chunks.splice(position..position, moved);
+        self.chunks = chunks;
+        Ok(position)
+    }
+
+    /// Gives every chunk of type `old` the type `new`, keeping its data and place and
+    /// computing the new CRC. Returns how many chunks were renamed.
+    pub fn rename_chunks(&mut self, old: &str, new: &str) -> Result<usize> {
+        if ChunkType::from_str(old)?.is_critical() || ChunkType::from_str(new)?.is_critical() {
+            return Err(
+                "Critical chunks cannot be renamed, nor chunks renamed to critical ones".into(),
+            );
+        }
+        if self.chunk_by_type(new).is_some() {
+            return Err(format!("The file already has a '{}' chunk", new).into());
+        }
+        let mut renamed = 0;
+        for chunk in self.chunks.iter_mut() {
+            if chunk.chunk_type().to_string() == old {
+                *chunk = Chunk::new(ChunkType::from_str(new)?, chunk.data().to_vec());
+                renamed += 1;
+            }
+        }
+        if renamed == 0 {
+            return Err(Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", old)));
+        }
+        Ok(renamed)
+    }
+
+    /// Removes every ancillary chunk whose type is not in `keep`, returning them
+    pub fn strip_ancillary(&mut self, keep: &[String]) -> Vec<Chunk> {
+        let (kept, stripped) = std::mem::take(&mut self.chunks).into_iter().partition(|c| {
+            c.chunk_type().is_critical() || keep.contains(&c.chunk_type().to_string())
+        });
+        self.chunks = kept;
+        stripped
+    }
+
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
+        self.remove_chunk_nth(chunk_type, 0)
+    }
+
+    /// Removes the chunk `chunk_by_type_nth` returns for the same arguments
+    pub fn remove_chunk_nth(&mut self, chunk_type: &str, n: usize) -> Result<Chunk> {
         let position = self
             .chunks
             .iter()
-            .position(|c| c.chunk_type().to_string() == chunk_type);
+            .enumerate()
+            .filter(|(_, c)| c.chunk_type().to_string() == chunk_type)
+            .nth(n)
+            .map(|(position, _)| position);
 
         if let Some(position) = position {
             Ok(self.chunks.remove(position))
         } else {
-            Err(Error::from("chunk not found"))
+            Err(Failure::ChunkNotFound.error("chunk not found"))
         }
     }
 
@@ -77,14 +868,52 @@ impl Png {
         &Self::STANDARD_HEADER
     }
 
+    /// Inflates and unfilters the image data
+    pub fn decode_pixels(&self) -> Result<Pixels> {
+        Pixels::from_png(self)
+    }
+
+    /// Where the chunk at `index` starts in the file: after the signature and the length,
+    /// type and CRC of every chunk before it, besides their data
+    pub fn chunk_offset(&self, index: usize) -> usize {
+        Self::STANDARD_HEADER.len()
+            + self.chunks[..index]
+                .iter()
+                .map(|chunk| chunk.data().len() + 12)
+                .sum::<usize>()
+    }
+
     pub fn chunks(&self) -> &[Chunk] {
         self.chunks.as_slice()
     }
 
+    pub fn chunks_mut(&mut self) -> &mut Vec<Chunk> {
+        &mut self.chunks
+    }
+
+    /// The chunks `predicate` accepts, in file order
+    pub fn chunks_where(&self, predicate: impl Fn(&Chunk) -> bool) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter().filter(move |c| predicate(c))
+    }
+
+    /// Every chunk of type `chunk_type`, in file order
+    pub fn chunks_by_type(&self, chunk_type: &str) -> impl Iterator<Item = &Chunk> {
+        self.chunks_where(move |c| c.chunk_type().to_string() == chunk_type)
+    }
+
+    /// Every chunk a decoder may ignore: metadata, animation and hidden data
+    pub fn ancillary_chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks_where(|c| !c.chunk_type().is_critical())
+    }
+
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        self.chunks
-            .iter()
-            .find(|c| c.chunk_type().to_string() == chunk_type)
+        self.chunk_by_type_nth(chunk_type, 0)
+    }
+
+    /// The chunk of type `chunk_type` after `n` others of that type, for files with
+    /// several `tEXt` or vault chunks
+    pub fn chunk_by_type_nth(&self, chunk_type: &str, n: usize) -> Option<&Chunk> {
+        self.chunks_by_type(chunk_type).nth(n)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -94,6 +923,7 @@ impl Png {
         for chunk in &self.chunks {
             result.extend(chunk.as_bytes());
         }
+        result.extend(&self.trailing);
         result
     }
 }
@@ -104,7 +934,6 @@ mod tests {
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
     use std::convert::TryFrom;
-    use std::str::FromStr;
 
     fn testing_chunks() -> Vec<Chunk> {
         vec![
@@ -128,6 +957,374 @@ mod tests {
         Ok(Chunk::new(chunk_type, data))
     }
 
+    fn apng_chunks() -> Vec<Chunk> {
+        let mut actl = 2u32.to_be_bytes().to_vec();
+        actl.extend(0u32.to_be_bytes());
+        let mut fctl = 0u32.to_be_bytes().to_vec();
+        fctl.extend([0, 0, 0, 4, 0, 0, 0, 2].iter().chain(&[0; 14]));
+        ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]
+            .into_iter()
+            .map(|chunk_type| {
+                let data = match chunk_type {
+                    "acTL" => actl.clone(),
+                    "fcTL" => fctl.clone(),
+                    _ => vec![0; 4],
+                };
+                Chunk::new(chunk_type.parse().unwrap(), data)
+            })
+            .collect()
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_ancillary_before_iend() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        assert!(!png.is_animated());
+        png.insert_ancillary(chunk_from_strings("ruSt", "one").unwrap())
+            .unwrap();
+        png.insert_ancillary(chunk_from_strings("ruSt", "two").unwrap())
+            .unwrap();
+        assert_eq!(
+            chunk_types(&png),
+            ["FrSt", "miDl", "LASt", "ruSt", "ruSt", "IEND"]
+        );
+        assert_eq!(png.chunks()[4].data_as_string().unwrap(), "two");
+
+        let chunk = chunk_from_strings("ruSt", "three").unwrap();
+        assert!(testing_png().insert_ancillary(chunk).is_err());
+    }
+
+    #[test]
+    fn test_nth_chunk_of_type() {
+        let mut png = Png::from_chunks(apng_chunks());
+        png.chunks_mut()[4] = Chunk::new("fcTL".parse().unwrap(), b"second".to_vec());
+        assert_eq!(png.chunk_by_type_nth("fcTL", 1).unwrap().data(), b"second");
+        assert!(png.chunk_by_type_nth("fcTL", 2).is_none());
+
+        assert!(png.remove_chunk_nth("fcTL", 2).is_err());
+        let removed = png.remove_chunk_nth("fcTL", 1).unwrap();
+        assert_eq!(removed.data(), b"second");
+        assert_eq!(
+            chunk_types(&png),
+            ["IHDR", "acTL", "fcTL", "IDAT", "fdAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_chunk_offset() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        assert_eq!(png.chunk_offset(0), 8);
+        // "I am the first chunk" is 20 bytes
+        assert_eq!(png.chunk_offset(1), 8 + 12 + 20);
+        assert_eq!(&bytes[png.chunk_offset(2) + 4..][..4], b"LASt");
+        assert_eq!(png.chunk_offset(3), bytes.len());
+    }
+
+    #[test]
+    fn test_chunk_queries() {
+        let png = Png::from_chunks(apng_chunks());
+        assert_eq!(png.chunks_by_type("fcTL").count(), 2);
+        assert_eq!(png.chunks_by_type("tEXt").count(), 0);
+        let ancillary: Vec<String> = png
+            .ancillary_chunks()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(ancillary, ["acTL", "fcTL", "fcTL", "fdAT"]);
+        assert_eq!(png.chunks_where(|chunk| chunk.data().len() == 4).count(), 4);
+    }
+
+    #[test]
+    fn test_insert_chunk_at_and_before() {
+        let mut png = Png::from_chunks(apng_chunks());
+        let index = png
+            .insert_before("IDAT", chunk_from_strings("iCCP", "").unwrap())
+            .unwrap();
+        assert_eq!(index, 3);
+        png.insert_chunk_at(1, chunk_from_strings("ruSt", "").unwrap())
+            .unwrap();
+        png.insert_chunk_at(8, chunk_from_strings("ruSt", "").unwrap())
+            .unwrap();
+        assert_eq!(
+            chunk_types(&png),
+            [
+                "IHDR", "ruSt", "acTL", "fcTL", "iCCP", "IDAT", "fcTL", "fdAT", "ruSt", "IEND"
+            ]
+        );
+
+        for index in [0, 10] {
+            let chunk = chunk_from_strings("ruSt", "").unwrap();
+            assert!(png.insert_chunk_at(index, chunk).is_err());
+        }
+        let chunk = chunk_from_strings("ruSt", "").unwrap();
+        assert!(png.insert_before("PLTE", chunk).is_err());
+    }
+
+    #[test]
+    fn test_insert_ancillary_before_first_frame() {
+        let mut png = Png::from_chunks(apng_chunks());
+        assert!(png.is_animated());
+        png.insert_ancillary(chunk_from_strings("ruSt", "one").unwrap())
+            .unwrap();
+        png.insert_ancillary(chunk_from_strings("ruSt", "two").unwrap())
+            .unwrap();
+        assert_eq!(
+            chunk_types(&png),
+            [
+                "IHDR", "acTL", "ruSt", "ruSt", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_animation_chunk() {
+        let chunks = apng_chunks();
+        let descriptions: Vec<Option<String>> =
+            chunks.iter().map(describe_animation_chunk).collect();
+        assert_eq!(descriptions[0], None);
+        assert_eq!(
+            descriptions[1].as_deref(),
+            Some("animation control: 2 frame(s), loops forever")
+        );
+        assert_eq!(
+            descriptions[2].as_deref(),
+            Some("frame control: sequence 0, 4x2 at (0, 0)")
+        );
+        assert_eq!(descriptions[5].as_deref(), Some("frame data: sequence 0"));
+    }
+
+    #[test]
+    fn test_describe_color_chunk() {
+        let chunk = |chunk_type: &str, data: Vec<u8>| {
+            describe_color_chunk(&Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data))
+        };
+        assert_eq!(
+            chunk("gAMA", 45455u32.to_be_bytes().to_vec()).as_deref(),
+            Some("gamma 0.45455 (display gamma 2.20)")
+        );
+        assert_eq!(
+            chunk("sRGB", vec![0]).as_deref(),
+            Some("sRGB color space, rendering intent: perceptual")
+        );
+        let chrm: Vec<u8> = [31270u32, 32900, 64000, 33000, 30000, 60000, 15000, 6000]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect();
+        assert_eq!(
+            chunk("cHRM", chrm).as_deref(),
+            Some(
+                "chromaticities: white (0.3127, 0.3290), red (0.6400, 0.3300), green (0.3000, 0.6000), blue (0.1500, 0.0600)"
+            )
+        );
+        let mut iccp = b"sRGB IEC61966-2.1\0\0".to_vec();
+        iccp.extend(zlib::deflate(&[7; 3000]));
+        let description = chunk("iCCP", iccp).unwrap();
+        assert!(description.starts_with("ICC profile 'sRGB IEC61966-2.1', 3000 bytes ("));
+        assert_eq!(chunk("gAMA", vec![0; 2]), None);
+        assert_eq!(chunk("tEXt", vec![0; 4]), None);
+    }
+
+    fn problems(data: &[u8]) -> Vec<(usize, String)> {
+        check_integrity(data)
+            .problems
+            .into_iter()
+            .map(|problem| (problem.offset, problem.description))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_integrity_of_valid_file() {
+        let mut png = Png::from_chunks(apng_chunks());
+        png.remove_chunk("fdAT").unwrap();
+        let integrity = check_integrity(&png.as_bytes());
+        assert_eq!(integrity.chunks, 6);
+        assert!(integrity.problems.is_empty());
+    }
+
+    #[test]
+    fn test_check_integrity_reports_offsets() {
+        let mut data = Png::from_chunks(apng_chunks()).as_bytes();
+        // Flip a data byte of the acTL chunk, which starts after the 16-byte test IHDR chunk
+        data[8 + 16 + 8 + 3] ^= 1;
+        data.extend(b"junk");
+        assert_eq!(
+            problems(&data),
+            [(
+                24,
+                "CRC mismatch in chunk 'acTL': stored f38d9370, computed ceedbac0".to_string()
+            )]
+        );
+        assert_eq!(check_integrity(&data).trailing, 4);
+
+        // Cut inside the data of the fdAT chunk at offset 136
+        let truncated = &data[..146];
+        let found = problems(truncated);
+        assert_eq!(found.len(), 2);
+        assert!(found[1].1.contains("declares"), "{:?}", found);
+    }
+
+    #[test]
+    fn test_check_integrity_without_iend() {
+        let mut png = testing_png();
+        png.remove_chunk("FrSt").unwrap();
+        let found = problems(&png.as_bytes());
+        assert_eq!(
+            found[0],
+            (8, "First chunk is 'miDl' instead of IHDR".to_string())
+        );
+        assert_eq!(found[1].1, "File does not end with an IEND chunk");
+        assert_eq!(problems(b"GIF89a")[0].0, 0);
+    }
+
+    #[test]
+    fn test_repair_fixes_crc_and_missing_iend() {
+        let mut data = Png::from_chunks(apng_chunks()).as_bytes();
+        let original = data.clone();
+        // Damage the acTL chunk at offset 24, keeping its old CRC
+        data[24 + 8 + 3] ^= 1;
+        let (repaired, fixes) = repair(&data).unwrap();
+        assert_eq!(fixes, ["Recomputed the CRC of chunk 'acTL' at offset 24"]);
+        assert!(check_integrity(&repaired).problems.is_empty());
+        assert_eq!(repaired[..24 + 8 + 8], data[..24 + 8 + 8]);
+
+        // Cut inside the fdAT chunk at offset 136
+        let (repaired, fixes) = repair(&original[..146]).unwrap();
+        assert_eq!(
+            fixes,
+            [
+                "Dropped 10 bytes from offset 136: Chunk 'fdAT' declares 4 bytes but only 2 remain",
+                "Appended the missing IEND chunk"
+            ]
+        );
+        assert_eq!(repaired[..136], original[..136]);
+        assert!(check_integrity(&repaired).problems.is_empty());
+    }
+
+    #[test]
+    fn test_repair_keeps_intact_files() {
+        let mut data = Png::from_chunks(apng_chunks()).as_bytes();
+        data.extend(b"trailing");
+        let (repaired, fixes) = repair(&data).unwrap();
+        assert!(fixes.is_empty());
+        assert_eq!(repaired, data);
+        assert!(repair(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_move_chunks() {
+        let mut png = Png::from_chunks(apng_chunks());
+        png.insert_ancillary(chunk_from_strings("ruSt", "one").unwrap())
+            .unwrap();
+        png.insert_ancillary(chunk_from_strings("ruSt", "two").unwrap())
+            .unwrap();
+
+        let after = Placement::After("fcTL".to_string());
+        assert_eq!(png.move_chunks("ruSt", &after).unwrap(), 5);
+        assert_eq!(
+            chunk_types(&png),
+            [
+                "IHDR", "acTL", "fcTL", "IDAT", "fcTL", "ruSt", "ruSt", "fdAT", "IEND"
+            ]
+        );
+        assert_eq!(png.chunks()[5].data_as_string().unwrap(), "one");
+
+        png.move_chunks("ruSt", &Placement::Index(1)).unwrap();
+        assert_eq!(chunk_types(&png)[..4], ["IHDR", "ruSt", "ruSt", "acTL"]);
+        let before = Placement::Before("IEND".to_string());
+        png.move_chunks("acTL", &before).unwrap();
+        assert_eq!(chunk_types(&png)[7..], ["acTL", "IEND"]);
+    }
+
+    #[test]
+    fn test_move_chunks_rejects_bad_placements() {
+        let mut png = Png::from_chunks(apng_chunks());
+        let before = Placement::Before("IDAT".to_string());
+        assert!(png.move_chunks("IDAT", &before).is_err());
+        assert!(png.move_chunks("miSs", &before).is_err());
+        assert!(png.move_chunks("acTL", &Placement::Index(0)).is_err());
+        assert!(png.move_chunks("acTL", &Placement::Index(7)).is_err());
+        let after = Placement::After("IEND".to_string());
+        assert!(png.move_chunks("acTL", &after).is_err());
+        assert_eq!(
+            chunk_types(&Png::from_chunks(apng_chunks())),
+            chunk_types(&png)
+        );
+    }
+
+    #[test]
+    fn test_strip_ancillary() {
+        let mut png = Png::from_chunks(apng_chunks());
+        png.insert_ancillary(chunk_from_strings("ruSt", "kept").unwrap())
+            .unwrap();
+        let stripped = png.strip_ancillary(&["ruSt".to_string()]);
+        assert_eq!(stripped.len(), 4);
+        assert_eq!(chunk_types(&png), ["IHDR", "ruSt", "IDAT", "IEND"]);
+        assert_eq!(png.strip_ancillary(&[]).len(), 1);
+    }
+
+    #[test]
+    fn test_rename_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "again").unwrap());
+        assert_eq!(png.rename_chunks("miDl", "ruSt").unwrap(), 2);
+        assert_eq!(chunk_types(&png), ["FrSt", "ruSt", "LASt", "ruSt"]);
+        let renamed = png.chunk_by_type_nth("ruSt", 1).unwrap();
+        assert_eq!(renamed.data(), b"again");
+        assert!(Chunk::try_from(renamed.as_bytes().as_slice()).is_ok());
+
+        assert!(png.rename_chunks("miDl", "abCd").is_err());
+        assert!(png.rename_chunks("ruSt", "LASt").is_err());
+        assert!(png.rename_chunks("FrSt", "abCd").is_err());
+        assert!(png.rename_chunks("ruSt", "ABCD").is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Png::from_chunks(apng_chunks());
+        assert!(diff(&old, &old).changes.is_empty());
+
+        let mut chunks = apng_chunks();
+        chunks[4] = Chunk::new(ChunkType::from_str("fcTL").unwrap(), vec![1; 26]);
+        chunks.remove(5);
+        chunks.insert(3, chunk_from_strings("ruSt", "hidden").unwrap());
+        let new = Png::from_chunks(chunks);
+
+        let result = diff(&old, &new);
+        assert!(!result.reordered);
+        let changes: Vec<String> = result
+            .changes
+            .iter()
+            .map(|change| match change {
+                ChunkChange::Added { index, chunk } => format!("+{} {}", chunk.chunk_type(), index),
+                ChunkChange::Removed { index, chunk } => {
+                    format!("-{} {}", chunk.chunk_type(), index)
+                }
+                ChunkChange::Changed { index, old, new } => {
+                    format!("~{} {} {}", new.chunk_type(), index, old.data().len())
+                }
+            })
+            .collect();
+        assert_eq!(changes, ["~fcTL 5 26", "-fdAT 5", "+ruSt 3"]);
+    }
+
+    #[test]
+    fn test_diff_reordered() {
+        let old = Png::from_chunks(apng_chunks());
+        let mut new = Png::from_chunks(apng_chunks());
+        new.move_chunks("acTL", &Placement::After("IDAT".to_string()))
+            .unwrap();
+        let result = diff(&old, &new);
+        assert!(result.changes.is_empty());
+        assert!(result.reordered);
+    }
+
     #[test]
     fn test_from_chunks() {
         let chunks = testing_chunks();
@@ -136,6 +1333,122 @@ mod tests {
         assert_eq!(png.chunks().len(), 3);
     }
 
+    #[test]
+    fn test_chunk_reader_headers() {
+        let data = Png::from_chunks(apng_chunks()).as_bytes();
+        let mut reader = ChunkReader::new(data.as_slice()).unwrap();
+        let mut headers = Vec::new();
+        while let Some(header) = reader.next_header().unwrap() {
+            headers.push((header.chunk_type.to_string(), header.offset, header.length));
+        }
+        assert_eq!(headers.len(), 7);
+        assert_eq!(headers[1], ("acTL".to_string(), 24, 8));
+        assert_eq!(headers[5], ("fdAT".to_string(), 136, 4));
+
+        // Skipped chunks still have their CRC checked
+        let mut damaged = data.clone();
+        damaged[8 + 16 + 8 + 3] ^= 1;
+        let mut reader = ChunkReader::new(damaged.as_slice()).unwrap();
+        let error = std::iter::from_fn(|| reader.next_header().transpose())
+            .find_map(|header| header.err())
+            .unwrap();
+        assert_eq!(error.to_string(), Chunk::INVALID_CRC);
+        assert!(ChunkReader::new(&b"GIF89a"[..]).is_err());
+
+        // So does a damaged type byte, before the type is shown anywhere
+        let mut damaged = data.clone();
+        damaged[24 + 4] = 0xFF;
+        let mut reader = ChunkReader::new(damaged.as_slice()).unwrap();
+        reader.next_header().unwrap();
+        let error = reader.next_header().err().unwrap();
+        assert_eq!(error.to_string(), Chunk::INVALID_CRC);
+        let mut reader = ChunkReader::ignoring_crc(damaged.as_slice()).unwrap();
+        reader.next_header().unwrap();
+        let header = reader.next_header().unwrap().unwrap();
+        assert_eq!(header.chunk_type.to_string(), "\u{FFFD}cTL");
+    }
+
+    #[test]
+    fn test_read_filtered() {
+        let mut data = Png::from_chunks(apng_chunks()).as_bytes();
+        data.extend(b"trailing");
+        let png =
+            Png::read_filtered(io::Cursor::new(&data), |chunk_type| chunk_type == "fcTL").unwrap();
+        assert_eq!(chunk_types(&png), ["fcTL", "fcTL"]);
+        assert!(png.trailing_data().is_empty());
+
+        // Image data is skipped unread, other chunks have their CRC checked
+        let offset_of = |wanted: &str| {
+            let mut reader = ChunkReader::new(data.as_slice()).unwrap();
+            while let Some(header) = reader.next_header().unwrap() {
+                if header.chunk_type.to_string() == wanted {
+                    return header.offset as usize;
+                }
+            }
+            unreachable!()
+        };
+        let mut damaged = data.clone();
+        damaged[offset_of("fcTL") + 8] ^= 1;
+        assert!(Png::read_filtered(io::Cursor::new(&damaged), |_| false).is_err());
+        damaged = data.clone();
+        damaged[offset_of("IDAT") + 8] ^= 1;
+        assert!(Png::read_filtered(io::Cursor::new(&damaged), |_| false).is_ok());
+        assert!(Png::read_filtered(io::Cursor::new(&damaged), |t| t == "IDAT").is_err());
+
+        let truncated = &data[..146];
+        assert!(Png::read_filtered(io::Cursor::new(truncated), |_| false).is_err());
+    }
+
+    #[test]
+    fn test_chunk_reader_reports_truncation() {
+        let data = Png::from_chunks(apng_chunks()).as_bytes();
+        let read_all = |data: &[u8]| -> Result<usize> {
+            let mut reader = ChunkReader::new(io::Cursor::new(data))?;
+            let mut count = 0;
+            while reader.next_header()?.is_some() {
+                reader.skip_chunk()?;
+                count += 1;
+            }
+            Ok(count)
+        };
+        assert_eq!(read_all(&data).unwrap(), 7);
+
+        // Cut off inside the image data, which is seeked past rather than read
+        let idat = data
+            .windows(4)
+            .position(|window| window == b"IDAT")
+            .unwrap();
+        let error = read_all(&data[..idat + 6]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Chunk 'IDAT' is cut off by the end of the file"
+        );
+
+        let iend = data
+            .windows(4)
+            .position(|window| window == b"IEND")
+            .unwrap()
+            - 4;
+        let error = read_all(&data[..iend]).unwrap_err();
+        assert_eq!(error.to_string(), "The file ends without an IEND chunk");
+        let error = read_all(&data[..iend + 5]).unwrap_err();
+        assert!(error.to_string().contains("cut off"));
+
+        // Parsing from memory still takes files without IEND
+        assert_eq!(Png::try_from(&data[..iend]).unwrap().chunks().len(), 6);
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut data = Png::from_chunks(apng_chunks()).as_bytes();
+        data.extend(b"appended by another tool");
+        let png = Png::try_from(data.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(png.trailing_data(), b"appended by another tool");
+        assert_eq!(png.as_bytes(), data);
+        assert!(testing_png().trailing_data().is_empty());
+    }
+
     #[test]
     fn test_valid_from_bytes() {
         let chunk_bytes: Vec<u8> = testing_chunks()
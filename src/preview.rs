@@ -0,0 +1,138 @@
+use crate::Result;
+
+/// Brightness ramp for `--ascii`, from dark to light
+const RAMP: &[u8] = b" .:-=+*#%@";
+/// Transparent pixels are shown over this gray, which reads on dark and light terminals
+const BACKGROUND: u8 = 0x80;
+
+/// How `render` draws the thumbnail
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// 24-bit ANSI colors, two pixels per character with the upper half block
+    Color,
+    /// Plain characters picked by brightness, for terminals without color
+    Ascii,
+}
+
+/// Lines of a thumbnail at most `columns` characters wide of the `width` x `height`
+/// image `rgba`. Character cells are about twice as tall as wide, which the vertical
+/// scale accounts for. Images are shrunk but never enlarged.
+pub fn render(
+    rgba: &[[u8; 4]],
+    width: usize,
+    height: usize,
+    columns: usize,
+    style: Style,
+) -> Result<Vec<String>> {
+    if rgba.len() != width * height || width == 0 || height == 0 {
+        return Err("Pixel count does not match the image dimensions".into());
+    }
+    let out_width = columns.clamp(1, width);
+    // Half blocks show two thumbnail rows per line, plain characters one
+    let rows_per_line = match style {
+        Style::Color => 2,
+        Style::Ascii => 1,
+    };
+    let out_height = (height * out_width * rows_per_line)
+        .div_ceil(width * 2)
+        .clamp(1, height);
+    let thumbnail = downscale(rgba, width, height, out_width, out_height);
+    let row = |y: usize| &thumbnail[y * out_width..(y + 1) * out_width];
+
+    let lines = match style {
+        Style::Color => (0..out_height)
+            .step_by(2)
+            .map(|y| {
+                let mut line = String::new();
+                for (x, top) in row(y).iter().enumerate() {
+                    line.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+                    match (y + 1 < out_height).then(|| row(y + 1)[x]) {
+                        Some(bottom) => line.push_str(&format!(
+                            "\x1b[48;2;{};{};{}m",
+                            bottom[0], bottom[1], bottom[2]
+                        )),
+                        None => line.push_str("\x1b[49m"),
+                    }
+                    line.push('▀');
+                }
+                line.push_str("\x1b[0m");
+                line
+            })
+            .collect(),
+        Style::Ascii => (0..out_height)
+            .map(|y| {
+                row(y)
+                    .iter()
+                    .map(|&[r, g, b]| {
+                        let luma = (299 * r as usize + 587 * g as usize + 114 * b as usize) / 1000;
+                        RAMP[luma * RAMP.len() / 256] as char
+                    })
+                    .collect()
+            })
+            .collect(),
+    };
+    Ok(lines)
+}
+
+/// Averages the pixels that fall into each cell of an `out_width` x `out_height` grid,
+/// after blending them over `BACKGROUND` by their alpha
+fn downscale(
+    rgba: &[[u8; 4]],
+    width: usize,
+    height: usize,
+    out_width: usize,
+    out_height: usize,
+) -> Vec<[u8; 3]> {
+    let mut thumbnail = Vec::with_capacity(out_width * out_height);
+    for y in 0..out_height {
+        let (top, bottom) = (y * height / out_height, (y + 1) * height / out_height);
+        for x in 0..out_width {
+            let (left, right) = (x * width / out_width, (x + 1) * width / out_width);
+            let mut sum = [0u64; 3];
+            for row in top..bottom {
+                for pixel in &rgba[row * width + left..row * width + right] {
+                    let alpha = pixel[3] as u64;
+                    for (total, &sample) in sum.iter_mut().zip(pixel) {
+                        *total += (sample as u64 * alpha + BACKGROUND as u64 * (255 - alpha)) / 255;
+                    }
+                }
+            }
+            let count = ((bottom - top) * (right - left)) as u64;
+            thumbnail.push(sum.map(|total| (total / count) as u8));
+        }
+    }
+    thumbnail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_ramp_and_scale() {
+        // Left half black, right half white, 8x4 pixels
+        let rgba: Vec<[u8; 4]> = (0..32)
+            .map(|i| if i % 8 < 4 { [0, 0, 0, 255] } else { [255; 4] })
+            .collect();
+        let lines = render(&rgba, 8, 4, 4, Style::Ascii).unwrap();
+        assert_eq!(lines, ["  @@"]);
+
+        // Never enlarged
+        let lines = render(&rgba, 8, 4, 100, Style::Ascii).unwrap();
+        assert_eq!(lines, ["    @@@@", "    @@@@"]);
+    }
+
+    #[test]
+    fn test_color_half_blocks() {
+        let rgba = [[255, 0, 0, 255], [0, 0, 255, 255], [0, 0, 0, 0]];
+        let lines = render(&rgba, 1, 3, 10, Style::Color).unwrap();
+        assert_eq!(
+            lines,
+            [
+                "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[0m",
+                "\x1b[38;2;128;128;128m\x1b[49m▀\x1b[0m"
+            ]
+        );
+        assert!(render(&rgba, 2, 2, 10, Style::Color).is_err());
+    }
+}
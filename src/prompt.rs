@@ -0,0 +1,135 @@
+use crate::Result;
+use crate::crypto::Secret;
+use std::io::{BufRead, Write};
+
+/// Prompts for a password on the terminal with echo disabled.
+///
+/// With `confirm`, the password is asked for twice and must match.
+pub fn read_password(prompt: &str, confirm: bool) -> Result<Secret> {
+    let password = platform::read_hidden(prompt)?;
+    if confirm && platform::read_hidden("Confirm password: ")?.expose() != password.expose() {
+        return Err("Passwords do not match".into());
+    }
+    if password.expose().is_empty() {
+        return Err("Password must not be empty".into());
+    }
+    Ok(password)
+}
+
+/// Prompts for a line of input that needs no hiding, such as a choice from a list
+pub fn read_line(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    if std::io::stdin().lock().read_line(&mut line)? == 0 {
+        return Err("No answer given".into());
+    }
+    Ok(trim_newline(line))
+}
+
+fn trim_newline(mut line: String) -> String {
+    while line.ends_with(['\n', '\r']) {
+        line.pop();
+    }
+    line
+}
+
+#[cfg(unix)]
+mod platform {
+    use crate::Result;
+    use crate::crypto::Secret;
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Command;
+
+    /// Reads a line from the controlling terminal (not stdin, which may be piped)
+    pub fn read_hidden(prompt: &str) -> Result<Secret> {
+        let mut tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|e| format!("No terminal available for the password prompt: {}", e))?;
+
+        write!(tty, "{}", prompt)?;
+        tty.flush()?;
+
+        set_echo(false)?;
+        let mut line = String::new();
+        let read = BufReader::new(&tty).read_line(&mut line);
+        // Wiped when dropped, even if reading failed
+        let line = Secret::from(super::trim_newline(line));
+        // Always restore echo, even if reading failed
+        set_echo(true)?;
+        writeln!(tty)?;
+
+        read?;
+        Ok(line)
+    }
+
+    fn set_echo(enabled: bool) -> Result<()> {
+        let status = Command::new("stty")
+            .arg(if enabled { "echo" } else { "-echo" })
+            .stdin(File::open("/dev/tty")?)
+            .status()
+            .map_err(|e| format!("Failed to run stty: {}", e))?;
+
+        if !status.success() {
+            return Err(format!("stty exited with {}", status).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use crate::Result;
+    use crate::crypto::Secret;
+    use std::ffi::c_void;
+    use std::io::{BufRead, Write};
+
+    const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    const ENABLE_ECHO_INPUT: u32 = 0x0004;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetStdHandle(std_handle: u32) -> *mut c_void;
+        fn GetConsoleMode(console: *mut c_void, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: *mut c_void, mode: u32) -> i32;
+    }
+
+    pub fn read_hidden(prompt: &str) -> Result<Secret> {
+        eprint!("{}", prompt);
+        std::io::stderr().flush()?;
+
+        let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        let mut mode = 0u32;
+        if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+            return Err("No console available for the password prompt".into());
+        }
+
+        unsafe { SetConsoleMode(handle, mode & !ENABLE_ECHO_INPUT) };
+        let mut line = String::new();
+        let read = std::io::stdin().lock().read_line(&mut line);
+        // Wiped when dropped, even if reading failed
+        let line = Secret::from(super::trim_newline(line));
+        // Always restore echo, even if reading failed
+        unsafe { SetConsoleMode(handle, mode) };
+        eprintln!();
+
+        read?;
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trim_newline() {
+        assert_eq!(trim_newline("hunter2\n".to_string()), "hunter2");
+        assert_eq!(trim_newline("hunter2\r\n".to_string()), "hunter2");
+        assert_eq!(trim_newline("hunter 2 ".to_string()), "hunter 2 ");
+    }
+}
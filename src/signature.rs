@@ -0,0 +1,169 @@
+use crate::exit::Failure;
+use crate::png::Png;
+use crate::{Error, Result};
+
+/// Chunk holding a detached Ed25519 signature: ancillary, private, and unsafe to copy
+/// since any edit to the image invalidates it
+pub const SIGNATURE_CHUNK: &str = "sgNT";
+
+const DOMAIN: &[u8] = b"hidden-pixel-vault/signature/v1\0";
+const WHOLE_IMAGE: [u8; 4] = [0; 4];
+const RECORD_LEN: usize = 4 + 32 + 64;
+
+/// Contents of a signature chunk.
+///
+/// Layout: scope (4, a chunk type or zeros for the whole image) | public key (32) | signature (64)
+pub struct SignatureRecord {
+    pub scope: Option<String>,
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl TryFrom<&[u8]> for SignatureRecord {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() != RECORD_LEN {
+            return Err(format!(
+                "Signature chunk has {} bytes, expected {}",
+                value.len(),
+                RECORD_LEN
+            )
+            .into());
+        }
+
+        let scope = if value[..4] == WHOLE_IMAGE {
+            None
+        } else {
+            Some(String::from_utf8(value[..4].to_vec())?)
+        };
+
+        Ok(SignatureRecord {
+            scope,
+            public_key: value[4..36].try_into()?,
+            signature: value[36..].try_into()?,
+        })
+    }
+}
+
+impl SignatureRecord {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(RECORD_LEN);
+        match &self.scope {
+            Some(chunk_type) => bytes.extend(chunk_type.as_bytes()),
+            None => bytes.extend(WHOLE_IMAGE),
+        }
+        bytes.extend(self.public_key);
+        bytes.extend(self.signature);
+        bytes
+    }
+
+    /// Human-readable description of what the signature covers
+    pub fn describe_scope(&self) -> String {
+        match &self.scope {
+            Some(chunk_type) => format!("chunk '{}'", chunk_type),
+            None => "whole image".to_string(),
+        }
+    }
+}
+
+/// Parses every signature chunk in the image
+pub fn records(png: &Png) -> Result<Vec<SignatureRecord>> {
+    png.chunks_by_type(SIGNATURE_CHUNK)
+        .map(|c| SignatureRecord::try_from(c.data()))
+        .collect()
+}
+
+/// Builds the bytes a signature with the given scope covers.
+///
+/// A whole-image signature covers every chunk except signature chunks, so chunk
+/// signatures can be added or removed without invalidating it.
+pub fn signed_message(png: &Png, scope: Option<&str>) -> Result<Vec<u8>> {
+    let mut message = DOMAIN.to_vec();
+
+    match scope {
+        Some(chunk_type) => {
+            let chunk = png.chunk_by_type(chunk_type).ok_or_else(|| {
+                Failure::ChunkNotFound.error(format!("Chunk type '{}' not found", chunk_type))
+            })?;
+            message.extend(chunk.chunk_type().bytes());
+            message.extend(chunk.data());
+        }
+        None => {
+            message.extend(WHOLE_IMAGE);
+            message.extend(Png::STANDARD_HEADER);
+            for chunk in png.chunks() {
+                if chunk.chunk_type().to_string() != SIGNATURE_CHUNK {
+                    message.extend(chunk.as_bytes());
+                }
+            }
+        }
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let mut png = Png::try_from(Png::STANDARD_HEADER.as_slice()).unwrap();
+        for (chunk_type, data) in [("FrSt", "first"), ("ruSt", "hidden"), ("LASt", "last")] {
+            let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+            png.append_chunk(Chunk::new(chunk_type, data.as_bytes().to_vec()));
+        }
+        png
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = SignatureRecord {
+            scope: Some("ruSt".to_string()),
+            public_key: [1; 32],
+            signature: [2; 64],
+        };
+        let parsed = SignatureRecord::try_from(record.as_bytes().as_slice()).unwrap();
+        assert_eq!(parsed.scope.as_deref(), Some("ruSt"));
+        assert_eq!(parsed.public_key, [1; 32]);
+        assert_eq!(parsed.signature, [2; 64]);
+
+        let whole = SignatureRecord {
+            scope: None,
+            ..parsed
+        };
+        assert!(
+            SignatureRecord::try_from(whole.as_bytes().as_slice())
+                .unwrap()
+                .scope
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_record_rejects_wrong_length() {
+        assert!(SignatureRecord::try_from([0u8; 99].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_whole_image_message_ignores_signature_chunks() {
+        let mut png = testing_png();
+        let before = signed_message(&png, None).unwrap();
+
+        let chunk_type = ChunkType::from_str(SIGNATURE_CHUNK).unwrap();
+        png.append_chunk(Chunk::new(chunk_type, vec![0; RECORD_LEN]));
+        assert_eq!(signed_message(&png, None).unwrap(), before);
+        assert_eq!(records(&png).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_chunk_message_covers_only_that_chunk() {
+        let png = testing_png();
+        let message = signed_message(&png, Some("ruSt")).unwrap();
+        assert!(message.ends_with(b"ruSthidden"));
+        assert_ne!(message, signed_message(&png, None).unwrap());
+        assert!(signed_message(&png, Some("noNe")).is_err());
+    }
+}
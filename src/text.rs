@@ -0,0 +1,269 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::exit::Failure;
+use crate::png::Png;
+use crate::{Result, zlib};
+use std::str::FromStr;
+
+/// Most bytes a zTXt or iTXt chunk may inflate to
+const MAX_TEXT_LEN: usize = 16 << 20;
+
+/// Checks a tEXt/iTXt keyword: 1-79 printable Latin-1 characters without
+/// leading, trailing or consecutive spaces
+pub fn validate_keyword(keyword: &str) -> Result<()> {
+    let invalid = |reason: &str| {
+        Failure::Validation.error(format!("Invalid text keyword '{}': {}", keyword, reason))
+    };
+    let count = keyword.chars().count();
+    if !(1..=79).contains(&count) {
+        return Err(invalid("must be 1 to 79 characters"));
+    }
+    if !keyword
+        .chars()
+        .all(|c| matches!(c as u32, 0x20..=0x7e | 0xa1..=0xff))
+    {
+        return Err(invalid("only printable Latin-1 characters are allowed"));
+    }
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+        return Err(invalid("no leading, trailing or double spaces"));
+    }
+    Ok(())
+}
+
+/// Builds a `tEXt` (or with `compress` a `zTXt`) chunk when `text` is Latin-1 without
+/// NUL characters, and an `iTXt` chunk, compressed likewise, otherwise
+pub fn text_chunk(keyword: &str, text: &str, compress: bool) -> Result<Chunk> {
+    validate_keyword(keyword)?;
+    let mut data: Vec<u8> = keyword.chars().map(|c| c as u8).collect();
+    data.push(0);
+
+    if text.chars().all(|c| c != '\0' && (c as u32) <= 0xff) {
+        let latin1: Vec<u8> = text.chars().map(|c| c as u8).collect();
+        if !compress {
+            data.extend(latin1);
+            return Ok(Chunk::new(ChunkType::from_str("tEXt")?, data));
+        }
+        // Compression method 0 is zlib, the only one defined
+        data.push(0);
+        data.extend(zlib::deflate(&latin1));
+        return Ok(Chunk::new(ChunkType::from_str("zTXt")?, data));
+    }
+
+    itxt_chunk(keyword, text, compress)
+}
+
+/// Builds an `iTXt` chunk, the only text chunk type that holds UTF-8
+pub fn itxt_chunk(keyword: &str, text: &str, compress: bool) -> Result<Chunk> {
+    validate_keyword(keyword)?;
+    let mut data: Vec<u8> = keyword.chars().map(|c| c as u8).collect();
+    data.push(0);
+    // Compression flag and method, then empty language tag and translated keyword
+    data.extend([compress as u8, 0, 0, 0]);
+    if compress {
+        data.extend(zlib::deflate(text.as_bytes()));
+    } else {
+        data.extend(text.as_bytes());
+    }
+    Ok(Chunk::new(ChunkType::from_str("iTXt")?, data))
+}
+
+/// Chunk types that hold a keyword and text
+pub const TEXT_CHUNKS: [&str; 3] = ["tEXt", "zTXt", "iTXt"];
+
+/// Keyword and text of a `tEXt`, `zTXt` or `iTXt` chunk, inflating compressed text;
+/// `None` for other chunk types
+pub fn read_text_chunk(chunk: &Chunk) -> Result<Option<(String, String)>> {
+    read_text(chunk, None)
+}
+
+/// Keyword and the start of the text of a text chunk, like [`read_text_chunk`], but
+/// inflating only as much compressed text as a preview of `chars` characters needs
+pub fn read_text_preview(chunk: &Chunk, chars: usize) -> Result<Option<(String, String)>> {
+    // No character takes more than 4 bytes; the extra one shows that the text goes on
+    read_text(chunk, Some(4 * (chars + 1)))
+}
+
+/// Reads a text chunk, with `preview` inflating only that many bytes of compressed text
+fn read_text(chunk: &Chunk, preview: Option<usize>) -> Result<Option<(String, String)>> {
+    let inflate = |compressed: &[u8]| match preview {
+        Some(len) => zlib::inflate_prefix(compressed, len),
+        None => zlib::inflate(compressed, MAX_TEXT_LEN),
+    };
+    let chunk_type = chunk.chunk_type().to_string();
+    if !TEXT_CHUNKS.contains(&chunk_type.as_str()) {
+        return Ok(None);
+    }
+    let data = chunk.data();
+    let separator = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| format!("{} chunk has no keyword terminator", chunk_type))?;
+    let keyword: String = data[..separator].iter().map(|&b| b as char).collect();
+    let rest = &data[separator + 1..];
+
+    let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| b as char).collect();
+    match chunk_type.as_str() {
+        "tEXt" => return Ok(Some((keyword, latin1(rest)))),
+        "zTXt" => {
+            let (&method, compressed) = rest.split_first().ok_or("zTXt chunk is truncated")?;
+            if method != 0 {
+                return Err(format!("Unknown zTXt compression method {}", method).into());
+            }
+            return Ok(Some((keyword, latin1(&inflate(compressed)?))));
+        }
+        _ => {}
+    }
+
+    let truncated = || "iTXt chunk is truncated";
+    let (&compressed, rest) = rest.split_first().ok_or_else(truncated)?;
+    let rest = rest.get(1..).ok_or_else(truncated)?;
+    // Skip the language tag and translated keyword
+    let text = rest.splitn(3, |&b| b == 0).nth(2).ok_or_else(truncated)?;
+    let text = if compressed == 1 {
+        inflate(text)?
+    } else {
+        text.to_vec()
+    };
+    let text = match String::from_utf8(text) {
+        Ok(text) => text,
+        // A preview may end in the middle of a character
+        Err(e) if preview.is_some() && e.utf8_error().error_len().is_none() => {
+            let valid = e.utf8_error().valid_up_to();
+            String::from_utf8(e.into_bytes()[..valid].to_vec())?
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some((keyword, text)))
+}
+
+/// Text stored under `keyword` in any `tEXt`, `zTXt` or `iTXt` chunk
+pub fn find_text(png: &Png, keyword: &str) -> Result<Option<String>> {
+    for chunk in png.chunks() {
+        if let Some((found, text)) = read_text_chunk(chunk)?
+            && found == keyword
+        {
+            return Ok(Some(text));
+        }
+    }
+    Ok(None)
+}
+
+/// `text` on one line of at most `limit` characters, for listings: line breaks and other
+/// control characters become spaces and longer text is cut with an ellipsis
+pub fn shorten(text: &str, limit: usize) -> String {
+    let flat: String = text
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if flat.chars().count() <= limit {
+        return flat;
+    }
+    let cut: String = flat.chars().take(limit.saturating_sub(1)).collect();
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shorten() {
+        assert_eq!(shorten("GIMP 2.10", 20), "GIMP 2.10");
+        assert_eq!(shorten("two\nlines\n", 20), "two lines");
+        assert_eq!(
+            shorten("Created with a very long tool name", 12),
+            "Created wit…"
+        );
+        assert_eq!(shorten("ééééé", 4), "ééé…");
+    }
+
+    #[test]
+    fn test_latin1_text_uses_text_chunk() {
+        let chunk = text_chunk("Comment", "café", false).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Comment\0caf\xe9");
+        assert_eq!(
+            read_text_chunk(&chunk).unwrap(),
+            Some(("Comment".to_string(), "café".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_unicode_text_uses_itxt_chunk() {
+        let chunk = text_chunk("Title", "秘密 🔐", false).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert!(chunk.data().starts_with(b"Title\0\0\0\0\0"));
+        assert_eq!(
+            read_text_chunk(&chunk).unwrap(),
+            Some(("Title".to_string(), "秘密 🔐".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_compressed_chunks_round_trip() {
+        let long = "Lorem ipsum dolor sit amet. ".repeat(40);
+        let chunk = text_chunk("Description", &long, true).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().len() < long.len() / 4);
+        assert_eq!(read_text_chunk(&chunk).unwrap().unwrap().1, long);
+
+        let chunk = text_chunk("Title", "秘密 🔐", true).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        assert_eq!(chunk.data()[6], 1);
+        assert_eq!(read_text_chunk(&chunk).unwrap().unwrap().1, "秘密 🔐");
+    }
+
+    #[test]
+    fn test_preview_inflates_only_the_start() {
+        let long = "Lorem ipsum dolor sit amet. ".repeat(40);
+        let chunk = text_chunk("Description", &long, true).unwrap();
+        let (keyword, preview) = read_text_preview(&chunk, 10).unwrap().unwrap();
+        assert_eq!(keyword, "Description");
+        assert_eq!(preview, long[..44]);
+
+        // Cut in the middle of the four-byte emoji
+        let chunk = text_chunk("Title", "秘密秘密秘密 🔐🔐🔐", true).unwrap();
+        let (_, preview) = read_text_preview(&chunk, 5).unwrap().unwrap();
+        assert_eq!(preview, "秘密秘密秘密 🔐");
+    }
+
+    #[test]
+    fn test_reads_ztxt_from_other_tools() {
+        // zTXt "Software" chunk as written by zlib at the default level
+        let mut data = b"Software\0\0".to_vec();
+        data.extend([
+            0x78, 0x9c, 0x73, 0xf7, 0xf4, 0x0d, 0x50, 0x30, 0xd2, 0x33, 0x34, 0xd0, 0x33, 0x02,
+            0x00, 0x0f, 0xfb, 0x02, 0x6f,
+        ]);
+        let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), data);
+        assert_eq!(
+            read_text_chunk(&chunk).unwrap(),
+            Some(("Software".to_string(), "GIMP 2.10.2".to_string()))
+        );
+
+        let bad_method = Chunk::new(ChunkType::from_str("zTXt").unwrap(), b"k\0\x01x".to_vec());
+        assert!(read_text_chunk(&bad_method).is_err());
+    }
+
+    #[test]
+    fn test_reads_compressed_itxt() {
+        let mut data = b"Description\0\x01\x00en\0Beschreibung\0".to_vec();
+        data.extend(zlib::deflate("compressed text".as_bytes()));
+        let chunk = Chunk::new(ChunkType::from_str("iTXt").unwrap(), data);
+        assert_eq!(
+            read_text_chunk(&chunk).unwrap().unwrap().1,
+            "compressed text"
+        );
+    }
+
+    #[test]
+    fn test_validate_keyword() {
+        assert!(validate_keyword("Author").is_ok());
+        assert!(validate_keyword("Creation Time").is_ok());
+        for keyword in ["", " lead", "trail ", "dou  ble", "tab\there", "日本"] {
+            assert!(validate_keyword(keyword).is_err(), "{:?}", keyword);
+        }
+        assert!(validate_keyword(&"k".repeat(80)).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::io;
+use std::path::Path;
+
+/// Moves `path` to the trash of the desktop, from where it can be put back, instead of
+/// deleting it
+pub fn move_to_trash(path: &Path) -> io::Result<()> {
+    platform::move_to_trash(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot move it to the trash: {}", e)))
+}
+
+/// Renames `from` to `to`, copying it when they are on different filesystems
+#[cfg(unix)]
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            std::fs::copy(from, to)?;
+            std::fs::remove_file(from)
+        }
+        moved => moved,
+    }
+}
+
+/// The trash of the freedesktop.org specification used by Linux and BSD desktops: the
+/// file goes to `Trash/files` with a `.trashinfo` in `Trash/info` recording where it
+/// came from
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use crate::date;
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    fn trash_dir() -> io::Result<PathBuf> {
+        let data = std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| Some(Path::new(&std::env::var_os("HOME")?).join(".local/share")))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        Ok(data.join("Trash"))
+    }
+
+    /// `path` percent-encoded as the `Path=` key of a `.trashinfo` wants it
+    pub(super) fn encode_path(path: &Path) -> String {
+        path.as_os_str()
+            .as_bytes()
+            .iter()
+            .map(|&byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    /// Contents of the `.trashinfo` of `path`, trashed at `timestamp`
+    pub(super) fn trash_info(path: &Path, timestamp: u64) -> String {
+        let deleted = date::format_timestamp(timestamp);
+        let deleted = deleted.trim_end_matches(" UTC").replacen(' ', "T", 1);
+        format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(path),
+            deleted
+        )
+    }
+
+    pub fn move_to_trash(path: &Path) -> io::Result<()> {
+        let trash = trash_dir()?;
+        let (files, info) = (trash.join("files"), trash.join("info"));
+        fs::create_dir_all(&files)?;
+        fs::create_dir_all(&info)?;
+
+        let absolute = std::path::absolute(path)?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+
+        // A name is claimed by creating its .trashinfo, so that files of the same name
+        // trashed at once do not overwrite each other
+        let mut counter = 1;
+        loop {
+            let mut trashed = name.to_owned();
+            if counter > 1 {
+                trashed.push(format!(".{}", counter));
+            }
+            let mut info_name = trashed.clone();
+            info_name.push(".trashinfo");
+            let info_path = info.join(info_name);
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_path)
+            {
+                Ok(mut file) => {
+                    let moved = file
+                        .write_all(trash_info(&absolute, date::now()).as_bytes())
+                        .and_then(|()| super::move_file(path, &files.join(&trashed)));
+                    if moved.is_err() {
+                        let _ = fs::remove_file(&info_path);
+                    }
+                    return moved;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => counter += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// The Finder's trash, `~/.Trash`
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::io;
+    use std::path::Path;
+
+    pub fn move_to_trash(path: &Path) -> io::Result<()> {
+        let home = std::env::var_os("HOME")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+        let trash = Path::new(&home).join(".Trash");
+        std::fs::create_dir_all(&trash)?;
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+        let mut trashed = trash.join(name);
+        let mut counter = 1;
+        while trashed.exists() {
+            counter += 1;
+            let mut numbered = name.to_owned();
+            numbered.push(format!(" {}", counter));
+            trashed = trash.join(numbered);
+        }
+        super::move_file(path, &trashed)
+    }
+}
+
+/// The Recycle Bin, through the shell so that Explorer can restore the file
+#[cfg(windows)]
+mod platform {
+    use std::ffi::c_void;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    const FO_DELETE: u32 = 3;
+    const FOF_SILENT: u16 = 0x0004;
+    const FOF_NOCONFIRMATION: u16 = 0x0010;
+    const FOF_ALLOWUNDO: u16 = 0x0040;
+    const FOF_NOERRORUI: u16 = 0x0400;
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    struct SHFILEOPSTRUCTW {
+        hwnd: *mut c_void,
+        wFunc: u32,
+        pFrom: *const u16,
+        pTo: *const u16,
+        fFlags: u16,
+        fAnyOperationsAborted: i32,
+        hNameMappings: *mut c_void,
+        lpszProgressTitle: *const u16,
+    }
+
+    #[link(name = "shell32")]
+    unsafe extern "system" {
+        fn SHFileOperationW(operation: *mut SHFILEOPSTRUCTW) -> i32;
+    }
+
+    pub fn move_to_trash(path: &Path) -> io::Result<()> {
+        // An absolute path, ended by two NULs as the list of files it takes
+        let mut from: Vec<u16> = std::path::absolute(path)?
+            .as_os_str()
+            .encode_wide()
+            .collect();
+        from.extend([0, 0]);
+        let mut operation = SHFILEOPSTRUCTW {
+            hwnd: std::ptr::null_mut(),
+            wFunc: FO_DELETE,
+            pFrom: from.as_ptr(),
+            pTo: std::ptr::null(),
+            fFlags: FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_SILENT | FOF_NOERRORUI,
+            fAnyOperationsAborted: 0,
+            hNameMappings: std::ptr::null_mut(),
+            lpszProgressTitle: std::ptr::null(),
+        };
+        match unsafe { SHFileOperationW(&mut operation) } {
+            0 if operation.fAnyOperationsAborted == 0 => Ok(()),
+            0 => Err(io::Error::new(io::ErrorKind::Interrupted, "aborted")),
+            code => Err(io::Error::other(format!("shell error {:#x}", code))),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(all(unix, not(target_os = "macos")))]
+mod tests {
+    use super::platform::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(
+            encode_path(Path::new("/photos/holiday 2024/ä.png")),
+            "/photos/holiday%202024/%C3%A4.png"
+        );
+    }
+
+    #[test]
+    fn test_trash_info() {
+        assert_eq!(
+            trash_info(Path::new("/photos/a.png.backup"), 1_709_164_800),
+            "[Trash Info]\nPath=/photos/a.png.backup\nDeletionDate=2024-02-29T00:00:00\n"
+        );
+    }
+}
@@ -0,0 +1,693 @@
+use crate::args::Args;
+use crate::chunk::Chunk;
+use crate::output::{self, Verbosity};
+use crate::payload::{self, Kind};
+use crate::png::Png;
+use crate::{Result, prompt, text};
+use clap::Parser;
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+
+/// Width of the chunk list on the left of the screen
+const LIST_WIDTH: usize = 28;
+
+/// A key pressed in the chunk browser
+#[derive(Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Tab,
+    Char(char),
+    Other,
+}
+
+/// Reads a key from `input` in raw mode, where arrows and paging keys arrive as escape
+/// sequences such as `ESC [ A`
+fn read_key(input: &mut impl Read) -> io::Result<Key> {
+    let mut byte = [0u8];
+    input.read_exact(&mut byte)?;
+    let key = match byte[0] {
+        0x1b => {
+            let mut sequence = [0u8; 2];
+            if input.read(&mut sequence[..1])? == 0 || !matches!(sequence[0], b'[' | b'O') {
+                return Ok(Key::Other);
+            }
+            input.read_exact(&mut sequence[1..])?;
+            match sequence[1] {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                digit @ b'1'..=b'8' => {
+                    let mut tilde = [0u8];
+                    input.read_exact(&mut tilde)?;
+                    match digit {
+                        b'5' => Key::PageUp,
+                        b'6' => Key::PageDown,
+                        b'1' | b'7' => Key::Home,
+                        b'4' | b'8' => Key::End,
+                        _ => Key::Other,
+                    }
+                }
+                _ => Key::Other,
+            }
+        }
+        b'\t' => Key::Tab,
+        // Ctrl-C arrives as a byte in raw mode
+        0x03 => Key::Char('q'),
+        byte if byte.is_ascii_graphic() => Key::Char(byte as char),
+        _ => Key::Other,
+    };
+    Ok(key)
+}
+
+/// What the preview pane shows of the selected chunk
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    Text,
+    Hex,
+}
+
+/// The chunk browser of `interactive`
+struct Browser {
+    chunks: Vec<Chunk>,
+    selected: usize,
+    /// First chunk shown in the list, which scrolls to keep the selection in view
+    scroll: usize,
+    view: View,
+}
+
+impl Browser {
+    fn new(chunks: Vec<Chunk>) -> Self {
+        Browser {
+            chunks,
+            selected: 0,
+            scroll: 0,
+            view: View::Text,
+        }
+    }
+
+    /// Moves the selection by `delta` chunks, stopping at either end
+    fn select(&mut self, delta: isize) {
+        let last = self.chunks.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// How many chunks before the selected one have its type, which is the `--index`
+    /// that `remove` takes for it
+    fn index_of_type(&self) -> usize {
+        let chunk_type = self.chunks[self.selected].chunk_type();
+        self.chunks[..self.selected]
+            .iter()
+            .filter(|chunk| chunk.chunk_type() == chunk_type)
+            .count()
+    }
+
+    /// The screen as `height` lines of at most `width` characters: a title, the chunk
+    /// list beside the preview of the selected chunk, and the keys at the bottom
+    fn render(&mut self, title: &str, width: usize, height: usize) -> Vec<String> {
+        let rows = height.saturating_sub(3).max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+        let preview_width = width.saturating_sub(LIST_WIDTH + 3).max(1);
+        let preview = match self.chunks.get(self.selected) {
+            Some(chunk) => preview(chunk, self.view, preview_width),
+            None => vec!["No chunks".to_string()],
+        };
+
+        let mut lines = vec![fit(title, width), "-".repeat(width)];
+        for row in 0..rows {
+            let index = self.scroll + row;
+            let item = match self.chunks.get(index) {
+                Some(chunk) => fit(
+                    &format!(
+                        "{:>3} {} {:>10}",
+                        index,
+                        chunk.chunk_type(),
+                        format!("{} B", chunk.data().len())
+                    ),
+                    LIST_WIDTH,
+                ),
+                None => " ".repeat(LIST_WIDTH),
+            };
+            let item = if index == self.selected && index < self.chunks.len() {
+                format!("\x1b[7m{}\x1b[0m", item)
+            } else {
+                item
+            };
+            let detail = preview.get(row).map(String::as_str).unwrap_or_default();
+            lines.push(format!("{} | {}", item, fit(detail, preview_width)));
+        }
+        lines.push(fit(
+            "Up/Down select  Tab text/hex  e encode  d remove  r restore  q quit",
+            width,
+        ));
+        lines
+    }
+}
+
+/// `text` cut or padded to `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted: String = text.chars().take(width).collect();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(' ', width - len));
+    fitted
+}
+
+/// Hex dump of `data` in rows that fit in `width` characters, each with its offset and
+/// the printable ASCII of its bytes
+fn hex_dump(data: &[u8], width: usize) -> Vec<String> {
+    // Offset, then three characters and one ASCII column for each byte
+    let per_row = if width >= 10 + 16 * 4 { 16 } else { 8 };
+    data.chunks(per_row)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {:<width$} {}",
+                row * per_row,
+                hex.join(" "),
+                ascii,
+                width = per_row * 3 - 1
+            )
+        })
+        .collect()
+}
+
+/// `text` broken into lines of at most `width` characters
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    text.lines()
+        .flat_map(|line| {
+            let chars: Vec<char> = line.chars().collect();
+            if chars.is_empty() {
+                return vec![String::new()];
+            }
+            chars
+                .chunks(width)
+                .map(|part| part.iter().collect())
+                .collect()
+        })
+        .collect()
+}
+
+/// The preview pane for `chunk`: a readable description and its text where it holds
+/// some, or a hex dump
+fn preview(chunk: &Chunk, view: View, width: usize) -> Vec<String> {
+    let mut lines = vec![format!(
+        "{} chunk, {} bytes, CRC {:08x}",
+        chunk.chunk_type(),
+        chunk.data().len(),
+        chunk.crc()
+    )];
+    if view == View::Hex {
+        lines.extend(hex_dump(chunk.data(), width));
+        return lines;
+    }
+    let text = match text::read_text_chunk(chunk) {
+        Ok(Some((keyword, value))) => Some(format!("{}: {}", keyword, value)),
+        _ => match payload::unwrap(chunk.data()) {
+            Ok((header, body)) if header.version > 0 => {
+                lines.push(format!("Hidden message: {}", header.kind.describe()));
+                (header.kind == Kind::Plain && header.compression.is_none())
+                    .then(|| std::str::from_utf8(body).ok().map(str::to_string))
+                    .flatten()
+            }
+            _ => std::str::from_utf8(chunk.data())
+                .ok()
+                .filter(|text| !text.chars().any(|c| c.is_control() && c != '\n'))
+                .map(str::to_string),
+        },
+    };
+    match text {
+        Some(text) => {
+            lines.push(String::new());
+            lines.extend(wrap(&text, width));
+        }
+        None => {
+            lines.push("Binary data; Tab shows it in hex".to_string());
+        }
+    }
+    lines
+}
+
+/// The command line of `command` with `options`, followed by `values` such as a path or
+/// a typed message after `--`, so that values starting with a dash are never options
+fn command_line(command: &str, options: &[OsString], values: &[OsString]) -> Vec<OsString> {
+    let mut line = vec![OsString::from(env!("CARGO_PKG_NAME")), command.into()];
+    line.extend(options.iter().cloned());
+    line.push("--".into());
+    line.extend(values.iter().cloned());
+    line
+}
+
+/// Runs the hidden-pixel-vault command given by `line`, with its usual output
+fn run_command(line: &[OsString]) -> Result<()> {
+    let args = Args::try_parse_from(line)?;
+    crate::run(&args.command)
+}
+
+/// Browses the chunks of the PNG file at `path` full screen, with keys to encode a
+/// message, remove the selected chunk or restore the newest backup
+pub fn interactive(path: &Path) -> Result<()> {
+    if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        return Err("interactive needs a terminal; use the other commands in scripts".into());
+    }
+    let load = || -> Result<Vec<Chunk>> {
+        let data = std::fs::read(path)
+            .map_err(|e| format!("Failed to read file '{}': {}", path.display(), e))?;
+        let mut png = Png::try_from(data.as_slice())
+            .map_err(|e| format!("interactive works on PNG files: {}", e))?;
+        Ok(std::mem::take(png.chunks_mut()))
+    };
+    let mut browser = Browser::new(load()?);
+    let title = format!("Hidden Pixel Vault: {}", path.display());
+    let path = path.as_os_str().to_owned();
+
+    let mut screen = Screen::enter()?;
+    loop {
+        let (width, height) = terminal::size().unwrap_or((80, 24));
+        screen.draw(&browser.render(&title, width, height))?;
+        let rows = height.saturating_sub(3).max(1) as isize;
+        let action: Vec<OsString> = match read_key(&mut terminal::input()?)? {
+            Key::Char('q') => return Ok(()),
+            Key::Up | Key::Char('k') => {
+                browser.select(-1);
+                continue;
+            }
+            Key::Down | Key::Char('j') => {
+                browser.select(1);
+                continue;
+            }
+            Key::PageUp => {
+                browser.select(-rows);
+                continue;
+            }
+            Key::PageDown => {
+                browser.select(rows);
+                continue;
+            }
+            Key::Home => {
+                browser.selected = 0;
+                continue;
+            }
+            Key::End => {
+                browser.select(isize::MAX);
+                continue;
+            }
+            Key::Tab => {
+                browser.view = match browser.view {
+                    View::Text => View::Hex,
+                    View::Hex => View::Text,
+                };
+                continue;
+            }
+            Key::Char('e') => {
+                screen.leave()?;
+                let chunk_type = prompt::read_line("Chunk type (e.g. ruSt): ")?;
+                let message = prompt::read_line("Message: ")?;
+                command_line(
+                    "encode",
+                    &[],
+                    &[path.clone(), chunk_type.into(), message.into()],
+                )
+            }
+            Key::Char('d') if !browser.chunks.is_empty() => {
+                let chunk = &browser.chunks[browser.selected];
+                screen.leave()?;
+                let question = format!(
+                    "Remove chunk {} ('{}', {} bytes)? [y/N] ",
+                    browser.selected,
+                    chunk.chunk_type(),
+                    chunk.data().len()
+                );
+                if !prompt::read_line(&question)?.eq_ignore_ascii_case("y") {
+                    screen.enter_again()?;
+                    continue;
+                }
+                command_line(
+                    "remove",
+                    &["--index".into(), browser.index_of_type().to_string().into()],
+                    &[path.clone(), chunk.chunk_type().to_string().into()],
+                )
+            }
+            Key::Char('r') => {
+                screen.leave()?;
+                if !prompt::read_line("Restore the newest backup? [y/N] ")?
+                    .eq_ignore_ascii_case("y")
+                {
+                    screen.enter_again()?;
+                    continue;
+                }
+                command_line("restore", &[], std::slice::from_ref(&path))
+            }
+            _ => continue,
+        };
+
+        // The command prints as usual, and the screen comes back once that is read
+        if let Err(e) = run_command(&action) {
+            output::line(Verbosity::Quiet, format!(" ❌ Error: {}", e));
+        }
+        prompt::read_line("Press Enter to go back")?;
+        browser.chunks = load()?;
+        browser.select(0);
+        screen.enter_again()?;
+    }
+}
+
+/// The terminal in full-screen raw mode while browsing, put back as it was when dropped
+struct Screen {
+    saved: Option<terminal::Mode>,
+}
+
+impl Screen {
+    fn enter() -> Result<Screen> {
+        let mut screen = Screen { saved: None };
+        screen.enter_again()?;
+        Ok(screen)
+    }
+
+    /// Switches to the alternate screen in raw mode, after `leave`
+    fn enter_again(&mut self) -> Result<()> {
+        self.saved = Some(terminal::enable_raw_mode()?);
+        // Alternate screen, cursor hidden
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(())
+    }
+
+    /// Back to the normal screen and line input, e.g. to run a command
+    fn leave(&mut self) -> Result<()> {
+        if let Some(mode) = self.saved.take() {
+            print!("\x1b[?25h\x1b[?1049l");
+            io::stdout().flush()?;
+            terminal::restore(mode)?;
+        }
+        Ok(())
+    }
+
+    fn draw(&self, lines: &[String]) -> Result<()> {
+        let mut out = io::stdout().lock();
+        write!(out, "\x1b[H")?;
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 {
+                // Raw mode does not return to the start of the line by itself
+                write!(out, "\r\n")?;
+            }
+            write!(out, "{}\x1b[K", line)?;
+        }
+        write!(out, "\x1b[J")?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.leave();
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use crate::Result;
+    use std::fs::File;
+    use std::process::{Command, Stdio};
+
+    /// The settings of the terminal before raw mode, as `stty -g` prints them
+    pub struct Mode(String);
+
+    fn stty(args: &[&str]) -> Result<String> {
+        let output = Command::new("stty")
+            .args(args)
+            .stdin(File::open("/dev/tty")?)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("Failed to run stty: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("stty exited with {}", output.status).into());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    pub fn enable_raw_mode() -> Result<Mode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(Mode(saved))
+    }
+
+    pub fn restore(mode: Mode) -> Result<()> {
+        stty(&[&mode.0]).map(|_| ())
+    }
+
+    /// Columns and rows of the terminal
+    pub fn size() -> Option<(usize, usize)> {
+        let size = stty(&["size"]).ok()?;
+        let (rows, columns) = size.split_once(' ')?;
+        Some((columns.parse().ok()?, rows.parse().ok()?))
+    }
+
+    /// Where keys are read from: the terminal, even if stdin were redirected
+    pub fn input() -> Result<File> {
+        Ok(File::open("/dev/tty")?)
+    }
+}
+
+#[cfg(windows)]
+mod terminal {
+    use crate::Result;
+    use std::ffi::c_void;
+
+    const STD_INPUT_HANDLE: u32 = -10i32 as u32;
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+    const ENABLE_LINE_INPUT: u32 = 0x0002;
+    const ENABLE_ECHO_INPUT: u32 = 0x0004;
+    const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
+    const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Coord {
+        x: i16,
+        y: i16,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SmallRect {
+        left: i16,
+        top: i16,
+        right: i16,
+        bottom: i16,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct ConsoleScreenBufferInfo {
+        size: Coord,
+        cursor_position: Coord,
+        attributes: u16,
+        window: SmallRect,
+        maximum_window_size: Coord,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetStdHandle(std_handle: u32) -> *mut c_void;
+        fn GetConsoleMode(console: *mut c_void, mode: *mut u32) -> i32;
+        fn SetConsoleMode(console: *mut c_void, mode: u32) -> i32;
+        fn GetConsoleScreenBufferInfo(
+            console: *mut c_void,
+            info: *mut ConsoleScreenBufferInfo,
+        ) -> i32;
+    }
+
+    /// The console modes of input and output before raw mode
+    pub struct Mode(u32, u32);
+
+    fn mode(handle: *mut c_void) -> Result<u32> {
+        let mut mode = 0u32;
+        if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+            return Err("No console available for interactive mode".into());
+        }
+        Ok(mode)
+    }
+
+    pub fn enable_raw_mode() -> Result<Mode> {
+        let input = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+        let output = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+        let saved = Mode(mode(input)?, mode(output)?);
+        let raw = (saved.0 & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT))
+            | ENABLE_VIRTUAL_TERMINAL_INPUT;
+        unsafe {
+            SetConsoleMode(input, raw);
+            SetConsoleMode(output, saved.1 | ENABLE_VIRTUAL_TERMINAL_PROCESSING);
+        }
+        Ok(saved)
+    }
+
+    pub fn restore(mode: Mode) -> Result<()> {
+        unsafe {
+            SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), mode.0);
+            SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), mode.1);
+        }
+        Ok(())
+    }
+
+    /// Columns and rows of the console window
+    pub fn size() -> Option<(usize, usize)> {
+        let mut info = ConsoleScreenBufferInfo::default();
+        let output = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+        if unsafe { GetConsoleScreenBufferInfo(output, &mut info) } == 0 {
+            return None;
+        }
+        let columns = info.window.right - info.window.left + 1;
+        let rows = info.window.bottom - info.window.top + 1;
+        Some((columns as usize, rows as usize))
+    }
+
+    pub fn input() -> Result<std::io::Stdin> {
+        Ok(std::io::stdin())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_read_key() {
+        let keys = b"\x1b[A\x1b[B\x1b[5~\x1b[6~\x1bOH\x1b[4~\tq\x03\x01";
+        let mut input = &keys[..];
+        let mut read = vec![];
+        while !input.is_empty() {
+            read.push(read_key(&mut input).unwrap());
+        }
+        assert_eq!(
+            read,
+            [
+                Key::Up,
+                Key::Down,
+                Key::PageUp,
+                Key::PageDown,
+                Key::Home,
+                Key::End,
+                Key::Tab,
+                Key::Char('q'),
+                Key::Char('q'),
+                Key::Other,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let lines = hex_dump(b"Hidden Pixel Vault\x00\xff", 80);
+        assert_eq!(
+            lines,
+            [
+                "00000000  48 69 64 64 65 6e 20 50 69 78 65 6c 20 56 61 75 Hidden Pixel Vau",
+                "00000010  6c 74 00 ff                                     lt..",
+            ]
+        );
+        assert_eq!(hex_dump(&[0; 9], 40).len(), 2);
+    }
+
+    #[test]
+    fn test_preview() {
+        let text = preview(&chunk("tEXt", b"Title\0Holiday"), View::Text, 40);
+        assert_eq!(text.last().unwrap(), "Title: Holiday");
+
+        let message = payload::wrap(&payload::Header::new(Kind::Plain), b"meet at noon");
+        let lines = preview(&chunk("ruSt", &message), View::Text, 40);
+        assert!(lines.contains(&"meet at noon".to_string()), "{:?}", lines);
+
+        let binary = preview(&chunk("ruSt", &[0, 1, 2]), View::Text, 40);
+        assert_eq!(binary.last().unwrap(), "Binary data; Tab shows it in hex");
+        let hex = preview(&chunk("ruSt", &[0, 1, 2]), View::Hex, 40);
+        assert!(hex[1].starts_with("00000000  00 01 02"));
+    }
+
+    #[test]
+    fn test_render_scrolls_to_the_selection() {
+        let chunks = (0..20).map(|i| chunk("ruSt", &[i])).collect();
+        let mut browser = Browser::new(chunks);
+        browser.select(15);
+        let lines = browser.render("title", 60, 10);
+        assert_eq!(lines.len(), 10);
+        // 7 chunks fit between the title and the keys, the selected one last
+        assert!(lines[8].contains(" 15 ruSt"), "{:?}", lines);
+        assert!(lines[8].starts_with("\x1b[7m"));
+        browser.select(-100);
+        assert_eq!(browser.selected, 0);
+        browser.render("title", 60, 10);
+        assert_eq!(browser.scroll, 0);
+    }
+
+    #[test]
+    fn test_typed_values_are_never_options() {
+        let line = command_line(
+            "encode",
+            &[],
+            &["-image.png".into(), "ruSt".into(), "--help".into()],
+        );
+        let Ok(Args {
+            command:
+                crate::args::Commands::Encode {
+                    path,
+                    chunk_type,
+                    message,
+                    ..
+                },
+            ..
+        }) = Args::try_parse_from(&line)
+        else {
+            panic!("{:?} did not parse as encode", line);
+        };
+        assert_eq!(path, Path::new("-image.png"));
+        assert_eq!(chunk_type, "ruSt");
+        assert_eq!(message.message.as_deref(), Some("--help"));
+
+        let line = command_line(
+            "remove",
+            &["--index".into(), "2".into()],
+            &["image.png".into(), "ruSt".into()],
+        );
+        assert!(Args::try_parse_from(&line).is_ok());
+    }
+
+    #[test]
+    fn test_index_of_type() {
+        let mut browser = Browser::new(vec![
+            chunk("ruSt", b"a"),
+            chunk("tEXt", b"b\0c"),
+            chunk("ruSt", b"d"),
+        ]);
+        browser.select(2);
+        assert_eq!(browser.index_of_type(), 1);
+    }
+}
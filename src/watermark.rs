@@ -0,0 +1,253 @@
+use crate::pixels::Pixels;
+use crate::{Result, crypto};
+use crc::{CRC_16_IBM_3740, Crc};
+
+/// Longest identifier a watermark can carry, in bytes
+pub const MAX_ID_LEN: usize = 12;
+/// Default quantization step of the block means: larger steps survive harsher
+/// recompression but shift block brightness more visibly
+pub const DEFAULT_STRENGTH: u8 = 12;
+/// Share of blocks that must agree with an expected identifier for it to count as present
+/// when the watermark is too damaged to read outright. Unmarked images agree about half
+/// the time, identifiers sharing a long prefix with the embedded one up to about 80%.
+pub const MATCH_THRESHOLD: f64 = 0.85;
+const BLOCK_SIZE: usize = 8;
+/// Identifier length (1) | identifier, padded to MAX_ID_LEN | CRC-16 of both (2)
+const PAYLOAD_LEN: usize = 1 + MAX_ID_LEN + 2;
+const PAYLOAD_BITS: usize = PAYLOAD_LEN * 8;
+/// Fewest copies of each bit for the majority vote to outweigh a few damaged blocks
+const MIN_COPIES: usize = 3;
+/// Scatters the bits when no key is given, so anyone can read such watermarks
+const DEFAULT_KEY: &[u8] = b"hidden-pixel-vault/watermark";
+const LABEL: &str = "watermark";
+const CRC16: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+/// What `detect` read from an image
+pub struct Detection {
+    /// The identifier, if the voted bits formed a valid payload
+    pub id: Option<String>,
+    /// Share of blocks agreeing with the majority vote of their bit
+    pub agreement: f64,
+}
+
+/// Hides `id` in the mean brightness of 8x8 blocks, each payload bit repeated over many
+/// blocks in an order derived from `key`. Block means barely move when an image is
+/// re-saved or mildly recompressed, unlike low bits. Returns the copies of each bit.
+pub fn embed(pixels: &mut Pixels, id: &str, key: Option<&str>, strength: u8) -> Result<usize> {
+    let payload = payload_bits(id)?;
+    let blocks = scattered_blocks(pixels, key)?;
+    let step = strength as f64;
+    for (i, block) in blocks.iter().enumerate() {
+        let bit = payload[i % PAYLOAD_BITS];
+        let mean = block_mean(pixels, block);
+        // Nearest point of the lattice for this bit: multiples of the step for 0,
+        // half a step off for 1, kept inside the sample range
+        let offset = if bit { step / 2.0 } else { 0.0 };
+        let mut target = ((mean - offset) / step).round() * step + offset;
+        if target < 0.0 {
+            target += step;
+        } else if target > 255.0 {
+            target -= step;
+        }
+
+        // Spread the change over the samples so their sum lands exactly on the target
+        let sum: i64 = block.iter().map(|&offset| pixels.byte(offset) as i64).sum();
+        let total = (target * block.len() as f64).round() as i64 - sum;
+        let count = block.len() as i64;
+        for (j, &offset) in block.iter().enumerate() {
+            let j = j as i64;
+            let change = total * (j + 1) / count - total * j / count;
+            let value = (pixels.byte(offset) as i64 + change).clamp(0, 255);
+            pixels.set_byte(offset, value as u8);
+        }
+    }
+    Ok(blocks.len() / PAYLOAD_BITS)
+}
+
+/// Reads back the identifier hidden by [`embed`] with the same key and strength
+pub fn detect(pixels: &Pixels, key: Option<&str>, strength: u8) -> Result<Detection> {
+    let readings = read_blocks(pixels, key, strength)?;
+    let mut votes = [0i64; PAYLOAD_BITS];
+    for (i, &bit) in readings.iter().enumerate() {
+        votes[i % PAYLOAD_BITS] += if bit { 1 } else { -1 };
+    }
+    let bits: Vec<bool> = votes.iter().map(|&vote| vote > 0).collect();
+    let agreeing = readings
+        .iter()
+        .enumerate()
+        .filter(|&(i, &bit)| bit == bits[i % PAYLOAD_BITS])
+        .count();
+    Ok(Detection {
+        id: parse_payload(&bits),
+        agreement: agreeing as f64 / readings.len() as f64,
+    })
+}
+
+/// Share of blocks carrying the bits of `id`, which is only reliable near 0.5 or 1
+pub fn match_rate(pixels: &Pixels, id: &str, key: Option<&str>, strength: u8) -> Result<f64> {
+    let expected = payload_bits(id)?;
+    let readings = read_blocks(pixels, key, strength)?;
+    let matching = readings
+        .iter()
+        .enumerate()
+        .filter(|&(i, &bit)| bit == expected[i % PAYLOAD_BITS])
+        .count();
+    Ok(matching as f64 / readings.len() as f64)
+}
+
+/// The bit each block's mean is closest to
+fn read_blocks(pixels: &Pixels, key: Option<&str>, strength: u8) -> Result<Vec<bool>> {
+    let step = strength as f64;
+    Ok(scattered_blocks(pixels, key)?
+        .iter()
+        .map(|block| {
+            let phase = (block_mean(pixels, block) / step).rem_euclid(1.0);
+            (0.25..0.75).contains(&phase)
+        })
+        .collect())
+}
+
+fn payload_bits(id: &str) -> Result<Vec<bool>> {
+    if id.is_empty() || id.len() > MAX_ID_LEN {
+        return Err(format!(
+            "Watermark identifier must be 1 to {} bytes long, got {}",
+            MAX_ID_LEN,
+            id.len()
+        )
+        .into());
+    }
+    let mut payload = vec![id.len() as u8];
+    payload.extend(id.as_bytes());
+    // Pad with bytes derived from the identifier rather than zeros, so the bits of two
+    // different identifiers match about half the time, as an unmarked image's do
+    let mut state = CRC16.checksum(id.as_bytes()) as u32;
+    while payload.len() < 1 + MAX_ID_LEN {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        payload.push((state >> 16) as u8);
+    }
+    payload.extend(CRC16.checksum(&payload).to_be_bytes());
+    Ok(payload
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .collect())
+}
+
+fn parse_payload(bits: &[bool]) -> Option<String> {
+    let bytes: Vec<u8> = bits
+        .chunks_exact(8)
+        .map(|byte| byte.iter().fold(0, |value, &bit| (value << 1) | bit as u8))
+        .collect();
+    let (data, checksum) = bytes.split_at(1 + MAX_ID_LEN);
+    if CRC16.checksum(data).to_be_bytes() != checksum {
+        return None;
+    }
+    let len = data[0] as usize;
+    if !(1..=MAX_ID_LEN).contains(&len) {
+        return None;
+    }
+    String::from_utf8(data[1..=len].to_vec()).ok()
+}
+
+/// Sample offsets of every full 8x8 block, shuffled by `key`
+fn scattered_blocks(pixels: &Pixels, key: Option<&str>) -> Result<Vec<Vec<usize>>> {
+    let ihdr = &pixels.ihdr;
+    if ihdr.bit_depth != 8 {
+        return Err(format!(
+            "Watermarks need an 8-bit image, this one is {}",
+            ihdr.describe()
+        )
+        .into());
+    }
+    let offsets = pixels.sample_offsets()?;
+    let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+    let channels = offsets.len() / (width * height);
+
+    let mut blocks = Vec::new();
+    for top in (0..height - height % BLOCK_SIZE).step_by(BLOCK_SIZE) {
+        for left in (0..width - width % BLOCK_SIZE).step_by(BLOCK_SIZE) {
+            let mut block = Vec::with_capacity(BLOCK_SIZE * BLOCK_SIZE * channels);
+            for y in top..top + BLOCK_SIZE {
+                let row = (y * width + left) * channels;
+                block.extend(&offsets[row..row + BLOCK_SIZE * channels]);
+            }
+            blocks.push(block);
+        }
+    }
+    if blocks.len() < PAYLOAD_BITS * MIN_COPIES {
+        return Err(format!(
+            "Image is too small for a watermark: it has {} 8x8 blocks but needs {}",
+            blocks.len(),
+            PAYLOAD_BITS * MIN_COPIES
+        )
+        .into());
+    }
+
+    let key = key.map(str::as_bytes).unwrap_or(DEFAULT_KEY);
+    crypto::keyed_shuffle(key, LABEL, &mut blocks)?;
+    Ok(blocks)
+}
+
+fn block_mean(pixels: &Pixels, block: &[usize]) -> f64 {
+    let sum: u64 = block.iter().map(|&offset| pixels.byte(offset) as u64).sum();
+    sum as f64 / block.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixels::tests::test_png;
+
+    fn cover() -> Pixels {
+        Pixels::from_png(&test_png(160, 160, 2, 8)).unwrap()
+    }
+
+    #[test]
+    fn test_embed_detect_round_trip() {
+        let mut pixels = cover();
+        let copies = embed(&mut pixels, "alice-2025", None, DEFAULT_STRENGTH).unwrap();
+        assert_eq!(copies, 400 / PAYLOAD_BITS);
+
+        let detection = detect(&pixels, None, DEFAULT_STRENGTH).unwrap();
+        assert_eq!(detection.id.as_deref(), Some("alice-2025"));
+        assert!(detection.agreement > 0.99);
+        assert!(match_rate(&pixels, "alice-2025", None, DEFAULT_STRENGTH).unwrap() > 0.99);
+        assert!(match_rate(&pixels, "bob", None, DEFAULT_STRENGTH).unwrap() < MATCH_THRESHOLD);
+    }
+
+    #[test]
+    fn test_survives_resave_and_noise() {
+        let png = test_png(160, 160, 2, 8);
+        let mut pixels = Pixels::from_png(&png).unwrap();
+        embed(&mut pixels, "leak-7", Some("studio"), DEFAULT_STRENGTH).unwrap();
+        let mut pixels = Pixels::from_png(&pixels.to_png(png).unwrap()).unwrap();
+
+        // Lossy recompression nudges every sample a little
+        let mut state = 99u32;
+        for offset in pixels.sample_offsets().unwrap() {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let noise = ((state >> 16) % 7) as i16 - 3;
+            let value = (pixels.byte(offset) as i16 + noise).clamp(0, 255);
+            pixels.set_byte(offset, value as u8);
+        }
+
+        let detection = detect(&pixels, Some("studio"), DEFAULT_STRENGTH).unwrap();
+        assert_eq!(detection.id.as_deref(), Some("leak-7"));
+        let wrong_key = detect(&pixels, Some("other"), DEFAULT_STRENGTH).unwrap();
+        assert_eq!(wrong_key.id, None);
+    }
+
+    #[test]
+    fn test_unmarked_and_unsupported_images() {
+        let pixels = cover();
+        assert_eq!(detect(&pixels, None, DEFAULT_STRENGTH).unwrap().id, None);
+        assert!(match_rate(&pixels, "alice", None, DEFAULT_STRENGTH).unwrap() < MATCH_THRESHOLD);
+
+        let mut pixels = cover();
+        assert!(embed(&mut pixels, "", None, DEFAULT_STRENGTH).is_err());
+        assert!(embed(&mut pixels, "much-too-long-id", None, DEFAULT_STRENGTH).is_err());
+        let small = Pixels::from_png(&test_png(64, 64, 2, 8)).unwrap();
+        assert!(detect(&small, None, DEFAULT_STRENGTH).is_err());
+        let deep = Pixels::from_png(&test_png(160, 160, 2, 16)).unwrap();
+        assert!(detect(&deep, None, DEFAULT_STRENGTH).is_err());
+    }
+}
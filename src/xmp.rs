@@ -0,0 +1,143 @@
+use crate::Result;
+use crate::exit::Failure;
+use crate::png::Png;
+use crate::text;
+
+/// iTXt keyword under which PNG files carry their XMP packet
+pub const KEYWORD: &str = "XML:com.adobe.xmp";
+const NAMESPACE: &str = "https://github.com/Gaurav-Sharmaa/hidden-pixel-vault/ns/1.0/";
+const PREFIX: &str = "hpv";
+
+/// Checks that `label` can name an XMP property: an ASCII letter or underscore followed
+/// by letters, digits, '-', '_' or '.'
+pub fn validate_label(label: &str) -> Result<()> {
+    let mut chars = label.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(Failure::Validation.error(format!(
+            "Invalid XMP property name '{}': use ASCII letters, digits, '-', '_' and '.', starting with a letter",
+            label
+        )));
+    }
+    Ok(())
+}
+
+/// Returns `png` with `text` stored as the property `label` of its XMP packet, adding a
+/// packet when the file has none
+pub fn embed(mut png: Png, label: &str, text: &str) -> Result<Png> {
+    validate_label(label)?;
+    let description = format!(
+        "  <rdf:Description rdf:about=\"\" xmlns:{prefix}=\"{NAMESPACE}\">\n   <{prefix}:{label}>{}</{prefix}:{label}>\n  </rdf:Description>\n",
+        escape(text),
+        prefix = PREFIX,
+    );
+
+    let existing = png.chunks().iter().position(|chunk| {
+        matches!(text::read_text_chunk(chunk), Ok(Some((keyword, _))) if keyword == KEYWORD)
+    });
+    let Some(position) = existing else {
+        let packet = format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n{}  </rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>",
+            description
+        );
+        png.insert_ancillary(text::itxt_chunk(KEYWORD, &packet, false)?)?;
+        return Ok(png);
+    };
+
+    // Add a description of its own to the packet written by other tools, keeping theirs
+    let (_, packet) = text::read_text_chunk(&png.chunks()[position])?.unwrap();
+    let end = packet
+        .find("</rdf:RDF>")
+        .ok_or("The XMP packet of this file has no rdf:RDF element")?;
+    let packet = format!("{}{}{}", &packet[..end], description, &packet[end..]);
+    png.chunks_mut()[position] = text::itxt_chunk(KEYWORD, &packet, false)?;
+    Ok(png)
+}
+
+/// Text of the property `label` written by `embed`, or `None` if the file has no such
+/// property
+pub fn extract(png: &Png, label: &str) -> Result<Option<String>> {
+    let Some(packet) = text::find_text(png, KEYWORD)? else {
+        return Ok(None);
+    };
+    let open = format!("<{}:{}>", PREFIX, label);
+    let close = format!("</{}:{}>", PREFIX, label);
+    let Some(start) = packet.find(&open).map(|start| start + open.len()) else {
+        return Ok(None);
+    };
+    let len = packet[start..]
+        .find(&close)
+        .ok_or_else(|| format!("XMP property '{}:{}' is not closed", PREFIX, label))?;
+    Ok(Some(unescape(&packet[start..start + len])))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn test_png() -> Png {
+        let chunks = ["IHDR", "IDAT", "IEND"]
+            .into_iter()
+            .map(|chunk_type| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![0; 4]))
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_embed_creates_packet() {
+        let png = embed(test_png(), "ruSt", "a < b & c").unwrap();
+        let chunk = &png.chunks()[2];
+        assert_eq!(chunk.chunk_type().to_string(), "iTXt");
+        let (keyword, packet) = text::read_text_chunk(chunk).unwrap().unwrap();
+        assert_eq!(keyword, KEYWORD);
+        assert!(packet.contains("<hpv:ruSt>a &lt; b &amp; c</hpv:ruSt>"));
+        assert_eq!(extract(&png, "ruSt").unwrap().unwrap(), "a < b & c");
+        assert_eq!(extract(&png, "miSs").unwrap(), None);
+    }
+
+    #[test]
+    fn test_embed_extends_existing_packet() {
+        let packet = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"><xmp:CreatorTool>GIMP</xmp:CreatorTool></rdf:Description></rdf:RDF></x:xmpmeta>";
+        let mut png = test_png();
+        png.insert_ancillary(text::itxt_chunk(KEYWORD, packet, false).unwrap())
+            .unwrap();
+
+        let png = embed(png, "one", "first").unwrap();
+        let png = embed(png, "two", "second").unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        let packet = text::find_text(&png, KEYWORD).unwrap().unwrap();
+        assert!(packet.contains("<xmp:CreatorTool>GIMP</xmp:CreatorTool>"));
+        assert!(packet.ends_with("</rdf:RDF></x:xmpmeta>"));
+        assert_eq!(extract(&png, "one").unwrap().unwrap(), "first");
+        assert_eq!(extract(&png, "two").unwrap().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_validate_label() {
+        assert!(validate_label("ruSt").is_ok());
+        assert!(validate_label("my_note-2").is_ok());
+        for label in ["", "2go", "with space", "a<b", "ns:name"] {
+            assert!(validate_label(label).is_err(), "{:?}", label);
+        }
+    }
+}
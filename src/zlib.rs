@@ -0,0 +1,224 @@
+use crate::Result;
+use flate2::Compression;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::io::{Read, Write};
+
+/// CMF and FLG bytes in front of the deflate data of a zlib stream
+const ZLIB_HEADER_LEN: usize = 2;
+/// Padding bits after the 3-bit header of a stored block that starts on a byte boundary
+const STORED_PADDING_BITS: usize = 5;
+/// LEN and NLEN of an empty stored block
+const EMPTY_STORED_LENGTHS: [u8; 4] = [0, 0, 0xff, 0xff];
+/// An empty stored block: its header byte followed by its lengths
+const EMPTY_STORED_LEN: usize = 1 + EMPTY_STORED_LENGTHS.len();
+
+/// Checks the two-byte zlib header and returns the deflate data after it
+fn deflate_data(data: &[u8]) -> Result<&[u8]> {
+    let [cmf, flg, ..] = *data else {
+        return Err("zlib stream is truncated".into());
+    };
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || !(u16::from_be_bytes([cmf, flg])).is_multiple_of(31) {
+        return Err("Invalid zlib header".into());
+    }
+    Ok(&data[ZLIB_HEADER_LEN..])
+}
+
+/// Reads `decoder` to the end, failing once the output would grow beyond `max_output`
+/// bytes
+fn read_capped(decoder: impl Read, max_output: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let cap = max_output.saturating_add(1) as u64;
+    decoder.take(cap).read_to_end(&mut output)?;
+    if output.len() > max_output {
+        return Err(format!("Decompressed data exceeds {} bytes", max_output).into());
+    }
+    Ok(output)
+}
+
+/// Decompresses a zlib stream (RFC 1950 wrapping RFC 1951 deflate data), failing
+/// once the output would grow beyond `max_output` bytes
+pub fn inflate(data: &[u8], max_output: usize) -> Result<Vec<u8>> {
+    deflate_data(data)?;
+    read_capped(ZlibDecoder::new(data), max_output)
+}
+
+/// The first `len` bytes a zlib stream inflates to, or all of them if there are fewer;
+/// the rest of the stream and its checksum are not read
+pub fn inflate_prefix(data: &[u8], len: usize) -> Result<Vec<u8>> {
+    deflate_data(data)?;
+    let mut output = Vec::new();
+    ZlibDecoder::new(data)
+        .take(len as u64)
+        .read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Compresses `data` into a zlib stream at the default level
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    deflate_hiding(data, &[])
+}
+
+/// Compresses `data` like [`deflate`], after leading empty stored blocks whose padding
+/// bits carry `hidden`, five bits per five-byte block. Decoders skip the padding, so the
+/// stream still inflates to exactly `data`.
+pub fn deflate_hiding(data: &[u8], hidden: &[u8]) -> Vec<u8> {
+    compress(data, hidden, Compression::default())
+}
+
+/// Compresses `data` at the best level, carrying `hidden` like [`deflate_hiding`]
+pub fn deflate_best(data: &[u8], hidden: &[u8]) -> Vec<u8> {
+    compress(data, hidden, Compression::best())
+}
+
+fn compress(data: &[u8], hidden: &[u8], level: Compression) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+    encoder
+        .write_all(data)
+        .expect("compressing into memory does not fail");
+    let stream = encoder
+        .finish()
+        .expect("compressing into memory does not fail");
+
+    let bits: Vec<u8> = hidden
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+        .collect();
+    let blocks = bits.len().div_ceil(STORED_PADDING_BITS);
+    let mut bytes = Vec::with_capacity(stream.len() + blocks * EMPTY_STORED_LEN);
+    bytes.extend(&stream[..ZLIB_HEADER_LEN]);
+    for group in bits.chunks(STORED_PADDING_BITS) {
+        // Deflate packs bits from the lowest up: not final and stored take the first three
+        let header = group
+            .iter()
+            .enumerate()
+            .fold(0, |header, (i, &bit)| header | bit << (3 + i));
+        bytes.push(header);
+        bytes.extend(EMPTY_STORED_LENGTHS);
+    }
+    bytes.extend(&stream[ZLIB_HEADER_LEN..]);
+    bytes
+}
+
+/// Reads the bits hidden by [`deflate_hiding`] from the padding of the empty stored blocks
+/// at the start of a zlib stream, packed into bytes (a trailing partial byte is dropped)
+pub fn read_hidden(data: &[u8]) -> Result<Vec<u8>> {
+    let mut bits = Vec::new();
+    for block in deflate_data(data)?.chunks_exact(EMPTY_STORED_LEN) {
+        if block[0] & 0x07 != 0 || block[1..] != EMPTY_STORED_LENGTHS {
+            break;
+        }
+        bits.extend((3..8).map(|i| (block[0] >> i) & 1));
+    }
+    Ok(bits
+        .chunks_exact(8)
+        .map(|byte| byte.iter().fold(0, |value, &bit| (value << 1) | bit))
+        .collect())
+}
+
+/// Compresses `data` into a gzip member (RFC 1952) with no file name or timestamp
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .expect("compressing into memory does not fail");
+    encoder
+        .finish()
+        .expect("compressing into memory does not fail")
+}
+
+/// Decompresses a single gzip member, failing once the output would grow beyond
+/// `max_output` bytes
+pub fn gunzip(data: &[u8], max_output: usize) -> Result<Vec<u8>> {
+    read_capped(GzDecoder::new(data), max_output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut long: Vec<u8> = (0..70_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        long.extend(std::iter::repeat_n(b'a', 1000));
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(500);
+        for data in [
+            b"".as_slice(),
+            b"a",
+            b"abcabcabcabcabc",
+            &long,
+            text.as_bytes(),
+        ] {
+            assert_eq!(inflate(&deflate(data), usize::MAX).unwrap(), data);
+            assert_eq!(inflate(&deflate_best(data, &[]), usize::MAX).unwrap(), data);
+        }
+        assert!(deflate(&long).len() < long.len() / 10);
+    }
+
+    #[test]
+    fn test_inflate_rejects_corrupt_streams() {
+        let mut data = deflate(b"some data to compress");
+        assert!(inflate(&data[..data.len() - 2], usize::MAX).is_err());
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(inflate(&data, usize::MAX).is_err());
+        assert!(inflate(b"\x78\x9d", usize::MAX).is_err());
+        assert!(inflate(b"", usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_inflate_stops_at_max_output() {
+        let bomb = deflate(&vec![0; 1 << 20]);
+        assert_eq!(inflate(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+        assert!(inflate(&bomb, (1 << 20) - 1).is_err());
+        assert!(inflate(&deflate(b"hello"), 4).is_err());
+        assert!(gunzip(&gzip(&vec![0; 4096]), 4095).is_err());
+    }
+
+    #[test]
+    fn test_inflate_prefix() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let stream = deflate(&data);
+        assert_eq!(inflate_prefix(&stream, 10).unwrap(), data[..10]);
+        assert_eq!(inflate_prefix(&stream, 70_000).unwrap(), data[..70_000]);
+        assert_eq!(inflate_prefix(&stream, 200_000).unwrap(), data);
+        // The corrupt tail is never reached
+        assert_eq!(
+            inflate_prefix(&stream[..stream.len() / 2], 10).unwrap(),
+            data[..10]
+        );
+    }
+
+    #[test]
+    fn test_hidden_padding_round_trip() {
+        let data = b"image data that must come out unchanged".repeat(20);
+        let stream = deflate_hiding(&data, b"secret");
+        assert_eq!(inflate(&stream, usize::MAX).unwrap(), data);
+        assert_eq!(read_hidden(&stream).unwrap(), b"secret");
+        // 48 bits need ten five-byte blocks
+        assert_eq!(stream.len(), deflate(&data).len() + 50);
+
+        let stream = deflate_best(&data, b"secret");
+        assert_eq!(read_hidden(&stream).unwrap(), b"secret");
+        assert_eq!(inflate(&stream, usize::MAX).unwrap(), data);
+
+        assert_eq!(read_hidden(&deflate(&data)).unwrap(), b"");
+        assert!(read_hidden(b"\x78\x9d").is_err());
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let text = b"compressible text, compressible text, compressible text".repeat(10);
+        for data in [b"".as_slice(), b"a", &text] {
+            assert_eq!(gunzip(&gzip(data), usize::MAX).unwrap(), data);
+        }
+        assert!(gzip(&text).len() < text.len() / 4);
+
+        let mut corrupt = gzip(&text);
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 1;
+        assert!(gunzip(&corrupt, usize::MAX).is_err());
+        assert!(gunzip(&gzip(&text)[..20], usize::MAX).is_err());
+        assert!(gunzip(b"\x78\x9c\x03\x00", usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Runs the built program the way a shell would, for what only shows at the process
+//! level: messages read from stdin and output written to stdout
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+/// A fresh directory for the files of test `name`, holding an 8x8 `image.png`
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hpv-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("image.png");
+    run(
+        &dir,
+        &["create", path(&image), "--width", "8", "--height", "8"],
+        b"",
+    );
+    dir
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// Runs the program in `dir` with `args` and `stdin`, failing the test if it fails. The
+/// journal goes into `dir` as well, away from the user's own.
+fn run(dir: &Path, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_Hidden-Pixel-Vault"))
+        .args(args)
+        .current_dir(dir)
+        .env("XDG_DATA_HOME", dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// The message hidden in `chunk_type` of the image in `dir`, as `decode --out` writes it
+fn decoded(dir: &Path, chunk_type: &str) -> Vec<u8> {
+    let out = dir.join(format!("{}.out", chunk_type));
+    let image = dir.join("image.png");
+    run(
+        dir,
+        &["decode", path(&image), chunk_type, "--out", path(&out)],
+        b"",
+    );
+    fs::read(out).unwrap()
+}
+
+#[test]
+fn test_message_from_file_and_stdin() {
+    let dir = test_dir("message");
+    let image = dir.join("image.png");
+    let text = b"first line\n\"quoted\" $HOME `and` more\n";
+
+    let message_file = dir.join("message.txt");
+    fs::write(&message_file, text).unwrap();
+    let from_file = [
+        "encode",
+        path(&image),
+        "fiLe",
+        "--message-file",
+        path(&message_file),
+    ];
+    run(&dir, &from_file, b"");
+    assert_eq!(decoded(&dir, "fiLe"), text);
+
+    run(&dir, &["encode", path(&image), "stIn", "-"], text);
+    assert_eq!(decoded(&dir, "stIn"), text);
+
+    run(
+        &dir,
+        &["encode", path(&image), "stDn", "--message-file", "-"],
+        text,
+    );
+    assert_eq!(decoded(&dir, "stDn"), text);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_decode_raw_writes_only_the_message() {
+    let dir = test_dir("raw");
+    let image = dir.join("image.png");
+    fs::create_dir(dir.join("input")).unwrap();
+    let secret = dir.join("input").join("secret.bin");
+    let bytes: Vec<u8> = (0..=255).collect();
+    fs::write(&secret, &bytes).unwrap();
+    let encode = [
+        "encode",
+        path(&image),
+        "biNa",
+        "--file",
+        path(&secret),
+        "--password",
+        "pw",
+    ];
+    run(&dir, &encode, b"");
+    run(&dir, &["encode", path(&image), "teXt", "no newline"], b"");
+
+    let raw = ["decode", path(&image), "biNa", "--raw", "--password", "pw"];
+    assert_eq!(run(&dir, &raw, b"").stdout, bytes);
+    let raw = ["decode", path(&image), "teXt", "--raw"];
+    assert_eq!(run(&dir, &raw, b"").stdout, b"no newline");
+    // The hidden file is not restored under its name either
+    assert!(!dir.join("secret.bin").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_dry_run_leaves_the_file_unchanged() {
+    let dir = test_dir("dry-run");
+    let image = dir.join("image.png");
+    let before = fs::read(&image).unwrap();
+    let files = fs::read_dir(&dir).unwrap().count();
+
+    let output = run(
+        &dir,
+        &["encode", path(&image), "ruSt", "secret", "--dry-run"],
+        b"",
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Dry run"));
+    // Nor is the keyring touched, which would fail where there is none
+    let keyring = [
+        "encode",
+        path(&image),
+        "seCr",
+        "secret",
+        "--password",
+        "pw",
+        "--use-keyring",
+        "hpv-dry-run-test",
+        "--dry-run",
+    ];
+    run(&dir, &keyring, b"");
+
+    assert_eq!(fs::read(&image).unwrap(), before);
+    assert_eq!(
+        fs::read_dir(&dir).unwrap().count(),
+        files,
+        "no backup or temp file"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Runs the program like [`run`] with `path` searched first for other programs, and
+/// gives back its output whether or not it succeeded
+#[cfg(unix)]
+fn run_with_path(dir: &Path, args: &[&str], path: &Path) -> Output {
+    let search = std::env::join_paths(std::iter::once(path.to_path_buf()).chain(
+        std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+    ))
+    .unwrap();
+    Command::new(env!("CARGO_BIN_EXE_Hidden-Pixel-Vault"))
+        .args(args)
+        .current_dir(dir)
+        .env("XDG_DATA_HOME", dir)
+        .env("PATH", search)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rekey_keeps_the_old_password_when_the_keyring_fails() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("rekey-keyring");
+    let image = dir.join("image.png");
+    run(
+        &dir,
+        &[
+            "encode",
+            path(&image),
+            "ruSt",
+            "secret",
+            "--password",
+            "old",
+        ],
+        b"",
+    );
+    // A keyring that holds the old password but refuses to store a new one
+    let bin = dir.join("bin");
+    fs::create_dir(&bin).unwrap();
+    let secret_tool = bin.join("secret-tool");
+    fs::write(
+        &secret_tool,
+        "#!/bin/sh\n[ \"$1\" = lookup ] && printf old && exit 0\nexit 1\n",
+    )
+    .unwrap();
+    fs::set_permissions(&secret_tool, fs::Permissions::from_mode(0o755)).unwrap();
+    let before = fs::read(&image).unwrap();
+
+    let rekey = [
+        "rekey",
+        path(&image),
+        "ruSt",
+        "--use-keyring",
+        "hpv-test",
+        "--new-password",
+        "new",
+    ];
+    let output = run_with_path(&dir, &rekey, &bin);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("old password"));
+    assert_eq!(fs::read(&image).unwrap(), before);
+    fs::remove_dir_all(&dir).unwrap();
+}